@@ -0,0 +1,518 @@
+//! Two-pass assembler for lc3as-style source
+//!
+//! Supports every LC-3 instruction, the trap aliases (GETC, OUT, PUTS, IN, PUTSP, HALT) and
+//! the `.ORIG`, `.FILL`, `.BLKW`, `.STRINGZ` and `.END` directives. A file may hold several
+//! `.ORIG`/`.END` blocks. Numbers are `#decimal`, plain decimal or `xHEX`. A PC-relative
+//! operand is a label, a `#decimal` offset, or an `xHEX` address (the form the disassembler
+//! prints).
+
+use crate::endian::{self, Endian};
+use crate::lc3::LC3Memory;
+use crate::symbols::{sym_path, Symbols};
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "usage: lc3-emu asm <program.asm> [-o <program.obj>]";
+
+const TRAPS: [(&str, u16); 6] = [("GETC", 0x20), ("OUT", 0x21), ("PUTS", 0x22), ("IN", 0x23), ("PUTSP", 0x24), ("HALT", 0x25)];
+
+const OPCODES: [&str; 16] = ["ADD", "AND", "NOT", "JMP", "RET", "JSR", "JSRR", "LD", "LDI", "LDR", "LEA", "ST", "STI", "STR", "TRAP", "RTI"];
+
+/// Words assembled to consecutive addresses from `origin`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    pub origin: u16,
+    pub words: Vec<i16>
+}
+
+impl Section {
+    /// The section as an lc3as .obj file
+    pub fn obj(&self) -> Vec<u8> {
+	let mut words = vec![self.origin as i16];
+	words.extend_from_slice(&self.words);
+	endian::bytes(&words, Endian::Big)
+    }
+}
+
+/// An assembled source file
+#[derive(Debug, Default, Clone)]
+pub struct Program {
+    pub sections: Vec<Section>,
+    pub symbols: BTreeMap<String, u16>,
+    pub lines: Vec<(u16, usize)> // address of each statement that emits words, and its source line
+}
+
+impl Program {
+    /// Writes every section into memory
+    pub fn load(&self, memory: &mut LC3Memory) {
+	for section in &self.sections {
+	    memory.write_words(section.origin, &section.words);
+	}
+    }
+
+    /// The labels as an lc3as .sym file
+    pub fn sym(&self) -> String {
+	Symbols::from(&self.symbols).to_sym()
+    }
+}
+
+/// `lc3-emu asm`, returns the process exit code
+pub fn main(args: &[String]) -> i32 {
+    match asm_command(args) {
+	Ok(()) => 0,
+	Err(e) => {
+	    eprintln!("{}", e);
+	    1
+	}
+    }
+}
+
+/// Writes `prog.obj` and `prog.sym` like lc3as. Blocks after the first go to `prog.2.obj`,
+/// `prog.3.obj` and so on, since an object file holds one origin.
+fn asm_command(args: &[String]) -> Result<(), String> {
+    let mut source = None;
+    let mut out = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+	match arg.as_str() {
+	    "-o" => out = Some(PathBuf::from(args.next().ok_or(USAGE)?)),
+	    a if source.is_none() && !a.starts_with('-') => source = Some(a.to_string()),
+	    _ => return Err(USAGE.to_string())
+	}
+    }
+    let source = source.ok_or(USAGE)?;
+    let text = std::fs::read_to_string(&source).map_err(|e| format!("{}: {}", source, e))?;
+    let program = assemble(&text).map_err(|e| format!("{}: {}", source, e))?;
+    if program.sections.is_empty() {
+	return Err(format!("{}: no .ORIG block", source));
+    }
+    let obj = out.unwrap_or_else(|| Path::new(&source).with_extension("obj"));
+    for (i, section) in program.sections.iter().enumerate() {
+	let path = if i == 0 { obj.clone() } else { obj.with_extension(format!("{}.obj", i + 1)) };
+	std::fs::write(&path, section.obj()).map_err(|e| format!("{}: {}", path.display(), e))?;
+	println!("{}: x{:04X}, {} words", path.display(), section.origin, section.words.len());
+    }
+    let sym = sym_path(&obj);
+    std::fs::write(&sym, program.sym()).map_err(|e| format!("{}: {}", sym.display(), e))?;
+    println!("{}: {} symbols", sym.display(), program.symbols.len());
+    Ok(())
+}
+
+/// A statement after pass one
+struct Statement {
+    line: usize,
+    addr: u16,
+    op: String, // upper case
+    operands: Vec<String>
+}
+
+/// Assembles a source file, reporting the first error with its line number
+pub fn assemble(source: &str) -> Result<Program, String> {
+    let mut program = Program::default();
+    let mut statements: Vec<(usize, Statement)> = Vec::new(); // section index, statement
+    let mut location: Option<u32> = None; // next address, None outside .ORIG/.END
+
+    // pass one: addresses and labels
+    for (i, text) in source.lines().enumerate() {
+	let line = i + 1;
+	let error = |e: String| format!("line {}: {}", line, e);
+	let mut tokens = tokens(text).map_err(error)?;
+	if tokens.is_empty() {
+	    continue;
+	}
+	if !is_op(&tokens[0]) {
+	    let label = tokens.remove(0);
+	    let label = label.strip_suffix(':').unwrap_or(&label).to_string();
+	    if !is_label(&label) || tokens.first().is_some_and(|t| !is_op(t)) {
+		return Err(error(format!("Unknown instruction: {}", label)));
+	    }
+	    let addr = location.ok_or_else(|| error(format!("Label outside .ORIG/.END: {}", label)))?;
+	    if addr > 0xFFFF {
+		return Err(error("Program extends past xFFFF".to_string()));
+	    }
+	    if program.symbols.insert(label.clone(), addr as u16).is_some() {
+		return Err(error(format!("Duplicate label: {}", label)));
+	    }
+	    if tokens.is_empty() {
+		continue;
+	    }
+	}
+	let op = tokens.remove(0).to_ascii_uppercase();
+	match (op.as_str(), location) {
+	    (".ORIG", None) => {
+		let origin = match tokens.as_slice() {
+		    [n] => number(n).filter(|n| (0..=0xFFFF).contains(n)),
+		    _ => None
+		};
+		let origin = origin.ok_or_else(|| error(".ORIG needs an address".to_string()))?;
+		program.sections.push(Section { origin: origin as u16, words: Vec::new() });
+		location = Some(origin as u32);
+		continue;
+	    }
+	    (".ORIG", Some(_)) => return Err(error(".ORIG inside a block, missing .END".to_string())),
+	    (".END", _) => {
+		location = None;
+		continue;
+	    }
+	    (_, None) => return Err(error(format!("{} outside .ORIG/.END", op))),
+	    _ => ()
+	}
+	let addr = location.unwrap();
+	let size = match op.as_str() {
+	    ".BLKW" => match tokens.as_slice() {
+		[n] => number(n).filter(|n| *n >= 0).ok_or_else(|| error(".BLKW needs a count".to_string()))? as u32,
+		_ => return Err(error(".BLKW needs a count".to_string()))
+	    },
+	    ".STRINGZ" => match tokens.as_slice() {
+		[s] => unquote(s).map_err(error)?.len() as u32 + 1,
+		_ => return Err(error(".STRINGZ needs one string".to_string()))
+	    },
+	    _ => 1
+	};
+	if addr + size > 0x10000 {
+	    return Err(error("Program extends past xFFFF".to_string()));
+	}
+	location = Some(addr + size);
+	let statement = Statement { line, addr: addr as u16, op, operands: tokens };
+	statements.push((program.sections.len() - 1, statement));
+    }
+    if location.is_some() {
+	return Err("Missing .END".to_string());
+    }
+
+    // pass two: encoding
+    for (section, statement) in statements {
+	let words = encode(&statement, &program.symbols).map_err(|e| format!("line {}: {}", statement.line, e))?;
+	if !words.is_empty() {
+	    program.lines.push((statement.addr, statement.line));
+	}
+	program.sections[section].words.extend(words);
+    }
+    Ok(program)
+}
+
+/// Splits a line into tokens at whitespace and commas, dropping the comment and keeping
+/// string literals (with their quotes) whole
+fn tokens(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+	match c {
+	    ';' => break,
+	    '"' => {
+		current.push(c);
+		loop {
+		    match chars.next() {
+			Some('"') => break,
+			Some('\\') => {
+			    current.push('\\');
+			    current.extend(chars.next());
+			}
+			Some(c) => current.push(c),
+			None => return Err("Unterminated string".to_string())
+		    }
+		}
+		current.push('"');
+	    }
+	    c if c.is_whitespace() || c == ',' => {
+		if !current.is_empty() {
+		    tokens.push(std::mem::take(&mut current));
+		}
+	    }
+	    c => current.push(c)
+	}
+    }
+    if !current.is_empty() {
+	tokens.push(current);
+    }
+    Ok(tokens)
+}
+
+/// The characters of a string literal, escapes resolved
+fn unquote(token: &str) -> Result<Vec<u8>, String> {
+    let inner = token.strip_prefix('"').and_then(|t| t.strip_suffix('"'))
+	.ok_or_else(|| format!("Expected a string, found {}", token))?;
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+	let c = match c {
+	    '\\' => match chars.next() {
+		Some('n') => '\n',
+		Some('t') => '\t',
+		Some('r') => '\r',
+		Some('e') => '\x1b',
+		Some('0') => '\0',
+		Some(c @ ('\\' | '"')) => c,
+		_ => return Err(format!("Unknown escape in {}", token))
+	    },
+	    c => c
+	};
+	if !c.is_ascii() {
+	    return Err(format!("Non-ASCII character in {}", token));
+	}
+	bytes.push(c as u8);
+    }
+    Ok(bytes)
+}
+
+fn branch(op: &str) -> Option<u16> {
+    let flags = op.strip_prefix("BR")?;
+    if flags.is_empty() {
+	return Some(0b111);
+    }
+    let mut mask = 0;
+    let mut last = 0b1000;
+    for c in flags.chars() {
+	let bit = match c {
+	    'N' => 0b100,
+	    'Z' => 0b010,
+	    'P' => 0b001,
+	    _ => return None
+	};
+	if bit >= last {
+	    return None; // out of order or repeated
+	}
+	mask |= bit;
+	last = bit;
+    }
+    Some(mask)
+}
+
+fn is_op(token: &str) -> bool {
+    let upper = token.to_ascii_uppercase();
+    upper.starts_with('.') || OPCODES.contains(&upper.as_str())
+	|| TRAPS.iter().any(|(name, _)| *name == upper) || branch(&upper).is_some()
+}
+
+fn is_label(token: &str) -> bool {
+    let mut chars = token.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+	&& chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+	&& register(token).is_none() && number(token).is_none()
+}
+
+fn register(token: &str) -> Option<u16> {
+    match token.as_bytes() {
+	[b'r' | b'R', n @ b'0'..=b'7'] => Some((n - b'0') as u16),
+	_ => None
+    }
+}
+
+/// `#-5`, `-5`, `x3000`
+fn number(token: &str) -> Option<i32> {
+    if let Some(hex) = token.strip_prefix('x').or_else(|| token.strip_prefix('X')) {
+	let (negative, hex) = match hex.strip_prefix('-') {
+	    Some(hex) => (true, hex),
+	    None => (false, hex)
+	};
+	let value = u16::from_str_radix(hex, 16).ok()? as i32;
+	return Some(if negative { -value } else { value });
+    }
+    let decimal = token.strip_prefix('#').unwrap_or(token);
+    if !decimal.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+	return None;
+    }
+    decimal.parse().ok()
+}
+
+/// `value` as a `bits` wide two's complement field
+fn signed(value: i32, bits: u32) -> Result<u16, String> {
+    let limit = 1 << (bits - 1);
+    if value < -limit || value >= limit {
+	return Err(format!("{} doesn't fit in {} bits", value, bits));
+    }
+    Ok(value as u16 & ((1 << bits) - 1))
+}
+
+/// The operands of one statement
+struct Operands<'a> {
+    tokens: &'a [String],
+    symbols: &'a BTreeMap<String, u16>,
+    next: u16 // address after the instruction, what PC offsets count from
+}
+
+impl Operands<'_> {
+    fn expect(&self, count: usize) -> Result<(), String> {
+	if self.tokens.len() != count {
+	    return Err(format!("Expected {} operand(s), found {}", count, self.tokens.len()));
+	}
+	Ok(())
+    }
+
+    fn reg(&self, i: usize) -> Result<u16, String> {
+	register(&self.tokens[i]).ok_or_else(|| format!("Expected a register, found {}", self.tokens[i]))
+    }
+
+    fn imm(&self, i: usize, bits: u32) -> Result<u16, String> {
+	let value = number(&self.tokens[i]).ok_or_else(|| format!("Expected a number, found {}", self.tokens[i]))?;
+	signed(value, bits)
+    }
+
+    /// A label or address relative to the next instruction, or a literal offset
+    fn offset(&self, i: usize, bits: u32) -> Result<u16, String> {
+	let token = &self.tokens[i];
+	let offset = if let Some(&addr) = self.symbols.get(token) {
+	    addr as i32 - self.next as i32
+	} else if token.starts_with(['x', 'X']) {
+	    let addr = number(token).ok_or_else(|| format!("Bad address: {}", token))?;
+	    addr as u16 as i32 - self.next as i32
+	} else if let Some(offset) = number(token) {
+	    offset
+	} else if is_label(token) {
+	    return Err(format!("Undefined label: {}", token));
+	} else {
+	    return Err(format!("Expected a label or offset, found {}", token));
+	};
+	signed(offset, bits).map_err(|_| format!("{} is too far away ({} words)", token, offset))
+    }
+}
+
+fn encode(statement: &Statement, symbols: &BTreeMap<String, u16>) -> Result<Vec<i16>, String> {
+    let args = Operands { tokens: &statement.operands, symbols, next: statement.addr.wrapping_add(1) };
+    let op = statement.op.as_str();
+    let word = match op {
+	".FILL" => {
+	    args.expect(1)?;
+	    let token = &args.tokens[0];
+	    let value = match symbols.get(token) {
+		Some(&addr) => addr as i32,
+		None => number(token).ok_or_else(|| format!("Undefined label: {}", token))?
+	    };
+	    if !(-0x8000..=0xFFFF).contains(&value) {
+		return Err(format!("{} doesn't fit in 16 bits", value));
+	    }
+	    value as u16
+	}
+	".BLKW" => return Ok(vec![0; number(&args.tokens[0]).unwrap() as usize]),
+	".STRINGZ" => {
+	    let mut words: Vec<i16> = unquote(&args.tokens[0])?.iter().map(|&b| b as i16).collect();
+	    words.push(0);
+	    return Ok(words);
+	}
+	"ADD" | "AND" => {
+	    args.expect(3)?;
+	    let base = if op == "ADD" { 0x1000 } else { 0x5000 };
+	    let last = match register(&args.tokens[2]) {
+		Some(sr2) => sr2,
+		None => 0b1_00000 | args.imm(2, 5)?
+	    };
+	    base | args.reg(0)? << 9 | args.reg(1)? << 6 | last
+	}
+	"NOT" => {
+	    args.expect(2)?;
+	    0x903F | args.reg(0)? << 9 | args.reg(1)? << 6
+	}
+	"JMP" | "JSRR" => {
+	    args.expect(1)?;
+	    (if op == "JMP" { 0xC000 } else { 0x4000 }) | args.reg(0)? << 6
+	}
+	"RET" => {
+	    args.expect(0)?;
+	    0xC1C0
+	}
+	"RTI" => {
+	    args.expect(0)?;
+	    0x8000
+	}
+	"JSR" => {
+	    args.expect(1)?;
+	    0x4800 | args.offset(0, 11)?
+	}
+	"LD" | "LDI" | "LEA" | "ST" | "STI" => {
+	    args.expect(2)?;
+	    let base = match op {
+		"LD" => 0x2000,
+		"LDI" => 0xA000,
+		"LEA" => 0xE000,
+		"ST" => 0x3000,
+		_ => 0xB000
+	    };
+	    base | args.reg(0)? << 9 | args.offset(1, 9)?
+	}
+	"LDR" | "STR" => {
+	    args.expect(3)?;
+	    (if op == "LDR" { 0x6000 } else { 0x7000 }) | args.reg(0)? << 9 | args.reg(1)? << 6 | args.imm(2, 6)?
+	}
+	"TRAP" => {
+	    args.expect(1)?;
+	    let vector = number(&args.tokens[0]).filter(|v| (0..=0xFF).contains(v))
+		.ok_or_else(|| format!("Bad trap vector: {}", args.tokens[0]))?;
+	    0xF000 | vector as u16
+	}
+	_ => if let Some(&(_, vector)) = TRAPS.iter().find(|(name, _)| *name == op) {
+	    args.expect(0)?;
+	    0xF000 | vector
+	} else if let Some(flags) = branch(op) {
+	    args.expect(1)?;
+	    flags << 9 | args.offset(0, 9)?
+	} else {
+	    return Err(format!("Unknown directive: {}", op));
+	}
+    };
+    Ok(vec![word as i16])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, number, tokens};
+    use crate::disasm::disassemble;
+
+    #[test]
+    fn tokens_test() {
+	assert_eq!(tokens("LOOP ADD R1,R1, #-1 ; count down").unwrap(), vec!["LOOP", "ADD", "R1", "R1", "#-1"]);
+	assert_eq!(tokens("MSG .STRINGZ \"a; b\\\"\"").unwrap(), vec!["MSG", ".STRINGZ", "\"a; b\\\"\""]);
+	assert!(tokens(".STRINGZ \"open").is_err());
+	assert_eq!(number("x-10"), Some(-16));
+	assert_eq!(number("LOOP"), None);
+    }
+
+    #[test]
+    fn assemble_test() {
+	let program = assemble("
+	    .ORIG x3000
+	    LEA R0, MSG
+	    PUTS
+	LOOP ADD R1, R1, #-1
+	    BRp LOOP
+	    LDR R2, R1, #-32
+	    NOT R3, R2
+	    JSR SUB
+	    HALT
+	SUB: AND R4, R4, R3
+	    LD R5, x3000
+	    RET
+	MSG .STRINGZ \"hi\\n\"
+	DATA .FILL MSG
+	    .BLKW 2
+	    .END").expect("Failed to assemble");
+	let section = &program.sections[0];
+	assert_eq!(section.origin, 0x3000);
+	let listing: Vec<String> = section.words[..11].iter().enumerate()
+	    .map(|(i, w)| disassemble(*w, 0x3000 + i as u16))
+	    .collect();
+	assert_eq!(listing, vec![
+	    "LEA R0, x300B", "PUTS", "ADD R1, R1, #-1", "BRp x3002", "LDR R2, R1, #-32", "NOT R3, R2",
+	    "JSR x3008", "HALT", "AND R4, R4, R3", "LD R5, x3000", "RET"
+	]);
+	assert_eq!(&section.words[11..], &[b'h' as i16, b'i' as i16, b'\n' as i16, 0, 0x300B, 0, 0]);
+	assert_eq!(program.symbols["LOOP"], 0x3002);
+	assert_eq!(program.symbols["SUB"], 0x3008);
+	assert!(program.sym().contains("//\tLOOP              3002\n"));
+	assert_eq!(program.lines[2], (0x3002, 5));
+	assert_eq!(section.obj()[..4], [0x30, 0x00, 0xE0, 0x0A]);
+    }
+
+    #[test]
+    fn error_test() {
+	let error = |source: &str| assemble(source).unwrap_err();
+	assert_eq!(error(".ORIG x3000\nFOO R1\n.END"), "line 2: Unknown instruction: FOO");
+	assert_eq!(error(".ORIG x3000\nBR NOWHERE\n.END"), "line 2: Undefined label: NOWHERE");
+	assert_eq!(error(".ORIG x3000\nADD R1, R1, #16\n.END"), "line 2: 16 doesn't fit in 5 bits");
+	assert_eq!(error(".ORIG x3000\nA ADD R1, R1, #1\nA RET\n.END"), "line 3: Duplicate label: A");
+	assert_eq!(error("ADD R1, R1, #1"), "line 1: ADD outside .ORIG/.END");
+	assert_eq!(error(".ORIG x3000\nRET"), "Missing .END");
+	assert!(error(".ORIG x3000\nBR x4000\n.END").contains("too far"));
+    }
+}
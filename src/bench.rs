@@ -0,0 +1,191 @@
+use crate::endian::{self, Endian};
+use crate::json::{self, Value};
+use crate::lc3::batch::StopReason;
+use crate::os::boot;
+
+use std::time::Instant;
+
+const USAGE: &str = "usage: lc3-emu bench <program.obj> [--runs N] [--limit N] [--little-endian] [--json <out.json>] [--baseline <base.json>]";
+
+/// Measurements from one run of a program
+#[derive(Debug, Copy, Clone)]
+struct Run {
+    instructions: u64,
+    cycles: u64, // clock() calls, including idle ones
+    host_secs: f64
+}
+
+/// `lc3-emu bench`, returns the process exit code
+pub fn main(args: &[String]) -> i32 {
+    match bench(args) {
+	Ok(()) => 0,
+	Err(e) => {
+	    eprintln!("{}", e);
+	    1
+	}
+    }
+}
+
+fn bench(args: &[String]) -> Result<(), String> {
+    let mut program = None;
+    let mut runs = 10;
+    let mut limit = 100_000_000;
+    let mut endian = Endian::Big;
+    let mut json_out = None;
+    let mut baseline = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+	match arg.as_str() {
+	    "--runs" => runs = number(args.next())?,
+	    "--limit" => limit = number(args.next())?,
+	    "--little-endian" => endian = Endian::Little,
+	    "--json" => json_out = Some(args.next().ok_or(USAGE)?.clone()),
+	    "--baseline" => baseline = Some(args.next().ok_or(USAGE)?.clone()),
+	    a if program.is_none() && !a.starts_with("--") => program = Some(a.to_string()),
+	    _ => return Err(USAGE.to_string())
+	}
+    }
+    let program = program.ok_or(USAGE)?;
+    if runs == 0 {
+	return Err("--runs must be at least 1".to_string());
+    }
+    let obj = std::fs::read(&program).map_err(|e| format!("{}: {}", program, e))?;
+    let obj = endian::convert(&obj, endian, Endian::Big).map_err(|e| format!("{}: {}", program, e))?;
+
+    let mut results = Vec::new();
+    for _ in 0..runs {
+	results.push(run_once(&obj, limit)?);
+    }
+    let report = report(&program, &results);
+    print_report(&report);
+
+    if let Some(path) = baseline {
+	let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+	let base = json::parse(&text).map_err(|e| format!("{}: {}", path, e))?;
+	print_comparison(&base, &report);
+    }
+    if let Some(path) = json_out {
+	std::fs::write(&path, report.to_string() + "\n").map_err(|e| format!("{}: {}", path, e))?;
+    }
+    Ok(())
+}
+
+fn number(arg: Option<&String>) -> Result<u64, String> {
+    arg.and_then(|a| a.parse().ok()).ok_or_else(|| USAGE.to_string())
+}
+
+/// Runs the program on a fresh machine until it halts
+fn run_once(obj: &[u8], limit: u64) -> Result<Run, String> {
+    let mut lc3 = boot(obj)?;
+    let start = Instant::now();
+    loop {
+	if lc3.instructions >= limit {
+	    return Err(format!("Program did not halt within {} instructions", limit));
+	}
+	match lc3.run_steps(limit - lc3.instructions) {
+	    StopReason::Halted => break,
+	    StopReason::Idle => return Err(format!("Program waited for keyboard input at 0x{:04x}", lc3.pc)),
+	    StopReason::Exception(vector) => return Err(format!("Program raised exception x{:02X} with no handler", vector)),
+	    StopReason::Breakpoint(addr) => return Err(format!("Unexpected breakpoint at 0x{:04x}", addr)),
+	    StopReason::Watch(watch) => return Err(format!("Unexpected watchpoint {:?}", watch)),
+	    StopReason::Reset | StopReason::Limit => ()
+	}
+    }
+    Ok(Run {
+	instructions: lc3.instructions,
+	cycles: lc3.ticks,
+	host_secs: start.elapsed().as_secs_f64()
+    })
+}
+
+/// Sample mean and standard deviation
+pub fn mean_stddev(samples: &[f64]) -> (f64, f64) {
+    let n = samples.len() as f64;
+    let mean = samples.iter().sum::<f64>() / n;
+    if samples.len() < 2 {
+	return (mean, 0.0);
+    }
+    let var = samples.iter().map(|s| (s - mean) * (s - mean)).sum::<f64>() / (n - 1.0);
+    (mean, var.sqrt())
+}
+
+fn stat(samples: &[f64]) -> Value {
+    let (mean, stddev) = mean_stddev(samples);
+    json::object(vec![("mean", mean.into()), ("stddev", stddev.into())])
+}
+
+fn report(program: &str, results: &[Run]) -> Value {
+    let instructions: Vec<f64> = results.iter().map(|r| r.instructions as f64).collect();
+    let cycles: Vec<f64> = results.iter().map(|r| r.cycles as f64).collect();
+    let host_ms: Vec<f64> = results.iter().map(|r| r.host_secs * 1000.0).collect();
+    let mips: Vec<f64> = results.iter().map(|r| r.instructions as f64 / r.host_secs.max(1e-9) / 1e6).collect();
+    json::object(vec![
+	("program", program.into()),
+	("engine", "interpreter".into()),
+	("runs", (results.len() as u64).into()),
+	("instructions", stat(&instructions)),
+	("cycles", stat(&cycles)),
+	("host_ms", stat(&host_ms)),
+	("mips", stat(&mips))
+    ])
+}
+
+const METRICS: [(&str, &str); 4] = [
+    ("instructions", "instructions"),
+    ("cycles", "cycles"),
+    ("host_ms", "host time (ms)"),
+    ("mips", "rate (MIPS)")
+];
+
+fn mean_of(report: &Value, key: &str) -> Option<(f64, f64)> {
+    let stat = report.get(key)?;
+    Some((stat.get("mean")?.as_f64()?, stat.get("stddev")?.as_f64()?))
+}
+
+fn print_report(report: &Value) {
+    println!("{} ({} engine, {} runs)",
+	     report.get("program").and_then(|p| p.as_str()).unwrap_or("?"),
+	     report.get("engine").and_then(|e| e.as_str()).unwrap_or("?"),
+	     report.get("runs").and_then(|r| r.as_f64()).unwrap_or(0.0));
+    for (key, label) in METRICS.iter() {
+	if let Some((mean, stddev)) = mean_of(report, key) {
+	    println!("  {:<16} {:>14.3} ± {:.3}", label, mean, stddev);
+	}
+    }
+}
+
+fn print_comparison(base: &Value, report: &Value) {
+    println!("compared to baseline:");
+    for (key, label) in METRICS.iter() {
+	match (mean_of(base, key), mean_of(report, key)) {
+	    (Some((old, _)), Some((new, _))) => {
+		let change = if old == 0.0 { 0.0 } else { (new - old) / old * 100.0 };
+		println!("  {:<16} {:>14.3} -> {:<14.3} ({:+.1}%)", label, old, new, change);
+	    }
+	    _ => println!("  {:<16} missing from baseline", label)
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mean_stddev, run_once};
+
+    #[test]
+    fn mean_stddev_test() {
+	assert_eq!(mean_stddev(&[3.0]), (3.0, 0.0));
+	let (mean, stddev) = mean_stddev(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+	assert_eq!(mean, 5.0);
+	assert!((stddev - 2.138).abs() < 0.001);
+    }
+
+    #[test]
+    fn run_once_test() {
+	// ADD R1, R1, #3 ; HALT
+	let obj = [0x30, 0x00, 0x12, 0x63, 0xF0, 0x25];
+	let run = run_once(&obj, 1000).expect("Failed to run");
+	assert_eq!(run.instructions, 2 + 404); // program plus the HALT routine printing its message
+	// BR #-1 never halts
+	assert!(run_once(&[0x30, 0x00, 0x0F, 0xFF], 1000).is_err());
+    }
+}
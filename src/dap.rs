@@ -0,0 +1,641 @@
+//! Debug Adapter Protocol server, behind the `dap` feature: `lc3-emu dap`
+//!
+//! An editor starts it and speaks DAP over stdin and stdout. `launch` takes an .asm file,
+//! which is assembled and run on the built-in OS so breakpoints can be set by source line,
+//! or an .obj file with its .sym beside it. Registers and the program's labels show up as
+//! variables, hovering a register or label shows its value, and the debug console takes
+//! monitor commands (`mem x3000 4`, `type abc`, `dump DATA x3080`).
+//!
+//! A VS Code launch configuration, given an extension that registers the `lc3` type:
+//!
+//! ```text
+//! { "type": "lc3", "request": "launch", "name": "Run", "program": "${file}", "stopOnEntry": true }
+//! ```
+
+use crate::asm;
+use crate::debugger::{self, Debugger};
+use crate::json::{self, object, Value};
+use crate::lc3::batch::StopReason;
+use crate::lc3::debug::{Breakpoint, Watch};
+use crate::lc3::LC3;
+use crate::os::{boot, prepare_supervisor, prepare_user_mode};
+use crate::symbols::{sym_path, Symbols};
+
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+const USAGE: &str = "usage: lc3-emu dap";
+
+/// Instructions run between checks for a pause request
+const SLICE: u64 = 100_000;
+
+/// The only thread
+const THREAD: u64 = 1;
+
+// variablesReference of each scope
+const REGISTERS: u64 = 1;
+const LABELS: u64 = 2;
+
+/// Reads one `Content-Length` framed message, `None` at end of input
+pub fn read_message(input: &mut impl BufRead) -> Result<Option<Value>, String> {
+    let mut length = None;
+    loop {
+	let mut line = String::new();
+	if input.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+	    return Ok(None);
+	}
+	let line = line.trim_end();
+	if line.is_empty() {
+	    break;
+	}
+	if let Some(n) = line.strip_prefix("Content-Length:") {
+	    length = Some(n.trim().parse::<usize>().map_err(|_| format!("Bad header: {}", line))?);
+	}
+    }
+    let mut body = vec![0; length.ok_or("Missing Content-Length")?];
+    input.read_exact(&mut body).map_err(|e| e.to_string())?;
+    let text = String::from_utf8(body).map_err(|_| "Message isn't UTF-8")?;
+    json::parse(&text).map(Some)
+}
+
+pub fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let text = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", text.len(), text)?;
+    output.flush()
+}
+
+/// What the machine is doing between requests
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Running {
+    Continue,
+    Depth(usize) // stepping over or out, until the call stack is this shallow
+}
+
+pub struct Session<W: Write> {
+    out: W,
+    seq: u64,
+    lc3: LC3,
+    symbols: Symbols,
+    source: Option<PathBuf>, // the .asm launched
+    lines: Vec<(u16, usize)>, // address of each statement and its source line
+    breakpoints: Vec<u16>, // set from the editor, replaced by each setBreakpoints
+    stop_on_entry: bool,
+    running: Option<Running>,
+    output: String, // display output not yet sent
+    events: Vec<(&'static str, Value)> // sent after the response to the current request
+}
+
+fn arg<'a>(args: &'a Value, key: &str) -> Result<&'a Value, String> {
+    args.get(key).ok_or_else(|| format!("Missing argument: {}", key))
+}
+
+fn number(args: &Value, key: &str) -> Result<u64, String> {
+    arg(args, key)?.as_f64().map(|n| n as u64).ok_or_else(|| format!("Not a number: {}", key))
+}
+
+/// Whether two paths name the same file
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+	(Ok(a), Ok(b)) => a == b,
+	_ => a == b
+    }
+}
+
+fn variable(name: &str, value: String) -> Value {
+    object(vec![("name", name.into()), ("value", value.into()), ("variablesReference", 0u64.into())])
+}
+
+/// `x0005 #5`
+fn word(value: i16) -> String {
+    format!("x{:04X} #{}", value as u16, value)
+}
+
+impl<W: Write> Session<W> {
+    pub fn new(out: W) -> Self {
+	Session {
+	    out,
+	    seq: 0,
+	    lc3: LC3::new(),
+	    symbols: Symbols::new(),
+	    source: None,
+	    lines: Vec::new(),
+	    breakpoints: Vec::new(),
+	    stop_on_entry: false,
+	    running: None,
+	    output: String::new(),
+	    events: Vec::new()
+	}
+    }
+
+    fn send(&mut self, mut fields: Vec<(&str, Value)>) {
+	self.seq += 1;
+	fields.insert(0, ("seq", self.seq.into()));
+	write_message(&mut self.out, &object(fields)).ok();
+    }
+
+    /// Queues an event to go out after the current response
+    fn event(&mut self, event: &'static str, body: Value) {
+	self.events.push((event, body));
+    }
+
+    fn send_events(&mut self) {
+	for (event, body) in std::mem::take(&mut self.events) {
+	    self.send(vec![("type", "event".into()), ("event", event.into()), ("body", body)]);
+	}
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) {
+	self.running = None;
+	self.flush_output();
+	let mut body = vec![("reason", reason.into()), ("threadId", THREAD.into()), ("allThreadsStopped", true.into())];
+	if let Some(text) = text {
+	    body.push(("text", text.into()));
+	}
+	self.event("stopped", object(body));
+    }
+
+    fn console(&mut self, text: String) {
+	self.event("output", object(vec![("category", "console".into()), ("output", text.into())]));
+    }
+
+    fn flush_output(&mut self) {
+	if !self.output.is_empty() {
+	    let output = std::mem::take(&mut self.output);
+	    self.event("output", object(vec![("category", "stdout".into()), ("output", output.into())]));
+	}
+    }
+
+    /// Handles one request, returning false once the editor has disconnected
+    pub fn handle(&mut self, request: &Value) -> bool {
+	let command = request.get("command").and_then(Value::as_str).unwrap_or("").to_string();
+	let args = request.get("arguments").cloned().unwrap_or(Value::Null);
+	let result = self.request(&command, &args);
+	let mut fields = vec![
+	    ("type", "response".into()),
+	    ("request_seq", request.get("seq").cloned().unwrap_or(Value::Null)),
+	    ("success", result.is_ok().into()),
+	    ("command", command.as_str().into())
+	];
+	match result {
+	    Ok(body) => fields.push(("body", body)),
+	    Err(e) => fields.push(("message", e.into()))
+	}
+	self.send(fields);
+	self.send_events();
+	!matches!(command.as_str(), "disconnect" | "terminate")
+    }
+
+    fn request(&mut self, command: &str, args: &Value) -> Result<Value, String> {
+	match command {
+	    "initialize" => Ok(object(vec![
+		("supportsConfigurationDoneRequest", true.into()),
+		("supportsEvaluateForHovers", true.into()),
+		("supportsTerminateRequest", true.into())
+	    ])),
+	    "launch" => {
+		self.launch(args)?;
+		self.event("initialized", Value::Null);
+		Ok(Value::Null)
+	    }
+	    "setBreakpoints" => self.set_breakpoints(args),
+	    "setExceptionBreakpoints" => Ok(object(vec![("breakpoints", Value::Array(Vec::new()))])),
+	    "configurationDone" => {
+		if self.stop_on_entry {
+		    self.stopped("entry", None);
+		} else {
+		    self.running = Some(Running::Continue);
+		}
+		Ok(Value::Null)
+	    }
+	    "threads" => Ok(object(vec![
+		("threads", Value::Array(vec![object(vec![("id", THREAD.into()), ("name", "LC-3".into())])]))
+	    ])),
+	    "stackTrace" => Ok(self.stack_trace()),
+	    "scopes" => Ok(object(vec![("scopes", Value::Array(vec![
+		object(vec![("name", "Registers".into()), ("variablesReference", REGISTERS.into()), ("expensive", false.into())]),
+		object(vec![("name", "Labels".into()), ("variablesReference", LABELS.into()), ("expensive", false.into())])
+	    ]))])),
+	    "variables" => self.variables(number(args, "variablesReference")?),
+	    "continue" => {
+		self.step(Some(Running::Continue))?;
+		Ok(object(vec![("allThreadsContinued", true.into())]))
+	    }
+	    "next" => {
+		let depth = self.lc3.call_stack().len();
+		self.step(Some(Running::Depth(depth)))?;
+		Ok(Value::Null)
+	    }
+	    "stepIn" => {
+		self.step(None)?;
+		Ok(Value::Null)
+	    }
+	    "stepOut" => match self.lc3.call_stack().len() {
+		0 => Err("Not in a subroutine".to_string()),
+		depth => {
+		    self.step(Some(Running::Depth(depth - 1)))?;
+		    Ok(Value::Null)
+		}
+	    },
+	    "pause" => {
+		if self.running.is_some() {
+		    self.stopped("pause", None);
+		}
+		Ok(Value::Null)
+	    }
+	    "evaluate" => self.evaluate(args),
+	    "disconnect" => Ok(Value::Null),
+	    "terminate" => {
+		self.event("terminated", Value::Null);
+		Ok(Value::Null)
+	    }
+	    _ => Err(format!("Unsupported request: {}", command))
+	}
+    }
+
+    /// Loads the program named by `args.program` on a fresh machine
+    fn launch(&mut self, args: &Value) -> Result<(), String> {
+	let path = PathBuf::from(arg(args, "program")?.as_str().ok_or("program should be a path")?);
+	let error = |e: String| format!("{}: {}", path.display(), e);
+	self.stop_on_entry = args.get("stopOnEntry").and_then(Value::as_bool).unwrap_or(false);
+	if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("asm")) {
+	    let text = std::fs::read_to_string(&path).map_err(|e| error(e.to_string()))?;
+	    let program = asm::assemble(&text).map_err(error)?;
+	    let origin = program.sections.first().ok_or_else(|| error("no .ORIG block".to_string()))?.origin;
+	    self.lc3 = LC3::new();
+	    prepare_supervisor(&mut self.lc3);
+	    program.load(&mut self.lc3.memory);
+	    prepare_user_mode(&mut self.lc3, origin);
+	    self.lc3.start();
+	    self.symbols = Symbols::from(&program.symbols);
+	    self.lines = program.lines;
+	    self.source = Some(path);
+	} else {
+	    let obj = std::fs::read(&path).map_err(|e| error(e.to_string()))?;
+	    self.lc3 = boot(&obj).map_err(|e| error(e.to_string()))?;
+	    self.symbols = Symbols::read_file(&sym_path(&path)).unwrap_or_default();
+	    self.lines.clear();
+	    self.source = None;
+	}
+	self.breakpoints.clear();
+	Ok(())
+    }
+
+    /// Replaces the breakpoints in a source file, moving each to the first statement at or
+    /// after its line
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+	let path = arg(args, "source")?.get("path").and_then(Value::as_str).map(PathBuf::from);
+	let ours = match (&path, &self.source) {
+	    (Some(path), Some(source)) => same_file(path, source),
+	    _ => false
+	};
+	for addr in std::mem::take(&mut self.breakpoints) {
+	    self.lc3.remove_breakpoint(addr);
+	}
+	let requested = match args.get("breakpoints") {
+	    Some(Value::Array(breakpoints)) => breakpoints.clone(),
+	    _ => Vec::new()
+	};
+	let mut results = Vec::new();
+	for breakpoint in &requested {
+	    let line = number(breakpoint, "line")? as usize;
+	    let statement = self.lines.iter()
+		.filter(|(_, l)| *l >= line)
+		.min_by_key(|(_, l)| *l)
+		.copied()
+		.filter(|_| ours);
+	    results.push(match statement {
+		Some((addr, line)) => {
+		    self.lc3.add_breakpoint(Breakpoint { addr, when: None });
+		    self.breakpoints.push(addr);
+		    object(vec![("verified", true.into()), ("line", (line as u64).into())])
+		}
+		None => object(vec![
+		    ("verified", false.into()),
+		    ("line", (line as u64).into()),
+		    ("message", "No instruction at or after this line".into())
+		])
+	    });
+	}
+	Ok(object(vec![("breakpoints", Value::Array(results))]))
+    }
+
+    /// The source line of the statement at `addr`
+    fn line(&self, addr: u16) -> Option<usize> {
+	self.lines.iter().find(|(a, _)| *a == addr).map(|(_, line)| *line)
+    }
+
+    /// PC, then where each call in progress was made from
+    fn stack_trace(&self) -> Value {
+	let mut places = vec![self.lc3.pc as u16];
+	places.extend(self.lc3.call_stack().iter().rev().map(|frame| frame.from));
+	let frames: Vec<Value> = places.iter().enumerate().map(|(id, addr)| {
+	    let name = self.symbols.nearest(*addr).unwrap_or_else(|| format!("x{:04X}", addr));
+	    let mut frame = vec![("id", (id as u64).into()), ("name", name.into()), ("column", 1u64.into())];
+	    match (self.line(*addr), &self.source) {
+		(Some(line), Some(source)) => {
+		    let name = source.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned());
+		    frame.push(("line", (line as u64).into()));
+		    frame.push(("source", object(vec![("name", name.into()), ("path", source.to_string_lossy().as_ref().into())])));
+		}
+		_ => frame.push(("line", 0u64.into()))
+	    }
+	    frame.push(("instructionPointerReference", format!("x{:04X}", addr).into()));
+	    object(frame)
+	}).collect();
+	let total = frames.len() as u64;
+	object(vec![("stackFrames", Value::Array(frames)), ("totalFrames", total.into())])
+    }
+
+    fn variables(&self, reference: u64) -> Result<Value, String> {
+	let lc3 = &self.lc3;
+	let variables = match reference {
+	    REGISTERS => {
+		let mut variables: Vec<Value> = lc3.regs().iter().enumerate()
+		    .map(|(i, r)| variable(&format!("R{}", i), word(*r)))
+		    .collect();
+		let nzp: String = [(0b100, 'n'), (0b010, 'z'), (0b001, 'p')].iter()
+		    .map(|(bit, c)| if lc3.psr & bit != 0 { *c } else { '-' })
+		    .collect();
+		variables.push(variable("PC", format!("x{:04X}", lc3.pc as u16)));
+		variables.push(variable("PSR", format!("x{:04X} {} {}", lc3.psr as u16, nzp, if lc3.psr < 0 { "user" } else { "supervisor" })));
+		variables
+	    }
+	    LABELS => {
+		let mut labels: Vec<(&str, u16)> = self.symbols.iter().collect();
+		labels.sort_by_key(|(_, addr)| *addr);
+		labels.iter()
+		    .map(|(name, addr)| variable(name, format!("{}  [x{:04X}]", word(lc3.memory.peek(*addr)), addr)))
+		    .collect()
+	    }
+	    _ => return Err(format!("No variables for reference {}", reference))
+	};
+	Ok(object(vec![("variables", Value::Array(variables))]))
+    }
+
+    /// A monitor command from the debug console, or a register, label or address to show the
+    /// value of for hovers and watches
+    fn evaluate(&mut self, args: &Value) -> Result<Value, String> {
+	let expression = arg(args, "expression")?.as_str().ok_or("expression should be a string")?.trim();
+	let result = if args.get("context").and_then(Value::as_str) == Some("repl") {
+	    let ticks = self.lc3.ticks;
+	    let mut debugger = Debugger::new(&mut self.lc3);
+	    debugger.symbols = self.symbols.clone();
+	    let result = debugger.command(expression).unwrap_or_else(|| Err("Stop debugging from the editor".to_string()));
+	    if self.lc3.ticks != ticks {
+		self.stopped("step", None); // so the editor fetches the new state
+	    }
+	    result?
+	} else {
+	    let upper = expression.to_ascii_uppercase();
+	    match upper.as_str() {
+		"PC" => word(self.lc3.pc),
+		"PSR" => word(self.lc3.psr),
+		_ => match upper.strip_prefix('R').and_then(|n| n.parse::<usize>().ok()).filter(|n| *n < 8) {
+		    Some(n) => word(self.lc3.r[n]),
+		    None => {
+			let addr = self.symbols.addr(expression)
+			    .or_else(|| debugger::number(expression).ok().map(|n| n as u16))
+			    .ok_or_else(|| format!("Not a register, label or address: {}", expression))?;
+			word(self.lc3.memory.peek(addr))
+		    }
+		}
+	    }
+	};
+	Ok(object(vec![("result", result.into()), ("variablesReference", 0u64.into())]))
+    }
+
+    /// Executes one instruction, even one with a breakpoint on it, then carries on as `then`
+    /// if it's given
+    fn step(&mut self, then: Option<Running>) -> Result<(), String> {
+	if self.lc3.halted {
+	    return Err("Machine is halted".to_string());
+	}
+	let pc = self.lc3.pc as u16;
+	let here: Vec<Breakpoint> = self.lc3.breakpoints().iter().filter(|b| b.addr == pc).copied().collect();
+	self.lc3.remove_breakpoint(pc);
+	let stop = self.lc3.run_steps(1);
+	for breakpoint in here {
+	    self.lc3.add_breakpoint(breakpoint);
+	}
+	self.running = Some(Running::Continue);
+	if self.stopping(Some(stop)) {
+	    return Ok(());
+	}
+	match then {
+	    Some(running) => self.running = Some(running),
+	    None => self.stopped("step", None)
+	}
+	Ok(())
+    }
+
+    /// Runs the machine for a while, returning whether it's still running
+    pub fn slice(&mut self) -> bool {
+	let start = self.lc3.ticks;
+	while let Some(running) = self.running {
+	    let left = SLICE.saturating_sub(self.lc3.ticks - start);
+	    if left == 0 {
+		self.flush_output();
+		break;
+	    }
+	    let stop = match running {
+		Running::Continue => Some(self.lc3.run_steps(left)),
+		Running::Depth(depth) => self.lc3.run_to_depth(depth, left)
+	    };
+	    if !self.stopping(stop) && stop.is_none() {
+		self.stopped("step", None); // back at the depth asked for
+	    }
+	}
+	self.send_events();
+	self.running.is_some()
+    }
+
+    /// Handles why a run returned, returning whether it stopped the machine
+    fn stopping(&mut self, stop: Option<StopReason>) -> bool {
+	self.output.extend(self.lc3.take_output().into_iter().map(|c| c as char));
+	match stop {
+	    None | Some(StopReason::Limit) => false,
+	    Some(StopReason::Reset) => {
+		self.output += "\n -- Processor reset -- \n";
+		false
+	    }
+	    Some(StopReason::Halted) => {
+		self.running = None;
+		self.flush_output();
+		self.event("exited", object(vec![("exitCode", 0u64.into())]));
+		self.event("terminated", Value::Null);
+		true
+	    }
+	    Some(StopReason::Idle) => {
+		self.stopped("pause", Some("Waiting for input".to_string()));
+		self.console("Waiting for keyboard input, queue it from the debug console with: type <text>\n".to_string());
+		true
+	    }
+	    Some(StopReason::Breakpoint(_)) => {
+		self.stopped("breakpoint", None);
+		true
+	    }
+	    Some(StopReason::Watch(watch)) => {
+		let text = match watch {
+		    Watch::Reg(reg) => format!("{:?} changed", reg),
+		    Watch::Read(addr) => format!("Read of x{:04X}", addr),
+		    Watch::Write(addr) => format!("Write to x{:04X}", addr)
+		};
+		self.stopped("data breakpoint", Some(text));
+		true
+	    }
+	    Some(StopReason::Exception(vector)) => {
+		self.stopped("exception", Some(format!("Exception x{:02X} with no handler", vector)));
+		true
+	    }
+	}
+    }
+}
+
+/// Serves requests from `input` until the editor disconnects or the input ends. Requests are
+/// read on a thread of their own so a pause can interrupt a running program.
+pub fn serve(input: impl BufRead + Send + 'static, output: impl Write) {
+    let (requests, received) = mpsc::channel();
+    thread::spawn(move || {
+	let mut input = input;
+	while let Ok(Some(message)) = read_message(&mut input) {
+	    if requests.send(message).is_err() {
+		break;
+	    }
+	}
+    });
+    let mut session = Session::new(output);
+    loop {
+	let request = if session.running.is_some() {
+	    match received.try_recv() {
+		Ok(request) => request,
+		Err(TryRecvError::Empty) => {
+		    session.slice();
+		    continue;
+		}
+		Err(TryRecvError::Disconnected) => return
+	    }
+	} else {
+	    match received.recv() {
+		Ok(request) => request,
+		Err(_) => return
+	    }
+	};
+	if !session.handle(&request) {
+	    return;
+	}
+    }
+}
+
+/// `lc3-emu dap`, returns the process exit code
+pub fn main(args: &[String]) -> i32 {
+    if !args.is_empty() {
+	eprintln!("{}", USAGE);
+	return 1;
+    }
+    serve(io::BufReader::new(io::stdin()), io::stdout());
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_message, write_message, Session};
+    use crate::json::{self, Value};
+
+    use std::io::Cursor;
+
+    const PROGRAM: &str = "; calls SUB, then prints
+.ORIG x3000
+	AND R1, R1, #0
+	JSR SUB
+	LEA R0, MSG
+	PUTS
+
+	HALT
+SUB	ADD R1, R1, #5
+	RET
+MSG	.STRINGZ \"hi\"
+.END
+";
+
+    /// Sends a request, runs the machine until it stops, and returns what came back
+    fn request(session: &mut Session<Vec<u8>>, command: &str, args: &str) -> Vec<Value> {
+	let text = format!("{{\"seq\":1,\"type\":\"request\",\"command\":\"{}\",\"arguments\":{}}}", command, args);
+	session.handle(&json::parse(&text).unwrap());
+	while session.slice() {}
+	let mut input = Cursor::new(std::mem::take(&mut session.out));
+	let mut messages = Vec::new();
+	while let Some(message) = read_message(&mut input).unwrap() {
+	    messages.push(message);
+	}
+	messages
+    }
+
+    /// The body of the response, which comes first
+    fn body(messages: &[Value]) -> String {
+	assert_eq!(messages[0].get("success"), Some(&Value::Bool(true)), "{}", messages[0]);
+	messages[0].get("body").map_or(String::new(), |b| b.to_string())
+    }
+
+    fn events(messages: &[Value]) -> Vec<String> {
+	messages[1..].iter().map(|m| m.to_string()).collect()
+    }
+
+    #[test]
+    fn message_test() {
+	let mut out = Vec::new();
+	write_message(&mut out, &json::parse("{\"seq\":1}").unwrap()).unwrap();
+	assert_eq!(out, b"Content-Length: 9\r\n\r\n{\"seq\":1}");
+	let mut input = Cursor::new(out);
+	assert_eq!(read_message(&mut input).unwrap().unwrap().get("seq"), Some(&Value::Number(1.0)));
+	assert_eq!(read_message(&mut input), Ok(None));
+	assert!(read_message(&mut Cursor::new(b"Content-Type: x\r\n\r\n{}")).is_err());
+    }
+
+    #[test]
+    fn session_test() {
+	let path = std::env::temp_dir().join(format!("lc3-dap-{}.asm", std::process::id()));
+	std::fs::write(&path, PROGRAM).unwrap();
+	let source = json::Value::from(path.to_str().unwrap()).to_string();
+	let mut session = Session::new(Vec::new());
+
+	assert!(body(&request(&mut session, "initialize", "{}")).contains("\"supportsConfigurationDoneRequest\":true"));
+	let launched = request(&mut session, "launch", &format!("{{\"program\":{}}}", source));
+	assert!(events(&launched)[0].contains("\"event\":\"initialized\""));
+	let breakpoints = request(&mut session, "setBreakpoints",
+				  &format!("{{\"source\":{{\"path\":{}}},\"breakpoints\":[{{\"line\":7}},{{\"line\":9}},{{\"line\":20}}]}}", source));
+	assert_eq!(body(&breakpoints), "{\"breakpoints\":[{\"verified\":true,\"line\":8},{\"verified\":true,\"line\":9},\
+				      {\"verified\":false,\"line\":20,\"message\":\"No instruction at or after this line\"}]}");
+
+	// runs to the breakpoint in SUB
+	let done = request(&mut session, "configurationDone", "{}");
+	assert!(events(&done)[0].contains("\"reason\":\"breakpoint\""));
+	let trace = body(&request(&mut session, "stackTrace", "{\"threadId\":1}"));
+	assert!(trace.contains("\"name\":\"SUB\",\"column\":1,\"line\":9"));
+	assert!(trace.contains("\"name\":\"x3001\",\"column\":1,\"line\":4"));
+	let registers = body(&request(&mut session, "variables", "{\"variablesReference\":1}"));
+	assert!(registers.contains("{\"name\":\"R7\",\"value\":\"x3002 #12290\",\"variablesReference\":0}"));
+	let labels = body(&request(&mut session, "variables", "{\"variablesReference\":2}"));
+	assert!(labels.contains("{\"name\":\"MSG\",\"value\":\"x0068 #104  [x3007]\",\"variablesReference\":0}"));
+	assert!(body(&request(&mut session, "evaluate", "{\"expression\":\"MSG\",\"context\":\"hover\"}")).contains("x0068 #104"));
+
+	// out of SUB, over PUTS, then onto the breakpoint at HALT
+	let out = request(&mut session, "stepOut", "{\"threadId\":1}");
+	assert!(events(&out)[0].contains("\"reason\":\"step\""));
+	assert!(body(&request(&mut session, "evaluate", "{\"expression\":\"r1\"}")).contains("x0005 #5"));
+	assert!(events(&request(&mut session, "next", "{\"threadId\":1}"))[0].contains("\"reason\":\"step\"")); // LEA
+	let over = events(&request(&mut session, "next", "{\"threadId\":1}"));
+	assert!(over[0].contains("\"output\":\"hi\""), "{:?}", over);
+	assert!(over[1].contains("\"reason\":\"step\""));
+	let console = request(&mut session, "evaluate", "{\"expression\":\"mem x3004\",\"context\":\"repl\"}");
+	assert!(body(&console).contains("x3004  F025  HALT"));
+	let end = events(&request(&mut session, "continue", "{\"threadId\":1}"));
+	assert!(end[0].contains("Halting the processor"));
+	assert!(end[1].contains("\"event\":\"exited\""));
+	assert!(end[2].contains("\"event\":\"terminated\""));
+	let halted = request(&mut session, "continue", "{\"threadId\":1}");
+	assert_eq!(halted[0].get("message"), Some(&Value::from("Machine is halted")));
+	std::fs::remove_file(&path).ok();
+    }
+}
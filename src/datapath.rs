@@ -0,0 +1,249 @@
+//! Datapath signal trace, one CSV row per state of the LC-3 control state machine. State
+//! numbers follow the state diagram in Patt & Patel, Appendix C. Columns:
+//!
+//! - `instruction`: count of instructions executed, starting at 1
+//! - `state`: control state number
+//! - `bus`: value gated onto the bus in hex, empty when nothing drives it
+//! - `ld_mar`, `ld_mdr`, `ld_ir`, `ld_cc`, `ld_pc`: load enables, 1 or 0
+//! - `ld_reg`: register file write enable, the destination register number or empty
+//! - `aluk`: ALU operation (`ADD`, `AND`, `NOT`) or empty
+//! - `mem`: memory enable, `R` for a read, `W` for a write, or empty
+//! - `mar`, `mdr`: the registers' contents at the end of the state, in hex
+//!
+//! The trace is derived from the architectural state before and after each instruction, so
+//! interrupts delivered between instructions don't appear and exceptions show as a single
+//! state that loads the handler address.
+
+use crate::lc3::{LC3, LC3IO};
+
+pub const HEADER: &str = "instruction,state,bus,ld_mar,ld_mdr,ld_ir,ld_reg,ld_cc,ld_pc,aluk,mem,mar,mdr";
+
+/// Signals asserted in one state
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Signal {
+    Mar, // MAR <- bus
+    Mdr(u16), // MDR <- bus or memory
+    Ir,
+    Reg(u8),
+    Cc,
+    Pc,
+    Alu(&'static str),
+    Read,
+    Write
+}
+use Signal::*;
+
+/// One row of the trace
+#[derive(Debug, Clone, PartialEq)]
+pub struct State {
+    pub instruction: u64,
+    pub state: u8,
+    pub bus: Option<u16>,
+    pub ld_mar: bool,
+    pub ld_mdr: bool,
+    pub ld_ir: bool,
+    pub ld_reg: Option<u8>,
+    pub ld_cc: bool,
+    pub ld_pc: bool,
+    pub aluk: Option<&'static str>,
+    pub mem: Option<char>,
+    pub mar: u16,
+    pub mdr: u16
+}
+
+impl State {
+    pub fn csv(&self) -> String {
+	let flag = |b: bool| if b { "1" } else { "0" };
+	format!("{},{},{},{},{},{},{},{},{},{},{},x{:04X},x{:04X}",
+		self.instruction, self.state,
+		self.bus.map(|b| format!("x{:04X}", b)).unwrap_or_default(),
+		flag(self.ld_mar), flag(self.ld_mdr), flag(self.ld_ir),
+		self.ld_reg.map(|r| r.to_string()).unwrap_or_default(),
+		flag(self.ld_cc), flag(self.ld_pc),
+		self.aluk.unwrap_or(""),
+		self.mem.map(String::from).unwrap_or_default(),
+		self.mar, self.mdr)
+    }
+}
+
+/// MAR and MDR carried between instructions while tracing
+#[derive(Debug, Default)]
+pub struct Datapath {
+    mar: u16,
+    mdr: u16,
+    count: u64
+}
+
+impl Datapath {
+    pub fn new() -> Self {
+	Self::default()
+    }
+
+    fn push(&mut self, out: &mut Vec<State>, state: u8, bus: Option<u16>, signals: &[Signal]) {
+	let mut row = State {
+	    instruction: self.count, state, bus,
+	    ld_mar: false, ld_mdr: false, ld_ir: false, ld_reg: None, ld_cc: false, ld_pc: false,
+	    aluk: None, mem: None, mar: 0, mdr: 0
+	};
+	for signal in signals {
+	    match *signal {
+		Mar => {
+		    row.ld_mar = true;
+		    self.mar = bus.unwrap_or(self.mar);
+		}
+		Mdr(value) => {
+		    row.ld_mdr = true;
+		    self.mdr = value;
+		}
+		Ir => row.ld_ir = true,
+		Reg(r) => row.ld_reg = Some(r),
+		Cc => row.ld_cc = true,
+		Pc => row.ld_pc = true,
+		Alu(k) => row.aluk = Some(k),
+		Read => row.mem = Some('R'),
+		Write => row.mem = Some('W')
+	    }
+	}
+	row.mar = self.mar;
+	row.mdr = self.mdr;
+	out.push(row);
+    }
+
+    /// Clocks `lc3` once and returns what it did along with the states the instruction took
+    pub fn step(&mut self, lc3: &mut LC3) -> (LC3IO, Vec<State>) {
+	let mut out = Vec::new();
+	if lc3.halted || lc3.sleeping {
+	    return (lc3.clock(), out);
+	}
+	let pc = lc3.pc as u16;
+	let before = lc3.regs();
+	let psr = lc3.psr;
+	let ir = lc3.memory.peek(pc);
+	let io = lc3.clock();
+	let after = lc3.regs();
+	self.count += 1;
+
+	let next = pc.wrapping_add(1);
+	let dr = ((ir >> 9) & 0b111) as u8;
+	let sext = |length: u32| ((ir << (16 - length)) >> (16 - length)) as u16;
+	let base = before[((ir >> 6) & 0b111) as usize] as u16;
+	let result = after[dr as usize] as u16;
+	let op = (ir as u16 >> 12) as u8;
+
+	// fetch and decode
+	self.push(&mut out, 18, Some(pc), &[Mar, Pc]);
+	self.push(&mut out, 33, None, &[Read, Mdr(ir as u16)]);
+	self.push(&mut out, 35, Some(ir as u16), &[Ir]);
+	self.push(&mut out, 32, None, &[]);
+
+	match op {
+	    0b0001 => self.push(&mut out, op, Some(result), &[Alu("ADD"), Reg(dr), Cc]),
+	    0b0101 => self.push(&mut out, op, Some(result), &[Alu("AND"), Reg(dr), Cc]),
+	    0b1001 => self.push(&mut out, op, Some(result), &[Alu("NOT"), Reg(dr), Cc]),
+	    0b0000 => {
+		self.push(&mut out, op, None, &[]);
+		if (ir >> 9) & psr & 0b111 != 0 { // BEN
+		    self.push(&mut out, 22, None, &[Pc]);
+		}
+	    }
+	    0b1100 => self.push(&mut out, op, None, &[Pc]),
+	    0b0100 => {
+		self.push(&mut out, op, Some(next), &[Reg(7)]);
+		let state = if ir & 0b1_00000000000 != 0 { 21 } else { 20 };
+		self.push(&mut out, state, None, &[Pc]);
+	    }
+	    0b0010 | 0b0110 | 0b1010 => {
+		let addr = if op == 0b0110 { base.wrapping_add(sext(6)) } else { next.wrapping_add(sext(9)) };
+		self.push(&mut out, op, Some(addr), &[Mar]);
+		if op == 0b1010 {
+		    let pointer = lc3.memory.peek(addr) as u16;
+		    self.push(&mut out, 24, None, &[Read, Mdr(pointer)]);
+		    self.push(&mut out, 26, Some(pointer), &[Mar]);
+		}
+		self.push(&mut out, 25, None, &[Read, Mdr(result)]);
+		self.push(&mut out, 27, Some(result), &[Reg(dr), Cc]);
+	    }
+	    0b1110 => self.push(&mut out, op, Some(result), &[Reg(dr), Cc]),
+	    0b0011 | 0b0111 | 0b1011 => {
+		let addr = if op == 0b0111 { base.wrapping_add(sext(6)) } else { next.wrapping_add(sext(9)) };
+		self.push(&mut out, op, Some(addr), &[Mar]);
+		if op == 0b1011 {
+		    let pointer = lc3.memory.peek(addr) as u16;
+		    self.push(&mut out, 29, None, &[Read, Mdr(pointer)]);
+		    self.push(&mut out, 31, Some(pointer), &[Mar]);
+		}
+		let sr = before[dr as usize] as u16;
+		self.push(&mut out, 23, Some(sr), &[Mdr(sr)]);
+		self.push(&mut out, 16, None, &[Write]);
+	    }
+	    0b1111 => {
+		let vector = ir as u16 & 0xFF;
+		self.push(&mut out, op, Some(vector), &[Mar]);
+		if let LC3IO::Reset = io {
+		    return (io, out);
+		}
+		if lc3.legacy_traps {
+		    self.push(&mut out, 28, Some(next), &[Read, Mdr(lc3.pc as u16), Reg(7)]);
+		} else {
+		    // the PSR and PC pushes aren't itemised, like exceptions
+		    self.push(&mut out, 28, None, &[Read, Mdr(lc3.pc as u16)]);
+		}
+		self.push(&mut out, 30, Some(lc3.pc as u16), &[Pc]);
+	    }
+	    0b1000 if psr >= 0 => {
+		// privileged RTI pops PC and PSR off the supervisor stack
+		let sp = before[6] as u16;
+		self.push(&mut out, op, Some(sp), &[Mar]);
+		self.push(&mut out, 36, None, &[Read, Mdr(lc3.pc as u16)]);
+		self.push(&mut out, 38, Some(lc3.pc as u16), &[Pc]);
+		self.push(&mut out, 39, Some(sp.wrapping_add(1)), &[Mar, Reg(6)]);
+		self.push(&mut out, 40, None, &[Read, Mdr(lc3.psr as u16)]);
+		self.push(&mut out, 42, Some(lc3.psr as u16), &[Cc]);
+		self.push(&mut out, 34, Some(after[6] as u16), &[Reg(6)]);
+	    }
+	    // RTI in user mode and the reserved opcode trap to their exception handlers
+	    0b1000 => self.push(&mut out, 44, Some(lc3.pc as u16), &[Pc]),
+	    _ => self.push(&mut out, 13, Some(lc3.pc as u16), &[Pc])
+	}
+	(io, out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Datapath;
+    use crate::lc3::LC3;
+
+    fn states(lc3: &mut LC3) -> Vec<u8> {
+	Datapath::new().step(lc3).1.iter().map(|s| s.state).collect()
+    }
+
+    #[test]
+    fn datapath_test() {
+	let mut lc3 = LC3::new();
+	lc3.halted = false;
+	lc3.pc = 0x3000;
+	lc3.r[2] = 4;
+	lc3.memory.put(0x3000, 0b0001_001_010_1_00001); // ADD R1, R2, #1
+	lc3.memory.put(0x3001, 0b1010_011_000000010); // LDI R3, x3004
+	lc3.memory.put(0x3002, 0b0111_001_010_000001); // STR R1, R2, #1
+	lc3.memory.put(0x3004, 0x3005);
+	lc3.memory.put(0x3005, 0x1234);
+
+	let mut datapath = Datapath::new();
+	let (_, add) = datapath.step(&mut lc3);
+	assert_eq!(add.iter().map(|s| s.state).collect::<Vec<_>>(), vec![18, 33, 35, 32, 1]);
+	assert_eq!(add[4].csv(), "1,1,x0005,0,0,0,1,1,0,ADD,,x3000,x12A1");
+
+	let (_, ldi) = datapath.step(&mut lc3);
+	assert_eq!(ldi.iter().map(|s| s.state).collect::<Vec<_>>(), vec![18, 33, 35, 32, 10, 24, 26, 25, 27]);
+	assert_eq!(ldi[6].mar, 0x3005);
+	assert_eq!(ldi[8].csv(), "2,27,x1234,0,0,0,3,1,0,,,x3005,x1234");
+
+	assert_eq!(states(&mut lc3), vec![18, 33, 35, 32, 7, 23, 16]);
+	assert_eq!(lc3.memory.get(5), 5);
+
+	lc3.memory.put(0x3003, 0b0000_111_000000000); // BRnzp to the next instruction
+	assert_eq!(states(&mut lc3), vec![18, 33, 35, 32, 0, 22]);
+    }
+}
@@ -0,0 +1,528 @@
+//! Interactive monitor: `lc3-emu --debug prog.obj`
+
+use crate::disasm;
+use crate::dump;
+use crate::lc3::batch::StopReason;
+use crate::lc3::debug::{Breakpoint, Watch};
+use crate::lc3::snapshot;
+use crate::lc3::stack::Kind;
+use crate::lc3::{LC3, Reg};
+use crate::symbols::Symbols;
+use crate::trace;
+
+use std::io::{BufRead, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// Instructions `continue` runs before giving the prompt back
+const CONTINUE_LIMIT: u64 = 10_000_000;
+
+/// Instructions `back` can undo
+const HISTORY: usize = 100_000;
+
+const HELP: &str = "\
+step [n]            execute n instructions (default 1)
+back [n]            undo the last n instructions (default 1)
+next                step over a subroutine call or TRAP
+finish              run until the current subroutine or service routine returns
+continue            run until a breakpoint, halt or the program waits for input
+backtrace           show the calls in progress
+break <addr> [if <reg> == <value>]
+		    toggle a breakpoint, optionally conditional
+watch <reg>         toggle stopping when a register changes
+watch read|write <addr>
+		    toggle stopping when memory is read or written
+regs                show registers
+mem <addr> [count]  disassemble memory
+dump <start> <end>  list memory with labels and characters
+export <start> <end> <file>
+		    write memory to an .obj file, or raw words for other names
+set <reg> <value>   set R0-R7, PC or PSR
+poke <addr> <value> write a word to memory
+type <text>         queue keys for the program to read
+save [--raw] <file>
+		    write a snapshot of the whole machine, --raw without compressing memory
+restore <file>      load a snapshot written by save
+sym <file>          load an lc3as symbol table
+quit                leave the debugger
+Numbers are decimal, #decimal or xHEX. Addresses can also be labels from the symbol table.";
+
+pub struct Debugger<'a> {
+    lc3: &'a mut LC3,
+    pub symbols: Symbols
+}
+
+/// Parses `r3` or `R3`
+fn register(text: &str) -> Result<Reg, String> {
+    const REGS: [Reg; 8] = [Reg::R0, Reg::R1, Reg::R2, Reg::R3, Reg::R4, Reg::R5, Reg::R6, Reg::R7];
+    match text.as_bytes() {
+	[b'r' | b'R', n @ b'0'..=b'7'] => Ok(REGS[(n - b'0') as usize]),
+	_ => Err(format!("Unknown register: {}", text))
+    }
+}
+
+/// Parses `x3000`, `#12`, `12` or `-5`
+pub fn number(text: &str) -> Result<i16, String> {
+    let parsed = if let Some(hex) = text.strip_prefix('x').or_else(|| text.strip_prefix('X')) {
+	u16::from_str_radix(hex, 16).map(|v| v as i16).ok()
+    } else {
+	let decimal = text.strip_prefix('#').unwrap_or(text);
+	decimal.parse::<i16>().ok().or_else(|| decimal.parse::<u16>().ok().map(|v| v as i16))
+    };
+    parsed.ok_or_else(|| format!("Bad number: {}", text))
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(lc3: &'a mut LC3) -> Self {
+	if lc3.history_len() == 0 {
+	    lc3.record_history(HISTORY);
+	}
+	Debugger { lc3, symbols: Symbols::new() }
+    }
+
+    /// Runs one command line, returning what to print, or `None` to quit
+    pub fn command(&mut self, line: &str) -> Option<Result<String, String>> {
+	let words: Vec<&str> = line.split_whitespace().collect();
+	let result = match words.as_slice() {
+	    [] => Ok(String::new()),
+	    ["quit"] | ["q"] => return None,
+	    ["help"] | ["h"] => Ok(HELP.to_string()),
+	    ["step"] | ["s"] => self.run(1),
+	    ["step", n] | ["s", n] => number(n).and_then(|n| self.run(n as u16 as u64)),
+	    ["back"] => self.back(1),
+	    ["back", n] => number(n).and_then(|n| self.back(n as u16 as usize)),
+	    ["continue"] | ["c"] => self.run(CONTINUE_LIMIT),
+	    ["next"] | ["n"] => self.run_to(self.lc3.call_stack().len(), true),
+	    ["finish"] => match self.lc3.call_stack().len() {
+		0 => Err("Not in a subroutine".to_string()),
+		depth => self.run_to(depth - 1, false)
+	    },
+	    ["backtrace"] | ["bt"] => Ok(self.backtrace()),
+	    ["break", addr] | ["b", addr] => self.address(addr).map(|addr| self.toggle_break(addr, None)),
+	    ["break", addr, "if", reg, "==", value] | ["b", addr, "if", reg, "==", value] => self.address(addr)
+		.and_then(|addr| Ok((addr, register(reg)?, number(value)?)))
+		.map(|(addr, reg, value)| self.toggle_break(addr, Some((reg, value)))),
+	    ["watch", "read", addr] => self.address(addr).map(|addr| self.toggle_watch(Watch::Read(addr))),
+	    ["watch", "write", addr] => self.address(addr).map(|addr| self.toggle_watch(Watch::Write(addr))),
+	    ["watch", reg] => register(reg).map(|reg| self.toggle_watch(Watch::Reg(reg))),
+	    ["regs"] | ["r"] => Ok(self.regs()),
+	    ["mem", addr] | ["m", addr] => self.address(addr).map(|addr| self.mem(addr, 1)),
+	    ["mem", addr, count] | ["m", addr, count] => self.address(addr)
+		.and_then(|addr| number(count).map(|count| self.mem(addr, count as u16))),
+	    ["dump", start, end] => self.range(start, end)
+		.map(|range| dump::listing(&self.lc3.memory, range, &self.symbols).join("\n")),
+	    ["export", start, end, path] => self.range(start, end)
+		.and_then(|range| dump::save(&self.lc3.memory, range, Path::new(path)))
+		.map(|n| format!("Wrote {} words to {}", n, path)),
+	    ["set", reg, value] => number(value).and_then(|value| self.set(reg, value)),
+	    ["poke", addr, value] => self.address(addr).and_then(|addr| number(value).map(|value| {
+		self.lc3.memory.put(addr, value);
+		disasm::line_with(addr, value, &self.symbols)
+	    })),
+	    ["type", ..] => {
+		let text = line.trim_start()[4..].trim_start();
+		let now = self.lc3.now();
+		for byte in text.bytes() {
+		    self.lc3.schedule_key(now, byte as i16);
+		}
+		Ok(format!("{} keys queued", text.len()))
+	    }
+	    ["save", path] => self.save(path, true),
+	    ["save", "--raw", path] => self.save(path, false),
+	    ["restore", path] => std::fs::read(path)
+		.map_err(|e| e.to_string())
+		.and_then(|bytes| snapshot::restore(self.lc3, &bytes).map_err(|e| e.to_string()))
+		.map(|_| format!("Restored {}\n{}", path, self.next()))
+		.map_err(|e| format!("{}: {}", path, e)),
+	    ["sym", path] => Symbols::read_file(Path::new(path)).map(|symbols| {
+		self.symbols = symbols;
+		format!("{} symbols\n{}", self.symbols.len(), self.next())
+	    }),
+	    _ => Err(format!("Unknown command: {} (try help)", line.trim()))
+	};
+	Some(result)
+    }
+
+    /// An address as a number or a label
+    fn address(&self, text: &str) -> Result<u16, String> {
+	number(text).map(|addr| addr as u16)
+	    .or_else(|_| self.symbols.addr(text).ok_or_else(|| format!("Not a number or label: {}", text)))
+    }
+
+    /// Inclusive range between two addresses
+    fn range(&self, start: &str, end: &str) -> Result<RangeInclusive<u16>, String> {
+	trace::span(self.address(start)?, self.address(end)?)
+    }
+
+    /// `x3004`, or `x3004 <LOOP+2>` when there's a label at or before it
+    fn place(&self, addr: u16) -> String {
+	match self.symbols.nearest(addr) {
+	    Some(label) => format!("x{:04X} <{}>", addr, label),
+	    None => format!("x{:04X}", addr)
+	}
+    }
+
+    fn toggle_break(&mut self, addr: u16, when: Option<(Reg, i16)>) -> String {
+	let breakpoint = Breakpoint { addr, when };
+	if self.lc3.clear_breakpoint(breakpoint) {
+	    format!("Breakpoint at x{:04X} removed", addr)
+	} else {
+	    self.lc3.add_breakpoint(breakpoint);
+	    format!("Breakpoint at x{:04X}", addr)
+	}
+    }
+
+    fn toggle_watch(&mut self, watch: Watch) -> String {
+	if self.lc3.unwatch(watch) {
+	    format!("{} removed", describe(watch))
+	} else {
+	    self.lc3.watch(watch);
+	    describe(watch)
+	}
+    }
+
+    /// Executes up to `count` instructions, stopping early at halts, breakpoints, watchpoints
+    /// and waits for input
+    fn run(&mut self, count: u64) -> Result<String, String> {
+	if self.lc3.halted {
+	    return Err("Machine is halted".to_string());
+	}
+	let mut output = String::new();
+	let mut stop = None;
+	let mut left = count;
+	while left > 0 && stop.is_none() {
+	    let start = self.lc3.ticks;
+	    let reason = self.lc3.run_steps(left);
+	    left = left.saturating_sub(self.lc3.ticks - start);
+	    stop = self.report(reason, &mut output);
+	}
+	Ok(self.summary(output, stop))
+    }
+
+    /// Runs until the call stack is no deeper than `depth`, executing one instruction first
+    /// if `step`, and stopping early like `continue`
+    fn run_to(&mut self, depth: usize, step: bool) -> Result<String, String> {
+	if self.lc3.halted {
+	    return Err("Machine is halted".to_string());
+	}
+	let mut output = String::new();
+	let mut stop = None;
+	if step {
+	    let reason = self.lc3.run_steps(1);
+	    stop = self.report(reason, &mut output);
+	}
+	let start = self.lc3.ticks;
+	while stop.is_none() && self.lc3.ticks - start < CONTINUE_LIMIT {
+	    match self.lc3.run_to_depth(depth, CONTINUE_LIMIT - (self.lc3.ticks - start)) {
+		Some(reason) => stop = self.report(reason, &mut output),
+		None => {
+		    self.report(StopReason::Limit, &mut output);
+		    break;
+		}
+	    }
+	}
+	Ok(self.summary(output, stop))
+    }
+
+    /// Adds the display output from the run to `output`, returning why execution stopped if
+    /// `reason` says it did
+    fn report(&mut self, reason: StopReason, output: &mut String) -> Option<String> {
+	output.extend(self.lc3.take_output().into_iter().map(|c| c as char));
+	match reason {
+	    StopReason::Reset => *output += "\n -- Processor reset -- \n",
+	    StopReason::Halted => return Some("Halted".to_string()),
+	    StopReason::Idle => return Some("Waiting for input (use type)".to_string()),
+	    StopReason::Limit => (),
+	    StopReason::Breakpoint(addr) => return Some(format!("Breakpoint at {}", self.place(addr))),
+	    StopReason::Watch(Watch::Reg(reg)) => {
+		let value = self.lc3.regs()[reg as usize];
+		return Some(format!("{:?} changed to x{:04X}", reg, value as u16));
+	    }
+	    StopReason::Watch(Watch::Read(addr)) => return Some(format!("Read of x{:04X}", addr)),
+	    StopReason::Watch(Watch::Write(addr)) => return Some(format!("Write to x{:04X}", addr)),
+	    StopReason::Exception(vector) => return Some(format!("Exception x{:02X} with no handler", vector))
+	}
+	None
+    }
+
+    /// Output from a run, why it stopped, then the next instruction
+    fn summary(&self, mut output: String, stop: Option<String>) -> String {
+	if !output.is_empty() && !output.ends_with('\n') {
+	    output.push('\n');
+	}
+	if let Some(stop) = stop {
+	    output += &stop;
+	    output.push('\n');
+	}
+	output += &self.next();
+	output
+    }
+
+    /// The calls in progress, innermost first
+    fn backtrace(&self) -> String {
+	let mut lines = vec![format!("#0  {}", self.place(self.lc3.pc as u16))];
+	for (i, frame) in self.lc3.call_stack().iter().rev().enumerate() {
+	    let call = match frame.kind {
+		Kind::Subroutine => "JSR".to_string(),
+		Kind::Trap(vector) => match disasm::trap_name(vector) {
+		    Some(name) => format!("TRAP x{:02X} {}", vector, name),
+		    None => format!("TRAP x{:02X}", vector)
+		},
+		Kind::Interrupt(vector) => format!("interrupt x{:02X}", vector),
+		Kind::Exception(vector) => format!("exception x{:02X}", vector)
+	    };
+	    lines[i] += &format!(" in {} from {}", call, self.place(frame.from));
+	    lines.push(format!("#{}  {}", i + 1, self.place(frame.from)));
+	}
+	lines.join("\n")
+    }
+
+    fn back(&mut self, count: usize) -> Result<String, String> {
+	match self.lc3.step_back(count) {
+	    0 => Err("Nothing to step back".to_string()),
+	    n => Ok(format!("Stepped back {}\n{}", n, self.next()))
+	}
+    }
+
+    /// The instruction about to execute
+    fn next(&self) -> String {
+	let pc = self.lc3.pc as u16;
+	match self.symbols.nearest(pc) {
+	    Some(label) => format!("=> {}  <{}>", disasm::line_with(pc, self.lc3.memory.peek(pc), &self.symbols), label),
+	    None => format!("=> {}", disasm::line(pc, self.lc3.memory.peek(pc)))
+	}
+    }
+
+    fn save(&self, path: &str, compress: bool) -> Result<String, String> {
+	std::fs::write(path, snapshot::save(self.lc3, compress))
+	    .map(|_| format!("Saved to {}", path))
+	    .map_err(|e| format!("{}: {}", path, e))
+    }
+
+    fn regs(&self) -> String {
+	let lc3 = &self.lc3;
+	let nzp: String = [(0b100, 'n'), (0b010, 'z'), (0b001, 'p')].iter()
+	    .map(|(bit, c)| if lc3.psr & bit != 0 { *c } else { '-' })
+	    .collect();
+	let regs: Vec<String> = lc3.regs().iter().enumerate()
+	    .map(|(i, r)| format!("R{} x{:04X}", i, *r as u16))
+	    .collect();
+	format!("{}\n{}\nPC x{:04X}  PSR x{:04X}  {}  {}\n{}",
+		regs[..4].join("  "), regs[4..].join("  "),
+		lc3.pc as u16, lc3.psr as u16, nzp,
+		if lc3.psr < 0 { "user" } else { "supervisor" }, self.next())
+    }
+
+    fn mem(&self, addr: u16, count: u16) -> String {
+	let end = addr.saturating_add(count.max(1));
+	disasm::listing_with(&self.lc3.memory, addr..end, &self.symbols).join("\n")
+    }
+
+    fn set(&mut self, reg: &str, value: i16) -> Result<String, String> {
+	match reg.to_ascii_uppercase().as_str() {
+	    "PC" => self.lc3.pc = value,
+	    "PSR" => self.lc3.psr = value,
+	    _ => self.lc3.put_reg(register(reg)? as i16, value)
+	}
+	Ok(format!("{} = x{:04X}", reg.to_ascii_uppercase(), value as u16))
+    }
+}
+
+fn describe(watch: Watch) -> String {
+    match watch {
+	Watch::Read(addr) => format!("Watching reads of x{:04X}", addr),
+	Watch::Write(addr) => format!("Watching writes to x{:04X}", addr),
+	Watch::Reg(reg) => format!("Watching {:?}", reg)
+    }
+}
+
+/// Reads commands from `input` until quit or end of input
+pub fn run(lc3: &mut LC3, symbols: Symbols, input: impl BufRead, mut output: impl Write) {
+    let mut debugger = Debugger::new(lc3);
+    debugger.symbols = symbols;
+    writeln!(output, "{}", debugger.next()).ok();
+    write!(output, "(lc3) ").ok();
+    output.flush().ok();
+    for line in input.lines() {
+	let line = match line {
+	    Ok(line) => line,
+	    Err(_) => break
+	};
+	match debugger.command(&line) {
+	    None => return,
+	    Some(Ok(text)) if text.is_empty() => (),
+	    Some(Ok(text)) => {
+		writeln!(output, "{}", text).ok();
+	    }
+	    Some(Err(e)) => {
+		writeln!(output, "error: {}", e).ok();
+	    }
+	}
+	write!(output, "(lc3) ").ok();
+	output.flush().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{number, run};
+    use crate::lc3::snapshot;
+    use crate::fixtures::Fixture;
+    use crate::symbols::Symbols;
+
+    #[test]
+    fn number_test() {
+	assert_eq!(number("x3000"), Ok(0x3000));
+	assert_eq!(number("#-5"), Ok(-5));
+	assert_eq!(number("65535"), Ok(-1));
+	assert!(number("x").is_err());
+    }
+
+    #[test]
+    fn debugger_test() {
+	let mut lc3 = Fixture::with_os()
+	    .code(&[
+		0b0001_001_001_1_00001, // ADD R1, R1, #1
+		0b0001_001_001_1_00001, // ADD R1, R1, #1
+		0b1110_000_000000010, // LEA R0, x3005
+		0b1111_0000_00100010, // PUTS
+		0b1111_0000_00100101 // HALT
+	    ])
+	    .string(0x3005, "hi")
+	    .build();
+	let script = "step\nregs\nset r3 x1234\nbreak x3003\ncontinue\nmem x3002 2\npoke x4000 #7\nbogus\n\
+		      watch r1\nbreak x3001\ncontinue\ncontinue\nwatch r1\nbreak x3002 if r1 == #5\ncontinue\nstep\nquit\nstep\n";
+	let mut out = Vec::new();
+	run(&mut lc3, Symbols::new(), script.as_bytes(), &mut out);
+	let out = String::from_utf8(out).unwrap();
+	assert!(out.contains("=> x3001  1261  ADD R1, R1, #1"));
+	assert!(out.contains("R0 x0000  R1 x0001  R2 x0000"));
+	assert!(out.contains("R3 = x1234"));
+	assert!(out.contains("Breakpoint at x3003\n=> x3003  F022  PUTS"));
+	assert!(out.contains("x3002  E002  LEA R0, x3005\nx3003  F022  PUTS\n"));
+	assert!(out.contains("error: Unknown command: bogus"));
+	assert!(out.contains("hi\n----- Halting the processor -----\nHalted\n"));
+	assert!(out.contains("error: Machine is halted"));
+	assert_eq!(lc3.memory.get(0x4000), 7);
+	assert_eq!(lc3.r[3], 0x1234);
+	assert!(out.contains("Watching R1\n"));
+	assert!(out.contains("R1 changed to x3005\n"));
+	assert!(out.contains("Breakpoint at x3002\n"));
+	assert_eq!(out.matches("(lc3) ").count(), 17);
+    }
+
+    #[test]
+    fn break_test() {
+	let mut lc3 = Fixture::bare().code(&[0b0001_001_001_1_00001, 0b0000_111_111111110]).build(); // ADD R1, R1, #1; BRnzp #-2
+	let script = "break x3000 if r1 == #2\nbreak x3000\nbreak x3000\ncontinue\n";
+	let mut out = Vec::new();
+	run(&mut lc3, Symbols::new(), script.as_bytes(), &mut out);
+	let out = String::from_utf8(out).unwrap();
+	assert!(out.contains("Breakpoint at x3000 removed\n"));
+	assert_eq!(lc3.r[1], 2); // the conditional one is still set
+    }
+
+    #[test]
+    fn snapshot_test() {
+	let path = std::env::temp_dir().join(format!("lc3-debugger-{}.snap", std::process::id()));
+	let path = path.to_str().unwrap();
+	let mut lc3 = Fixture::bare().code(&[0b0001_001_001_1_00001, 0b0001_001_001_1_00001]).build();
+	let script = format!("step\nsave {0}\nstep\nrestore {0}\nrestore /nonexistent/snap\n", path);
+	let mut out = Vec::new();
+	run(&mut lc3, Symbols::new(), script.as_bytes(), &mut out);
+	std::fs::remove_file(path).ok();
+	let out = String::from_utf8(out).unwrap();
+	assert!(out.contains(&format!("Restored {}\n=> x3001", path)));
+	assert!(out.contains("error: /nonexistent/snap: "));
+	assert_eq!(lc3.r[1], 1);
+	assert_eq!(lc3.pc, 0x3001);
+    }
+
+    #[test]
+    fn raw_save_test() {
+	let path = std::env::temp_dir().join(format!("lc3-debugger-raw-{}.snap", std::process::id()));
+	let path = path.to_str().unwrap();
+	let mut lc3 = Fixture::bare().code(&[0b0001_001_001_1_00001]).build();
+	let mut out = Vec::new();
+	run(&mut lc3, Symbols::new(), format!("save --raw {}
+", path).as_bytes(), &mut out);
+	let bytes = std::fs::read(path).expect("Failed to read snapshot");
+	std::fs::remove_file(path).ok();
+	assert!(bytes.len() > 65536 * 2); // every word stored
+	assert_eq!(bytes[6], b'S');
+	let mut other = Fixture::bare().build();
+	snapshot::restore(&mut other, &bytes).expect("Failed to restore");
+	assert_eq!(other.memory.mem[0x3000], 0b0001_001_001_1_00001);
+    }
+
+    #[test]
+    fn back_test() {
+	let mut lc3 = Fixture::bare().code(&[0b0001_001_001_1_00001, 0b0001_001_001_1_00001]).build();
+	let mut out = Vec::new();
+	run(&mut lc3, Symbols::new(), "back\nstep 2\nback\n".as_bytes(), &mut out);
+	let out = String::from_utf8(out).unwrap();
+	assert!(out.contains("error: Nothing to step back"));
+	assert!(out.contains("Stepped back 1\n=> x3001  1261  ADD R1, R1, #1"));
+	assert_eq!(lc3.r[1], 1);
+    }
+
+    #[test]
+    fn symbols_test() {
+	let mut lc3 = Fixture::bare()
+	    .code(&[0b0001_001_001_1_00001, 0b0000_111_111111110]) // ADD R1, R1, #1; BRnzp #-2
+	    .build();
+	let mut symbols = Symbols::new();
+	symbols.insert("LOOP", 0x3000);
+	symbols.insert("DATA", 0x3010);
+	let mut out = Vec::new();
+	run(&mut lc3, symbols, "break LOOP+1\nbreak loop\nstep\nstep\ncontinue\nmem DATA\npoke DATA #5\nbreak NOWHERE\n".as_bytes(), &mut out);
+	let out = String::from_utf8(out).unwrap();
+	assert!(out.contains("error: Not a number or label: LOOP+1"));
+	assert!(out.contains("Breakpoint at x3000\n"));
+	assert!(out.contains("=> x3001  0FFE  BRnzp LOOP  <LOOP+1>"));
+	assert!(out.contains("Breakpoint at x3000 <LOOP>\n"));
+	assert!(out.contains("x3010  0000  NOP                ; DATA"));
+	assert_eq!(lc3.memory.peek(0x3010), 5);
+	assert!(out.contains("error: Not a number or label: NOWHERE"));
+    }
+
+    #[test]
+    fn next_test() {
+	let mut lc3 = Fixture::with_os()
+	    .code(&[
+		0b0100_1_00000000001, // JSR x3002
+		0b1111_0000_00100101, // HALT
+		0b0001_001_001_1_00001, // ADD R1, R1, #1
+		0b1110_000_000000010, // LEA R0, x3006
+		0b1111_0000_00100010, // PUTS
+		0b1100_000_111_000000 // RET
+	    ])
+	    .string(0x3006, "hi")
+	    .build();
+	let script = "finish\nstep\nnext\nnext\nbt\nstep\nstep\nfinish\nnext\n";
+	let mut out = Vec::new();
+	run(&mut lc3, Symbols::new(), script.as_bytes(), &mut out);
+	let out = String::from_utf8(out).unwrap();
+	assert!(out.contains("error: Not in a subroutine"));
+	assert!(out.contains("#0  x3004 in JSR from x3000\n#1  x3000\n"));
+	assert!(out.contains("(lc3) hi\n=> x3005  C1C0  RET\n(lc3) => x3001  F025  HALT\n"));
+	assert_eq!(lc3.r[1], 1);
+    }
+
+    #[test]
+    fn dump_test() {
+	let path = std::env::temp_dir().join(format!("lc3-debugger-{}.obj", std::process::id()));
+	let path = path.to_str().unwrap();
+	let mut lc3 = Fixture::bare().code(&[0b0001_001_001_1_00001]).data(0x3001, &[0x41]).build();
+	let script = format!("dump x3000 x3001\nexport x3000 x3001 {}\ndump x3001 x3000\n", path);
+	let mut out = Vec::new();
+	run(&mut lc3, Symbols::new(), script.as_bytes(), &mut out);
+	let obj = std::fs::read(path).unwrap();
+	std::fs::remove_file(path).ok();
+	let out = String::from_utf8(out).unwrap();
+	assert!(out.contains("x3000  1261  ADD R1, R1, #1\nx3001  0041  NOP                ; 'A'\n"));
+	assert!(out.contains(&format!("Wrote 2 words to {}", path)));
+	assert!(out.contains("error: x3000 is before x3001"));
+	assert_eq!(obj, vec![0x30, 0x00, 0x12, 0x61, 0x00, 0x41]);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::lc3::LC3Memory;
+use crate::symbols::Symbols;
+
+use std::ops::Range;
+
+/// Mnemonics by opcode
+pub const OPCODES: [&str; 16] = [
+    "BR", "ADD", "LD", "ST", "JSR", "AND", "LDR", "STR",
+    "RTI", "NOT", "LDI", "STI", "JMP", "(reserved)", "LEA", "TRAP"
+];
+
+/// Service routine names for the standard trap vectors
+const TRAPS: [&str; 6] = ["GETC", "OUT", "PUTS", "IN", "PUTSP", "HALT"];
+
+/// Service routine name for a trap vector, if it's one of the standard ones
+pub fn trap_name(vector: u8) -> Option<&'static str> {
+    TRAPS.get((vector as usize).wrapping_sub(0x20)).copied()
+}
+
+/// 2's complement sign-extension of the low `length` bits
+fn sext(value: i16, length: u32) -> i16 {
+    let shift = 16 - length;
+    (value << shift) >> shift
+}
+
+/// Absolute target of a PC-relative operand
+fn target(word: i16, addr: u16, length: u32) -> u16 {
+    addr.wrapping_add(1).wrapping_add(sext(word, length) as u16)
+}
+
+/// A PC-relative target by label if it has one
+fn place(addr: u16, symbols: &Symbols) -> String {
+    match symbols.name(addr) {
+	Some(name) => name.to_string(),
+	None => format!("x{:04X}", addr)
+    }
+}
+
+/// Turns the instruction at `addr` back into assembly, PC-relative operands shown as absolute
+/// addresses
+pub fn disassemble(word: i16, addr: u16) -> String {
+    disassemble_with(word, addr, &Symbols::new())
+}
+
+/// `disassemble()`, showing PC-relative operands that land on a label by name
+pub fn disassemble_with(word: i16, addr: u16, symbols: &Symbols) -> String {
+    let dr = (word >> 9) & 0b111;
+    let sr1 = (word >> 6) & 0b111;
+    let op = (word as u16 >> 12) as usize;
+    match op {
+	0b0001 | 0b0101 => if word & 0b100000 != 0 {
+	    format!("{} R{}, R{}, #{}", OPCODES[op], dr, sr1, sext(word, 5))
+	} else {
+	    format!("{} R{}, R{}, R{}", OPCODES[op], dr, sr1, word & 0b111)
+	},
+	0b0000 => {
+	    let flags = (word >> 9) & 0b111;
+	    if flags == 0 {
+		return "NOP".to_string();
+	    }
+	    let mut name = "BR".to_string();
+	    for (bit, c) in [(0b100, 'n'), (0b010, 'z'), (0b001, 'p')].iter() {
+		if flags & bit != 0 {
+		    name.push(*c);
+		}
+	    }
+	    format!("{} {}", name, place(target(word, addr, 9), symbols))
+	}
+	0b1100 if sr1 == 7 => "RET".to_string(),
+	0b1100 => format!("JMP R{}", sr1),
+	0b0100 if word & 0b1_00000000000 != 0 => format!("JSR {}", place(target(word, addr, 11), symbols)),
+	0b0100 => format!("JSRR R{}", sr1),
+	0b0010 | 0b1010 | 0b1110 | 0b0011 | 0b1011 => {
+	    format!("{} R{}, {}", OPCODES[op], dr, place(target(word, addr, 9), symbols))
+	}
+	0b0110 | 0b0111 => format!("{} R{}, R{}, #{}", OPCODES[op], dr, sr1, sext(word, 6)),
+	0b1001 => format!("NOT R{}, R{}", dr, sr1),
+	0b1000 => "RTI".to_string(),
+	0b1111 => {
+	    let vector = word & 0xFF;
+	    match trap_name(vector as u8) {
+		Some(name) => name.to_string(),
+		None => format!("TRAP x{:02X}", vector)
+	    }
+	}
+	_ => format!(".FILL x{:04X}", word as u16)
+    }
+}
+
+/// One listing line: address, raw word and disassembly
+pub fn line(addr: u16, word: i16) -> String {
+    format!("x{:04X}  {:04X}  {}", addr, word as u16, disassemble(word, addr))
+}
+
+/// `line()` using labels
+pub fn line_with(addr: u16, word: i16, symbols: &Symbols) -> String {
+    format!("x{:04X}  {:04X}  {}", addr, word as u16, disassemble_with(word, addr, symbols))
+}
+
+/// Disassembles a range of memory, one line per word. Reads have no device side effects.
+pub fn listing(memory: &LC3Memory, range: Range<u16>) -> Vec<String> {
+    listing_with(memory, range, &Symbols::new())
+}
+
+/// `listing()` using labels, with each labelled address's label on the end of its line
+pub fn listing_with(memory: &LC3Memory, range: Range<u16>, symbols: &Symbols) -> Vec<String> {
+    range.map(|addr| {
+	let line = line_with(addr, memory.peek(addr), symbols);
+	match symbols.name(addr) {
+	    Some(name) => format!("{:<32}; {}", line, name),
+	    None => line
+	}
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disassemble, disassemble_with, listing, listing_with};
+    use crate::lc3::LC3Memory;
+    use crate::symbols::Symbols;
+
+    #[test]
+    fn disassemble_test() {
+	assert_eq!(disassemble(0b0001_001_010_1_00001, 0x3000), "ADD R1, R2, #1");
+	assert_eq!(disassemble(0b0001_001_010_0_00_011, 0x3000), "ADD R1, R2, R3");
+	assert_eq!(disassemble(0b0101_001_001_1_10000, 0x3000), "AND R1, R1, #-16");
+	assert_eq!(disassemble(0b0000_110_000000101, 0x3001), "BRnz x3007");
+	assert_eq!(disassemble(0b0000_111_111111110, 0x3001), "BRnzp x3000");
+	assert_eq!(disassemble(0b1100_000_111_000000, 0x3000), "RET");
+	assert_eq!(disassemble(0b1100_000_010_000000, 0x3000), "JMP R2");
+	assert_eq!(disassemble(0b0100_1_11111111110, 0x3000), "JSR x2FFF");
+	assert_eq!(disassemble(0b0100_0_00_010_000000, 0x3000), "JSRR R2");
+	assert_eq!(disassemble(0b1110_000_000000010, 0x3000), "LEA R0, x3003");
+	assert_eq!(disassemble(0b0110_010_001_111111, 0x3000), "LDR R2, R1, #-1");
+	assert_eq!(disassemble(0b1001_010_001_1_11111, 0x3000), "NOT R2, R1");
+	assert_eq!(disassemble(0b1111_0000_00100101, 0x3000), "HALT");
+	assert_eq!(disassemble(0b1111_0000_00000001, 0x3000), "TRAP x01");
+	assert_eq!(disassemble(0b1000_0000_0000_0000, 0x3000), "RTI");
+	assert_eq!(disassemble(0xD123, 0x3000), ".FILL xD123");
+    }
+
+    #[test]
+    fn listing_test() {
+	let mut memory = LC3Memory::new();
+	memory.write_words(0x3000, &[0b0000_110_000000101, 0b1111_0000_00100101]);
+	assert_eq!(listing(&memory, 0x3000..0x3002), vec!["x3000  0C05  BRnz x3006", "x3001  F025  HALT"]);
+	let mut symbols = Symbols::new();
+	symbols.insert("DONE", 0x3001);
+	symbols.insert("DATA", 0x3006);
+	assert_eq!(listing_with(&memory, 0x3000..0x3002, &symbols),
+		   vec!["x3000  0C05  BRnz DATA", "x3001  F025  HALT               ; DONE"]);
+    }
+
+    #[test]
+    fn symbols_test() {
+	let mut symbols = Symbols::new();
+	symbols.insert("LOOP", 0x3000);
+	assert_eq!(disassemble_with(0b0000_111_111111110, 0x3001, &symbols), "BRnzp LOOP");
+	assert_eq!(disassemble_with(0b0100_1_11111111110, 0x3001, &symbols), "JSR LOOP");
+	assert_eq!(disassemble_with(0b0010_011_111111110, 0x3001, &symbols), "LD R3, LOOP");
+	assert_eq!(disassemble_with(0b0010_011_111111111, 0x3001, &symbols), "LD R3, x3001");
+    }
+}
@@ -0,0 +1,200 @@
+//! Block storage: a disk of 256-word sectors backed by a host file, with DMA transfers and a
+//! completion interrupt. `lc3-emu os.obj --disk disk.img`
+//!
+//! Registers, following the LC-3 device conventions (bit 15 ready, bit 14 interrupt enable):
+//!
+//! ```text
+//! xFE14 DSKSR    status: ready, interrupt enable, bit 0 set if the last command failed
+//! xFE16 DSKSEC   sector number
+//! xFE18 DSKADDR  memory address of the 256-word buffer
+//! xFE1A DSKCMD   write 1 to read the sector into memory, 2 to write memory to the sector
+//! ```
+//!
+//! A command takes `latency` clocks, then the words move and ready is set. With interrupts
+//! enabled that raises x82 at priority 3, until the handler reads DSKSR. Sectors are stored
+//! big-endian like .obj files; reading past the end of the file gives zeros.
+
+use crate::lc3::device::Device;
+use crate::lc3::LC3Memory;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+
+pub const DSKSR: u16 = 0xFE14;
+pub const DSKSEC: u16 = 0xFE16;
+pub const DSKADDR: u16 = 0xFE18;
+pub const DSKCMD: u16 = 0xFE1A;
+
+/// Words per sector
+pub const SECTOR: usize = 256;
+
+/// Completion interrupt
+pub const VECTOR: u8 = 0x82;
+pub const PRIORITY: u8 = 3;
+
+/// Clocks a command takes by default
+pub const LATENCY: u32 = 100;
+
+const READY: i16 = 1 << 15;
+const ENABLE: i16 = 1 << 14;
+const ERROR: i16 = 1;
+
+const READ: i16 = 1;
+const WRITE: i16 = 2;
+
+#[derive(Debug)]
+pub struct Disk {
+    file: File,
+    pub latency: u32,
+    status: i16,
+    sector: i16,
+    addr: i16,
+    command: Option<(i16, u32)>, // in progress, and clocks left
+    interrupt: bool // completed with interrupts enabled, not yet acknowledged
+}
+
+impl Disk {
+    /// Opens or creates the image file
+    pub fn open(path: &Path) -> io::Result<Disk> {
+	let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+	Ok(Disk { file, latency: LATENCY, status: READY, sector: 0, addr: 0, command: None, interrupt: false })
+    }
+
+    fn read_sector(&mut self) -> io::Result<Vec<i16>> {
+	let mut bytes = Vec::new();
+	self.file.seek(SeekFrom::Start(self.offset()))?;
+	(&mut self.file).take(SECTOR as u64 * 2).read_to_end(&mut bytes)?;
+	bytes.resize(SECTOR * 2, 0);
+	Ok(bytes.chunks(2).map(|b| i16::from_be_bytes([b[0], b[1]])).collect())
+    }
+
+    fn write_sector(&mut self, words: &[i16]) -> io::Result<()> {
+	let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+	self.file.seek(SeekFrom::Start(self.offset()))?;
+	self.file.write_all(&bytes)?;
+	self.file.flush()
+    }
+
+    fn offset(&self) -> u64 {
+	self.sector as u16 as u64 * SECTOR as u64 * 2
+    }
+}
+
+impl Device for Disk {
+    fn range(&self) -> Range<u16> {
+	DSKSR..DSKCMD + 1
+    }
+
+    fn read(&mut self, addr: u16) -> i16 {
+	match addr {
+	    DSKSR => {
+		self.interrupt = false; // acknowledged
+		self.status
+	    }
+	    DSKSEC => self.sector,
+	    DSKADDR => self.addr,
+	    _ => 0
+	}
+    }
+
+    fn write(&mut self, addr: u16, value: i16) {
+	match addr {
+	    DSKSR => self.status = (self.status & !ENABLE) | (value & ENABLE),
+	    DSKSEC => self.sector = value,
+	    DSKADDR => self.addr = value,
+	    DSKCMD if self.command.is_none() => {
+		self.status &= !(READY | ERROR);
+		self.command = Some((value, self.latency));
+	    }
+	    _ => () // busy, the command is ignored
+	}
+    }
+
+    fn tick(&mut self) {
+	if let Some((_, left)) = self.command.as_mut() {
+	    *left = left.saturating_sub(1);
+	}
+    }
+
+    fn dma(&mut self, memory: &mut LC3Memory) {
+	let command = match self.command {
+	    Some((command, 0)) => command,
+	    _ => return
+	};
+	self.command = None;
+	let addr = self.addr as u16;
+	let result = match command {
+	    READ => self.read_sector().map(|words| memory.write_words(addr, &words)),
+	    WRITE => self.write_sector(&memory.read_words(addr, SECTOR)),
+	    _ => Err(io::Error::other("unknown command"))
+	};
+	if result.is_err() {
+	    self.status |= ERROR;
+	}
+	self.status |= READY;
+	self.interrupt = self.status & ENABLE != 0;
+    }
+
+    fn interrupt(&self) -> Option<(u8, u8)> {
+	if self.interrupt { Some((VECTOR, PRIORITY)) } else { None }
+    }
+
+    fn save(&self) -> Vec<i16> {
+	let (command, left) = self.command.unwrap_or((0, 0));
+	vec![self.status, self.sector, self.addr, command, left as i16, self.interrupt as i16]
+    }
+
+    fn restore(&mut self, state: &[i16]) {
+	if let [status, sector, addr, command, left, interrupt] = *state {
+	    self.status = status;
+	    self.sector = sector;
+	    self.addr = addr;
+	    self.command = if command == 0 { None } else { Some((command, left as u16 as u32)) };
+	    self.interrupt = interrupt != 0;
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Disk, DSKADDR, DSKCMD, DSKSEC, DSKSR, SECTOR, VECTOR};
+    use crate::lc3::LC3;
+
+    #[test]
+    fn disk_test() {
+	let path = std::env::temp_dir().join(format!("lc3-disk-{}.img", std::process::id()));
+	let mut disk = Disk::open(&path).expect("Failed to open");
+	disk.latency = 3;
+	let mut lc3 = LC3::new();
+	lc3.memory.attach(Box::new(disk)).expect("Failed to attach");
+	lc3.memory.put(0x100 + VECTOR as u16, 0x1200); // completion handler
+	lc3.memory.write_words(0x4000, &(0..SECTOR as i16).collect::<Vec<_>>());
+	lc3.pc = 0x3000;
+	lc3.r[6] = 0x3000;
+	lc3.start();
+
+	// write x4000 to sector 2, polling
+	lc3.memory.put(DSKSEC, 2);
+	lc3.memory.put(DSKADDR, 0x4000);
+	lc3.memory.put(DSKCMD, 2);
+	assert_eq!(lc3.memory.get(DSKSR), 0);
+	for _ in 0..3 {
+	    lc3.clock();
+	}
+	assert_eq!(lc3.memory.get(DSKSR) as u16, 0x8000);
+	assert_eq!(std::fs::metadata(&path).unwrap().len(), 3 * 512);
+
+	// read it back to x5000, with the interrupt
+	lc3.memory.put(DSKSR, 1 << 14);
+	lc3.memory.put(DSKADDR, 0x5000);
+	lc3.memory.put(DSKCMD, 1);
+	for _ in 0..3 {
+	    lc3.clock();
+	}
+	assert_eq!(lc3.pc, 0x1200);
+	assert_eq!(lc3.memory.read_words(0x5000, SECTOR), lc3.memory.read_words(0x4000, SECTOR));
+	std::fs::remove_file(&path).ok();
+    }
+}
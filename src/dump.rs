@@ -0,0 +1,101 @@
+//! Memory dumps: a range as a hex and disassembly listing, or exported as an .obj file or raw
+//! big-endian words. `lc3-emu prog.obj --dump x4000-x40FF --export x4000-x40FF data.obj`
+//! does both once the program stops.
+
+use crate::asm::Section;
+use crate::disasm;
+use crate::endian::{self, Endian};
+use crate::lc3::LC3Memory;
+use crate::symbols::Symbols;
+
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    Obj, // origin, then the words
+    Raw // just the words
+}
+
+impl Format {
+    /// .obj for `*.obj`, raw for anything else
+    pub fn for_path(path: &Path) -> Format {
+	match path.extension() {
+	    Some(ext) if ext.eq_ignore_ascii_case("obj") => Format::Obj,
+	    _ => Format::Raw
+	}
+    }
+}
+
+/// One line per word: address, hex, disassembly, then any label and the character it holds.
+/// Reads have no device side effects.
+pub fn listing(memory: &LC3Memory, range: RangeInclusive<u16>, symbols: &Symbols) -> Vec<String> {
+    range.map(|addr| {
+	let word = memory.peek(addr);
+	let line = disasm::line_with(addr, word, symbols);
+	let mut notes = Vec::new();
+	if let Some(name) = symbols.name(addr) {
+	    notes.push(name.to_string());
+	}
+	if (0x20..0x7F).contains(&word) {
+	    notes.push(format!("'{}'", word as u8 as char));
+	}
+	if notes.is_empty() {
+	    line
+	} else {
+	    format!("{:<32}; {}", line, notes.join(" "))
+	}
+    }).collect()
+}
+
+/// The words in `range` as a file in `format`
+pub fn export(memory: &LC3Memory, range: RangeInclusive<u16>, format: Format) -> Vec<u8> {
+    let origin = *range.start();
+    let words = memory.read_words(origin, range.count());
+    match format {
+	Format::Obj => Section { origin, words }.obj(),
+	Format::Raw => endian::bytes(&words, Endian::Big)
+    }
+}
+
+/// Writes `range` to `path`, as an .obj file if it's named like one, returning the word count
+pub fn save(memory: &LC3Memory, range: RangeInclusive<u16>, path: &Path) -> Result<usize, String> {
+    let len = range.clone().count();
+    std::fs::write(path, export(memory, range, Format::for_path(path)))
+	.map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export, listing, Format};
+    use crate::lc3::LC3Memory;
+    use crate::loader::load_obj;
+    use crate::symbols::Symbols;
+
+    #[test]
+    fn listing_test() {
+	let mut memory = LC3Memory::new();
+	memory.write_words(0x4000, &[0x1265, 0x0048]);
+	let mut symbols = Symbols::new();
+	symbols.insert("DATA", 0x4001);
+	let lines = listing(&memory, 0x4000..=0x4001, &symbols);
+	assert_eq!(lines[0], "x4000  1265  ADD R1, R1, #5");
+	assert_eq!(lines[1], "x4001  0048  NOP                ; DATA 'H'");
+	assert_eq!(listing(&memory, 0xFFFF..=0xFFFF, &symbols).len(), 1);
+    }
+
+    #[test]
+    fn export_test() {
+	let mut memory = LC3Memory::new();
+	memory.write_words(0x4000, &[0x1265, -2]);
+	assert_eq!(export(&memory, 0x4000..=0x4001, Format::Raw), vec![0x12, 0x65, 0xFF, 0xFE]);
+	let obj = export(&memory, 0x4000..=0x4001, Format::Obj);
+	assert_eq!(obj, vec![0x40, 0x00, 0x12, 0x65, 0xFF, 0xFE]);
+	let mut other = LC3Memory::new();
+	assert_eq!(load_obj(&mut other, &obj), Ok(0x4000));
+	assert_eq!(other.read_words(0x4000, 2), vec![0x1265, -2]);
+	assert_eq!(Format::for_path("data.OBJ".as_ref()), Format::Obj);
+	assert_eq!(Format::for_path("data.bin".as_ref()), Format::Raw);
+    }
+}
@@ -0,0 +1,46 @@
+/// Byte order of 16-bit words in a host buffer. lc3as writes big-endian, some other
+/// toolchains and raw memory dumps are little-endian.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Endian {
+    Big,
+    Little
+}
+
+/// Host bytes to LC-3 words
+pub fn words(bytes: &[u8], endian: Endian) -> Result<Vec<i16>, &'static str> {
+    if !bytes.len().is_multiple_of(2) {
+	return Err("Buffer has an odd number of bytes");
+    }
+    Ok(bytes.chunks(2).map(|b| match endian {
+	Endian::Big => i16::from_be_bytes([b[0], b[1]]),
+	Endian::Little => i16::from_le_bytes([b[0], b[1]])
+    }).collect())
+}
+
+/// LC-3 words to host bytes
+pub fn bytes(words: &[i16], endian: Endian) -> Vec<u8> {
+    words.iter().flat_map(|w| match endian {
+	Endian::Big => w.to_be_bytes(),
+	Endian::Little => w.to_le_bytes()
+    }).collect()
+}
+
+/// Rewrites a buffer from one byte order to the other
+pub fn convert(buffer: &[u8], from: Endian, to: Endian) -> Result<Vec<u8>, &'static str> {
+    Ok(bytes(&words(buffer, from)?, to))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bytes, convert, words, Endian};
+
+    #[test]
+    fn endian_test() {
+	assert_eq!(words(&[0x30, 0x00, 0xF0, 0x25], Endian::Big), Ok(vec![0x3000, 0xF025]));
+	assert_eq!(words(&[0x00, 0x30, 0x25, 0xF0], Endian::Little), Ok(vec![0x3000, 0xF025]));
+	assert!(words(&[0x30], Endian::Big).is_err());
+	assert_eq!(bytes(&[0x1234], Endian::Big), vec![0x12, 0x34]);
+	assert_eq!(bytes(&[0x1234], Endian::Little), vec![0x34, 0x12]);
+	assert_eq!(convert(&[1, 2, 3, 4], Endian::Little, Endian::Big), Ok(vec![2, 1, 4, 3]));
+    }
+}
@@ -0,0 +1,104 @@
+//! Ready-made machines for tests. Code placed with `code` goes at a cursor that starts at
+//! x3000, which is also where execution starts.
+
+use crate::lc3::LC3;
+use crate::os::{prepare_supervisor, prepare_user_mode};
+use crate::stdlib;
+
+pub struct Fixture {
+    lc3: Box<LC3>, // boxed so chained calls don't each copy the 128K of memory on the stack
+    cursor: u16
+}
+
+impl Fixture {
+    /// CPU and zeroed memory, running in supervisor mode with no OS loaded
+    pub fn bare() -> Self {
+	let mut lc3 = Box::new(LC3::new());
+	lc3.pc = 0x3000;
+	lc3.halted = false;
+	Fixture { lc3, cursor: 0x3000 }
+    }
+
+    /// CPU with the trap and interrupt routines loaded, ready to run a user program
+    pub fn with_os() -> Self {
+	let mut lc3 = Box::new(LC3::new());
+	prepare_supervisor(&mut lc3);
+	prepare_user_mode(&mut lc3, 0x3000);
+	lc3.start();
+	Fixture { lc3, cursor: 0x3000 }
+    }
+
+    /// `with_os()` plus the standard library at xA000, see `stdlib::routine()` for addresses
+    pub fn with_stdlib() -> Self {
+	let mut fixture = Self::with_os();
+	stdlib::load(&mut fixture.lc3);
+	fixture
+    }
+
+    /// Moves the code cursor
+    pub fn at(mut self, addr: u16) -> Self {
+	self.cursor = addr;
+	self
+    }
+
+    /// Places instructions at the cursor and moves it past them
+    pub fn code(mut self, words: &[i16]) -> Self {
+	self.lc3.memory.write_words(self.cursor, words);
+	self.cursor = self.cursor.wrapping_add(words.len() as u16);
+	self
+    }
+
+    /// Places words at `addr`, leaving the cursor alone
+    pub fn data(mut self, addr: u16, words: &[i16]) -> Self {
+	self.lc3.memory.write_words(addr, words);
+	self
+    }
+
+    /// Places a null-terminated string at `addr`
+    pub fn string(mut self, addr: u16, text: &str) -> Self {
+	self.lc3.memory.write_cstr(addr, text);
+	self
+    }
+
+    pub fn reg(mut self, code: i16, value: i16) -> Self {
+	self.lc3.put_reg(code, value);
+	self
+    }
+
+    /// Where execution starts
+    pub fn pc(mut self, pc: u16) -> Self {
+	self.lc3.pc = pc as i16;
+	self
+    }
+
+    pub fn build(self) -> LC3 {
+	*self.lc3
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Fixture;
+    use crate::lc3::LC3IO;
+
+    #[test]
+    fn fixture_test() {
+	let mut lc3 = Fixture::bare().at(0x4000).code(&[0b0001_001_001_1_00001]).pc(0x4000).reg(1, 41).build();
+	lc3.clock();
+	assert_regs!(lc3, r1 = 42);
+
+	let mut lc3 = Fixture::with_os()
+	    .code(&[0b1110_000_000000010, 0b1111_0000_00100010, 0b1111_0000_00100101])
+	    .string(0x3003, "ok")
+	    .build();
+	let mut output = String::new();
+	loop {
+	    match lc3.clock() {
+		LC3IO::Display(c) => output.push((c as u8) as char),
+		LC3IO::Halt => break,
+		_ => ()
+	    }
+	}
+	assert_eq!(output, "ok\n----- Halting the processor -----\n");
+    }
+}
@@ -0,0 +1,430 @@
+//! Autograding: `lc3-emu grade prog.obj tests.json`
+//!
+//! A test file holds one test or an array of them:
+//!
+//! ```text
+//! {
+//!   "name": "adds two numbers",
+//!   "registers": {"R0": 5, "R1": "x0003"},
+//!   "memory": {"x4000": [1, 2, 3]},
+//!   "data": [[4, 5, 6]],
+//!   "input": "q",
+//!   "limit": 100000,
+//!   "expect": {
+//!     "registers": {"R2": 8},
+//!     "memory": {"x4003": 6},
+//!     "output": "8\n",
+//!     "halted": true
+//!   }
+//! }
+//! ```
+//!
+//! Words are numbers or `"xHEX"`/`"#decimal"` strings. Registers are R0-R7, PC and PSR. Each
+//! `data` block is placed clear of the program with its address in R0, R1, ... before the
+//! registers are set, as `lc3-emu gen` writes them. Every test boots the program fresh on the
+//! built-in OS, and the OS's halt message isn't part of the output. The exit code is 0 when every test passes, 1 when any fails and 2 when they
+//! couldn't be run.
+
+use crate::json::{self, Value};
+use crate::lc3::batch::StopReason;
+use crate::lc3::LC3;
+use crate::loader::{place_blocks, DATA_REGION};
+use crate::os::{boot, HALT_MESSAGE};
+
+const USAGE: &str = "usage: lc3-emu grade <program.obj> <tests.json>... [--json <report.json>]";
+
+/// Instructions a test may run when it doesn't give a limit
+pub const LIMIT: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Case {
+    pub name: String,
+    pub registers: Vec<(String, i16)>,
+    pub memory: Vec<(u16, Vec<i16>)>,
+    pub data: Vec<Vec<i16>>, // blocks placed anywhere, addresses in R0, R1, ...
+    pub input: Vec<u8>,
+    pub limit: u64,
+    pub expect: Expect
+}
+
+/// What a passing run ends with, anything left out isn't checked
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Expect {
+    pub registers: Vec<(String, i16)>,
+    pub memory: Vec<(u16, Vec<i16>)>,
+    pub output: Option<String>,
+    pub halted: Option<bool>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseResult {
+    pub name: String,
+    pub stop: &'static str, // halted, limit, waiting for input, unhandled exception or breakpoint
+    pub instructions: u64,
+    pub output: String,
+    pub failures: Vec<String>
+}
+
+impl CaseResult {
+    pub fn passed(&self) -> bool {
+	self.failures.is_empty()
+    }
+}
+
+/// `lc3-emu grade`, returns the process exit code
+pub fn main(args: &[String]) -> i32 {
+    match grade(args) {
+	Ok(true) => 0,
+	Ok(false) => 1,
+	Err(e) => {
+	    eprintln!("{}", e);
+	    2
+	}
+    }
+}
+
+fn grade(args: &[String]) -> Result<bool, String> {
+    let mut program = None;
+    let mut tests = Vec::new();
+    let mut json_out = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+	match arg.as_str() {
+	    "--json" => json_out = Some(args.next().ok_or(USAGE)?.clone()),
+	    a if a.starts_with("--") => return Err(USAGE.to_string()),
+	    a if program.is_none() => program = Some(a.to_string()),
+	    a => tests.push(a.to_string())
+	}
+    }
+    let program = program.ok_or(USAGE)?;
+    if tests.is_empty() {
+	return Err(USAGE.to_string());
+    }
+    let obj = std::fs::read(&program).map_err(|e| format!("{}: {}", program, e))?;
+    let mut cases = Vec::new();
+    for path in &tests {
+	let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+	let value = json::parse(&text).map_err(|e| format!("{}: {}", path, e))?;
+	cases.extend(parse_cases(&value).map_err(|e| format!("{}: {}", path, e))?);
+    }
+    let mut results = Vec::new();
+    for case in &cases {
+	let result = run_case(&obj, case).map_err(|e| format!("{}: {}", program, e))?;
+	if result.passed() {
+	    println!("PASS {} ({} instructions)", result.name, result.instructions);
+	} else {
+	    println!("FAIL {}", result.name);
+	    for failure in &result.failures {
+		println!("  {}", failure);
+	    }
+	}
+	results.push(result);
+    }
+    let passed = results.iter().filter(|r| r.passed()).count();
+    println!("{}/{} passed", passed, results.len());
+    if let Some(path) = json_out {
+	let text = to_json(&program, &results).to_string() + "\n";
+	std::fs::write(&path, text).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    Ok(passed == results.len())
+}
+
+/// Reads a test or an array of them
+pub fn parse_cases(value: &Value) -> Result<Vec<Case>, String> {
+    match value {
+	Value::Array(items) => items.iter().enumerate()
+	    .map(|(i, item)| parse_case(item, i + 1))
+	    .collect(),
+	_ => Ok(vec![parse_case(value, 1)?])
+    }
+}
+
+fn parse_case(value: &Value, n: usize) -> Result<Case, String> {
+    let name = match value.get("name") {
+	Some(name) => name.as_str().ok_or("test name must be a string")?.to_string(),
+	None => format!("test {}", n)
+    };
+    let context = |e: String| format!("{}: {}", name, e);
+    let expect = value.get("expect").ok_or_else(|| context("no expect".to_string()))?;
+    Ok(Case {
+	registers: registers(value.get("registers")).map_err(context)?,
+	memory: memory(value.get("memory")).map_err(context)?,
+	data: data(value.get("data")).map_err(context)?,
+	input: match value.get("input") {
+	    Some(input) => input.as_str().ok_or_else(|| context("input must be a string".to_string()))?.bytes().collect(),
+	    None => Vec::new()
+	},
+	limit: match value.get("limit") {
+	    Some(limit) => limit.as_f64().filter(|n| *n >= 1.0).ok_or_else(|| context("bad limit".to_string()))? as u64,
+	    None => LIMIT
+	},
+	expect: Expect {
+	    registers: registers(expect.get("registers")).map_err(context)?,
+	    memory: memory(expect.get("memory")).map_err(context)?,
+	    output: match expect.get("output") {
+		Some(output) => Some(output.as_str().ok_or_else(|| context("output must be a string".to_string()))?.to_string()),
+		None => None
+	    },
+	    halted: match expect.get("halted") {
+		Some(halted) => Some(halted.as_bool().ok_or_else(|| context("halted must be true or false".to_string()))?),
+		None => None
+	    }
+	},
+	name
+    })
+}
+
+/// `{"R0": 5, "PC": "x3000"}`
+fn registers(value: Option<&Value>) -> Result<Vec<(String, i16)>, String> {
+    let fields = match value {
+	Some(Value::Object(fields)) => fields,
+	Some(_) => return Err("registers must be an object".to_string()),
+	None => return Ok(Vec::new())
+    };
+    fields.iter().map(|(name, value)| {
+	let name = name.to_ascii_uppercase();
+	match name.as_str() {
+	    "R0" | "R1" | "R2" | "R3" | "R4" | "R5" | "R6" | "R7" | "PC" | "PSR" => Ok((name, word(value)?)),
+	    _ => Err(format!("unknown register {}", name))
+	}
+    }).collect()
+}
+
+/// `{"x4000": 7, "x4001": [1, 2]}`
+fn memory(value: Option<&Value>) -> Result<Vec<(u16, Vec<i16>)>, String> {
+    let fields = match value {
+	Some(Value::Object(fields)) => fields,
+	Some(_) => return Err("memory must be an object".to_string()),
+	None => return Ok(Vec::new())
+    };
+    fields.iter().map(|(addr, value)| {
+	let addr = word(&Value::String(addr.clone()))? as u16;
+	let words = match value {
+	    Value::Array(items) => items.iter().map(word).collect::<Result<Vec<_>, _>>()?,
+	    _ => vec![word(value)?]
+	};
+	Ok((addr, words))
+    }).collect()
+}
+
+/// `[[1, 2], [3]]`, at most one block per register R0-R5
+fn data(value: Option<&Value>) -> Result<Vec<Vec<i16>>, String> {
+    let blocks = match value {
+	Some(Value::Array(blocks)) if blocks.len() <= 6 => blocks,
+	Some(Value::Array(_)) => return Err("at most 6 data blocks can be passed in R0-R5".to_string()),
+	Some(_) => return Err("data must be an array of blocks".to_string()),
+	None => return Ok(Vec::new())
+    };
+    blocks.iter().map(|block| match block {
+	Value::Array(items) => items.iter().map(word).collect(),
+	_ => Err("data block must be an array".to_string())
+    }).collect()
+}
+
+/// A 16-bit word from a number or an `xHEX`/`#decimal` string
+fn word(value: &Value) -> Result<i16, String> {
+    let n = match value {
+	Value::Number(n) if n.fract() == 0.0 => Some(*n as i64),
+	Value::String(s) => match s.strip_prefix('x').or_else(|| s.strip_prefix('X')) {
+	    Some(hex) => i64::from_str_radix(hex, 16).ok(),
+	    None => s.strip_prefix('#').unwrap_or(s).parse().ok()
+	},
+	_ => None
+    };
+    match n {
+	Some(n) if (-0x8000..=0xFFFF).contains(&n) => Ok(n as u16 as i16),
+	_ => Err(format!("bad word {}", value))
+    }
+}
+
+fn get(lc3: &LC3, name: &str) -> i16 {
+    match name {
+	"PC" => lc3.pc,
+	"PSR" => lc3.psr,
+	r => lc3.regs()[(r.as_bytes()[1] - b'0') as usize]
+    }
+}
+
+fn set(lc3: &mut LC3, name: &str, value: i16) {
+    match name {
+	"PC" => lc3.pc = value,
+	"PSR" => lc3.psr = value,
+	r => lc3.put_reg((r.as_bytes()[1] - b'0') as i16, value)
+    }
+}
+
+/// Boots the program, sets it up, runs it and checks the expectations
+pub fn run_case(obj: &[u8], case: &Case) -> Result<CaseResult, &'static str> {
+    let mut lc3 = boot(obj)?;
+    let origin = lc3.pc as u16;
+    let program = origin..origin.saturating_add((obj.len() / 2).saturating_sub(1) as u16);
+    let blocks = place_blocks(&mut lc3.memory, &case.data, DATA_REGION, &[program], None)?;
+    for (code, addr) in blocks.iter().enumerate() {
+	lc3.put_reg(code as i16, *addr as i16);
+    }
+    for (name, value) in &case.registers {
+	set(&mut lc3, name, *value);
+    }
+    for (addr, words) in &case.memory {
+	lc3.memory.write_words(*addr, words);
+    }
+    for key in &case.input {
+	lc3.schedule_key(0, *key as i16);
+    }
+    lc3.fast_forward = true;
+    let stop = loop {
+	let left = case.limit.saturating_sub(lc3.instructions);
+	if left == 0 {
+	    break "limit";
+	}
+	match lc3.run_steps(left) {
+	    StopReason::Halted => break "halted",
+	    StopReason::Idle => break "waiting for input",
+	    StopReason::Exception(_) => break "unhandled exception",
+	    StopReason::Breakpoint(_) => break "breakpoint",
+	    StopReason::Watch(_) => break "watchpoint",
+	    StopReason::Reset | StopReason::Limit => ()
+	}
+    };
+    let output = String::from_utf8_lossy(&lc3.take_output()).into_owned();
+    let output = output.strip_suffix(HALT_MESSAGE).unwrap_or(&output).to_string(); // the OS's, not the program's
+
+    let expect = &case.expect;
+    let mut failures = Vec::new();
+    if let Some(halted) = expect.halted {
+	if halted != lc3.halted {
+	    failures.push(format!("expected the program to {}, it stopped on {}",
+				  if halted { "halt" } else { "keep running" }, stop));
+	}
+    }
+    for (name, value) in &expect.registers {
+	let actual = get(&lc3, name);
+	if actual != *value {
+	    failures.push(format!("{}: expected x{:04X}, got x{:04X}", name, *value as u16, actual as u16));
+	}
+    }
+    for (addr, words) in &expect.memory {
+	for (i, value) in words.iter().enumerate() {
+	    let addr = addr.wrapping_add(i as u16);
+	    let actual = lc3.memory.peek(addr);
+	    if actual != *value {
+		failures.push(format!("x{:04X}: expected x{:04X}, got x{:04X}", addr, *value as u16, actual as u16));
+	    }
+	}
+    }
+    if let Some(expected) = &expect.output {
+	if *expected != output {
+	    failures.push(format!("output: expected {:?}, got {:?}", expected, output));
+	}
+    }
+    Ok(CaseResult { name: case.name.clone(), stop, instructions: lc3.instructions, output, failures })
+}
+
+/// Machine-readable report for a run of `lc3-emu grade`
+pub fn to_json(program: &str, results: &[CaseResult]) -> Value {
+    let passed = results.iter().filter(|r| r.passed()).count() as u64;
+    let tests = results.iter().map(|r| json::object(vec![
+	("name", r.name.as_str().into()),
+	("passed", r.passed().into()),
+	("stop", r.stop.into()),
+	("instructions", r.instructions.into()),
+	("output", r.output.as_str().into()),
+	("failures", Value::Array(r.failures.iter().map(|f| f.as_str().into()).collect()))
+    ])).collect();
+    json::object(vec![
+	("program", program.into()),
+	("passed", passed.into()),
+	("failed", (results.len() as u64 - passed).into()),
+	("tests", Value::Array(tests))
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_cases, run_case, to_json};
+    use crate::asm::assemble;
+    use crate::json;
+    use crate::testgen::{self, Pattern, Spec};
+
+    const SOURCE: &str = "
+	.ORIG x3000
+	ADD R2, R0, R1
+	STI R2, RESULT
+	GETC
+	OUT
+	HALT
+RESULT	.FILL x4000
+	.END";
+
+    #[test]
+    fn grade_test() {
+	let obj = assemble(SOURCE).expect("Failed to assemble").sections[0].obj();
+	let tests = json::parse(r##"[
+	    {"name": "sum", "registers": {"R0": 5, "r1": "x0003"}, "input": "k",
+	     "expect": {"registers": {"R2": 8}, "memory": {"x4000": [8]}, "output": "k", "halted": true}},
+	    {"registers": {"R0": -1, "R1": "#-2"}, "input": "x",
+	     "expect": {"registers": {"R2": 0}, "memory": {"x4000": "xFFFD"}, "halted": true}},
+	    {"name": "no input", "limit": 50000, "expect": {"halted": true}}
+	]"##).unwrap();
+	let cases = parse_cases(&tests).expect("Failed to parse");
+	assert_eq!(cases[1].name, "test 2");
+	let results: Vec<_> = cases.iter().map(|c| run_case(&obj, c).unwrap()).collect();
+	assert!(results[0].passed(), "{:?}", results[0].failures);
+	assert_eq!(results[0].stop, "halted");
+	assert_eq!(results[1].failures, vec!["R2: expected x0000, got xFFFD"]);
+	assert_eq!(results[2].stop, "waiting for input");
+	assert_eq!(results[2].failures.len(), 1);
+	let report = to_json("prog.obj", &results);
+	assert_eq!(report.get("passed").and_then(|p| p.as_f64()), Some(1.0));
+	assert_eq!(report.get("failed").and_then(|p| p.as_f64()), Some(2.0));
+    }
+
+    #[test]
+    fn parse_test() {
+	let bad = |text: &str| parse_cases(&json::parse(text).unwrap()).unwrap_err();
+	assert_eq!(bad(r#"{"name": "t"}"#), "t: no expect");
+	assert_eq!(bad(r#"{"registers": {"R9": 1}, "expect": {}}"#), "test 1: unknown register R9");
+	assert_eq!(bad(r#"{"memory": {"x4000": 70000}, "expect": {}}"#), "test 1: bad word 70000");
+	assert_eq!(bad(r#"{"data": [1], "expect": {}}"#), "test 1: data block must be an array");
+    }
+
+    #[test]
+    fn gen_test() {
+	// R2 = sum of the R1 words at R0, the layout `lc3-emu gen sum` writes
+	let obj = assemble("
+	.ORIG x3000
+	AND R2, R2, #0
+	ADD R1, R1, #0
+	BRz DONE
+LOOP	LDR R3, R0, #0
+	ADD R2, R2, R3
+	ADD R0, R0, #1
+	ADD R1, R1, #-1
+	BRp LOOP
+DONE	HALT
+	.END").expect("Failed to assemble").sections[0].obj();
+	let spec = Spec { pattern: Pattern::SumArray, count: 5, min_len: 0, max_len: 20, seed: 3 };
+	let text = testgen::to_json(&testgen::generate(&spec)).to_string();
+	let cases = parse_cases(&json::parse(&text).unwrap()).expect("Failed to parse");
+	assert_eq!(cases.len(), 5);
+	for case in &cases {
+	    let result = run_case(&obj, case).unwrap();
+	    assert!(result.passed(), "{}: {:?}", result.name, result.failures);
+	}
+
+	let echo = assemble("
+	.ORIG x3000
+LOOP	GETC
+	OUT
+	ADD R1, R0, #-10
+	BRnp LOOP
+	HALT
+	.END").expect("Failed to assemble").sections[0].obj();
+	let spec = Spec { pattern: Pattern::Echo, count: 3, min_len: 1, max_len: 10, seed: 3 };
+	let text = testgen::to_json(&testgen::generate(&spec)).to_string();
+	for case in &parse_cases(&json::parse(&text).unwrap()).unwrap() {
+	    let result = run_case(&echo, case).unwrap();
+	    assert!(result.passed(), "{}: {:?}", result.name, result.failures);
+	}
+    }
+}
@@ -0,0 +1,304 @@
+//! Just enough JSON for the reports the frontend reads and writes
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>) // keeps key order for readable output
+}
+
+impl Value {
+    /// Looks up a key in an object
+    pub fn get(&self, key: &str) -> Option<&Value> {
+	match self {
+	    Value::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+	    _ => None
+	}
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+	match self {
+	    Value::Number(n) => Some(*n),
+	    _ => None
+	}
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+	match self {
+	    Value::Bool(b) => Some(*b),
+	    _ => None
+	}
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+	match self {
+	    Value::String(s) => Some(s),
+	    _ => None
+	}
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+	Value::Number(n)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Self {
+	Value::Number(n as f64)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+	Value::Bool(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+	Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+	Value::String(s)
+    }
+}
+
+/// Builds an object from key/value pairs
+pub fn object(fields: Vec<(&str, Value)>) -> Value {
+    Value::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self {
+	    Value::Null => write!(f, "null"),
+	    Value::Bool(b) => write!(f, "{}", b),
+	    Value::Number(n) if n.is_finite() => write!(f, "{}", n),
+	    Value::Number(_) => write!(f, "null"),
+	    Value::String(s) => write_string(f, s),
+	    Value::Array(items) => {
+		write!(f, "[")?;
+		for (i, item) in items.iter().enumerate() {
+		    if i > 0 {
+			write!(f, ",")?;
+		    }
+		    write!(f, "{}", item)?;
+		}
+		write!(f, "]")
+	    }
+	    Value::Object(fields) => {
+		write!(f, "{{")?;
+		for (i, (key, value)) in fields.iter().enumerate() {
+		    if i > 0 {
+			write!(f, ",")?;
+		    }
+		    write_string(f, key)?;
+		    write!(f, ":{}", value)?;
+		}
+		write!(f, "}}")
+	    }
+	}
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+	match c {
+	    '"' => write!(f, "\\\"")?,
+	    '\\' => write!(f, "\\\\")?,
+	    '\n' => write!(f, "\\n")?,
+	    '\r' => write!(f, "\\r")?,
+	    '\t' => write!(f, "\\t")?,
+	    c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+	    c => write!(f, "{}", c)?
+	}
+    }
+    write!(f, "\"")
+}
+
+/// Parses a JSON document
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut p = Parser { chars: text.chars().collect(), pos: 0 };
+    let value = p.value()?;
+    p.skip_ws();
+    if p.pos != p.chars.len() {
+	return Err(format!("Unexpected trailing data at offset {}", p.pos));
+    }
+    Ok(value)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize
+}
+
+impl Parser {
+    fn skip_ws(&mut self) {
+	while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+	    self.pos += 1;
+	}
+    }
+
+    fn peek(&self) -> Option<char> {
+	self.chars.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+	self.skip_ws();
+	if self.peek() == Some(c) {
+	    self.pos += 1;
+	    Ok(())
+	} else {
+	    Err(format!("Expected '{}' at offset {}", c, self.pos))
+	}
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, String> {
+	let end = self.pos + word.len();
+	if end <= self.chars.len() && self.chars[self.pos..end].iter().copied().eq(word.chars()) {
+	    self.pos = end;
+	    Ok(value)
+	} else {
+	    Err(format!("Invalid literal at offset {}", self.pos))
+	}
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+	self.skip_ws();
+	match self.peek() {
+	    Some('n') => self.literal("null", Value::Null),
+	    Some('t') => self.literal("true", Value::Bool(true)),
+	    Some('f') => self.literal("false", Value::Bool(false)),
+	    Some('"') => Ok(Value::String(self.string()?)),
+	    Some('[') => {
+		self.pos += 1;
+		let mut items = Vec::new();
+		self.skip_ws();
+		if self.peek() == Some(']') {
+		    self.pos += 1;
+		    return Ok(Value::Array(items));
+		}
+		loop {
+		    items.push(self.value()?);
+		    self.skip_ws();
+		    match self.peek() {
+			Some(',') => self.pos += 1,
+			Some(']') => {
+			    self.pos += 1;
+			    return Ok(Value::Array(items));
+			}
+			_ => return Err(format!("Expected ',' or ']' at offset {}", self.pos))
+		    }
+		}
+	    }
+	    Some('{') => {
+		self.pos += 1;
+		let mut fields = Vec::new();
+		self.skip_ws();
+		if self.peek() == Some('}') {
+		    self.pos += 1;
+		    return Ok(Value::Object(fields));
+		}
+		loop {
+		    self.skip_ws();
+		    let key = self.string()?;
+		    self.expect(':')?;
+		    fields.push((key, self.value()?));
+		    self.skip_ws();
+		    match self.peek() {
+			Some(',') => self.pos += 1,
+			Some('}') => {
+			    self.pos += 1;
+			    return Ok(Value::Object(fields));
+			}
+			_ => return Err(format!("Expected ',' or '}}' at offset {}", self.pos))
+		    }
+		}
+	    }
+	    Some(c) if c == '-' || c.is_ascii_digit() => {
+		let start = self.pos;
+		while let Some(c) = self.peek() {
+		    if c.is_ascii_digit() || "+-.eE".contains(c) {
+			self.pos += 1;
+		    } else {
+			break;
+		    }
+		}
+		let text: String = self.chars[start..self.pos].iter().collect();
+		text.parse().map(Value::Number).map_err(|_| format!("Invalid number at offset {}", start))
+	    }
+	    _ => Err(format!("Unexpected character at offset {}", self.pos))
+	}
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+	if self.peek() != Some('"') {
+	    return Err(format!("Expected a string at offset {}", self.pos));
+	}
+	self.pos += 1;
+	let mut out = String::new();
+	loop {
+	    let c = self.peek().ok_or("Unterminated string")?;
+	    self.pos += 1;
+	    match c {
+		'"' => return Ok(out),
+		'\\' => {
+		    let e = self.peek().ok_or("Unterminated string")?;
+		    self.pos += 1;
+		    match e {
+			'n' => out.push('\n'),
+			'r' => out.push('\r'),
+			't' => out.push('\t'),
+			'b' => out.push('\u{8}'),
+			'f' => out.push('\u{c}'),
+			'u' => {
+			    let hex: String = self.chars.get(self.pos..self.pos + 4).ok_or("Bad escape")?.iter().collect();
+			    let code = u32::from_str_radix(&hex, 16).map_err(|_| "Bad escape")?;
+			    out.push(std::char::from_u32(code).unwrap_or('\u{fffd}'));
+			    self.pos += 4;
+			}
+			c => out.push(c)
+		    }
+		}
+		c => out.push(c)
+	    }
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{object, parse, Value};
+
+    #[test]
+    fn roundtrip_test() {
+	let value = object(vec![
+	    ("name", "a \"quoted\"\nline".into()),
+	    ("runs", Value::Array(vec![1.5.into(), 2u64.into()])),
+	    ("ok", true.into()),
+	    ("none", Value::Null)
+	]);
+	let text = value.to_string();
+	assert_eq!(parse(&text), Ok(value));
+    }
+
+    #[test]
+    fn parse_test() {
+	let value = parse(" { \"a\" : [ 1, -2.5e1 ] , \"b\": {} } ").expect("Failed to parse");
+	assert_eq!(value.get("a"), Some(&Value::Array(vec![1.0.into(), (-25.0).into()])));
+	assert_eq!(value.get("b"), Some(&Value::Object(vec![])));
+	assert!(parse("{\"a\": }").is_err());
+	assert!(parse("[1, 2] x").is_err());
+    }
+}
@@ -1,42 +1,143 @@
-#![allow(overflowing_literals, dead_code)]
+#![allow(overflowing_literals, dead_code, clippy::unusual_byte_groupings)]
 // for crying out loud
 
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+
+pub mod batch;
+pub mod call;
+pub mod console;
+pub mod debug;
+pub mod device;
+mod fast;
+pub mod history;
+pub mod hooks;
+pub mod interrupt;
+pub mod lc3b;
+pub mod snapshot;
+pub mod stack;
+pub mod time;
+
+use console::Sink;
+use debug::{Breakpoint, Watch};
+use device::{Bus, Device};
+use hooks::{AccessHook, Hooks};
+use interrupt::Controller;
+use stack::{Frame, Kind};
+use time::{InstructionTime, TimeSource};
+
+pub use lc3b::Isa;
+
 #[derive(Debug, Copy, Clone)]
 pub enum LC3IO {
     Halt,
+    Reset, // warm reset through the reset trap
+    Idle, // asleep until the next interrupt
     Display(i16),
     None
 }
 
+/// General purpose registers by name
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Reg {
+    R0, R1, R2, R3, R4, R5, R6, R7
+}
+
+/// Machine state captured by `start()` that a warm reset returns to
+#[derive(Debug, Copy, Clone, Default)]
+struct BootState {
+    pc: i16,
+    psr: i16,
+    r6: i16,
+    saved_usp: i16,
+    saved_ssp: i16
+}
+
+/// Consecutive identical KBSR polls before the host is told the program is idle
+const POLL_LIMIT: u32 = 16;
+
+/// Where and in what state the program last polled an unready keyboard
+#[derive(Debug, Copy, Clone)]
+struct PollState {
+    pc: i16,
+    regs: [i16; 8],
+    psr: i16,
+    count: u32
+}
+
 /// LC-3 (Little Computer 3)
 #[derive(Debug)]
 pub struct LC3 {
     last_io: LC3IO,
+    pub isa: Isa, // LC-3 or LC-3b decoding
     pub halted: bool, // processor stop and start
+    pub sleeping: bool, // waiting for an interrupt
     ie: u8, // interrupt enable
+    interrupts: Controller, // requests waiting to be taken
     pub pc: i16, // instruction pointer
     pub psr: i16, // process status
 
     pub saved_usp: i16, // user stack ptr
     pub saved_ssp: i16, // supervisor stack ptr
-    
-    pub r0: i16, // temp
-    pub r1: i16, // temp
-    pub r2: i16, // temp
-    pub r3: i16, // temp
-    pub r4: i16, // .data
-    pub r5: i16, // frame pointer
-    pub r6: i16, // stack pointer
-    pub r7: i16, // return address
+    boot: BootState,
+    pub reset_trap: Option<u8>, // trap vector that warm resets instead of calling the OS
+    pub legacy_traps: bool, // TRAP only saves PC in R7 (pre-3rd edition), set before loading the OS
+    poll: Option<PollState>, // busy-wait loop detection
+    pub instructions: u64, // instructions executed
+    pub last_instruction: Option<(u16, i16)>, // address and word of the last one fetched
+    pub ticks: u64, // clocks while running, including ones spent asleep
+    pub time: Box<dyn TimeSource>, // clock for everything time dependent
+    script: VecDeque<(u64, i16)>, // scripted keys and the time they arrive
+    pub fast_forward: bool, // skip idle waits straight to the next scripted key
+    breakpoints: Vec<Breakpoint>,
+    reg_watches: Vec<Reg>,
+    watch_hit: Option<Watch>, // fired but not yet reported by run_steps()
+    resume_at: Option<u16>, // breakpoint just reported, not to stop at again straight away
+    history: Option<history::History>, // undo journal for step_back()
+    output: Vec<u8>, // display output held by run_steps()
+    raised: Option<u8>, // exception the last clock raised
+    hooks: Hooks, // instruction callbacks
+    sink: Option<Sink>, // where display output goes besides clock()'s result
+    calls: Vec<Frame>, // shadow call stack
+    cache: fast::Cache, // predecoded instructions
+
+    pub r: [i16; 8], // R0-R7: R5 frame pointer, R6 stack pointer, R7 return address
     pub memory: LC3Memory
 }
 
+/// System space and device registers, privileged under the 3rd edition LC-3. Off by default
+/// so user programs written for older simulators can still poll the devices themselves; the
+/// built-in OS runs its service routines in supervisor mode either way
+pub const PRIVILEGED: [RangeInclusive<u16>; 2] = [0x0000..=0x2FFF, 0xFE00..=0xFFFF];
+
 /// LC-3 Memory (also manages mmapped IO, protection)
 pub struct LC3Memory { 
     pub mem: [i16; 65536],
     keyboard_ready: bool,
-    last_char: Option<i16>
-    // more stuff for memory mapped io
+    last_char: Option<i16>,
+    pub display_delay: u32, // microseconds of machine time DSR stays busy after a DDR write, 0 for always ready
+    display_until: u64, // machine time DSR is ready again
+    clock_time: u64, // machine time at the current clock, kept by LC3::cycle() while there's a delay
+    halt_requested: bool, // MCR was cleared
+    wait_requested: bool, // WFI register was written
+    kbsr_poll: bool, // KBSR was read while no key was ready
+    written: bool, // any write since the last KBSR poll
+    mirrors: Vec<Mirror>,
+    protected: Vec<RangeInclusive<u16>>, // off limits to user mode
+    read_watches: Vec<u16>,
+    write_watches: Vec<u16>,
+    watch_hit: Option<Watch>, // first watched access since it was last cleared
+    journal: Option<Vec<(u16, i16)>>, // old values of words written this clock, for step_back()
+    access_hooks: Vec<AccessHook>,
+    bus: Bus // attached devices
+}
+
+/// An aliased address range: accesses to `mirror..mirror + len` land on `base..base + len`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mirror {
+    pub base: u16,
+    pub mirror: u16,
+    pub len: u16
 }
 
 impl std::fmt::Debug for LC3Memory {
@@ -56,148 +157,318 @@ impl std::fmt::Debug for LC3Memory {
 // 0x3000
 //  User program and User Stack
 // 0xFE00
-//  Device register addresses
+//  Device register addresses (xFE10 = wait for interrupt)
 // 0xFFFF
 
+impl Default for LC3 {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
 impl LC3 {
+    /// A halted machine with zeroed registers and memory
     pub fn new() -> Self {
 	Self {
 	    last_io: LC3IO::None,
+	    isa: Isa::Lc3,
 	    halted: true, // starts halted
+	    sleeping: false,
 	    ie: 0b1,
+	    interrupts: Controller::default(),
 	    pc: 0,
 	    psr: 0,
 
 	    saved_usp: 0,
 	    saved_ssp: 0,
+	    boot: BootState::default(),
+	    reset_trap: None,
+	    legacy_traps: false,
+	    poll: None,
+	    instructions: 0,
+	    last_instruction: None,
+	    ticks: 0,
+	    time: Box::new(InstructionTime::default()),
+	    script: VecDeque::new(),
+	    fast_forward: false,
+	    breakpoints: Vec::new(),
+	    reg_watches: Vec::new(),
+	    watch_hit: None,
+	    resume_at: None,
+	    history: None,
+	    output: Vec::new(),
+	    raised: None,
+	    hooks: Hooks::default(),
+	    sink: None,
+	    calls: Vec::new(),
+	    cache: fast::Cache::default(),
 
-	    r0: 0,
-	    r1: 0,
-	    r2: 0,
-	    r3: 0,
-	    r4: 0,
-	    r5: 0,
-	    r6: 0,
-	    r7: 0,
+	    r: [0; 8],
 	    memory: LC3Memory::new() // starts 0'd
 	}
     }
 
+    /// Records the state a warm reset returns to, sets the machine control register and
+    /// starts the clock
     pub fn start(&mut self) {
+	self.boot = BootState {
+	    pc: self.pc,
+	    psr: self.psr,
+	    r6: self.r[6],
+	    saved_usp: self.saved_usp,
+	    saved_ssp: self.saved_ssp
+	};
+	self.halted = false;
+	self.memory.put(0xFFFE, 0b1);
+    }
+
+    /// Warm reset: PC, stacks, and privilege go back to how `start()` found them, NZP is
+    /// cleared, pending interrupts are dropped, and memory is left alone
+    pub fn warm_reset(&mut self) {
+	self.interrupts.clear();
+	self.calls.clear();
+	self.pc = self.boot.pc;
+	self.psr = self.boot.psr & !0b111;
+	self.r[6] = self.boot.r6;
+	self.saved_usp = self.boot.saved_usp;
+	self.saved_ssp = self.boot.saved_ssp;
 	self.halted = false;
 	self.memory.put(0xFFFE, 0b1);
     }
     
     /// Executes one Fetch Decode Execute cycle
     pub fn clock(&mut self) -> LC3IO {
+	if self.run_fast(1) == 1 {
+	    return LC3IO::None;
+	}
+	self.cycle()
+    }
+
+    /// `clock()` without trying the fast path first, for callers that just did
+    pub(super) fn cycle(&mut self) -> LC3IO {
+	if self.history.is_some() {
+	    self.begin_undo();
+	}
+	let fetch_pc = self.pc;
+	self.raised = None;
 	if !self.halted {
+	    self.ticks += 1;
+	    if self.memory.display_delay > 0 {
+		self.memory.clock_time = self.time.now(self.ticks);
+	    }
+	    if !self.interrupts.pending().is_empty() {
+		self.service_interrupts(); // posted since the last clock
+	    }
+	}
+	if !self.halted && !self.sleeping && (self.access_violation(self.pc as u16) || self.unaligned(self.pc as u16)) {
+	    self.instructions += 1; // the fetch faulted, the ACV handler runs next
+	} else if !self.halted && !self.sleeping {
+	    self.instructions += 1;
+	    let fetch_addr = self.pc as u16;
+	    let opcode = (self.memory.peek(fetch_addr) as u16 >> 12) as u8;
+	    self.before_instruction(fetch_addr, opcode);
 	    // fetch
 	    let instruction = self.memory.get(self.pc as u16);
-	    self.pc = self.pc.wrapping_add(1);
+	    self.last_instruction = Some((self.pc as u16, instruction));
+	    if self.memory.watch_hit == Some(Watch::Read(self.pc as u16)) {
+		self.memory.watch_hit = None; // fetches don't trip read watchpoints
+	    }
+	    self.pc = self.pc.wrapping_add(self.word_size());
 	    // decode
 	    let code = (instruction as u16 & 0b1111000000000000) >> 12;
-	    // execute based on the code
-	    match code {
-		0b0001 => self.add(instruction),
-		0b0101 => self.and(instruction),
-		0b0000 => self.br(instruction),
-		0b1100 => self.jmp(instruction),
-		0b0100 => self.jsr(instruction),
-		0b0010 => self.ld(instruction),
-		0b1010 => self.ldi(instruction),
-		0b0110 => self.ldr(instruction),
-		0b1110 => self.lea(instruction),
-		0b1001 => self.not(instruction),
-		0b1000 => self.rti(instruction), // Causes exception in user mode
-		0b0011 => self.st(instruction),
-		0b1011 => self.sti(instruction),
-		0b0111 => self.stor(instruction), // str name conflict
-		0b1111 => self.trap(instruction),
-		_ => self.exception(1) // Illegal opcode exception
+	    if self.isa == Isa::Lc3b {
+		self.execute_lc3b(instruction);
+	    } else {
+		// execute based on the code
+		match code {
+		    0b0001 => self.add(instruction),
+		    0b0101 => self.and(instruction),
+		    0b0000 => self.br(instruction),
+		    0b1100 => self.jmp(instruction),
+		    0b0100 => self.jsr(instruction),
+		    0b0010 => self.ld(instruction),
+		    0b1010 => self.ldi(instruction),
+		    0b0110 => self.ldr(instruction),
+		    0b1110 => self.lea(instruction),
+		    0b1001 => self.not(instruction),
+		    0b1000 => self.rti(instruction), // Causes exception in user mode
+		    0b0011 => self.st(instruction),
+		    0b1011 => self.sti(instruction),
+		    0b0111 => self.stor(instruction), // str name conflict
+		    0b1111 => self.trap(instruction),
+		    _ => self.exception(1) // Illegal opcode exception
+		}
 	    }
+	    self.after_instruction(fetch_addr, opcode);
 	}
 
 	
 	// check memory for char
-	if self.memory.last_char.is_some() {
-	    self.last_io = LC3IO::Display(self.memory.last_char.unwrap());
-	    self.memory.last_char = None;
+	if let Some(c) = self.memory.last_char.take() {
+	    self.display(c);
+	    self.last_io = LC3IO::Display(c);
 	}
 	// check memory for halt
-	if self.memory.get(0xFFFE) == 0b0 {
+	if self.memory.halt_requested {
+	    self.memory.halt_requested = false;
 	    self.halted = true;
 	    self.last_io = LC3IO::Halt;
 	}
+	// check memory for wait-for-interrupt
+	if self.memory.wait_requested {
+	    self.memory.wait_requested = false;
+	    self.sleeping = true;
+	}
+	// let attached devices run, then take any interrupt now allowed at this boundary
+	if !self.halted && !self.memory.bus.is_empty() {
+	    self.memory.bus.tick();
+	    self.memory.run_dma();
+	}
+	if !self.halted && (!self.memory.bus.is_empty() || !self.interrupts.pending().is_empty()) {
+	    self.service_interrupts();
+	}
+	// check for a busy-wait on the keyboard
+	if self.memory.kbsr_poll {
+	    self.memory.kbsr_poll = false;
+	    let regs = self.regs();
+	    let written = self.memory.written;
+	    match &mut self.poll {
+		Some(p) if p.pc == fetch_pc && p.regs == regs && p.psr == self.psr && !written => {
+		    p.count += 1;
+		}
+		_ => self.poll = Some(PollState { pc: fetch_pc, regs, psr: self.psr, count: 1 })
+	    }
+	    self.memory.written = false;
+	}
+	if self.memory.keyboard_ready {
+	    self.poll = None; // a key is waiting, the polling loop is about to see it
+	}
+	let mut busy_waiting = self.poll.is_some_and(|p| p.count >= POLL_LIMIT);
+	// deliver scripted input whose time has come
+	if let Some(&(at, key)) = self.script.front() {
+	    if self.fast_forward && (self.sleeping || busy_waiting) && !self.halted {
+		if let Some(skipped) = self.time.ticks_until(self.ticks, at) {
+		    self.ticks += skipped;
+		    if busy_waiting {
+			self.instructions += skipped; // the polling loop would have kept running
+		    }
+		}
+	    }
+	    if at <= self.now() && !self.memory.keyboard_ready {
+		self.script.pop_front();
+		if self.sleeping {
+		    self.interrupt(0x80, 4, key).ok();
+		} else {
+		    self.memory.key_press(key);
+		}
+		self.poll = None;
+		busy_waiting = false;
+	    }
+	}
+	if (self.sleeping || busy_waiting) && !self.halted {
+	    if let LC3IO::None = self.last_io {
+		self.last_io = LC3IO::Idle;
+	    }
+	}
+	if self.history.is_some() {
+	    self.end_undo();
+	}
 	let tmp = self.last_io;
 	self.last_io = LC3IO::None;
 	tmp
     }
 
-    /// External interrupt
+    /// Microseconds of machine time since it started
+    pub fn now(&self) -> u64 {
+	self.time.now(self.ticks)
+    }
+
+    /// Queues a key to arrive once machine time reaches `at` microseconds
+    pub fn schedule_key(&mut self, at: u64, key: i16) {
+	let index = self.script.iter().position(|&(t, _)| t > at).unwrap_or(self.script.len());
+	self.script.insert(index, (at, key));
+    }
+
+    /// Scripted keys the program hasn't read yet, including one waiting in KBDR
+    pub fn unread_keys(&self) -> usize {
+	self.script.len() + self.memory.keyboard_ready as usize
+    }
+
+    /// Keyboard interrupt: latches `data` as the key pressed and requests the interrupt
     pub fn interrupt(&mut self, code: u8, priority: u8, data: i16) -> Result<u8, &'static str> {
-	// check interrupt enable
-	if !(self.ie == 0b1) {
-	    return Err("Interrupt Enable is 0");
-	}
-	// check priority in psr
-	let prio = (self.psr >> 8) as u8 & 0b111;
-	if prio >= priority {
-	    return Err("Currently servicing a higher or equal priority task.");
-	}
-
-	// set keyboard input memory
-	self.memory.put(0xFE02, data);
-	// set keyboard ready
-	self.memory.keyboard_ready = true;
-
-	self.saved_usp = self.r6;
-	self.r6 = self.saved_ssp;
-	self.r6 = self.r6.wrapping_sub(1);
-	self.memory.put(self.r6 as u16, self.psr);
-	self.r6 = self.r6.wrapping_sub(1);
-	self.memory.put(self.r6 as u16, self.pc);
-	self.psr &= 0b0_111_1000_1111_1111;
-	self.psr |= (priority as i16 & 0b111) << 8;
-	self.pc = self.memory.get(0x100 as u16 + code as u16);
-	
-	Ok(priority)
+	self.memory.key_press(data);
+	self.request_interrupt(code, priority)
+    }
+
+    /// External interrupt through vector `x0100 + code`, taken straight away if it can be.
+    /// Otherwise it stays pending and the error says why.
+    pub fn request_interrupt(&mut self, code: u8, priority: u8) -> Result<u8, &'static str> {
+	self.post_interrupt(code, priority);
+	if self.ie != 0b1 {
+	    return Err("Interrupt Enable is 0, the request is pending");
+	}
+	match self.service_interrupts() {
+	    Some(request) if request.vector == code => Ok(request.priority),
+	    _ => Err("Currently servicing a higher or equal priority task, the request is pending")
+	}
+    }
+
+    /// Switches to the supervisor stack if coming from user mode
+    fn enter_supervisor(&mut self) {
+	if self.psr < 0 {
+	    self.saved_usp = self.r[6];
+	    self.r[6] = self.saved_ssp;
+	}
     }
 
     /// Internal exception
     fn exception(&mut self, code: u8) {
-	self.saved_usp = self.r6;
-	self.r6 = self.saved_ssp;
-	self.r6 = self.r6.wrapping_sub(1);
-	self.memory.put(self.r6 as u16, self.psr);
-	self.r6 = self.r6.wrapping_sub(1);
-	self.memory.put(self.r6 as u16, self.pc);
+	self.raised = Some(code);
+	let ret = self.pc as u16;
+	self.enter_service(0x100 + code as u16);
+	self.push_frame(Kind::Exception(code), ret);
+    }
+
+    /// Pushes PSR and PC onto the supervisor stack and jumps through the vector table entry
+    /// `vector` in supervisor mode, for exceptions and 3rd edition TRAPs
+    fn enter_service(&mut self, vector: u16) {
+	self.enter_supervisor();
+	self.r[6] = self.r[6].wrapping_sub(self.word_size());
+	self.memory.put(self.r[6] as u16, self.psr);
+	self.r[6] = self.r[6].wrapping_sub(self.word_size());
+	self.memory.put(self.r[6] as u16, self.pc);
 	self.psr &= 0b0_111_1111_1111_1111;
-	self.pc = self.memory.get(0x100 as u16  + code as u16);
+	self.pc = self.memory.get(self.vector_entry(vector));
     }
 
-    /// Takes a 3b register code and produces an exclusive ref to the proper register
-    fn reg(&mut self, code: i16) -> &mut i16 {
-	match code & 0b111 {
-	    0 => &mut self.r0,
-	    1 => &mut self.r1,
-	    2 => &mut self.r2,
-	    3 => &mut self.r3,
-	    4 => &mut self.r4,
-	    5 => &mut self.r5,
-	    6 => &mut self.r6,
-	    7 => &mut self.r7,
-	    _ => unreachable!()
+    /// Checks a memory access by the running program, raising the ACV exception (x02) if
+    /// it's a user mode access to protected memory
+    fn access_violation(&mut self, addr: u16) -> bool {
+	if self.psr < 0 && self.memory.is_protected(addr) {
+	    self.exception(0x02);
+	    return true;
 	}
+	false
+    }
+
+    /// Vector of the exception the last clock raised, if it raised one
+    pub fn raised(&self) -> Option<u8> {
+	self.raised
+    }
+
+    /// Snapshot of R0-R7
+    pub fn regs(&self) -> [i16; 8] {
+	self.r
     }
 
     /// Gets the value of a register based on its 3b code
-    fn get_reg(&mut self, code: i16) -> i16 {
-	*self.reg(code)
+    pub fn get_reg(&self, code: i16) -> i16 {
+	self.r[(code & 0b111) as usize]
     }
 
     /// Sets the value of a register based on its 3b code
-    fn put_reg(&mut self, code: i16, data: i16) {
-	*self.reg(code) = data
+    pub fn put_reg(&mut self, code: i16, data: i16) {
+	self.r[(code & 0b111) as usize] = data
     }
 
     /// Sets the NZP bits of the PSR
@@ -224,7 +495,7 @@ impl LC3 {
 	    self.put_reg(dr, res);
 	} else { // register
 	    let sr2 = instruction & 0b111;
-	    let res = self.get_reg(sr1) + self.get_reg(sr2);
+	    let res = self.get_reg(sr1).wrapping_add(self.get_reg(sr2));
 	    self.codes(res);
 	    self.put_reg(dr, res);
 	}
@@ -255,13 +526,14 @@ impl LC3 {
  	let p = self.psr & 0b1;
 
 	// requested condition codes
-	let i_n = (instruction >> 9) & 0b1;
+	let i_n = (instruction >> 11) & 0b1;
 	let i_z = (instruction >> 10) & 0b1;
-	let i_p = (instruction >> 11) & 0b1;
+	let i_p = (instruction >> 9) & 0b1;
 
 	// check if requested set bits match condition codes
 	if (i_n == 1 && n == 1) || (i_z == 1 && z == 1) || (i_p == 1 && p == 1) {
-	    self.pc = self.pc.wrapping_add(sign_extend(instruction & 0b111_111_111, 9));
+	    let offset = sign_extend(instruction & 0b111_111_111, 9).wrapping_mul(self.word_size());
+	    self.pc = self.pc.wrapping_add(offset);
 	}
     }
 
@@ -269,6 +541,7 @@ impl LC3 {
     fn jmp(&mut self, instruction: i16) {
 	let dest = self.get_reg((instruction >> 6) & 0b111);
 	self.pc = dest;
+	self.returned();
     }
 
     /// JSR / JSRR
@@ -276,17 +549,22 @@ impl LC3 {
 	let mode = (instruction >> 11) & 0b1;
 	let temp = self.pc;
 	if mode == 0b1 { // jsr
-	    self.pc = self.pc.wrapping_add(sign_extend(instruction & 0b11111111111, 11));
+	    let offset = sign_extend(instruction & 0b11111111111, 11).wrapping_mul(self.word_size());
+	    self.pc = self.pc.wrapping_add(offset);
 	} else { // jsrr
 	    self.pc = self.get_reg((instruction >> 6) & 0b111);
 	}
-	self.r7 = temp;
+	self.r[7] = temp;
+	self.push_frame(Kind::Subroutine, temp as u16);
     }
 
     /// LD
     fn ld(&mut self, instruction: i16) {
 	let dr = (instruction >> 9) & 0b111;
 	let addr = self.pc.wrapping_add(sign_extend(instruction & 0b111_111_111, 9));
+	if self.access_violation(addr as u16) {
+	    return;
+	}
 	let res = self.memory.get(addr as u16);
 	self.codes(res);
 	self.put_reg(dr, res);
@@ -296,18 +574,28 @@ impl LC3 {
     fn ldi(&mut self, instruction: i16) {
 	let dr = (instruction >> 9) & 0b111;
 	let addr = self.pc.wrapping_add(sign_extend(instruction & 0b111_111_111, 9));
+	if self.access_violation(addr as u16) {
+	    return;
+	}
 	let addr2 = self.memory.get(addr as u16);
+	if self.access_violation(addr2 as u16) {
+	    return;
+	}
 	let res = self.memory.get(addr2 as u16);
 	self.codes(res);
 	self.put_reg(dr, res);
     }
 
-    /// LDR
+    /// LDR, and LDW on the LC-3b
     fn ldr(&mut self, instruction: i16) {
 	let dr = (instruction >> 9) & 0b111;
 	let base_r = self.get_reg((instruction >> 6) & 0b111);
-	let offset = sign_extend(instruction & 0b111111, 6);
-	let res = self.memory.get((base_r.wrapping_add(offset)) as u16);
+	let offset = sign_extend(instruction & 0b111111, 6).wrapping_mul(self.word_size());
+	let addr = base_r.wrapping_add(offset) as u16;
+	if self.access_violation(addr) || self.unaligned(addr) {
+	    return;
+	}
+	let res = self.memory.get(addr);
 	self.codes(res);
 	self.put_reg(dr, res);
     }
@@ -334,14 +622,17 @@ impl LC3 {
 	let priv_bit = (self.psr >> 15) & 0b1;
 	if priv_bit == 0b0 { // ok
 	    // pop pc from supervisor stack
-	    self.pc = self.memory.get(self.r6 as u16);
-	    self.r6 = self.r6.wrapping_add(1);
+	    self.pc = self.memory.get(self.r[6] as u16);
+	    self.r[6] = self.r[6].wrapping_add(self.word_size());
 	    // pop psr from supervisor stack
-	    self.psr = self.memory.get(self.r6 as u16);
-	    self.r6 = self.r6.wrapping_add(1);
-	    // restore user stack ptr
-	    self.saved_ssp = self.r6;
-	    self.r6 = self.saved_usp;
+	    self.psr = self.memory.get(self.r[6] as u16);
+	    self.r[6] = self.r[6].wrapping_add(self.word_size());
+	    // back to the user stack if returning to user mode
+	    if self.psr < 0 {
+		self.saved_ssp = self.r[6];
+		self.r[6] = self.saved_usp;
+	    }
+	    self.returned();
 	} else { // not ok, priv exception
 	    self.exception(0);
 	}
@@ -351,6 +642,9 @@ impl LC3 {
     fn st(&mut self, instruction: i16) {
 	let sr = self.get_reg((instruction >> 9) & 0b111);
 	let addr = self.pc.wrapping_add(sign_extend(instruction & 0b111_111_111, 9));
+	if self.access_violation(addr as u16) {
+	    return;
+	}
 	self.memory.put(addr as u16, sr);
     }
 
@@ -358,23 +652,43 @@ impl LC3 {
     fn sti(&mut self, instruction: i16) {
 	let sr = self.get_reg((instruction >> 9) & 0b111);
 	let addr = self.pc.wrapping_add(sign_extend(instruction & 0b111_111_111, 9));
+	if self.access_violation(addr as u16) {
+	    return;
+	}
 	let addr2 = self.memory.get(addr as u16);
+	if self.access_violation(addr2 as u16) {
+	    return;
+	}
 	self.memory.put(addr2 as u16, sr);
     }
 
-    /// STR
+    /// STR, and STW on the LC-3b
     fn stor(&mut self, instruction: i16) {
 	let sr = self.get_reg((instruction >> 9) & 0b111);
 	let base_r = self.get_reg((instruction >> 6) & 0b111);
-	let addr = base_r.wrapping_add(sign_extend(instruction & 0b111111, 6));
+	let addr = base_r.wrapping_add(sign_extend(instruction & 0b111111, 6).wrapping_mul(self.word_size()));
+	if self.access_violation(addr as u16) || self.unaligned(addr as u16) {
+	    return;
+	}
 	self.memory.put(addr as u16, sr);
     }
 
     /// TRAP
     fn trap(&mut self, instruction: i16) {
-	self.r7 = self.pc;
 	let vector_index = instruction as u16 & 0b11111111;
-	self.pc = self.memory.get(vector_index);
+	if self.reset_trap == Some(vector_index as u8) {
+	    self.warm_reset();
+	    self.last_io = LC3IO::Reset;
+	    return;
+	}
+	let ret = self.pc as u16;
+	if self.legacy_traps {
+	    self.r[7] = self.pc;
+	    self.pc = self.memory.get(vector_index);
+	} else {
+	    self.enter_service(vector_index);
+	}
+	self.push_frame(Kind::Trap(vector_index as u8), ret);
     }
     
 }
@@ -397,41 +711,279 @@ fn sign_extend(value: i16, length: usize) -> i16 {
     out
 }
 
+impl Default for LC3Memory {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
 impl LC3Memory {
+    /// Zeroed memory with no key waiting and no mirrors
     pub fn new() -> Self {
 	Self {
 	    mem: [0; 65536],
 	    keyboard_ready: false,
-	    last_char: None
+	    last_char: None,
+	    display_delay: 0,
+	    display_until: 0,
+	    clock_time: 0,
+	    halt_requested: false,
+	    wait_requested: false,
+	    kbsr_poll: false,
+	    written: false,
+	    mirrors: Vec::new(),
+	    protected: Vec::new(),
+	    read_watches: Vec::new(),
+	    write_watches: Vec::new(),
+	    watch_hit: None,
+	    journal: None,
+	    access_hooks: Vec::new(),
+	    bus: Bus::new()
+	}
+    }
+
+    /// Makes `mirror..mirror + len` an alias of `base..base + len`
+    pub fn add_mirror(&mut self, base: u16, mirror: u16, len: u16) -> Result<(), &'static str> {
+	if len == 0 {
+	    return Err("Mirror length is 0");
+	}
+	if base.checked_add(len - 1).is_none() || mirror.checked_add(len - 1).is_none() {
+	    return Err("Mirror extends past 0xFFFF");
 	}
+	let overlaps = |a: u16, a_len: u16, b: u16, b_len: u16| {
+	    (a as u32) < b as u32 + b_len as u32 && (b as u32) < a as u32 + a_len as u32
+	};
+	if overlaps(base, len, mirror, len) {
+	    return Err("Mirror overlaps its own base range");
+	}
+	for m in &self.mirrors {
+	    if overlaps(m.mirror, m.len, mirror, len) {
+		return Err("Mirror overlaps an existing mirror");
+	    }
+	    // resolve() maps once, so neither side may land on another mirror
+	    if overlaps(m.mirror, m.len, base, len) {
+		return Err("Mirror base lies inside an existing mirror");
+	    }
+	    if overlaps(mirror, len, m.base, m.len) {
+		return Err("Mirror covers an existing mirror's base");
+	    }
+	}
+	self.mirrors.push(Mirror { base, mirror, len });
+	Ok(())
+    }
+
+    /// Removes every configured mirror
+    pub fn clear_mirrors(&mut self) {
+	self.mirrors.clear();
+    }
+
+    pub fn mirrors(&self) -> &[Mirror] {
+	&self.mirrors
+    }
+
+    /// Makes a range off limits to user mode, accesses raise an ACV exception
+    pub fn protect(&mut self, range: RangeInclusive<u16>) {
+	self.protected.push(range);
+    }
+
+    /// Protects the system space and device page, as the 3rd edition LC-3 does
+    pub fn protect_privileged(&mut self) {
+	for range in PRIVILEGED {
+	    self.protect(range);
+	}
+    }
+
+    /// Lifts all memory protection
+    pub fn clear_protection(&mut self) {
+	self.protected.clear();
+    }
+
+    pub fn protection(&self) -> &[RangeInclusive<u16>] {
+	&self.protected
+    }
+
+    /// Whether user mode is kept out of `addr`, or out of the memory it mirrors
+    pub fn is_protected(&self, addr: u16) -> bool {
+	let base = self.resolve(addr);
+	self.protected.iter().any(|r| r.contains(&addr) || r.contains(&base))
+    }
+
+    /// Maps an address through the mirror table to the address actually backing it
+    fn resolve(&self, index: u16) -> u16 {
+	for m in &self.mirrors {
+	    if index >= m.mirror && index - m.mirror < m.len {
+		return m.base + (index - m.mirror);
+	    }
+	}
+	index
+    }
+
+    /// Attaches a device to the bus so CPU accesses to its registers reach it
+    pub fn attach(&mut self, device: Box<dyn Device>) -> Result<(), &'static str> {
+	self.bus.attach(device)
+    }
+
+    pub fn bus(&mut self) -> &mut Bus {
+	&mut self.bus
+    }
+
+    /// Lets attached devices transfer to and from memory, with the bus set aside meanwhile
+    fn run_dma(&mut self) {
+	let mut bus = std::mem::take(&mut self.bus);
+	bus.dma(self);
+	self.bus = bus;
+    }
+
+    /// Microseconds of machine time before DSR reports ready again
+    pub fn display_busy(&self) -> u64 {
+	self.display_until.saturating_sub(self.clock_time)
+    }
+
+    /// Latches a key into KBDR and sets KBSR ready
+    pub fn key_press(&mut self, data: i16) {
+	self.mem[0xFE02] = data;
+	self.keyboard_ready = true;
+    }
+
+    /// Reads a word without the side effects of the device registers
+    pub fn peek(&self, index: u16) -> i16 {
+	let index = self.resolve(index);
+	if index >= self.bus.floor() {
+	    if let Some(value) = self.bus.peek(index) {
+		return value;
+	    }
+	}
+	self.mem[index as usize]
+    }
+
+    /// Whether `addr` is ordinary memory, with no device register or attached device on it
+    #[inline(always)]
+    pub(super) fn plain(&self, addr: u16) -> bool {
+	addr < self.bus.floor() || self.unclaimed(addr)
+    }
+
+    /// The rest of `plain()`, out of line since the fast path rarely gets this far
+    #[cold]
+    #[inline(never)]
+    fn unclaimed(&self, addr: u16) -> bool {
+	!(device::BUILTIN.contains(&addr) || self.bus.claims(addr))
     }
+
+    /// Reads a word the way the CPU does, including device register side effects
     pub fn get(&mut self, index: u16) -> i16 {
-	if index == 0xFE04 { // Display is always ready (?)
-	    return 0b1;
-	} else if index == 0xFE00 { // keyboard ready
-	    if self.keyboard_ready {
-		return 0b1;
-	    } else {
+	let value = self.read(index);
+	if !self.access_hooks.is_empty() {
+	    self.accessed(index, value, false);
+	}
+	value
+    }
+
+    fn read(&mut self, index: u16) -> i16 {
+	if self.read_watches.contains(&index) {
+	    self.watch_hit = self.watch_hit.or(Some(Watch::Read(index)));
+	}
+	let index = self.resolve(index);
+	if index < self.bus.floor() {
+	    return self.mem[index as usize];
+	}
+	if let Some(value) = self.bus.read(index) {
+	    return value;
+	}
+	match index {
+	    0xFE04 => return ((self.display_busy() == 0) as u16 * 0x8000) as i16, // ready bit 15, clear while a write is still going out
+	    0xFE00 if self.keyboard_ready => return 0x8000u16 as i16, // ready bit 15
+	    0xFE00 => {
+		self.kbsr_poll = true;
 		return 0b0;
 	    }
-	} else if index == 0xFE02 {
-	    self.keyboard_ready = false;
+	    0xFE02 => self.keyboard_ready = false,
+	    _ => ()
 	}
-	return self.mem[index as usize];
+	self.mem[index as usize]
     }
+
+    /// Writes a word the way the CPU does, triggering the display, MCR and WFI registers and
+    /// attached devices
     pub fn put(&mut self, index: u16, value: i16) {
+	if self.write_watches.contains(&index) {
+	    self.watch_hit = self.watch_hit.or(Some(Watch::Write(index)));
+	}
+	if !self.access_hooks.is_empty() {
+	    self.accessed(index, value, true);
+	}
 	// println!("put {:04x} @ {:04x}", value, index);
-	if index == 0xFE06 { // write here so cpu can check
-	    self.last_char = Some(value)
+	let index = self.resolve(index);
+	self.written = true;
+	if index >= self.bus.floor() && self.bus.write(index, value) {
+	    return;
+	}
+	match index {
+	    0xFE06 => {
+		self.last_char = Some(value); // write here so cpu can check
+		self.display_until = self.clock_time + self.display_delay as u64;
+	    }
+	    0xFFFE if value == 0b0 => self.halt_requested = true, // machine control register cleared
+	    0xFE10 => self.wait_requested = true, // wait for interrupt
+	    _ => ()
 	}
-	self.mem[index as usize % 65536] = value;
+	if let Some(journal) = self.journal.as_mut() {
+	    journal.push((index, self.mem[index as usize]));
+	}
+	self.mem[index as usize] = value;
+    }
+
+    /// Reads `len` words starting at `addr`, without device side effects
+    pub fn read_words(&self, addr: u16, len: usize) -> Vec<i16> {
+	(0..len).map(|i| self.peek(addr.wrapping_add(i as u16))).collect()
+    }
+
+    /// Writes consecutive words starting at `addr`
+    pub fn write_words(&mut self, addr: u16, words: &[i16]) {
+	for (i, word) in words.iter().enumerate() {
+	    self.put(addr.wrapping_add(i as u16), *word);
+	}
+    }
+
+    /// Reads a null-terminated string, one character per word
+    pub fn read_cstr(&self, addr: u16) -> String {
+	self.chars(addr, false).collect()
+    }
+
+    /// Reads a null-terminated PUTSP-style string, two characters per word, low byte first
+    pub fn read_packed_cstr(&self, addr: u16) -> String {
+	self.chars(addr, true).collect()
+    }
+
+    /// Writes a null-terminated string, one character per word
+    pub fn write_cstr(&mut self, addr: u16, text: &str) {
+	let words: Vec<i16> = text.bytes().map(|b| b as i16).chain(std::iter::once(0)).collect();
+	self.write_words(addr, &words);
+    }
+
+    /// Writes a null-terminated PUTSP-style string, two characters per word, low byte first
+    pub fn write_packed_cstr(&mut self, addr: u16, text: &str) {
+	let mut words: Vec<i16> = text.as_bytes().chunks(2)
+	    .map(|pair| (pair[0] as u16 | (*pair.get(1).unwrap_or(&0) as u16) << 8) as i16)
+	    .collect();
+	words.push(0);
+	self.write_words(addr, &words);
+    }
+
+    /// Characters of the string at `addr` up to the terminator, stopping after one pass over memory
+    pub fn chars(&self, addr: u16, packed: bool) -> impl Iterator<Item = char> + '_ {
+	(0..=0xFFFF).map(move |i| self.peek(addr.wrapping_add(i)) as u16)
+	    .flat_map(move |word| if packed { vec![word & 0xFF, word >> 8] } else { vec![word] })
+	    .take_while(|c| *c != 0)
+	    .map(|c| (c as u8) as char)
     }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use super::LC3;
+    use super::{LC3, LC3IO, LC3Memory};
+    use crate::fixtures::Fixture;
+    use super::batch::StopReason;
     use super::{mux, sign_extend};
     
     #[test]
@@ -441,8 +993,8 @@ mod tests {
 
     #[test]
     fn mux_test() {
-	assert_eq!(false, mux(0b0101000000000000));
-	assert_eq!(true, mux(0b0001000000100000));
+	assert!(!mux(0b0101000000000000));
+	assert!(mux(0b0001000000100000));
     }
 
     #[test]
@@ -462,21 +1014,29 @@ mod tests {
 	let mut lc3 = LC3::new();
 	lc3.memory.put(0x3000, 0b0001_001_010_1_01111); // ADD R1, R2, #1
 	lc3.pc = 0x3000;
-	lc3.r2 = 100;
+	lc3.r[2] = 100;
 	lc3.halted = false;
 	lc3.clock();
-	assert_eq!(lc3.r1, 100 + sign_extend(0b01111, 5));
+	assert_eq!(lc3.r[1], 100 + sign_extend(0b01111, 5));
 
 	// register
 	let mut lc3 = LC3::new();
 	lc3.memory.put(0x3000, 0b001_001_010_0_00_011); // ADD R1, R2, R3
 	lc3.pc = 0x3000;
-	lc3.r2 = 100;
-	lc3.r3 = -50;
+	lc3.r[2] = 100;
+	lc3.r[3] = -50;
 	lc3.halted = false;
 	lc3.clock();
-	assert_eq!(lc3.r1, 50);
-	assert_eq!(lc3.psr & 0b111, 0b001);
+	assert_regs!(lc3, r1 = 50);
+	assert_cc!(lc3, P);
+
+	// register overflow wraps
+	lc3.pc = 0x3000;
+	lc3.r[2] = 0x7FFF;
+	lc3.r[3] = 1;
+	lc3.clock();
+	assert_regs!(lc3, r1 = 0x8000);
+	assert_cc!(lc3, N);
     }
 
     #[test]
@@ -485,21 +1045,21 @@ mod tests {
 	let mut lc3 = LC3::new();
 	lc3.memory.put(0x3000, 0b0101_001_001_1_00000); // AND R1, R1, #0 ; zero R1
 	lc3.pc = 0x3000;
-	lc3.r1 = -1283;
+	lc3.r[1] = -1283;
 	lc3.halted = false;
 	lc3.clock();
-	assert_eq!(lc3.r1, 0);
-	assert_eq!(lc3.psr & 0b111, 0b010);
+	assert_regs!(lc3, r1 = 0);
+	assert_cc!(lc3, Z);
 	
 	// register
 	let mut lc3 = LC3::new();
 	lc3.memory.put(0x3000, 0b0101_001_001_0_00_001); // AND R1, R1, R1 ; do nothing
 	lc3.pc = 0x3000;
-	lc3.r1 = -1283;
+	lc3.r[1] = -1283;
 	lc3.halted = false;
 	lc3.clock();
-	assert_eq!(lc3.r1, -1283);
-	assert_eq!(lc3.psr & 0b111, 0b100);
+	assert_regs!(lc3, r1 = -1283);
+	assert_cc!(lc3, N);
     }
 
     #[test]
@@ -508,7 +1068,7 @@ mod tests {
 	lc3.memory.put(0x3000, 0b0101_001_001_1_00000); // AND R1, R1, #0 ; zero R1                      
 	lc3.memory.put(0x3001, 0b0000_110_000000101); // BRnz #5
 	lc3.pc = 0x3000;                                                                                 
-        lc3.r1 = -1283;                                                                                  
+	lc3.r[1] = -1283;                                                                                  
         lc3.halted = false;                                                                              
         lc3.clock();
 	// Z should now be set
@@ -521,7 +1081,7 @@ mod tests {
 	let mut lc3 = LC3::new();
         lc3.memory.put(0x3000, 0b1100_000_010_000000); // JMP R2
 	lc3.pc = 0x3000;
-	lc3.r2 = 0x3500;
+	lc3.r[2] = 0x3500;
 	lc3.halted = false;
 	lc3.clock();
 	assert_eq!(lc3.pc, 0x3500);
@@ -536,119 +1096,94 @@ mod tests {
 	lc3.halted = false;
 	lc3.clock();
 	assert_eq!(lc3.pc, 0x2FFF);
-	assert_eq!(lc3.r7, 0x3001); // check for saved pc
+	assert_eq!(lc3.r[7], 0x3001); // check for saved pc
 
 	// JSRR
 	let mut lc3 = LC3::new();
 	lc3.memory.put(0x3000, 0b0100_0_00_010_000000); // JSRR R2
 	lc3.pc = 0x3000;
-	lc3.r2 = 0xB33F;
+	lc3.r[2] = 0xB33F;
 	lc3.halted = false;
 	lc3.clock();
 	assert_eq!(lc3.pc, 0xB33F);
-	assert_eq!(lc3.r7, 0x3001);
+	assert_eq!(lc3.r[7], 0x3001);
     }
 
     #[test]
     fn ld_test() {
-	let mut lc3 = LC3::new();
-	lc3.memory.put(0x3002, 0xB773);
-	lc3.memory.put(0x3000, 0b0010_010_000000001);
-	lc3.pc = 0x3000;
-	lc3.halted = false;
+	let mut lc3 = Fixture::bare().code(&[0b0010_010_000000001]).data(0x3002, &[0xB773]).build();
 	lc3.clock();
-	assert_eq!(lc3.r2, 0xB773);
+	assert_eq!(lc3.r[2], 0xB773);
     }
 
     #[test]
     fn ldi_test() {
-	let mut lc3 = LC3::new();
-	lc3.memory.put(0x3002, 0xF33D);
-	lc3.memory.put(0xF33D, 0xB33F);
-	lc3.memory.put(0x3000, 0b1010_010_000000001);
-	lc3.pc = 0x3000;
-	lc3.halted = false;
+	let mut lc3 = Fixture::bare()
+	    .code(&[0b1010_010_000000001])
+	    .data(0x3002, &[0xF33D])
+	    .data(0xF33D, &[0xB33F])
+	    .build();
 	lc3.clock();
-	assert_eq!(lc3.r2, 0xB33F);
+	assert_eq!(lc3.r[2], 0xB33F);
     }
 
     #[test]
     fn ldr_test() {
-	let mut lc3 = LC3::new();
-	lc3.memory.put(0x3000, 0b0110_010_001_000001);
-	lc3.memory.put(0x5001, 0x5372);
-	lc3.pc = 0x3000;
-	lc3.r1 = 0x5000;
-	lc3.halted = false;
+	let mut lc3 = Fixture::bare().code(&[0b0110_010_001_000001]).data(0x5001, &[0x5372]).reg(1, 0x5000).build();
 	lc3.clock();
-	assert_eq!(lc3.r2, 0x5372);
+	assert_eq!(lc3.r[2], 0x5372);
     }
 
     #[test]
     fn lea_test() {
-	let mut lc3 = LC3::new();
-	lc3.memory.put(0x3000, 0b1110_010_000000010);
-	lc3.pc = 0x3000;
-	lc3.halted = false;
+	let mut lc3 = Fixture::bare().code(&[0b1110_010_000000010]).build();
 	lc3.clock();
-	assert_eq!(lc3.r2, 0x3003);
+	assert_eq!(lc3.r[2], 0x3003);
     }
 
     #[test]
     fn not_test() {
-	let mut lc3 = LC3::new();
-	lc3.memory.put(0x3000, 0b1001_010_001_1_11111);
-	lc3.pc = 0x3000;
-	lc3.r1 = 0b1010101010101010;
-	lc3.halted = false;
+	let mut lc3 = Fixture::bare().code(&[0b1001_010_001_1_11111]).reg(1, 0b1010101010101010).build();
 	lc3.clock();
-	assert_eq!(lc3.r2, 0b0101010101010101);
+	assert_eq!(lc3.r[2], 0b0101010101010101);
     }
 
     #[test]
     fn st_test() {
-	let mut lc3 = LC3::new();
-	lc3.memory.put(0x3000, 0b0011_010_000000001);
-	lc3.pc = 0x3000;
-	lc3.r2 = 0x2534;
-	lc3.halted = false;
+	let mut lc3 = Fixture::bare().code(&[0b0011_010_000000001]).reg(2, 0x2534).build();
 	lc3.clock();
 	assert_eq!(lc3.memory.get(0x3002), 0x2534);
     }
 
     #[test]
     fn sti_test() {
-	let mut lc3 = LC3::new();
-	lc3.memory.put(0x3000, 0b1011_010_000000001);
-	lc3.memory.put(0x3002, 0x5000);
-	lc3.pc = 0x3000;
-	lc3.r2 = 0xB33F;
-	lc3.halted = false;
+	let mut lc3 = Fixture::bare().code(&[0b1011_010_000000001]).data(0x3002, &[0x5000]).reg(2, 0xB33F).build();
 	lc3.clock();
 	assert_eq!(lc3.memory.get(0x5000), 0xB33F)
     }
 
     #[test]
     fn stor_test() {
-	let mut lc3 = LC3::new();
-	lc3.memory.put(0x3000, 0b0111_010_001_000001);
-	lc3.pc = 0x3000;
-	lc3.r2 = 0xFEED;
-	lc3.r1 = 0x0001;
-	lc3.halted = false;
+	let mut lc3 = Fixture::bare().code(&[0b0111_010_001_000001]).reg(2, 0xFEED).reg(1, 0x0001).build();
 	lc3.clock();
 	assert_eq!(lc3.memory.get(0x0002), 0xFEED);
     }
 
     #[test]
     fn trap_test() {
-	let mut lc3 = LC3::new();
-	lc3.memory.put(0x3000, 0b1111_0000_00000001);
-	lc3.memory.put(0x0001, 0x1337);
-	lc3.pc = 0x3000;
-	lc3.halted = false;
+	let mut lc3 = Fixture::bare().code(&[0b1111_0000_00000001]).data(0x0001, &[0x1337]).reg(6, 0x2F00).build();
+	lc3.clock();
+	assert_eq!(lc3.pc, 0x1337);
+	assert_eq!(lc3.r[6], 0x2EFE);
+	assert_eq!(lc3.memory.peek(0x2EFE), 0x3001);
+	assert_eq!(lc3.r[7], 0);
+
+	// pre-3rd edition
+	let mut lc3 = Fixture::bare().code(&[0b1111_0000_00000001]).data(0x0001, &[0x1337]).build();
+	lc3.legacy_traps = true;
 	lc3.clock();
 	assert_eq!(lc3.pc, 0x1337);
+	assert_regs!(lc3, r7 = 0x3001);
     }
 
     #[test]
@@ -667,10 +1202,43 @@ mod tests {
 	lc3.clock();
 	lc3.clock();
 	println!("after: {:#?}", lc3);
-	assert_eq!(lc3.r0, 1);
+	assert_eq!(lc3.r[0], 1);
 	assert_eq!(lc3.memory.get(0x3000 - 2), 0x3001);
     }
 
+    #[test]
+    fn acv_test() {
+	let program = [
+	    0b0010_000_000000010, // LD R0, [PC + 2]
+	    0b1011_000_000000010, // STI R0, [PC + 2]
+	    0b0001_001_001_1_00001, // ADD R1, R1, #1
+	    0x1234,
+	    0x0200 // into the OS
+	];
+	let mut lc3 = Fixture::bare().code(&program).data(0x0102, &[0x1000]).build();
+	lc3.psr |= 0b1 << 15; // user mode
+	lc3.saved_ssp = 0x3000;
+	lc3.clock();
+	lc3.clock();
+	assert_eq!(lc3.memory.peek(0x0200), 0x1234); // unprotected by default
+	lc3.memory.protect_privileged();
+	lc3.pc = 0x3001;
+	lc3.memory.put(0x0200, 0);
+	lc3.clock();
+	assert_eq!(lc3.pc, 0x1000); // ACV handler
+	assert_eq!(lc3.memory.peek(0x0200), 0);
+	assert_eq!(lc3.memory.peek(0x2FFE), 0x3002); // return address
+	assert!(lc3.psr >= 0);
+	// user mode fetches from the OS fault too
+	let mut lc3 = Fixture::bare().data(0x0102, &[0x1000]).pc(0x0200).build();
+	lc3.psr |= 0b1 << 15;
+	lc3.saved_ssp = 0x3000;
+	lc3.memory.protect(0x0000..=0x2FFF);
+	lc3.clock();
+	assert_eq!(lc3.pc, 0x1000);
+	assert_eq!(lc3.memory.peek(0x2FFE), 0x0200);
+    }
+
     #[test]
     fn interrupt_test() {
 	let mut lc3 = LC3::new();
@@ -687,4 +1255,169 @@ mod tests {
 	// panic!();
 	assert_eq!(lc3.memory.get(0xFE02), 'A' as i16);
     }
+
+    #[test]
+    fn string_test() {
+	let mut memory = LC3Memory::new();
+	memory.write_cstr(0x4000, "hi");
+	assert_eq!(memory.read_words(0x4000, 3), vec![0x68, 0x69, 0]);
+	assert_eq!(memory.read_cstr(0x4000), "hi");
+	memory.write_packed_cstr(0x4000, "abc");
+	assert_eq!(memory.read_words(0x4000, 3), vec![0x6261, 0x63, 0]);
+	assert_eq!(memory.read_packed_cstr(0x4000), "abc");
+	memory.write_words(0xFFFF, &[1, 2]);
+	assert_eq!(memory.read_words(0xFFFF, 2), vec![1, 2]);
+    }
+
+    #[test]
+    fn mirror_test() {
+	let mut lc3 = LC3::new();
+	lc3.memory.add_mirror(0x4000, 0x8000, 0x10).expect("Failed to mirror");
+	lc3.memory.put(0x8003, 0x1234); // write through the mirror
+	assert_eq!(lc3.memory.get(0x4003), 0x1234);
+	lc3.memory.put(0x400F, 0x4321); // write to the base
+	assert_eq!(lc3.memory.get(0x800F), 0x4321);
+	assert_eq!(lc3.memory.get(0x8010), 0); // past the end of the mirror
+	assert!(lc3.memory.add_mirror(0x4000, 0x4008, 0x10).is_err());
+	assert!(lc3.memory.add_mirror(0x5000, 0x8008, 0x10).is_err());
+	assert!(lc3.memory.add_mirror(0x5000, 0xFFF8, 0x10).is_err());
+	assert!(lc3.memory.add_mirror(0x8004, 0x9000, 0x10).is_err()); // base inside x4000's mirror
+	assert!(lc3.memory.add_mirror(0x5000, 0x3FF8, 0x10).is_err()); // window covers x4000
+	assert_eq!(lc3.memory.mirrors().len(), 1);
+    }
+
+    #[test]
+    fn mirror_order_test() {
+	// a mirror of a mirror, added in either order, is refused rather than half resolved
+	let mut lc3 = LC3::new();
+	lc3.memory.add_mirror(0x5000, 0x6000, 0x10).expect("Failed to mirror");
+	assert!(lc3.memory.add_mirror(0x6000, 0x7000, 0x10).is_err());
+	let mut lc3 = LC3::new();
+	lc3.memory.add_mirror(0x6000, 0x7000, 0x10).expect("Failed to mirror");
+	assert!(lc3.memory.add_mirror(0x5000, 0x6000, 0x10).is_err());
+	lc3.memory.add_mirror(0x5000, 0x8000, 0x10).expect("Failed to mirror"); // unrelated ranges still work
+	lc3.memory.put(0x8001, 7);
+	assert_eq!(lc3.memory.get(0x5001), 7);
+    }
+
+    #[test]
+    fn reset_test() {
+	let mut lc3 = LC3::new();
+	lc3.memory.put(0x3000, 0b0001_000_000_1_00101); // ADD R0, R0, #5
+	lc3.memory.put(0x3001, 0b0001_110_110_1_11111); // ADD R6, R6, #-1
+	lc3.memory.put(0x3002, 0b1111_0000_00100110);   // TRAP 0x26
+	lc3.pc = 0x3000;
+	lc3.r[6] = 0xFE00;
+	lc3.reset_trap = Some(0x26);
+	lc3.start();
+	lc3.clock();
+	lc3.clock();
+	assert_eq!(lc3.r[6], 0xFDFF);
+	match lc3.clock() {
+	    LC3IO::Reset => (),
+	    other => panic!("expected a reset, got {:?}", other)
+	}
+	assert_eq!(lc3.pc, 0x3000);
+	assert_eq!(lc3.r[6], 0xFE00);
+	assert_eq!(lc3.psr & 0b111, 0);
+	assert_eq!(lc3.r[0], 5); // registers and memory survive
+	assert_eq!(lc3.memory.get(0x3002), 0b1111_0000_00100110);
+	assert!(!lc3.halted);
+    }
+
+    #[test]
+    fn wfi_test() {
+	let mut lc3 = LC3::new();
+	lc3.memory.put(0x3000, 0b1011_000_000000001); // STI R0, [PC + 1] ; sleep
+	lc3.memory.put(0x3001, 0b0001_001_001_1_00001); // ADD R1, R1, #1
+	lc3.memory.put(0x3002, 0xFE10);
+	lc3.memory.put(0x100 + 0x80, 0x1200); // interrupt handler
+	lc3.pc = 0x3000;
+	lc3.saved_ssp = 0x3000;
+	lc3.start();
+	match lc3.clock() {
+	    LC3IO::Idle => (),
+	    other => panic!("expected idle, got {:?}", other)
+	}
+	lc3.clock();
+	assert_eq!(lc3.pc, 0x3001);
+	assert_eq!(lc3.r[1], 0);
+	lc3.interrupt(0x80, 4, 'A' as i16).expect("Failed to interrupt");
+	assert!(!lc3.sleeping);
+	assert_eq!(lc3.pc, 0x1200);
+    }
+
+    #[test]
+    fn busy_wait_test() {
+	let mut lc3 = LC3::new();
+	lc3.memory.put(0x3000, 0b1010_000_000000010); // LDI R0, [PC + 2] ; KBSR
+	lc3.memory.put(0x3001, 0b0000_010_111111110); // BRz PC - 2
+	lc3.memory.put(0x3002, 0b0000_111_000000001); // BR PC + 1
+	lc3.memory.put(0x3003, 0xFE00);
+	lc3.memory.put(0x3004, 0b1010_000_000000001); // LDI R0, [PC + 1] ; KBDR
+	lc3.memory.put(0x3006, 0xFE02);
+	lc3.pc = 0x3000;
+	lc3.start();
+	let mut cycles = 0;
+	while let LC3IO::None = lc3.clock() {
+	    cycles += 1;
+	    assert!(cycles < 1000, "polling loop never reported idle");
+	}
+	assert!(cycles >= 2 * 15);
+	lc3.memory.key_press('k' as i16);
+	for _ in 0..5 {
+	    if lc3.pc == 0x3005 {
+		break;
+	    }
+	    lc3.clock();
+	}
+	assert_eq!(lc3.pc, 0x3005);
+	assert_eq!(lc3.r[0], 'k' as i16);
+    }
+
+    #[test]
+    fn kbsr_test() {
+	let mut lc3 = Fixture::bare()
+	    .code(&[
+		0b1010_000_000000010, // LDI R0, [PC + 2] ; KBSR
+		0b0000_011_111111110, // BRzp PC - 2
+		0b0000_111_000000001, // BR PC + 1
+		0xFE00u16 as i16,
+		0b1010_000_000000001, // LDI R0, [PC + 1] ; KBDR
+		0b0000_111_111111111, // BR PC - 1
+		0xFE02u16 as i16
+	    ])
+	    .build();
+	assert_eq!(lc3.run_steps(1000), StopReason::Idle);
+	lc3.schedule_key(lc3.ticks, 'q' as i16);
+	assert_eq!(lc3.run_steps(1000), StopReason::Limit);
+	assert_eq!((lc3.pc, lc3.r[0]), (0x3005, 'q' as i16));
+	assert!(!lc3.memory.keyboard_ready);
+    }
+
+    #[test]
+    fn fast_forward_test() {
+	let mut lc3 = LC3::new();
+	lc3.memory.put(0x3000, 0b1010_000_000000010); // LDI R0, [PC + 2] ; KBSR
+	lc3.memory.put(0x3001, 0b0000_010_111111110); // BRz PC - 2
+	lc3.memory.put(0x3002, 0b0000_111_000000001); // BR PC + 1
+	lc3.memory.put(0x3003, 0xFE00);
+	lc3.memory.put(0x3004, 0b1010_000_000000001); // LDI R0, [PC + 1] ; KBDR
+	lc3.memory.put(0x3005, 0b1111_0000_00100101); // TRAP 0x25
+	lc3.memory.put(0x3006, 0xFE02);
+	lc3.pc = 0x3000;
+	lc3.fast_forward = true;
+	lc3.schedule_key(1_000_000, 'z' as i16);
+	lc3.start();
+	for _ in 0..200 {
+	    if lc3.pc == 0x3005 {
+		break;
+	    }
+	    lc3.clock();
+	}
+	assert_eq!(lc3.pc, 0x3005);
+	assert_eq!(lc3.r[0], 'z' as i16);
+	assert!(lc3.ticks >= 1_000_000);
+	assert!(lc3.instructions >= 1_000_000);
+    }
 }
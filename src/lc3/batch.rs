@@ -0,0 +1,163 @@
+//! Running the machine many instructions per call, with the reason it stopped as a value.
+//! Console output is buffered on the machine instead of coming back one character at a time.
+
+use super::debug::Watch;
+use super::{LC3, LC3IO};
+
+/// Why `run_steps()` gave control back
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StopReason {
+    Halted,
+    Idle, // KBSR polled with no key waiting, or asleep with nothing to wake it
+    Reset,
+    Breakpoint(u16), // stopped before the instruction at this address, running again runs it
+    Watch(Watch), // after an instruction that tripped a watchpoint
+    Exception(u8), // raised with no handler in the IVT
+    Limit // instruction budget used up
+}
+
+impl LC3 {
+    /// Clocks up to `n` times, stopping early when the machine halts, resets, goes idle, hits
+    /// a breakpoint or watchpoint or raises an exception with no handler. A breakpoint that
+    /// was just reported doesn't stop the next call again, so calling again continues from
+    /// it. Characters written to the display are kept for `take_output()`.
+    pub fn run_steps(&mut self, n: u64) -> StopReason {
+	let mut left = n;
+	while left > 0 {
+	    if let Some(watch) = self.watch_hit.take() {
+		return StopReason::Watch(watch);
+	    }
+	    if self.halted {
+		return StopReason::Halted;
+	    }
+	    if self.resume_at.is_none() {
+		left -= self.run_fast(left); // up to the next breakpoint or instruction it can't run
+		if left == 0 {
+		    break;
+		}
+	    }
+	    let pc = self.pc as u16;
+	    let resuming = self.resume_at.take() == Some(pc);
+	    if !self.sleeping && !resuming && self.breakpoint_hit(pc) {
+		self.resume_at = Some(pc);
+		return StopReason::Breakpoint(pc);
+	    }
+	    left -= 1;
+	    let regs = if self.reg_watches.is_empty() { None } else { Some(self.regs()) };
+	    let io = self.cycle();
+	    self.watch_hit = self.memory.watch_hit.take().or_else(|| {
+		let after = self.regs();
+		regs.and_then(|regs| self.reg_watches.iter().find(|r| regs[**r as usize] != after[**r as usize]).map(|r| Watch::Reg(*r)))
+	    });
+	    if let LC3IO::Display(c) = io {
+		if self.sink.is_none() {
+		    self.output.push(c as u8);
+		}
+	    }
+	    if let Some(vector) = self.raised {
+		if self.memory.peek(0x100 + vector as u16) == 0 {
+		    return StopReason::Exception(vector);
+		}
+	    }
+	    match io {
+		LC3IO::None | LC3IO::Display(_) => (),
+		LC3IO::Halt => return StopReason::Halted,
+		LC3IO::Idle => return StopReason::Idle,
+		LC3IO::Reset => return StopReason::Reset
+	    }
+	}
+	match self.watch_hit.take() {
+	    Some(watch) => StopReason::Watch(watch),
+	    None => StopReason::Limit
+	}
+    }
+
+    /// Runs until the machine halts, goes idle or stops for a breakpoint or exception,
+    /// carrying on through warm resets
+    pub fn run_until_halt(&mut self) -> StopReason {
+	loop {
+	    match self.run_steps(u64::MAX) {
+		StopReason::Reset | StopReason::Limit => (),
+		stop => return stop
+	    }
+	}
+    }
+
+    /// Display output buffered by `run_steps()` since the last call, empty while a sink is
+    /// attached
+    pub fn take_output(&mut self) -> Vec<u8> {
+	std::mem::take(&mut self.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StopReason;
+    use crate::lc3::debug::Breakpoint;
+    use crate::fixtures::Fixture;
+
+    #[test]
+    fn run_steps_test() {
+	let mut lc3 = Fixture::with_os()
+	    .code(&[
+		0b0001_001_001_1_00001, // ADD R1, R1, #1
+		0b1110_000_000000010, // LEA R0, x3004
+		0b1111_0000_00100010, // PUTS
+		0b1111_0000_00100101 // HALT
+	    ])
+	    .string(0x3004, "ok")
+	    .build();
+	assert_eq!(lc3.run_steps(1), StopReason::Limit);
+	assert_eq!((lc3.r[1], lc3.instructions), (1, 1));
+	assert!(lc3.take_output().is_empty());
+	assert_eq!(lc3.run_until_halt(), StopReason::Halted);
+	assert_eq!(lc3.take_output(), b"ok\n----- Halting the processor -----\n");
+	assert!(lc3.take_output().is_empty());
+	assert_eq!(lc3.run_until_halt(), StopReason::Halted);
+    }
+
+    #[test]
+    fn idle_test() {
+	let mut lc3 = Fixture::with_os()
+	    .code(&[
+		0b1111_0000_00100000, // GETC
+		0b0001_001_000_1_00000, // ADD R1, R0, #0
+		0b1111_0000_00100101 // HALT
+	    ])
+	    .build();
+	assert_eq!(lc3.run_until_halt(), StopReason::Idle);
+	lc3.memory.key_press('y' as i16);
+	assert_eq!(lc3.run_until_halt(), StopReason::Halted);
+	assert_eq!(lc3.r[1], 'y' as i16);
+    }
+
+    #[test]
+    fn step_test() {
+	let mut lc3 = Fixture::with_os()
+	    .code(&[
+		0b0101_000_000_1_00000, // AND R0, R0, #0
+		0b0001_000_000_1_01111, // ADD R0, R0, #15
+		0b0001_000_000_1_01111, // ADD R0, R0, #15
+		0b0001_000_000_1_00011, // ADD R0, R0, #3
+		0b1111_0000_00100001 // OUT
+	    ])
+	    .build();
+	lc3.add_breakpoint(Breakpoint { addr: 0x3001, when: None });
+	assert_eq!(lc3.run_steps(1), StopReason::Limit);
+	assert_eq!(lc3.run_steps(1), StopReason::Breakpoint(0x3001));
+	assert_eq!(lc3.run_steps(1), StopReason::Limit);
+	assert_eq!(lc3.run_steps(100), StopReason::Limit);
+	assert_eq!(lc3.take_output(), b"!");
+    }
+
+    #[test]
+    fn exception_test() {
+	let mut lc3 = Fixture::bare().code(&[0b1101_0000_0000_0000]).build(); // reserved opcode
+	lc3.saved_ssp = 0x3000;
+	assert_eq!(lc3.run_steps(10), StopReason::Exception(1));
+	let mut lc3 = Fixture::bare().code(&[0b1101_0000_0000_0000]).data(0x0101, &[0x0200]).build();
+	lc3.saved_ssp = 0x3000;
+	assert_eq!(lc3.run_steps(1), StopReason::Limit); // handled
+	assert_eq!(lc3.pc, 0x0200);
+    }
+}
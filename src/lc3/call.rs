@@ -0,0 +1,127 @@
+//! Calling a single subroutine, the way a unit test of one student routine wants to: set the
+//! arguments, run until it returns, and see what it left in registers and memory.
+
+use super::{LC3, LC3IO, Reg};
+
+/// Return address handed to the routine in R7. It's an unused trap vector, so nothing
+/// legitimately executes there.
+pub const RETURN: u16 = 0x00FF;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Limits {
+    pub instructions: u64
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+	Limits { instructions: 1_000_000 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Returned,
+    Halted,
+    Fault(&'static str),
+    Waiting, // blocked on keyboard input nobody will type
+    TimedOut
+}
+
+#[derive(Debug, Clone)]
+pub struct CallResult {
+    pub outcome: Outcome,
+    pub regs: [i16; 8],
+    pub instructions: u64,
+    pub writes: Vec<(u16, i16, i16)>, // address, old value, new value
+    pub output: String
+}
+
+impl CallResult {
+    pub fn reg(&self, reg: Reg) -> i16 {
+	self.regs[reg as usize]
+    }
+}
+
+impl LC3 {
+    /// Runs the subroutine at `addr` with the given registers, R7 set to `RETURN`, until
+    /// it returns, halts, faults, blocks on input or runs out of instructions
+    pub fn call_subroutine(&mut self, addr: u16, args: &[(Reg, i16)], limits: Limits) -> CallResult {
+	for (reg, value) in args {
+	    self.put_reg(*reg as i16, *value);
+	}
+	self.r[7] = RETURN as i16;
+	self.pc = addr as i16;
+	self.halted = false;
+	self.sleeping = false;
+	let before = self.memory.mem.to_vec();
+	let start = self.instructions;
+	let mut output = String::new();
+	let mut outcome = Outcome::TimedOut;
+	while self.instructions - start < limits.instructions {
+	    let user = self.psr < 0;
+	    match self.clock() {
+		LC3IO::Display(c) => output.push((c as u8) as char),
+		LC3IO::Halt => {
+		    outcome = Outcome::Halted;
+		    break;
+		}
+		LC3IO::Idle => {
+		    outcome = Outcome::Waiting;
+		    break;
+		}
+		_ => ()
+	    }
+	    let op = self.last_instruction.map(|(_, word)| word as u16 >> 12);
+	    if op == Some(0b1101) {
+		outcome = Outcome::Fault("Illegal opcode");
+		break;
+	    }
+	    if op == Some(0b1000) && user {
+		outcome = Outcome::Fault("Privilege violation");
+		break;
+	    }
+	    if self.pc as u16 == RETURN {
+		outcome = Outcome::Returned;
+		break;
+	    }
+	}
+	let writes = (0..=0xFFFF)
+	    .filter(|&a| before[a] != self.memory.mem[a])
+	    .map(|a| (a as u16, before[a], self.memory.mem[a]))
+	    .collect();
+	CallResult { outcome, regs: self.regs(), instructions: self.instructions - start, writes, output }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Limits, Outcome};
+    use crate::fixtures::Fixture;
+    use crate::lc3::Reg;
+
+    #[test]
+    fn call_test() {
+	let mut lc3 = Fixture::bare()
+	    .at(0x4000)
+	    .code(&[
+		0b0001_000_000_0_00_001, // ADD R0, R0, R1
+		0b0011_000_000000001, // ST R0, x4003
+		0b1100_000_111_000000 // RET
+	    ])
+	    .build();
+	let result = lc3.call_subroutine(0x4000, &[(Reg::R0, 40), (Reg::R1, 2)], Limits::default());
+	assert_eq!(result.outcome, Outcome::Returned);
+	assert_eq!(result.reg(Reg::R0), 42);
+	assert_eq!(result.instructions, 3);
+	assert_eq!(result.writes, vec![(0x4003, 0, 42)]);
+
+	let mut lc3 = Fixture::bare().at(0x4000).code(&[0b0000_111_111111111]).build(); // BRnzp to itself
+	let result = lc3.call_subroutine(0x4000, &[], Limits { instructions: 100 });
+	assert_eq!(result.outcome, Outcome::TimedOut);
+	assert_eq!(result.instructions, 100);
+
+	let mut lc3 = Fixture::bare().at(0x4000).code(&[0xD000]).build();
+	let result = lc3.call_subroutine(0x4000, &[], Limits::default());
+	assert_eq!(result.outcome, Outcome::Fault("Illegal opcode"));
+    }
+}
@@ -0,0 +1,178 @@
+//! Where display output goes: an attached sink gets every character written to DDR as the
+//! clock that wrote it finishes, so hosts don't have to route `LC3IO::Display` themselves
+
+use super::LC3;
+
+use std::io::{self, Write};
+
+pub enum Sink {
+    Writer(Box<dyn Write>),
+    Callback(Box<dyn FnMut(u8)>)
+}
+
+impl std::fmt::Debug for Sink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	match self {
+	    Sink::Writer(_) => write!(f, "Writer"),
+	    Sink::Callback(_) => write!(f, "Callback")
+	}
+    }
+}
+
+impl Sink {
+    fn write(&mut self, c: u8) {
+	match self {
+	    Sink::Writer(out) => {
+		out.write_all(&[c]).ok(); // the program can't be told, like a disconnected terminal
+	    }
+	    Sink::Callback(callback) => callback(c)
+	}
+    }
+}
+
+impl LC3 {
+    /// Sends display output to `out`. Characters are still reported by `clock()`, but
+    /// `run_steps()` no longer buffers them.
+    pub fn output_to(&mut self, out: impl Write + 'static) {
+	self.sink = Some(Sink::Writer(Box::new(out)));
+    }
+
+    /// Calls `callback` with every character written to the display
+    pub fn on_display(&mut self, callback: impl FnMut(u8) + 'static) {
+	self.sink = Some(Sink::Callback(Box::new(callback)));
+    }
+
+    /// Goes back to reporting output only through `clock()` and `run_steps()`
+    pub fn detach_output(&mut self) -> Option<Sink> {
+	self.sink.take()
+    }
+
+    /// Flushes a writer attached with `output_to()`
+    pub fn flush_output(&mut self) -> io::Result<()> {
+	match self.sink.as_mut() {
+	    Some(Sink::Writer(out)) => out.flush(),
+	    _ => Ok(())
+	}
+    }
+
+    pub(super) fn display(&mut self, c: i16) {
+	if let Some(sink) = self.sink.as_mut() {
+	    sink.write(c as u8);
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::Fixture;
+    use crate::lc3::batch::StopReason;
+    use crate::lc3::LC3IO;
+    use crate::lc3::time::InstructionTime;
+
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    /// A writer tests can still read after handing it over
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+	    self.0.borrow_mut().extend_from_slice(buf);
+	    Ok(buf.len())
+	}
+	fn flush(&mut self) -> io::Result<()> {
+	    Ok(())
+	}
+    }
+
+    #[test]
+    fn sink_test() {
+	let mut lc3 = Fixture::with_os()
+	    .code(&[
+		0b1110_000_000000010, // LEA R0, x3003
+		0b1111_0000_00100010, // PUTS
+		0b1111_0000_00100101 // HALT
+	    ])
+	    .string(0x3003, "hi")
+	    .build();
+	lc3.memory.display_delay = 3; // the OS waits on DSR between characters
+	let out = Shared::default();
+	lc3.output_to(out.clone());
+	assert_eq!(lc3.run_until_halt(), StopReason::Halted);
+	assert!(out.0.borrow().starts_with(b"hi\n"));
+	assert!(lc3.take_output().is_empty());
+	assert!(lc3.detach_output().is_some());
+    }
+
+    #[test]
+    fn display_delay_test() {
+	let mut lc3 = Fixture::bare()
+	    .code(&[
+		0b1011_000_000000100, // STI R0, [DDR]
+		0b1010_001_000000010, // LDI R1, [DSR]
+		0b1010_001_000000001, // LDI R1, [DSR]
+		0b0000_000_000000000, // NOP
+		0xFE04u16 as i16,
+		0xFE06u16 as i16
+	    ])
+	    .build();
+	let seen = Rc::new(RefCell::new(Vec::new()));
+	let log = seen.clone();
+	lc3.on_display(move |c| log.borrow_mut().push(c));
+	lc3.memory.display_delay = 2;
+	lc3.r[0] = 'x' as i16;
+	assert!(matches!(lc3.clock(), LC3IO::Display(0x78)));
+	assert_eq!(*seen.borrow(), b"x");
+	lc3.clock();
+	assert_eq!(lc3.r[1], 0); // busy
+	lc3.clock();
+	assert_eq!(lc3.r[1], 0x8000u16 as i16); // ready again
+    }
+
+    #[test]
+    fn display_time_test() {
+	let mut lc3 = Fixture::bare()
+	    .code(&[
+		0b1011_000_000000100, // STI R0, [DDR]
+		0b1010_001_000000010, // LDI R1, [DSR]
+		0b1010_010_000000001, // LDI R2, [DSR]
+		0b1010_011_000000000, // LDI R3, [DSR]
+		0xFE04u16 as i16,
+		0xFE06u16 as i16
+	    ])
+	    .build();
+	lc3.time = Box::new(InstructionTime::new(2_000_000));
+	lc3.memory.display_delay = 2; // microseconds, so twice as many clocks at 2 MHz
+	lc3.run_steps(4);
+	assert_eq!(&lc3.r[1..4], &[0, 0, 0x8000u16 as i16]);
+    }
+
+    #[test]
+    fn poll_test() {
+	let mut lc3 = Fixture::bare()
+	    .code(&[
+		0b1010_001_000000101, // LDI R1, [DSR]
+		0b0000_011_111111110, // BRzp #-2 ; wait for the ready bit
+		0b1011_000_000000100, // STI R0, [DDR]
+		0b0001_010_010_1_00001, // ADD R2, R2, #1
+		0b0000_111_111111011, // BRnzp #-5
+		0,
+		0xFE04u16 as i16,
+		0xFE06u16 as i16
+	    ])
+	    .build();
+	lc3.memory.display_delay = 4;
+	lc3.r[0] = 'x' as i16;
+	let mut written = Vec::new();
+	for clock in 0..40 {
+	    if let LC3IO::Display(_) = lc3.clock() {
+		written.push(clock);
+	    }
+	}
+	// 5 clocks apart when always ready, the busy time costs each write another pass of the poll
+	assert_eq!(written, vec![2, 9, 16, 23, 30, 37]);
+	assert_eq!(lc3.r[2], 6);
+    }
+}
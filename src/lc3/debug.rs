@@ -0,0 +1,129 @@
+//! Breakpoints and watchpoints, which stop `run_steps()` when they fire
+
+use super::{LC3, Reg};
+
+/// Stop before executing `addr`, optionally only when a register holds a value
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    pub when: Option<(Reg, i16)>
+}
+
+/// Stop after an instruction that touches the address (not counting fetches) or changes
+/// the register
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Watch {
+    Read(u16),
+    Write(u16),
+    Reg(Reg)
+}
+
+impl LC3 {
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+	add(&mut self.breakpoints, breakpoint);
+    }
+
+    /// Removes every breakpoint at `addr`, returning whether there were any
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+	let before = self.breakpoints.len();
+	self.breakpoints.retain(|b| b.addr != addr);
+	self.breakpoints.len() != before
+    }
+
+    /// Removes one breakpoint, condition included, returning whether it was set
+    pub fn clear_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+	remove(&mut self.breakpoints, breakpoint)
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+	&self.breakpoints
+    }
+
+    pub fn watch(&mut self, watch: Watch) {
+	match watch {
+	    Watch::Read(addr) => add(&mut self.memory.read_watches, addr),
+	    Watch::Write(addr) => add(&mut self.memory.write_watches, addr),
+	    Watch::Reg(reg) => add(&mut self.reg_watches, reg)
+	}
+    }
+
+    /// Removes a watchpoint, returning whether it was set
+    pub fn unwatch(&mut self, watch: Watch) -> bool {
+	match watch {
+	    Watch::Read(addr) => remove(&mut self.memory.read_watches, addr),
+	    Watch::Write(addr) => remove(&mut self.memory.write_watches, addr),
+	    Watch::Reg(reg) => remove(&mut self.reg_watches, reg)
+	}
+    }
+
+    /// Whether a breakpoint at `pc` should stop the machine, its condition included
+    pub(super) fn breakpoint_hit(&self, pc: u16) -> bool {
+	let regs = self.regs();
+	self.breakpoints.iter()
+	    .any(|b| b.addr == pc && b.when.is_none_or(|(reg, value)| regs[reg as usize] == value))
+    }
+}
+
+fn add<T: PartialEq>(list: &mut Vec<T>, item: T) {
+    if !list.contains(&item) {
+	list.push(item);
+    }
+}
+
+fn remove<T: PartialEq>(list: &mut Vec<T>, item: T) -> bool {
+    let before = list.len();
+    list.retain(|x| *x != item);
+    list.len() != before
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Breakpoint, Watch};
+    use crate::fixtures::Fixture;
+    use crate::lc3::batch::StopReason;
+    use crate::lc3::Reg;
+
+    #[test]
+    fn breakpoint_test() {
+	let mut lc3 = Fixture::bare()
+	    .code(&[
+		0b0001_000_000_1_11111, // ADD R0, R0, #-1
+		0b0000_101_111111110 // BRnp #-2
+	    ])
+	    .reg(0, 3)
+	    .build();
+	lc3.add_breakpoint(Breakpoint { addr: 0x3001, when: Some((Reg::R0, 0)) });
+	assert_eq!(lc3.run_steps(100), StopReason::Breakpoint(0x3001));
+	assert_eq!(lc3.r[0], 0);
+	assert_eq!(lc3.run_steps(1), StopReason::Limit);
+	assert_eq!(lc3.pc, 0x3002);
+	lc3.add_breakpoint(Breakpoint { addr: 0x3001, when: None });
+	assert!(!lc3.clear_breakpoint(Breakpoint { addr: 0x3001, when: Some((Reg::R0, 1)) }));
+	assert!(lc3.clear_breakpoint(Breakpoint { addr: 0x3001, when: None }));
+	assert_eq!(lc3.breakpoints(), [Breakpoint { addr: 0x3001, when: Some((Reg::R0, 0)) }]);
+	assert!(lc3.remove_breakpoint(0x3001));
+	assert!(!lc3.remove_breakpoint(0x3001));
+    }
+
+    #[test]
+    fn watch_test() {
+	let mut lc3 = Fixture::bare()
+	    .code(&[
+		0b0001_001_001_1_00001, // ADD R1, R1, #1
+		0b0010_010_000000011, // LD R2, x3005
+		0b0011_001_000000010, // ST R1, x3005
+		0b1111_0000_00100101 // TRAP x25
+	    ])
+	    .build();
+	lc3.watch(Watch::Read(0x3005));
+	lc3.watch(Watch::Write(0x3005));
+	lc3.watch(Watch::Reg(Reg::R1));
+	assert_eq!(lc3.run_steps(100), StopReason::Watch(Watch::Reg(Reg::R1)));
+	assert_eq!(lc3.run_steps(100), StopReason::Watch(Watch::Read(0x3005)));
+	assert_eq!(lc3.run_steps(100), StopReason::Watch(Watch::Write(0x3005)));
+	assert!(lc3.unwatch(Watch::Reg(Reg::R1)));
+	lc3.watch(Watch::Read(0x3003)); // fetching it doesn't count
+	assert_eq!(lc3.run_steps(1), StopReason::Limit);
+	assert_eq!(lc3.pc, 0);
+    }
+}
@@ -0,0 +1,252 @@
+//! Memory-mapped devices
+//!
+//! Peripherals implement `Device` and are attached to the memory's `Bus`, which hands them
+//! every CPU access to their registers. The keyboard, display, MCR and WFI registers stay
+//! built into `LC3Memory` and can't be claimed by an attached device.
+//!
+//! Registers usually sit in the device page, but a device can also map a block of memory
+//! below it (a framebuffer, say); accesses under the lowest such block never reach the bus.
+
+use super::LC3Memory;
+
+use std::ops::Range;
+
+/// Registers `LC3Memory` handles itself
+pub const BUILTIN: [u16; 6] = [0xFE00, 0xFE02, 0xFE04, 0xFE06, 0xFE10, 0xFFFE];
+
+/// Start of the device register page
+pub const DEVICE_PAGE: u16 = 0xFE00;
+
+/// Start of user space, the lowest address a device can map
+pub const USER_SPACE: u16 = 0x3000;
+
+pub trait Device: std::fmt::Debug {
+    /// Registers the device answers for, in the device page or mapped into user space
+    fn range(&self) -> Range<u16>;
+
+    /// The CPU read one of the registers
+    fn read(&mut self, addr: u16) -> i16;
+
+    /// A register's value without the side effects of reading it, for debuggers and dumps.
+    /// `None` shows whatever memory holds at `addr`.
+    fn peek(&self, _addr: u16) -> Option<i16> {
+	None
+    }
+
+    /// The CPU wrote one of the registers
+    fn write(&mut self, addr: u16, value: i16);
+
+    /// Called once per machine clock
+    fn tick(&mut self) {}
+
+    /// Called after `tick()` with the machine's memory, for devices that move blocks in and
+    /// out of it themselves (DMA). Accesses to the device page don't reach the bus from here.
+    fn dma(&mut self, _memory: &mut LC3Memory) {}
+
+    /// Interrupt vector and priority the device is requesting. Checked every clock, so a
+    /// device keeps asking until its handler acknowledges it
+    fn interrupt(&self) -> Option<(u8, u8)> {
+	None
+    }
+
+    /// Internal state to keep in snapshots, handed back to `restore`
+    fn save(&self) -> Vec<i16> {
+	Vec::new()
+    }
+
+    fn restore(&mut self, _state: &[i16]) {}
+}
+
+/// Attached devices, dispatched by address
+#[derive(Debug)]
+pub struct Bus {
+    devices: Vec<Box<dyn Device>>,
+    floor: u16 // lowest address any device answers for, or the device page
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+	Self { devices: Vec::new(), floor: DEVICE_PAGE }
+    }
+}
+
+impl Bus {
+    pub fn new() -> Self {
+	Self::default()
+    }
+
+    /// Adds a device, which must stay out of system space and off every built-in and
+    /// already attached register
+    pub fn attach(&mut self, device: Box<dyn Device>) -> Result<(), &'static str> {
+	let range = device.range();
+	if range.is_empty() {
+	    return Err("Device has no registers");
+	}
+	if range.start < USER_SPACE {
+	    return Err("Device registers must be in user space or the device page (x3000-xFFFF)");
+	}
+	if BUILTIN.iter().any(|addr| range.contains(addr)) {
+	    return Err("Device overlaps a built-in device register");
+	}
+	if self.devices.iter().any(|d| {
+	    let other = d.range();
+	    range.start < other.end && other.start < range.end
+	}) {
+	    return Err("Device overlaps an attached device");
+	}
+	self.floor = self.floor.min(range.start);
+	self.devices.push(device);
+	Ok(())
+    }
+
+    /// Removes every attached device
+    pub fn clear(&mut self) {
+	self.devices.clear();
+	self.floor = DEVICE_PAGE;
+    }
+
+    /// Accesses below this address are plain memory the bus has no part in
+    pub fn floor(&self) -> u16 {
+	self.floor
+    }
+
+    pub fn len(&self) -> usize {
+	self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+	self.devices.is_empty()
+    }
+
+    /// Whether an attached device owns `addr`
+    pub fn claims(&self, addr: u16) -> bool {
+	self.devices.iter().any(|d| d.range().contains(&addr))
+    }
+
+    fn find(&mut self, addr: u16) -> Option<&mut Box<dyn Device>> {
+	self.devices.iter_mut().find(|d| d.range().contains(&addr))
+    }
+
+    /// The value of an attached device's register, or `None` if no device owns `addr`
+    pub fn read(&mut self, addr: u16) -> Option<i16> {
+	self.find(addr).map(|d| d.read(addr))
+    }
+
+    /// An attached device's register without side effects, see `Device::peek`
+    pub fn peek(&self, addr: u16) -> Option<i16> {
+	self.devices.iter().find(|d| d.range().contains(&addr)).and_then(|d| d.peek(addr))
+    }
+
+    /// Passes a write to the device owning `addr`, returning whether there was one
+    pub fn write(&mut self, addr: u16, value: i16) -> bool {
+	self.find(addr).map(|d| d.write(addr, value)).is_some()
+    }
+
+    pub fn tick(&mut self) {
+	for device in self.devices.iter_mut() {
+	    device.tick();
+	}
+    }
+
+    pub fn dma(&mut self, memory: &mut LC3Memory) {
+	for device in self.devices.iter_mut() {
+	    device.dma(memory);
+	}
+    }
+
+    /// Each device's `save()`, in attach order
+    pub fn save(&self) -> Vec<Vec<i16>> {
+	self.devices.iter().map(|d| d.save()).collect()
+    }
+
+    /// Each device's registers, in attach order
+    pub fn ranges(&self) -> Vec<Range<u16>> {
+	self.devices.iter().map(|d| d.range()).collect()
+    }
+
+    /// Hands states from `save()` back to the devices in the same order
+    pub fn restore(&mut self, states: &[Vec<i16>]) {
+	for (device, state) in self.devices.iter_mut().zip(states) {
+	    device.restore(state);
+	}
+    }
+
+    /// The highest priority interrupt any device is requesting
+    pub fn interrupt(&self) -> Option<(u8, u8)> {
+	self.devices.iter().filter_map(|d| d.interrupt()).max_by_key(|&(_, priority)| priority)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bus, Device};
+    use crate::lc3::LC3;
+    use std::ops::Range;
+
+    /// Counts down from the value written to xFE08 and interrupts at zero until xFE08 is read
+    #[derive(Debug, Default)]
+    struct Timer {
+	count: i16,
+	expired: bool
+    }
+
+    impl Device for Timer {
+	fn range(&self) -> Range<u16> {
+	    0xFE08..0xFE09
+	}
+	fn read(&mut self, _addr: u16) -> i16 {
+	    self.expired = false;
+	    self.count
+	}
+	fn write(&mut self, _addr: u16, value: i16) {
+	    self.count = value;
+	}
+	fn tick(&mut self) {
+	    if self.count > 0 {
+		self.count -= 1;
+		self.expired = self.count == 0;
+	    }
+	}
+	fn interrupt(&self) -> Option<(u8, u8)> {
+	    if self.expired { Some((0x81, 2)) } else { None }
+	}
+    }
+
+    #[test]
+    fn attach_test() {
+	let mut bus = Bus::new();
+	bus.attach(Box::new(Timer::default())).expect("Failed to attach");
+	assert!(bus.attach(Box::new(Timer::default())).is_err());
+	assert_eq!(bus.len(), 1);
+	assert!(bus.write(0xFE08, 3));
+	assert_eq!(bus.read(0xFE08), Some(3));
+	assert_eq!(bus.read(0xFE0A), None);
+	assert!(!bus.write(0x3000, 1));
+	assert_eq!(bus.floor(), 0xFE00);
+    }
+
+    #[test]
+    fn device_test() {
+	let mut lc3 = LC3::new();
+	lc3.memory.attach(Box::new(Timer::default())).expect("Failed to attach");
+	lc3.memory.put(0x3000, 0b1011_001_000000010); // STI R1, [PC + 2] ; start the timer
+	lc3.memory.put(0x3001, 0b0000_111_111111111); // BRnzp #-1
+	lc3.memory.put(0x3003, 0xFE08);
+	lc3.memory.put(0x100 + 0x81, 0x1200); // timer handler
+	lc3.memory.put(0x1200, 0b1010_000_000000001); // LDI R0, [PC + 1] ; acknowledge
+	lc3.memory.put(0x1202, 0xFE08);
+	lc3.r[1] = 3;
+	lc3.pc = 0x3000;
+	lc3.saved_ssp = 0x3000;
+	lc3.start();
+	lc3.clock();
+	assert_eq!(lc3.memory.peek(0xFE08), 0); // the write went to the timer
+	lc3.clock();
+	lc3.clock();
+	assert_eq!(lc3.pc, 0x1200);
+	assert_eq!((lc3.psr >> 8) & 0b111, 2);
+	lc3.clock();
+	assert_eq!(lc3.r[0], 0);
+	assert_eq!(lc3.pc, 0x1201); // acknowledged, no second interrupt
+    }
+}
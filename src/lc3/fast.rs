@@ -0,0 +1,437 @@
+//! Fast path for the execution core. Instructions are predecoded into a cache, and while
+//! nothing is observing the machine (hooks, watchpoints, undo history, pending interrupts)
+//! the ones that only touch plain memory run without `clock()`'s bookkeeping. TRAP, RTI,
+//! illegal opcodes, accesses to device registers and the LC-3b all go through `clock()` as
+//! before. Attached devices still tick after every instruction.
+
+use super::{sign_extend, Isa, LC3, POLL_LIMIT};
+
+use std::convert::TryInto;
+
+/// An instruction with its fields pulled out, registers as indexes into `LC3::r` and
+/// PC-relative operands as the addresses they come to from where the instruction sits
+#[derive(Debug, Copy, Clone)]
+enum Op {
+    Add(u8, u8, u8), // DR, SR1, SR2
+    AddImm(u8, u8, i16),
+    And(u8, u8, u8),
+    AndImm(u8, u8, i16),
+    Not(u8, u8),
+    Br(u8, u16), // NZP mask, target
+    Jmp(u8),
+    Jsr(u16),
+    Jsrr(u8),
+    Ld(u8, u16),
+    Ldi(u8, u16),
+    Ldr(u8, u8, i16),
+    Lea(u8, u16),
+    St(u8, u16),
+    Sti(u8, u16),
+    Str(u8, u8, i16),
+    Slow // left to clock()
+}
+
+fn decode(word: i16, addr: u16) -> Op {
+    let dr = ((word >> 9) & 0b111) as u8;
+    let sr1 = ((word >> 6) & 0b111) as u8;
+    let sr2 = (word & 0b111) as u8;
+    let imm5 = sign_extend(word & 0b11111, 5);
+    let offset6 = sign_extend(word & 0b111111, 6);
+    let relative = addr.wrapping_add(1).wrapping_add(sign_extend(word & 0b111_111_111, 9) as u16);
+    let immediate = word & 0b100000 != 0;
+    match (word as u16) >> 12 {
+	0b0001 if immediate => Op::AddImm(dr, sr1, imm5),
+	0b0001 => Op::Add(dr, sr1, sr2),
+	0b0101 if immediate => Op::AndImm(dr, sr1, imm5),
+	0b0101 => Op::And(dr, sr1, sr2),
+	0b1001 => Op::Not(dr, sr1),
+	0b0000 => Op::Br(dr, relative),
+	0b1100 => Op::Jmp(sr1),
+	0b0100 if word & 0b1000_0000_0000 != 0 => {
+	    Op::Jsr(addr.wrapping_add(1).wrapping_add(sign_extend(word & 0b111_1111_1111, 11) as u16))
+	}
+	0b0100 => Op::Jsrr(sr1),
+	0b0010 => Op::Ld(dr, relative),
+	0b1010 => Op::Ldi(dr, relative),
+	0b0110 => Op::Ldr(dr, sr1, offset6),
+	0b1110 => Op::Lea(dr, relative),
+	0b0011 => Op::St(dr, relative),
+	0b1011 => Op::Sti(dr, relative),
+	0b0111 => Op::Str(dr, sr1, offset6),
+	_ => Op::Slow
+    }
+}
+
+/// A decoded instruction and the word it came from, so a write to its address by any
+/// route invalidates it
+#[derive(Debug, Copy, Clone)]
+#[repr(align(8))]
+struct Entry {
+    word: i16,
+    op: Op
+}
+
+type Entries = Box<[Entry; 0x10000]>;
+
+/// Decoded instructions by address
+#[derive(Default)]
+pub(super) struct Cache {
+    entries: Option<Entries> // allocated on first use
+}
+
+impl std::fmt::Debug for Cache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	write!(f, "{} entries", self.entries.as_ref().map_or(0, |e| e.len()))
+    }
+}
+
+impl Cache {
+    /// Takes the entries out for a run, allocating them the first time
+    fn take(&mut self) -> Entries {
+	self.entries.take().unwrap_or_else(|| {
+	    // word 0 decodes to a BR that never branches wherever it sits, so every entry is right
+	    vec![Entry { word: 0, op: Op::Br(0, 0) }; 0x10000].into_boxed_slice().try_into().unwrap()
+	})
+    }
+}
+
+impl LC3 {
+    /// Runs up to `limit` instructions on the fast path and returns how many it ran,
+    /// stopping at the first one that needs `clock()` or has a breakpoint on it
+    pub(super) fn run_fast(&mut self, limit: u64) -> u64 {
+	let budget = self.fast_budget(limit);
+	if budget == 0 {
+	    return 0;
+	}
+	if self.memory.keyboard_ready {
+	    self.poll = None; // as clock() does while a key waits
+	}
+	let mut entries = self.cache.take();
+	let (done, last) = if self.breakpoints.is_empty() && self.memory.bus.is_empty() {
+	    self.batch::<false>(&mut entries, budget)
+	} else {
+	    self.batch::<true>(&mut entries, budget)
+	};
+	if done > 0 {
+	    self.retire(done, Some((last, entries[last as usize].word)));
+	}
+	self.cache.entries = Some(entries);
+	done
+    }
+
+    /// The fast path's loop, checking for breakpoints and ticking devices between
+    /// instructions if `WATCHED`. Returns how many instructions ran and where the last was.
+    fn batch<const WATCHED: bool>(&mut self, entries: &mut [Entry; 0x10000], budget: u64) -> (u64, u16) {
+	// PC and the condition codes stay out of memory, written back whenever anything else looks
+	let mut pc = self.pc as u16;
+	let mut cc = self.psr & 0b111;
+	let mut last = pc;
+	let mut left = budget;
+	while left > 0 {
+	    if WATCHED && self.breakpoints.iter().any(|b| b.addr == pc) {
+		break;
+	    }
+	    match self.execute(entries, pc, &mut cc) {
+		Some(next) => {
+		    last = pc;
+		    pc = next;
+		}
+		None => break
+	    }
+	    left -= 1;
+	    if WATCHED && !self.memory.bus.is_empty() {
+		self.pc = pc as i16;
+		self.psr = (self.psr & !0b111) | cc;
+		let interrupted = self.devices_ran();
+		pc = self.pc as u16;
+		cc = self.psr & 0b111;
+		if interrupted {
+		    break;
+		}
+	    }
+	}
+	self.pc = pc as i16;
+	self.psr = (self.psr & !0b111) | cc;
+	(budget - left, last)
+    }
+
+    /// Clocks the fast path may run: none while anything is watching or waiting on the
+    /// machine, and fewer than it takes for the next scripted key to come due
+    pub(super) fn fast_budget(&self, limit: u64) -> u64 {
+	let memory = &self.memory;
+	let ready = !self.halted && !self.sleeping && self.isa == Isa::Lc3 && self.history.is_none()
+	    && self.hooks.is_empty() && memory.access_hooks.is_empty()
+	    && memory.read_watches.is_empty() && memory.write_watches.is_empty() && self.reg_watches.is_empty()
+	    && memory.mirrors.is_empty() && memory.protected.is_empty()
+	    && memory.display_busy() == 0 && self.interrupts.pending().is_empty()
+	    && self.poll.is_none_or(|p| p.count < POLL_LIMIT);
+	if !ready {
+	    return 0;
+	}
+	match self.script.front() {
+	    None => limit,
+	    Some(&(at, _)) => self.time.ticks_until(self.ticks, at).map_or(0, |t| t.saturating_sub(1).min(limit))
+	}
+    }
+
+    /// What `clock()` does after an instruction when devices are attached: ticks them, lets
+    /// them at memory and takes an interrupt one raises, returning whether it did
+    #[inline(never)]
+    fn devices_ran(&mut self) -> bool {
+	self.memory.bus.tick();
+	self.memory.run_dma();
+	self.memory.bus.interrupt().is_some() && self.service_interrupts().is_some()
+    }
+
+    /// What `clock()` does after `done` instructions, the last of them `last`
+    fn retire(&mut self, done: u64, last: Option<(u16, i16)>) {
+	self.ticks += done;
+	self.instructions += done;
+	self.raised = None;
+	self.last_instruction = last;
+    }
+
+    /// Executes the instruction at `pc` and returns the address of the next one, or `None`
+    /// without changing anything if it needs `clock()`. PC and the counters are left to the
+    /// caller.
+    #[inline(always)]
+    fn execute(&mut self, entries: &mut [Entry; 0x10000], pc: u16, cc: &mut i16) -> Option<u16> {
+	if !self.memory.plain(pc) {
+	    return None;
+	}
+	let word = self.memory.mem[pc as usize];
+	let entry = &mut entries[pc as usize];
+	if entry.word != word {
+	    *entry = Entry { word, op: decode(word, pc) };
+	}
+	let next = pc.wrapping_add(1) as i16;
+	let target = match entry.op {
+	    Op::Add(dr, a, b) => self.load(cc, dr, self.reg(a).wrapping_add(self.reg(b)), next),
+	    Op::AddImm(dr, a, imm) => self.load(cc, dr, self.reg(a).wrapping_add(imm), next),
+	    Op::And(dr, a, b) => self.load(cc, dr, self.reg(a) & self.reg(b), next),
+	    Op::AndImm(dr, a, imm) => self.load(cc, dr, self.reg(a) & imm, next),
+	    Op::Not(dr, a) => self.load(cc, dr, !self.reg(a), next),
+	    Op::Br(mask, target) => if *cc & mask as i16 != 0 { target as i16 } else { next },
+	    Op::Jmp(base) => self.jump(self.reg(base)),
+	    Op::Jsr(target) => self.call(pc, target as i16),
+	    Op::Jsrr(base) => self.call(pc, self.reg(base)),
+	    Op::Lea(dr, addr) => self.load(cc, dr, addr as i16, next),
+	    Op::Ld(dr, addr) => {
+		if !self.memory.plain(addr) {
+		    return None;
+		}
+		self.load(cc, dr, self.memory.mem[addr as usize], next)
+	    }
+	    Op::Ldi(dr, ptr) => {
+		if !self.memory.plain(ptr) {
+		    return None;
+		}
+		let addr = self.memory.mem[ptr as usize] as u16;
+		if !self.memory.plain(addr) {
+		    return None;
+		}
+		self.load(cc, dr, self.memory.mem[addr as usize], next)
+	    }
+	    Op::Ldr(dr, base, offset) => {
+		let addr = self.reg(base).wrapping_add(offset) as u16;
+		if !self.memory.plain(addr) {
+		    return None;
+		}
+		self.load(cc, dr, self.memory.mem[addr as usize], next)
+	    }
+	    Op::St(sr, addr) => {
+		if !self.memory.plain(addr) {
+		    return None;
+		}
+		self.store(addr, self.reg(sr), next)
+	    }
+	    Op::Sti(sr, ptr) => {
+		if !self.memory.plain(ptr) {
+		    return None;
+		}
+		let addr = self.memory.mem[ptr as usize] as u16;
+		if !self.memory.plain(addr) {
+		    return None;
+		}
+		self.store(addr, self.reg(sr), next)
+	    }
+	    Op::Str(sr, base, offset) => {
+		let addr = self.reg(base).wrapping_add(offset) as u16;
+		if !self.memory.plain(addr) {
+		    return None;
+		}
+		self.store(addr, self.reg(sr), next)
+	    }
+	    Op::Slow => return None
+	};
+	Some(target as u16)
+    }
+
+    /// JMP or RET to `target`, returning it
+    fn jump(&mut self, target: i16) -> i16 {
+	self.returned_to(target as u16);
+	target
+    }
+
+    /// JSR or JSRR from `pc` to `target`, returning `target`
+    fn call(&mut self, pc: u16, target: i16) -> i16 {
+	let next = pc.wrapping_add(1);
+	self.r[7] = next as i16;
+	self.push_call(pc, target as u16, next);
+	target
+    }
+
+    #[inline(always)]
+    fn reg(&self, r: u8) -> i16 {
+	self.r[(r & 0b111) as usize]
+    }
+
+    /// Sets a register and the condition codes `cc` from it, returning `next`
+    #[inline(always)]
+    fn load(&mut self, cc: &mut i16, dr: u8, value: i16, next: i16) -> i16 {
+	// N, Z or P without branching on the sign
+	*cc = 0b010 << (value < 0) as i16 >> (value > 0) as i16;
+	self.r[(dr & 0b111) as usize] = value;
+	next
+    }
+
+    /// A store to plain memory, with no journal or watchpoints to tell
+    #[inline(always)]
+    fn store(&mut self, addr: u16, value: i16, next: i16) -> i16 {
+	self.memory.written = true;
+	self.memory.mem[addr as usize] = value;
+	next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::Fixture;
+    use crate::lc3::batch::StopReason;
+    use crate::lc3::debug::Breakpoint;
+    use crate::lc3::{Reg, LC3};
+    use crate::video::{Framebuffer, Vsync};
+
+    /// Runs the same program instruction by instruction through `clock()` with the fast
+    /// path disabled, and through `run_steps()`, which uses it
+    fn both(program: &[i16], setup: fn(&mut LC3)) -> (LC3, LC3) {
+	let build = || {
+	    let mut lc3 = Fixture::with_os().code(program).build();
+	    lc3.r[6] = 0x4000;
+	    setup(&mut lc3);
+	    lc3
+	};
+	let mut slow = build();
+	slow.record_history(1); // keeps clock() off the fast path
+	while !slow.halted {
+	    slow.clock();
+	}
+	let mut fast = build();
+	assert_eq!(fast.run_until_halt(), StopReason::Halted);
+	(slow, fast)
+    }
+
+    #[test]
+    fn fast_test() {
+	let (slow, fast) = both(&[
+	    0b0101_001_001_1_00000, // AND R1, R1, #0
+	    0b0001_001_001_1_00101, // ADD R1, R1, #5
+	    0b0010_010_000001101, // LD R2, x3010
+	    0b0001_011_001_0_00_010, // ADD R3, R1, R2
+	    0b1001_100_011_111111, // NOT R4, R3
+	    0b0111_100_110_111111, // STR R4, R6, #-1
+	    0b0110_101_110_111111, // LDR R5, R6, #-1
+	    0b1010_000_000001000, // LDI R0, [x3010] -> x0009
+	    0b0100_1_00000000100, // JSR x300D
+	    0b0001_001_001_1_11111, // ADD R1, R1, #-1
+	    0b0000_001_111111110, // BRp x3009
+	    0b1011_001_000000100, // STI R1, [x3010]
+	    0b1111_0000_00100101, // HALT
+	    0b1100_000_111_000000, // RET
+	    0,
+	    0,
+	    0x0009, // at x3010
+	], |_| ());
+	assert_eq!(fast.regs(), slow.regs());
+	assert_eq!((fast.pc, fast.psr, fast.instructions, fast.ticks), (slow.pc, slow.psr, slow.instructions, slow.ticks));
+	assert_eq!(fast.memory.mem[..], slow.memory.mem[..]);
+	assert_eq!(fast.r[4], !(5 + 9));
+	assert_eq!(fast.memory.peek(0x0009), 0);
+    }
+
+    #[test]
+    fn invalidate_test() {
+	// self-modifying: the ST rewrites the ADD at x3002 after it has run once
+	let mut lc3 = Fixture::bare()
+	    .code(&[
+		0b0010_000_000000100, // LD R0, x3005
+		0b0000_111_000000000, // NOP
+		0b0001_001_001_1_00001, // ADD R1, R1, #1
+		0b0011_000_111111110, // ST R0, x3002
+		0b0000_111_111111101, // BRnzp x3002
+		0b0001_001_001_1_00100u16 as i16 // ADD R1, R1, #4
+	    ])
+	    .build();
+	assert_eq!(lc3.run_fast(7), 7);
+	assert_eq!(lc3.r[1], 5);
+    }
+
+    #[test]
+    fn breakpoint_test() {
+	let mut lc3 = Fixture::bare()
+	    .code(&[
+		0b0001_001_001_1_00001, // ADD R1, R1, #1
+		0b0000_111_111111110 // BRnzp #-2
+	    ])
+	    .build();
+	lc3.add_breakpoint(Breakpoint { addr: 0x3001, when: Some((Reg::R1, 3)) });
+	assert_eq!(lc3.run_fast(100), 1); // stops short of every breakpoint, met or not
+	assert_eq!(lc3.run_steps(100), StopReason::Breakpoint(0x3001));
+	assert_eq!((lc3.r[1], lc3.instructions), (3, 5));
+    }
+
+    #[test]
+    fn device_test() {
+	// framebuffer and VSYNC accesses go through clock(), the rest stays on the fast path
+	let (slow, mut fast) = both(&[
+	    0b0101_001_001_1_00000, // AND R1, R1, #0
+	    0b0001_001_001_1_00011, // ADD R1, R1, #3
+	    0b0010_010_000001000, // LD R2, x300B
+	    0b0111_001_010_000000, // STR R1, R2, #0
+	    0b0001_010_010_1_00001, // ADD R2, R2, #1
+	    0b0001_001_001_1_11111, // ADD R1, R1, #-1
+	    0b0000_001_111111100, // BRp x3003
+	    0b0110_011_010_111111, // LDR R3, R2, #-1
+	    0b1011_010_000000011u16 as i16, // STI R2, [x300C]
+	    0b1111_0000_00100101u16 as i16, // HALT
+	    0,
+	    0xC000u16 as i16, // at x300B
+	    0xFE12u16 as i16
+	], |lc3| {
+	    lc3.memory.attach(Box::new(Framebuffer::default())).expect("Failed to attach");
+	    lc3.memory.attach(Box::new(Vsync::new().0)).expect("Failed to attach");
+	});
+	assert_eq!(fast.regs(), slow.regs());
+	assert_eq!((fast.pc, fast.psr, fast.instructions, fast.ticks), (slow.pc, slow.psr, slow.instructions, slow.ticks));
+	assert_eq!(fast.memory.read_words(0xC000, 3), vec![3, 2, 1]);
+	assert_eq!(fast.memory.read_words(0xC000, 3), slow.memory.read_words(0xC000, 3));
+	assert_eq!((fast.r[3], fast.memory.get(0xFE12)), (1, 1)); // one frame finished
+    }
+
+    #[test]
+    fn device_page_test() {
+	// the words above the default user stack are plain memory, only the registers aren't
+	let mut lc3 = Fixture::bare()
+	    .code(&[
+		0b0111_001_110_000001, // STR R1, R6, #1
+		0b0110_010_110_000001, // LDR R2, R6, #1
+		0b0110_011_110_000000 // LDR R3, R6, #0 ; KBSR
+	    ])
+	    .reg(1, 7)
+	    .reg(6, 0xFE00u16 as i16)
+	    .build();
+	lc3.memory.attach(Box::new(Vsync::new().0)).expect("Failed to attach");
+	assert_eq!(lc3.run_fast(10), 2);
+	assert_eq!((lc3.r[2], lc3.pc), (7, 0x3002));
+    }
+}
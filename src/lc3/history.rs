@@ -0,0 +1,255 @@
+//! Reverse execution: an undo journal of what each clock changed, kept in a bounded ring so
+//! `step_back()` can rewind the most recent instructions
+
+use super::interrupt::Controller;
+use super::stack::Frame;
+use super::{PollState, LC3};
+
+use std::collections::VecDeque;
+
+/// State before one clock, and the memory it overwrote
+#[derive(Debug, Clone)]
+struct Undo {
+    pc: i16,
+    psr: i16,
+    regs: [i16; 8],
+    saved_usp: i16,
+    saved_ssp: i16,
+    halted: bool,
+    sleeping: bool,
+    ie: u8,
+    poll: Option<PollState>,
+    instructions: u64,
+    last_instruction: Option<(u16, i16)>,
+    ticks: u64,
+    keyboard_ready: bool,
+    kbdr: i16,
+    display_until: u64,
+    key: Option<(u64, i16)>, // scripted key the clock delivered
+    interrupts: Controller,
+    calls: Option<Vec<Frame>>, // the call stack, if the clock changed it
+    writes: Vec<(u16, i16)> // address and old value, in the order they were written
+}
+
+#[derive(Debug, Clone)]
+pub struct History {
+    limit: usize,
+    entries: VecDeque<Undo>,
+    pending: Option<(Undo, usize)> // clock in progress and the script length before it
+}
+
+impl LC3 {
+    /// Starts keeping the last `limit` clocks for `step_back()`, or stops with 0. Changing the
+    /// limit drops the oldest entries that no longer fit.
+    pub fn record_history(&mut self, limit: usize) {
+	if limit == 0 {
+	    self.history = None;
+	    self.memory.journal = None;
+	    return;
+	}
+	let history = self.history.get_or_insert_with(|| History {
+	    limit,
+	    entries: VecDeque::new(),
+	    pending: None
+	});
+	history.limit = limit;
+	while history.entries.len() > limit {
+	    history.entries.pop_front();
+	}
+    }
+
+    /// Forgets every recorded clock, keeping the limit
+    pub fn clear_history(&mut self) {
+	if let Some(history) = self.history.as_mut() {
+	    history.entries.clear();
+	}
+    }
+
+    /// Clocks that can be stepped back
+    pub fn history_len(&self) -> usize {
+	self.history.as_ref().map_or(0, |h| h.entries.len())
+    }
+
+    /// Undoes the last `n` clocks, returning how many there were to undo. Registers, memory,
+    /// counters, interrupt enable, pending interrupts, scripted keys, the display's busy time
+    /// and busy-wait detection go back; console output already printed and the state of
+    /// attached devices don't.
+    pub fn step_back(&mut self, n: usize) -> usize {
+	let mut undone = 0;
+	while undone < n {
+	    let undo = match self.history.as_mut().and_then(|h| h.entries.pop_back()) {
+		Some(undo) => undo,
+		None => break
+	    };
+	    for &(addr, old) in undo.writes.iter().rev() {
+		self.memory.mem[addr as usize] = old;
+	    }
+	    self.r = undo.regs;
+	    self.pc = undo.pc;
+	    self.psr = undo.psr;
+	    self.saved_usp = undo.saved_usp;
+	    self.saved_ssp = undo.saved_ssp;
+	    self.halted = undo.halted;
+	    self.sleeping = undo.sleeping;
+	    self.ie = undo.ie;
+	    self.poll = undo.poll;
+	    self.instructions = undo.instructions;
+	    self.last_instruction = undo.last_instruction;
+	    self.ticks = undo.ticks;
+	    self.memory.keyboard_ready = undo.keyboard_ready;
+	    self.memory.mem[0xFE02] = undo.kbdr;
+	    self.memory.display_until = undo.display_until;
+	    self.memory.clock_time = self.time.now(self.ticks);
+	    if let Some(key) = undo.key {
+		self.script.push_front(key);
+	    }
+	    self.interrupts = undo.interrupts;
+	    if let Some(calls) = undo.calls {
+		self.calls = calls;
+	    }
+	    undone += 1;
+	}
+	if undone > 0 {
+	    self.watch_hit = None;
+	    self.memory.watch_hit = None;
+	    self.resume_at = None;
+	}
+	undone
+    }
+
+    /// Called by `clock()` before it changes anything
+    pub(super) fn begin_undo(&mut self) {
+	let undo = Undo {
+	    pc: self.pc,
+	    psr: self.psr,
+	    regs: self.regs(),
+	    saved_usp: self.saved_usp,
+	    saved_ssp: self.saved_ssp,
+	    halted: self.halted,
+	    sleeping: self.sleeping,
+	    ie: self.ie,
+	    poll: self.poll,
+	    instructions: self.instructions,
+	    last_instruction: self.last_instruction,
+	    ticks: self.ticks,
+	    keyboard_ready: self.memory.keyboard_ready,
+	    kbdr: self.memory.mem[0xFE02],
+	    display_until: self.memory.display_until,
+	    key: self.script.front().copied(),
+	    interrupts: self.interrupts.clone(),
+	    calls: None,
+	    writes: Vec::new()
+	};
+	let script = self.script.len();
+	if let Some(history) = self.history.as_mut() {
+	    history.pending = Some((undo, script));
+	}
+	self.memory.journal = Some(Vec::new());
+    }
+
+    /// Keeps the call stack as it was before the clock in progress, ahead of a change to it
+    pub(super) fn save_calls(&mut self) {
+	if let Some((undo, _)) = self.history.as_mut().and_then(|h| h.pending.as_mut()) {
+	    if undo.calls.is_none() {
+		undo.calls = Some(self.calls.clone());
+	    }
+	}
+    }
+
+    /// Called by `clock()` when it's done, keeps the clock unless it didn't run
+    pub(super) fn end_undo(&mut self) {
+	let writes = self.memory.journal.take().unwrap_or_default();
+	let script = self.script.len();
+	let history = match self.history.as_mut() {
+	    Some(history) => history,
+	    None => return
+	};
+	if let Some((mut undo, before)) = history.pending.take() {
+	    if undo.halted && writes.is_empty() && script == before {
+		return; // nothing happened
+	    }
+	    if script == before {
+		undo.key = None;
+	    }
+	    undo.writes = writes;
+	    if history.entries.len() == history.limit {
+		history.entries.pop_front();
+	    }
+	    history.entries.push_back(undo);
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::Fixture;
+
+    #[test]
+    fn step_back_test() {
+	let mut lc3 = Fixture::bare()
+	    .code(&[
+		0b0001_001_001_1_00001, // ADD R1, R1, #1
+		0b0011_001_000000010, // ST R1, [PC + 2]
+		0b0001_001_001_1_00001, // ADD R1, R1, #1
+		0b0011_001_000000000, // ST R1, [PC + 0]
+	    ])
+	    .build();
+	lc3.record_history(2);
+	for _ in 0..4 {
+	    lc3.clock();
+	}
+	assert_eq!(lc3.memory.peek(0x3004), 2);
+	assert_eq!(lc3.history_len(), 2);
+	assert_eq!(lc3.step_back(1), 1);
+	assert_eq!(lc3.memory.peek(0x3004), 1);
+	assert_eq!((lc3.pc, lc3.r[1], lc3.instructions), (0x3003, 2, 3));
+	assert_eq!(lc3.step_back(5), 1); // only two were kept
+	assert_eq!((lc3.pc, lc3.r[1], lc3.psr & 0b111), (0x3002, 1, 0b001));
+	lc3.clock();
+	lc3.clock();
+	assert_eq!(lc3.memory.peek(0x3004), 2);
+	lc3.record_history(0);
+	assert_eq!(lc3.step_back(1), 0);
+    }
+
+    #[test]
+    fn key_test() {
+	let mut lc3 = Fixture::bare()
+	    .code(&[0, 0b1010_000_000000001]) // NOP, LDI R0, [PC + 1]
+	    .data(0x3003, &[0xFE02])
+	    .build();
+	lc3.record_history(10);
+	lc3.schedule_key(0, 'k' as i16);
+	lc3.clock();
+	lc3.clock();
+	assert_eq!(lc3.r[0], 'k' as i16);
+	assert_eq!(lc3.step_back(2), 2);
+	assert_eq!(lc3.r[0], 0);
+	lc3.clock();
+	lc3.clock();
+	assert_eq!(lc3.r[0], 'k' as i16); // the key was delivered again
+    }
+
+    #[test]
+    fn device_state_test() {
+	let mut lc3 = Fixture::bare()
+	    .code(&[
+		0b1011_000_000000010, // STI R0, [PC + 2] ; DDR
+		0b1010_001_000000010, // LDI R1, [PC + 2] ; KBSR
+		0b0000_111_111111110, // BRnzp #-2
+		0xFE06u16 as i16,
+		0xFE00u16 as i16
+	    ])
+	    .build();
+	lc3.memory.display_delay = 5;
+	lc3.record_history(10);
+	for _ in 0..5 {
+	    lc3.clock();
+	}
+	assert_eq!((lc3.memory.display_busy(), lc3.poll.map(|p| p.count)), (1, Some(2)));
+	assert_eq!(lc3.step_back(2), 2);
+	assert_eq!((lc3.memory.display_busy(), lc3.poll.map(|p| p.count)), (3, Some(1)));
+	assert_eq!(lc3.step_back(3), 3);
+	assert_eq!((lc3.memory.display_busy(), lc3.poll.map(|p| p.count)), (0, None));
+    }
+}
@@ -0,0 +1,146 @@
+//! Instrumentation callbacks: closures run before and after every instruction and on every
+//! memory access the CPU makes, so tools can watch the machine without their own run loop
+
+use super::{LC3, LC3Memory};
+
+/// One read or write through `LC3Memory::get()` or `put()`, instruction fetches included
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Access {
+    pub addr: u16, // as the program addressed it, before mirroring
+    pub value: i16, // read, or about to be written
+    pub write: bool,
+    pub device: bool // lands on a device register, built in or attached
+}
+
+/// Called with the instruction's address and its opcode, the top four bits
+pub type InstructionHook = Box<dyn FnMut(u16, u8)>;
+
+pub type AccessHook = Box<dyn FnMut(Access)>;
+
+#[derive(Default)]
+pub struct Hooks {
+    before: Vec<InstructionHook>,
+    after: Vec<InstructionHook>
+}
+
+impl std::fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	write!(f, "{} before, {} after", self.before.len(), self.after.len())
+    }
+}
+
+impl Hooks {
+    pub(super) fn is_empty(&self) -> bool {
+	self.before.is_empty() && self.after.is_empty()
+    }
+}
+
+impl LC3 {
+    /// Runs `hook` before each instruction executes, not for clocks spent asleep or halted
+    pub fn on_before_instruction(&mut self, hook: impl FnMut(u16, u8) + 'static) {
+	self.hooks.before.push(Box::new(hook));
+    }
+
+    /// Runs `hook` once an instruction has executed, with its address and opcode
+    pub fn on_after_instruction(&mut self, hook: impl FnMut(u16, u8) + 'static) {
+	self.hooks.after.push(Box::new(hook));
+    }
+
+    /// Runs `hook` on every memory read and write, from the CPU or the host
+    pub fn on_memory_access(&mut self, hook: impl FnMut(Access) + 'static) {
+	self.memory.access_hooks.push(Box::new(hook));
+    }
+
+    /// Removes every hook
+    pub fn clear_hooks(&mut self) {
+	self.hooks = Hooks::default();
+	self.memory.access_hooks.clear();
+    }
+
+    pub(super) fn before_instruction(&mut self, pc: u16, opcode: u8) {
+	for hook in &mut self.hooks.before {
+	    hook(pc, opcode);
+	}
+    }
+
+    pub(super) fn after_instruction(&mut self, pc: u16, opcode: u8) {
+	for hook in &mut self.hooks.after {
+	    hook(pc, opcode);
+	}
+    }
+}
+
+impl LC3Memory {
+    pub(super) fn accessed(&mut self, addr: u16, value: i16, write: bool) {
+	let device = !self.plain(self.resolve(addr)); // attached devices can sit below xFE00
+	for hook in &mut self.access_hooks {
+	    hook(Access { addr, value, write, device });
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Access;
+    use crate::fixtures::Fixture;
+    use crate::video::Framebuffer;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn hooks_test() {
+	let mut lc3 = Fixture::bare()
+	    .code(&[
+		0b0010_001_000000010, // LD R1, x3003
+		0b0011_001_000000010, // ST R1, x3004
+		0b1010_010_000000011, // LDI R2, [x3006] (KBSR)
+		0x1234,
+		0x0000,
+		0x0000,
+		0xFE00u16 as i16
+	    ])
+	    .build();
+	let instructions = Rc::new(RefCell::new(Vec::new()));
+	let accesses = Rc::new(RefCell::new(Vec::new()));
+	let log = instructions.clone();
+	lc3.on_before_instruction(move |pc, op| log.borrow_mut().push((pc, op, false)));
+	let log = instructions.clone();
+	lc3.on_after_instruction(move |pc, op| log.borrow_mut().push((pc, op, true)));
+	let log = accesses.clone();
+	lc3.on_memory_access(move |access| log.borrow_mut().push(access));
+	for _ in 0..3 {
+	    lc3.clock();
+	}
+	assert_eq!(*instructions.borrow(), [
+	    (0x3000, 0b0010, false), (0x3000, 0b0010, true),
+	    (0x3001, 0b0011, false), (0x3001, 0b0011, true),
+	    (0x3002, 0b1010, false), (0x3002, 0b1010, true)
+	]);
+	let access = |addr, value, write, device| Access { addr, value, write, device };
+	assert_eq!(accesses.borrow()[..4], [
+	    access(0x3000, 0b0010_001_000000010, false, false), // fetch
+	    access(0x3003, 0x1234, false, false),
+	    access(0x3001, 0b0011_001_000000010, false, false),
+	    access(0x3004, 0x1234, true, false)
+	]);
+	assert_eq!(accesses.borrow().last(), Some(&access(0xFE00, 0, false, true)));
+	lc3.clear_hooks();
+	lc3.memory.put(0x4000, 1);
+	assert_eq!(accesses.borrow().len(), 7);
+    }
+
+    #[test]
+    fn device_access_test() {
+	let mut lc3 = Fixture::bare().build();
+	lc3.memory.attach(Box::new(Framebuffer::default())).expect("Failed to attach");
+	let accesses = Rc::new(RefCell::new(Vec::new()));
+	let log = accesses.clone();
+	lc3.on_memory_access(move |access| log.borrow_mut().push((access.addr, access.device)));
+	lc3.memory.put(0xBFFF, 1);
+	lc3.memory.put(0xC000, 1); // the framebuffer's first pixel
+	lc3.memory.get(0xFE04); // DSR
+	lc3.memory.get(0xFE30); // nothing answers here
+	assert_eq!(*accesses.borrow(), [(0xBFFF, false), (0xC000, true), (0xFE04, true), (0xFE30, false)]);
+    }
+}
@@ -0,0 +1,152 @@
+//! Interrupt controller: requests wait in a pending queue until the CPU takes them at an
+//! instruction boundary, which happens once interrupts are enabled and the request's priority
+//! is above the PSR's. Attached devices hold their line up themselves and are checked live.
+
+use super::stack::Kind;
+use super::LC3;
+
+/// An interrupt waiting to be taken
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Request {
+    pub vector: u8, // through x0100 + vector
+    pub priority: u8
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Controller {
+    pending: Vec<Request> // in the order they were posted
+}
+
+impl Controller {
+    /// Queues a request. Posting a vector that's already pending raises its priority if
+    /// higher rather than queueing it twice.
+    pub fn post(&mut self, vector: u8, priority: u8) {
+	let priority = priority & 0b111;
+	match self.pending.iter_mut().find(|r| r.vector == vector) {
+	    Some(request) => request.priority = request.priority.max(priority),
+	    None => self.pending.push(Request { vector, priority })
+	}
+    }
+
+    pub fn pending(&self) -> &[Request] {
+	&self.pending
+    }
+
+    pub fn clear(&mut self) {
+	self.pending.clear();
+    }
+
+    /// The highest priority request above `level`, the earliest posted among equals
+    fn highest(&self, level: u8) -> Option<usize> {
+	let mut best: Option<usize> = None;
+	for (i, request) in self.pending.iter().enumerate() {
+	    if request.priority > level && best.is_none_or(|b| request.priority > self.pending[b].priority) {
+		best = Some(i);
+	    }
+	}
+	best
+    }
+}
+
+impl LC3 {
+    /// Posts an interrupt to the controller, waking a sleeping processor. It's taken at the
+    /// next instruction boundary its priority allows.
+    pub fn post_interrupt(&mut self, vector: u8, priority: u8) {
+	self.sleeping = false;
+	self.interrupts.post(vector, priority);
+    }
+
+    /// Requests waiting for interrupts to be enabled or the PSR priority to drop
+    pub fn pending_interrupts(&self) -> &[Request] {
+	self.interrupts.pending()
+    }
+
+    /// Takes the highest priority request above the current one, queued or from a device,
+    /// returning it. Called between instructions.
+    pub(super) fn service_interrupts(&mut self) -> Option<Request> {
+	if self.ie != 0b1 {
+	    return None;
+	}
+	let level = (self.psr >> 8) as u8 & 0b111;
+	let queued = self.interrupts.highest(level);
+	let device = self.memory.bus.interrupt()
+	    .map(|(vector, priority)| Request { vector, priority: priority & 0b111 })
+	    .filter(|r| r.priority > level);
+	let request = match (queued, device) {
+	    (Some(i), Some(d)) if d.priority > self.interrupts.pending[i].priority => d,
+	    (Some(i), _) => self.interrupts.pending.remove(i),
+	    (None, Some(d)) => d,
+	    (None, None) => return None
+	};
+	self.sleeping = false;
+	self.enter_interrupt(request);
+	Some(request)
+    }
+
+    /// Pushes PSR and PC onto the supervisor stack and runs the handler at the request's
+    /// priority
+    fn enter_interrupt(&mut self, request: Request) {
+	let ret = self.pc as u16;
+	self.enter_supervisor();
+	self.r[6] = self.r[6].wrapping_sub(self.word_size());
+	self.memory.put(self.r[6] as u16, self.psr);
+	self.r[6] = self.r[6].wrapping_sub(self.word_size());
+	self.memory.put(self.r[6] as u16, self.pc);
+	self.psr &= 0b0_111_1000_1111_1111;
+	self.psr |= (request.priority as i16) << 8;
+	self.pc = self.memory.get(self.vector_entry(0x100 + request.vector as u16));
+	self.push_frame(Kind::Interrupt(request.vector), ret);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Controller, Request};
+    use crate::fixtures::Fixture;
+    use crate::lc3::{snapshot, LC3};
+
+    #[test]
+    fn controller_test() {
+	let mut controller = Controller::default();
+	controller.post(0x80, 4);
+	controller.post(0x81, 6);
+	controller.post(0x82, 6);
+	controller.post(0x80, 2); // already pending at a higher priority
+	assert_eq!(controller.pending()[0], Request { vector: 0x80, priority: 4 });
+	assert_eq!(controller.highest(0), Some(1));
+	assert_eq!(controller.highest(5), Some(1));
+	assert_eq!(controller.highest(6), None);
+    }
+
+    #[test]
+    fn deferred_test() {
+	let mut lc3 = Fixture::bare()
+	    .code(&[
+		0b0001_001_001_1_00001, // ADD R1, R1, #1
+		0b0001_001_001_1_00001 // ADD R1, R1, #1
+	    ])
+	    .data(0x0180, &[0x1200])
+	    .data(0x1200, &[
+		0b0001_010_010_1_00001, // ADD R2, R2, #1
+		0b1000_0000_0000_0000 // RTI
+	    ])
+	    .build();
+	lc3.saved_ssp = 0x3000;
+	lc3.psr = 0x0400; // supervisor at priority 4
+	lc3.r[6] = 0x3000;
+	assert!(lc3.request_interrupt(0x80, 4).is_err());
+	assert_eq!(lc3.pending_interrupts().len(), 1); // kept, not dropped
+	let mut other = LC3::new();
+	snapshot::restore(&mut other, &snapshot::save(&lc3, true)).expect("Failed to restore");
+	assert_eq!(other.pending_interrupts(), lc3.pending_interrupts());
+	lc3.clock();
+	assert_eq!((lc3.pc, lc3.r[1]), (0x3001, 1));
+	lc3.psr = 0x0000; // priority drops
+	lc3.clock();
+	assert_eq!((lc3.pc, lc3.r[2]), (0x1201, 1)); // taken before the next instruction
+	assert!(lc3.pending_interrupts().is_empty());
+	lc3.clock();
+	lc3.clock();
+	assert_eq!((lc3.pc, lc3.r[1], lc3.psr & 0x0700), (0x3002, 2, 0)); // back at priority 0
+    }
+}
@@ -0,0 +1,245 @@
+//! The LC-3b variant, chosen with `LC3::new_with_isa(Isa::Lc3b)`
+//!
+//! Memory is byte addressed: the word at an even address `a` holds the byte at `a` in its low
+//! half and `a + 1` in its high half, and is stored at `mem[a]`, so the device registers keep
+//! their LC-3 addresses. PC-relative offsets count words and are shifted left by one. LDI
+//! and STI are gone (their opcodes are illegal); LDR, STR and NOT become LDW, STW and XOR,
+//! and LDB, STB and SHF are new. Word accesses to odd addresses raise exception x03. TRAP
+//! saves PC in R7 and jumps through the table at `trapvect8 << 1`; exception and interrupt
+//! vectors are at `x0200 + (vector << 1)`.
+
+use super::stack::Kind;
+use super::{sign_extend, LC3, LC3IO, LC3Memory};
+use crate::endian::{self, Endian};
+
+/// Instruction set the machine decodes
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Isa {
+    #[default]
+    Lc3,
+    Lc3b
+}
+
+/// Exception raised by a word access to an odd address
+pub const UNALIGNED: u8 = 0x03;
+
+impl LC3 {
+    /// A machine decoding `isa`
+    pub fn new_with_isa(isa: Isa) -> Self {
+	LC3 { isa, ..LC3::new() }
+    }
+
+    /// Addresses a word spans: 1 on the LC-3, 2 bytes on the LC-3b
+    pub(super) fn word_size(&self) -> i16 {
+	match self.isa {
+	    Isa::Lc3 => 1,
+	    Isa::Lc3b => 2
+	}
+    }
+
+    /// Where the handler address for an `x0100 + code` vector is kept
+    pub(super) fn vector_entry(&self, vector: u16) -> u16 {
+	match self.isa {
+	    Isa::Lc3 => vector,
+	    Isa::Lc3b => 0x0200 + ((vector & 0xFF) << 1)
+	}
+    }
+
+    /// Raises the unaligned access exception for a word access to an odd LC-3b address
+    pub(super) fn unaligned(&mut self, addr: u16) -> bool {
+	if self.isa == Isa::Lc3b && addr & 1 != 0 {
+	    self.exception(UNALIGNED);
+	    return true;
+	}
+	false
+    }
+
+    pub(super) fn execute_lc3b(&mut self, instruction: i16) {
+	match (instruction as u16) >> 12 {
+	    0b0001 => self.add(instruction),
+	    0b0101 => self.and(instruction),
+	    0b0000 => self.br(instruction),
+	    0b1100 => self.jmp(instruction),
+	    0b0100 => self.jsr(instruction),
+	    0b0010 => self.ldb(instruction),
+	    0b0110 => self.ldr(instruction), // LDW
+	    0b1110 => self.lea_b(instruction),
+	    0b1001 => self.xor(instruction),
+	    0b1000 => self.rti(instruction),
+	    0b1101 => self.shf(instruction),
+	    0b0011 => self.stb(instruction),
+	    0b0111 => self.stor(instruction), // STW
+	    0b1111 => self.trap_b(instruction),
+	    _ => self.exception(1) // LDI and STI's old opcodes
+	}
+    }
+
+    /// LDB: sign-extended byte at BaseR + boffset6
+    fn ldb(&mut self, instruction: i16) {
+	let dr = (instruction >> 9) & 0b111;
+	let base_r = self.get_reg((instruction >> 6) & 0b111);
+	let addr = base_r.wrapping_add(sign_extend(instruction & 0b111111, 6)) as u16;
+	if self.access_violation(addr) {
+	    return;
+	}
+	let word = self.memory.get(addr & !1);
+	let byte = if addr & 1 == 0 { word & 0xFF } else { (word >> 8) & 0xFF };
+	let res = sign_extend(byte, 8);
+	self.codes(res);
+	self.put_reg(dr, res);
+    }
+
+    /// STB: low byte of SR to BaseR + boffset6, leaving the other half of the word alone
+    fn stb(&mut self, instruction: i16) {
+	let sr = self.get_reg((instruction >> 9) & 0b111);
+	let base_r = self.get_reg((instruction >> 6) & 0b111);
+	let addr = base_r.wrapping_add(sign_extend(instruction & 0b111111, 6)) as u16;
+	if self.access_violation(addr) {
+	    return;
+	}
+	let word = self.memory.peek(addr & !1);
+	let res = if addr & 1 == 0 {
+	    (word & 0xFF00u16 as i16) | (sr & 0xFF)
+	} else {
+	    (word & 0xFF) | (sr << 8)
+	};
+	self.memory.put(addr & !1, res);
+    }
+
+    /// LEA without touching the condition codes
+    fn lea_b(&mut self, instruction: i16) {
+	let dr = (instruction >> 9) & 0b111;
+	let offset = sign_extend(instruction & 0b111_111_111, 9) << 1;
+	self.put_reg(dr, self.pc.wrapping_add(offset));
+    }
+
+    /// XOR, NOT being XOR with #-1
+    fn xor(&mut self, instruction: i16) {
+	let dr = (instruction >> 9) & 0b111;
+	let sr1 = self.get_reg((instruction >> 6) & 0b111);
+	let operand = if instruction & 0b100000 != 0 {
+	    sign_extend(instruction & 0b11111, 5)
+	} else {
+	    self.get_reg(instruction & 0b111)
+	};
+	let res = sr1 ^ operand;
+	self.codes(res);
+	self.put_reg(dr, res);
+    }
+
+    /// SHF: LSHF, RSHFL or RSHFA by amount4
+    fn shf(&mut self, instruction: i16) {
+	let dr = (instruction >> 9) & 0b111;
+	let sr = self.get_reg((instruction >> 6) & 0b111);
+	let amount = (instruction & 0b1111) as u32;
+	let res = match (instruction >> 4) & 0b11 {
+	    0b01 => ((sr as u16) >> amount) as i16, // logical
+	    0b11 => sr >> amount, // arithmetic
+	    _ => sr << amount
+	};
+	self.codes(res);
+	self.put_reg(dr, res);
+    }
+
+    /// TRAP: R7 gets PC, PC gets the word at trapvect8 << 1
+    fn trap_b(&mut self, instruction: i16) {
+	let vector = instruction as u16 & 0xFF;
+	if self.reset_trap == Some(vector as u8) {
+	    self.warm_reset();
+	    self.last_io = LC3IO::Reset;
+	    return;
+	}
+	self.r[7] = self.pc;
+	self.pc = self.memory.get(vector << 1);
+	self.push_frame(Kind::Trap(vector as u8), self.r[7] as u16);
+    }
+}
+
+/// Loads an .obj image assembled for the LC-3b: the origin is a byte address and each word
+/// takes two. Returns the origin.
+pub fn load_obj(memory: &mut LC3Memory, bytes: &[u8]) -> Result<u16, &'static str> {
+    if bytes.len() < 2 {
+	return Err("Object file has no origin");
+    }
+    let words = endian::words(bytes, Endian::Big).map_err(|_| "Object file has an odd number of bytes")?;
+    let origin = words[0] as u16;
+    if origin & 1 != 0 {
+	return Err("Object file origin is not word aligned");
+    }
+    if 2 * (words.len() - 1) > 0x10000 - origin as usize {
+	return Err("Object file runs past the end of memory");
+    }
+    for (i, word) in words[1..].iter().enumerate() {
+	memory.put(origin.wrapping_add(2 * i as u16), *word);
+    }
+    Ok(origin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_obj, Isa, UNALIGNED};
+    use crate::lc3::LC3;
+
+    fn machine(program: &[u16]) -> LC3 {
+	let mut lc3 = LC3::new_with_isa(Isa::Lc3b);
+	let mut obj = vec![0x30, 0x00];
+	for word in program {
+	    obj.extend_from_slice(&word.to_be_bytes());
+	}
+	assert_eq!(load_obj(&mut lc3.memory, &obj), Ok(0x3000));
+	lc3.pc = 0x3000;
+	lc3.r[6] = 0x3000; // supervisor stack
+	lc3.start();
+	lc3
+    }
+
+    #[test]
+    fn lc3b_test() {
+	let mut lc3 = machine(&[
+	    0b1110_000_000000100, // LEA R0, x300A
+	    0b0010_001_000_000001, // LDB R1, R0, #1
+	    0b0110_010_000_000000, // LDW R2, R0, #0
+	    0b0011_001_000_000000, // STB R1, R0, #0
+	    0b1101_011_010_01_0100, // RSHFL R3, R2, #4
+	    0xFF80, // data at x300A: bytes x80, xFF
+	]);
+	for _ in 0..5 {
+	    lc3.clock();
+	}
+	assert_eq!(lc3.pc, 0x300A);
+	assert_eq!(lc3.r[0], 0x300A);
+	assert_eq!(lc3.r[1], -1); // xFF sign extended
+	assert_eq!(lc3.r[2] as u16, 0xFF80);
+	assert_eq!(lc3.memory.peek(0x300A) as u16, 0xFFFF);
+	assert_eq!(lc3.r[3], 0x0FF8);
+	assert_eq!(lc3.psr & 0b111, 0b001);
+    }
+
+    #[test]
+    fn control_test() {
+	let mut lc3 = machine(&[
+	    0b1001_000_000_1_11111, // NOT R0, R0 (XOR #-1)
+	    0b0000_100_000000010, // BRn x3008
+	    0b0001_001_001_1_00001, // ADD R1, R1, #1 (skipped)
+	    0b0001_001_001_1_00010, // ADD R1, R1, #2 (skipped)
+	    0b1111_0000_00010000, // TRAP x10 at x3008
+	    0b1010_000_000000000 // old LDI, illegal
+	]);
+	lc3.memory.put(0x0020, 0x300A); // trap table entry for x10 at x10 << 1
+	lc3.memory.put(0x0200 + 2, 0x4000); // illegal opcode handler
+	lc3.memory.put(0x0200 + 2 * UNALIGNED as u16, 0x5000);
+	for _ in 0..4 {
+	    lc3.clock();
+	}
+	assert_eq!((lc3.r[0], lc3.r[1]), (-1, 0));
+	assert_eq!(lc3.r[7], 0x300A);
+	assert_eq!(lc3.pc, 0x4000);
+	assert_eq!(lc3.r[6], 0x3000 - 4); // two words pushed
+	assert_eq!(lc3.memory.peek(0x3000 - 4), 0x300C);
+
+	lc3.r[2] = 0x3001;
+	lc3.memory.put(0x4000, 0b0110_011_010_000000); // LDW R3, R2, #0
+	lc3.clock();
+	assert_eq!(lc3.pc, 0x5000);
+    }
+}
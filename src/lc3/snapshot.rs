@@ -0,0 +1,668 @@
+//! Whole-machine snapshots
+//!
+//! A snapshot starts with the magic `LC3V`, a format version word, and a kind byte that
+//! says whether the memory image that follows is stored raw (`S`) or run-length compressed
+//! (`Z`), so `restore` accepts either. Deltas (`D`) only store the memory words that differ
+//! from a base snapshot. Everything is big-endian like the .obj format.
+//!
+//! After the registers come each attached device's register range and `Device::save` state,
+//! the interrupt requests still pending, the display's delay and the microseconds it has
+//! left to stay busy, the state a warm reset returns to, the reset trap, mirrors, and
+//! protected ranges. A snapshot only restores onto a machine with the same devices attached.
+
+use super::interrupt::Request;
+use super::{BootState, Isa, Mirror, LC3};
+
+use std::ops::{Range, RangeInclusive};
+
+const MAGIC: &[u8; 4] = b"LC3V";
+
+/// Snapshot format written by this version of the crate
+pub const FORMAT_VERSION: u16 = 1;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Kind {
+    Raw = b'S' as isize,
+    Compressed = b'Z' as isize,
+    Delta = b'D' as isize
+}
+
+/// High bit of a run header marks a run of zero words, otherwise it counts literal words
+const ZERO_RUN: u16 = 0x8000;
+
+/// Serializes the entire machine state
+pub fn save(lc3: &LC3, compress: bool) -> Vec<u8> {
+    let mut out = Vec::new();
+    preamble(&mut out, if compress { Kind::Compressed } else { Kind::Raw });
+    Header::of(lc3).write(&mut out);
+    if compress {
+	compress_words(&lc3.memory.mem, &mut out);
+    } else {
+	for word in lc3.memory.mem.iter() {
+	    push_word(&mut out, *word);
+	}
+    }
+    out
+}
+
+/// Restores a snapshot produced by `save`, compressed or not
+pub fn restore(lc3: &mut LC3, bytes: &[u8]) -> Result<(), &'static str> {
+    let (header, mem) = decode(bytes)?;
+    // only touch the machine once everything parsed
+    header.check(lc3)?;
+    header.apply(lc3);
+    lc3.memory.mem.copy_from_slice(&mem);
+    Ok(())
+}
+
+/// Serializes the machine as the difference from `base`, a snapshot from `save`
+pub fn save_delta(base: &[u8], lc3: &LC3) -> Result<Vec<u8>, &'static str> {
+    let (_, base_mem) = decode(base)?;
+    let mut out = Vec::new();
+    preamble(&mut out, Kind::Delta);
+    Header::of(lc3).write(&mut out);
+    let changed: Vec<usize> = (0..base_mem.len()).filter(|&i| base_mem[i] != lc3.memory.mem[i]).collect();
+    out.extend_from_slice(&(changed.len() as u32).to_be_bytes());
+    for i in changed {
+	push_word(&mut out, i as i16);
+	push_word(&mut out, lc3.memory.mem[i]);
+    }
+    Ok(out)
+}
+
+/// Restores the machine a delta from `save_delta` describes, given the same base
+pub fn restore_delta(lc3: &mut LC3, base: &[u8], delta: &[u8]) -> Result<(), &'static str> {
+    let (_, mut mem) = decode(base)?;
+    let mut r = Reader { bytes: delta, pos: 0 };
+    if read_preamble(&mut r)?.1 != Kind::Delta {
+	return Err("Not an LC-3 snapshot delta");
+    }
+    let header = Header::read(&mut r)?;
+    let count = r.count()?;
+    for _ in 0..count {
+	let addr = r.word()? as u16;
+	mem[addr as usize] = r.word()?;
+    }
+    r.finish()?;
+    header.check(lc3)?;
+    header.apply(lc3);
+    lc3.memory.mem.copy_from_slice(&mem);
+    Ok(())
+}
+
+/// A base snapshot and a delta per later checkpoint, any of which can be materialized
+#[derive(Debug, Default)]
+pub struct Checkpoints {
+    base: Vec<u8>,
+    deltas: Vec<Vec<u8>>
+}
+
+impl Checkpoints {
+    /// Starts a checkpoint series with `lc3` as checkpoint 0
+    pub fn new(lc3: &LC3) -> Self {
+	Self {
+	    base: save(lc3, true),
+	    deltas: Vec::new()
+	}
+    }
+
+    /// Records the machine as a new checkpoint and returns its index
+    pub fn record(&mut self, lc3: &LC3) -> usize {
+	let delta = save_delta(&self.base, lc3).expect("base snapshot is always valid");
+	self.deltas.push(delta);
+	self.deltas.len()
+    }
+
+    /// Number of checkpoints, including the base, so never 0
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+	self.deltas.len() + 1
+    }
+
+    /// Bytes stored for a checkpoint
+    pub fn size_of(&self, index: usize) -> Option<usize> {
+	match index {
+	    0 => Some(self.base.len()),
+	    i => self.deltas.get(i - 1).map(|d| d.len())
+	}
+    }
+
+    /// Restores checkpoint `index` into `lc3`
+    pub fn materialize(&self, index: usize, lc3: &mut LC3) -> Result<(), &'static str> {
+	match index {
+	    0 => restore(lc3, &self.base),
+	    i => match self.deltas.get(i - 1) {
+		Some(delta) => restore_delta(lc3, &self.base, delta),
+		None => Err("No such checkpoint")
+	    }
+	}
+    }
+}
+
+/// Format version a snapshot or delta was written with
+pub fn format_version(bytes: &[u8]) -> Result<u16, &'static str> {
+    read_preamble(&mut Reader { bytes, pos: 0 }).map(|(version, _)| version)
+}
+
+fn preamble(out: &mut Vec<u8>, kind: Kind) {
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+    out.push(kind as u8);
+}
+
+fn read_preamble(r: &mut Reader) -> Result<(u16, Kind), &'static str> {
+    if r.take(4)? != MAGIC {
+	return Err("Not an LC-3 snapshot");
+    }
+    let version = r.word()? as u16;
+    if version > FORMAT_VERSION {
+	return Err("Snapshot was written by a newer lc3-emu, upgrade to load it");
+    }
+    if version != FORMAT_VERSION {
+	return Err("Unsupported snapshot version");
+    }
+    let kind = r.take(1)?[0];
+    match kind {
+	b'S' => Ok((version, Kind::Raw)),
+	b'Z' => Ok((version, Kind::Compressed)),
+	b'D' => Ok((version, Kind::Delta)),
+	_ => Err("Unknown snapshot kind")
+    }
+}
+
+/// Parses a full snapshot without touching any machine
+fn decode(bytes: &[u8]) -> Result<(Header, Vec<i16>), &'static str> {
+    let mut r = Reader { bytes, pos: 0 };
+    let compressed = match read_preamble(&mut r)?.1 {
+	Kind::Raw => false,
+	Kind::Compressed => true,
+	Kind::Delta => return Err("Snapshot is a delta, restore it with its base")
+    };
+    let header = Header::read(&mut r)?;
+    let mut mem = vec![0i16; 65536];
+    if compressed {
+	decompress_words(&mut r, &mut mem)?;
+    } else {
+	for word in mem.iter_mut() {
+	    *word = r.word()?;
+	}
+    }
+    r.finish()?;
+    Ok((header, mem))
+}
+
+/// Everything in a snapshot except memory
+#[derive(Debug, Clone)]
+struct Header {
+    words: [i16; 12], // pc, psr, saved_usp, saved_ssp, r0-r7
+    flags: u8,
+    instructions: u64,
+    ticks: u64,
+    devices: Vec<DeviceState>, // each attached device, in attach order
+    interrupts: Vec<Request>, // pending, in the order they were posted
+    display_busy: u32,
+    display_delay: u32,
+    boot: BootState,
+    reset_trap: Option<u8>,
+    mirrors: Vec<Mirror>,
+    protected: Vec<RangeInclusive<u16>>
+}
+
+/// An attached device's registers and what its `Device::save` returned
+#[derive(Debug, Clone)]
+struct DeviceState {
+    range: Range<u16>,
+    state: Vec<i16>
+}
+
+impl Header {
+    fn of(lc3: &LC3) -> Self {
+	let mut words = [0i16; 12];
+	words[..4].copy_from_slice(&[lc3.pc, lc3.psr, lc3.saved_usp, lc3.saved_ssp]);
+	words[4..].copy_from_slice(&lc3.regs());
+	Self {
+	    words,
+	    flags: lc3.halted as u8
+		| (lc3.sleeping as u8) << 1
+		| (lc3.memory.keyboard_ready as u8) << 2
+		| (lc3.ie & 0b1) << 3
+		| (lc3.legacy_traps as u8) << 4
+		| ((lc3.isa == Isa::Lc3b) as u8) << 5,
+	    instructions: lc3.instructions,
+	    ticks: lc3.ticks,
+	    devices: lc3.memory.bus.ranges().into_iter().zip(lc3.memory.bus.save())
+		.map(|(range, state)| DeviceState { range, state })
+		.collect(),
+	    interrupts: lc3.interrupts.pending().to_vec(),
+	    display_busy: lc3.memory.display_busy().min(u32::MAX as u64) as u32,
+	    display_delay: lc3.memory.display_delay,
+	    boot: lc3.boot,
+	    reset_trap: lc3.reset_trap,
+	    mirrors: lc3.memory.mirrors.clone(),
+	    protected: lc3.memory.protected.clone()
+	}
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+	for word in &self.words {
+	    push_word(out, *word);
+	}
+	out.push(self.flags);
+	out.extend_from_slice(&self.instructions.to_be_bytes());
+	out.extend_from_slice(&self.ticks.to_be_bytes());
+	push_word(out, self.devices.len() as i16);
+	for device in &self.devices {
+	    push_word(out, device.range.start as i16);
+	    push_word(out, device.range.end as i16);
+	    push_word(out, device.state.len() as i16);
+	    for word in &device.state {
+		push_word(out, *word);
+	    }
+	}
+	push_word(out, self.interrupts.len() as i16);
+	for request in &self.interrupts {
+	    push_word(out, (request.vector as i16) << 8 | request.priority as i16);
+	}
+	out.extend_from_slice(&self.display_busy.to_be_bytes());
+	out.extend_from_slice(&self.display_delay.to_be_bytes());
+	let boot = self.boot;
+	for word in [boot.pc, boot.psr, boot.r6, boot.saved_usp, boot.saved_ssp] {
+	    push_word(out, word);
+	}
+	push_word(out, self.reset_trap.map_or(-1, |vector| vector as i16));
+	push_word(out, self.mirrors.len() as i16);
+	for m in &self.mirrors {
+	    for word in [m.base, m.mirror, m.len] {
+		push_word(out, word as i16);
+	    }
+	}
+	push_word(out, self.protected.len() as i16);
+	for range in &self.protected {
+	    push_word(out, *range.start() as i16);
+	    push_word(out, *range.end() as i16);
+	}
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, &'static str> {
+	let mut words = [0i16; 12];
+	for word in words.iter_mut() {
+	    *word = r.word()?;
+	}
+	let flags = r.take(1)?[0];
+	let instructions = r.long()?;
+	let ticks = r.long()?;
+	let mut devices = Vec::new();
+	for _ in 0..r.word()? as u16 {
+	    let range = r.word()? as u16..r.word()? as u16;
+	    let len = r.word()? as u16;
+	    let state = (0..len).map(|_| r.word()).collect::<Result<_, _>>()?;
+	    devices.push(DeviceState { range, state });
+	}
+	let mut interrupts = Vec::new();
+	for _ in 0..r.word()? as u16 {
+	    let word = r.word()? as u16;
+	    interrupts.push(Request { vector: (word >> 8) as u8, priority: word as u8 & 0b111 });
+	}
+	let display_busy = r.count()?;
+	let display_delay = r.count()?;
+	let boot = BootState {
+	    pc: r.word()?,
+	    psr: r.word()?,
+	    r6: r.word()?,
+	    saved_usp: r.word()?,
+	    saved_ssp: r.word()?
+	};
+	let reset_trap = match r.word()? {
+	    -1 => None,
+	    vector => Some(vector as u8)
+	};
+	let mut mirrors = Vec::new();
+	for _ in 0..r.word()? as u16 {
+	    mirrors.push(Mirror { base: r.word()? as u16, mirror: r.word()? as u16, len: r.word()? as u16 });
+	}
+	let mut protected = Vec::new();
+	for _ in 0..r.word()? as u16 {
+	    protected.push(r.word()? as u16..=r.word()? as u16);
+	}
+	Ok(Self {
+	    words,
+	    flags,
+	    instructions,
+	    ticks,
+	    devices,
+	    interrupts,
+	    display_busy,
+	    display_delay,
+	    boot,
+	    reset_trap,
+	    mirrors,
+	    protected
+	})
+    }
+
+    /// Whether the header can be applied to `lc3`, checked before touching the machine
+    fn check(&self, lc3: &LC3) -> Result<(), &'static str> {
+	let ranges = lc3.memory.bus.ranges();
+	if self.devices.len() != ranges.len()
+	    || self.devices.iter().zip(&ranges).any(|(d, range)| d.range != *range) {
+	    return Err("Snapshot device state doesn't match the attached devices");
+	}
+	Ok(())
+    }
+
+    fn apply(&self, lc3: &mut LC3) {
+	lc3.pc = self.words[0];
+	lc3.psr = self.words[1];
+	lc3.saved_usp = self.words[2];
+	lc3.saved_ssp = self.words[3];
+	for (code, word) in self.words[4..].iter().enumerate() {
+	    lc3.put_reg(code as i16, *word);
+	}
+	lc3.halted = self.flags & 0b1 != 0;
+	lc3.sleeping = self.flags & 0b10 != 0;
+	lc3.memory.keyboard_ready = self.flags & 0b100 != 0;
+	lc3.ie = (self.flags >> 3) & 0b1;
+	lc3.legacy_traps = self.flags & 0b1_0000 != 0;
+	lc3.isa = if self.flags & 0b10_0000 != 0 { Isa::Lc3b } else { Isa::Lc3 };
+	lc3.instructions = self.instructions;
+	lc3.ticks = self.ticks;
+	lc3.boot = self.boot;
+	lc3.reset_trap = self.reset_trap;
+	lc3.memory.mirrors = self.mirrors.clone();
+	lc3.memory.protected = self.protected.clone();
+	lc3.memory.display_delay = self.display_delay;
+	lc3.memory.clock_time = lc3.time.now(lc3.ticks);
+	lc3.memory.display_until = lc3.memory.clock_time + self.display_busy as u64;
+	lc3.poll = None;
+	lc3.interrupts.clear();
+	for request in &self.interrupts {
+	    lc3.interrupts.post(request.vector, request.priority);
+	}
+	lc3.clear_history(); // it describes the machine being replaced
+	lc3.calls.clear();
+	let states: Vec<Vec<i16>> = self.devices.iter().map(|d| d.state.clone()).collect();
+	lc3.memory.bus.restore(&states);
+    }
+}
+
+/// Writes a snapshot to a file
+pub fn save_file(lc3: &LC3, path: &str, compress: bool) -> std::io::Result<()> {
+    std::fs::write(path, save(lc3, compress))
+}
+
+/// Restores a snapshot from a file
+pub fn restore_file(lc3: &mut LC3, path: &str) -> std::io::Result<()> {
+    let bytes = std::fs::read(path)?;
+    restore(lc3, &bytes).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+fn push_word(out: &mut Vec<u8>, word: i16) {
+    out.extend_from_slice(&word.to_be_bytes());
+}
+
+/// Run-length encodes zero runs, everything else is stored literally
+fn compress_words(words: &[i16], out: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < words.len() {
+	let start = i;
+	if words[i] == 0 {
+	    while i < words.len() && words[i] == 0 && i - start < 0x7FFF {
+		i += 1;
+	    }
+	    push_word(out, (ZERO_RUN | (i - start) as u16) as i16);
+	} else {
+	    // a literal run ends at the first pair of zeros, a lone zero is cheaper inline
+	    while i < words.len() && i - start < 0x7FFF
+		&& !(words[i] == 0 && words.get(i + 1).is_none_or(|w| *w == 0)) {
+		i += 1;
+	    }
+	    push_word(out, (i - start) as i16);
+	    for word in &words[start..i] {
+		push_word(out, *word);
+	    }
+	}
+    }
+}
+
+fn decompress_words(r: &mut Reader, mem: &mut [i16]) -> Result<(), &'static str> {
+    let mut i = 0;
+    while i < mem.len() {
+	let header = r.word()? as u16;
+	let len = (header & !ZERO_RUN) as usize;
+	if len == 0 || i + len > mem.len() {
+	    return Err("Corrupt memory image in snapshot");
+	}
+	if header & ZERO_RUN == 0 {
+	    for word in mem[i..i + len].iter_mut() {
+		*word = r.word()?;
+	    }
+	} // zero runs are already zeroed
+	i += len;
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+	if self.pos + len > self.bytes.len() {
+	    return Err("Snapshot is truncated");
+	}
+	let out = &self.bytes[self.pos..self.pos + len];
+	self.pos += len;
+	Ok(out)
+    }
+
+    fn word(&mut self) -> Result<i16, &'static str> {
+	let b = self.take(2)?;
+	Ok(i16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn finish(&self) -> Result<(), &'static str> {
+	if self.pos != self.bytes.len() {
+	    return Err("Trailing data after snapshot");
+	}
+	Ok(())
+    }
+
+    fn count(&mut self) -> Result<u32, &'static str> {
+	let mut b = [0u8; 4];
+	b.copy_from_slice(self.take(4)?);
+	Ok(u32::from_be_bytes(b))
+    }
+
+    fn long(&mut self) -> Result<u64, &'static str> {
+	let mut b = [0u8; 8];
+	b.copy_from_slice(self.take(8)?);
+	Ok(u64::from_be_bytes(b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Isa, LC3};
+    use super::super::device::Device;
+    use super::{format_version, restore, save, Checkpoints, Header, FORMAT_VERSION};
+    use std::ops::Range;
+
+    /// Holds the last word written to xFE20
+    #[derive(Debug, Default)]
+    struct Latch(i16);
+
+    impl Device for Latch {
+	fn range(&self) -> Range<u16> {
+	    0xFE20..0xFE21
+	}
+	fn read(&mut self, _addr: u16) -> i16 {
+	    self.0
+	}
+	fn write(&mut self, _addr: u16, value: i16) {
+	    self.0 = value;
+	}
+	fn save(&self) -> Vec<i16> {
+	    vec![self.0]
+	}
+	fn restore(&mut self, state: &[i16]) {
+	    self.0 = state[0];
+	}
+    }
+
+    /// A register at xFE22 that holds nothing
+    #[derive(Debug)]
+    struct Elsewhere;
+
+    impl Device for Elsewhere {
+	fn range(&self) -> Range<u16> {
+	    0xFE22..0xFE23
+	}
+	fn read(&mut self, _addr: u16) -> i16 {
+	    0
+	}
+	fn write(&mut self, _addr: u16, _value: i16) {}
+    }
+
+    fn machine() -> LC3 {
+	let mut lc3 = LC3::new();
+	lc3.memory.put(0x3000, 0b0001_001_010_1_00001);
+	lc3.memory.put(0x3002, 0x1234);
+	lc3.memory.put(0x4000, -1);
+	lc3.memory.put(0xFFFF, 7);
+	lc3.pc = 0x3001;
+	lc3.r[1] = 0x55;
+	lc3.r[7] = -2;
+	lc3.saved_ssp = 0x3000;
+	lc3.instructions = 42;
+	lc3.memory.display_until = 3;
+	lc3
+    }
+
+    #[test]
+    fn roundtrip_test() {
+	for &compress in &[false, true] {
+	    let lc3 = machine();
+	    let bytes = save(&lc3, compress);
+	    let mut other = LC3::new();
+	    restore(&mut other, &bytes).expect("Failed to restore");
+	    assert_eq!(other.pc, 0x3001);
+	    assert_eq!(other.r[1], 0x55);
+	    assert_eq!(other.r[7], -2);
+	    assert_eq!(other.saved_ssp, 0x3000);
+	    assert_eq!(other.instructions, 42);
+	    assert_eq!(other.memory.display_busy(), 3);
+	    assert!(other.halted);
+	    assert_eq!(&other.memory.mem[..], &lc3.memory.mem[..]);
+	}
+    }
+
+    #[test]
+    fn compression_test() {
+	let lc3 = machine();
+	assert!(save(&lc3, true).len() < 128);
+	assert!(save(&lc3, false).len() > 65536 * 2);
+    }
+
+    #[test]
+    fn bad_snapshot_test() {
+	let mut lc3 = LC3::new();
+	assert!(restore(&mut lc3, b"nope").is_err());
+	let bytes = save(&machine(), true);
+	assert!(restore(&mut lc3, &bytes[..bytes.len() - 1]).is_err());
+	assert_eq!(lc3.pc, 0); // untouched
+    }
+
+    #[test]
+    fn checkpoint_test() {
+	let mut lc3 = machine();
+	let mut checkpoints = Checkpoints::new(&lc3);
+	lc3.memory.put(0x5000, 0x0BAD);
+	lc3.r[3] = 3;
+	assert_eq!(checkpoints.record(&lc3), 1);
+	lc3.memory.put(0x5000, 0x0F00);
+	lc3.memory.put(0x3002, 0);
+	lc3.pc = 0x3100;
+	assert_eq!(checkpoints.record(&lc3), 2);
+	assert_eq!(checkpoints.len(), 3);
+	let mut header = Vec::new();
+	Header::of(&lc3).write(&mut header);
+	// preamble, header, change count, then two changed words with their addresses
+	assert_eq!(checkpoints.size_of(2), Some(7 + header.len() + 4 + 2 * 4));
+
+	let mut other = LC3::new();
+	checkpoints.materialize(1, &mut other).expect("Failed to materialize");
+	assert_eq!(other.memory.mem[0x5000], 0x0BAD);
+	assert_eq!(other.memory.mem[0x3002], 0x1234);
+	assert_eq!(other.r[3], 3);
+	checkpoints.materialize(2, &mut other).expect("Failed to materialize");
+	assert_eq!(other.memory.mem[0x5000], 0x0F00);
+	assert_eq!(other.memory.mem[0x3002], 0);
+	assert_eq!(other.pc, 0x3100);
+	checkpoints.materialize(0, &mut other).expect("Failed to materialize");
+	assert_eq!(other.memory.mem[0x5000], 0);
+	assert_eq!(other.pc, 0x3001);
+	assert!(checkpoints.materialize(3, &mut other).is_err());
+    }
+
+    #[test]
+    fn device_test() {
+	let mut lc3 = machine();
+	lc3.legacy_traps = true;
+	lc3.isa = Isa::Lc3b;
+	lc3.memory.attach(Box::new(Latch::default())).unwrap();
+	lc3.memory.put(0xFE20, 0x0ACE);
+	let bytes = save(&lc3, true);
+
+	let mut other = LC3::new();
+	assert!(restore(&mut other, &bytes).is_err()); // no device to take the state
+	other.memory.attach(Box::new(Elsewhere)).unwrap();
+	assert!(restore(&mut other, &bytes).is_err()); // one device, but not this one
+	other.memory.bus().clear();
+	other.memory.attach(Box::new(Latch::default())).unwrap();
+	restore(&mut other, &bytes).expect("Failed to restore");
+	assert_eq!(other.memory.get(0xFE20), 0x0ACE);
+	assert!(other.legacy_traps);
+	assert_eq!(other.isa, Isa::Lc3b);
+
+	// a snapshot taken without devices has no state for the ones attached here either
+	let bare = save(&machine(), true);
+	assert!(restore(&mut other, &bare).is_err());
+    }
+
+    #[test]
+    fn config_test() {
+	let mut lc3 = machine();
+	lc3.pc = 0x3000;
+	lc3.start();
+	lc3.pc = 0x3456;
+	lc3.reset_trap = Some(0x26);
+	lc3.memory.display_delay = 9;
+	lc3.memory.add_mirror(0x4000, 0x8000, 0x10).unwrap();
+	lc3.memory.protect_privileged();
+	let bytes = save(&lc3, true);
+
+	let mut other = LC3::new();
+	restore(&mut other, &bytes).expect("Failed to restore");
+	assert_eq!(other.reset_trap, Some(0x26));
+	assert_eq!(other.memory.display_delay, 9);
+	assert_eq!(other.memory.mirrors(), lc3.memory.mirrors());
+	assert_eq!(other.memory.protection(), lc3.memory.protection());
+	assert_eq!(other.memory.get(0x8000), -1); // x4000 through the mirror
+	other.warm_reset();
+	assert_eq!(other.pc, 0x3000); // where start() found it, not PC 0
+    }
+
+    #[test]
+    fn version_test() {
+	let bytes = save(&machine(), true);
+	assert_eq!(format_version(&bytes), Ok(FORMAT_VERSION));
+
+	// snapshots from the future don't load
+	let mut lc3 = LC3::new();
+	let mut future = bytes.clone();
+	future[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_be_bytes());
+	assert!(restore(&mut lc3, &future).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! Shadow call stack: JSR, JSRR, TRAP, exceptions and interrupts push a frame, and a JMP, RET
+//! or RTI to a frame's return address pops it and anything above it. The debugger's `next`
+//! and `finish` step over and out of calls with it.
+
+use super::batch::StopReason;
+use super::LC3;
+
+/// Frames kept before the oldest are dropped, for recursion that never unwinds
+const DEPTH: usize = 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Kind {
+    Subroutine, // JSR or JSRR
+    Trap(u8),
+    Interrupt(u8),
+    Exception(u8)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frame {
+    pub kind: Kind,
+    pub from: u16, // the calling instruction, or where an interrupt or exception left off
+    pub to: u16, // the routine entered
+    pub ret: u16 // where it returns to
+}
+
+impl LC3 {
+    /// Calls in progress, outermost first
+    pub fn call_stack(&self) -> &[Frame] {
+	&self.calls
+    }
+
+    /// Like `run_steps()`, but also stops before the next instruction once the call stack is
+    /// no deeper than `depth`, returning `None` then
+    pub fn run_to_depth(&mut self, depth: usize, limit: u64) -> Option<StopReason> {
+	for _ in 0..limit {
+	    if self.calls.len() <= depth {
+		return None;
+	    }
+	    match self.run_steps(1) {
+		StopReason::Limit => (),
+		stop => return Some(stop)
+	    }
+	}
+	if self.calls.len() <= depth { None } else { Some(StopReason::Limit) }
+    }
+
+    /// Called once PC is in the routine, with the address it returns to
+    pub(super) fn push_frame(&mut self, kind: Kind, ret: u16) {
+	self.save_calls();
+	if self.calls.len() == DEPTH {
+	    self.calls.remove(0);
+	}
+	let from = match kind {
+	    Kind::Subroutine | Kind::Trap(_) => self.last_instruction.map_or(ret, |(addr, _)| addr),
+	    Kind::Interrupt(_) | Kind::Exception(_) => ret
+	};
+	self.calls.push(Frame { kind, from, to: self.pc as u16, ret });
+    }
+
+    /// Called after a jump or RTI, pops frames down to the one it returned from
+    pub(super) fn returned(&mut self) {
+	let pc = self.pc as u16;
+	if let Some(i) = self.calls.iter().rposition(|f| f.ret == pc) {
+	    self.save_calls();
+	    self.calls.truncate(i);
+	}
+    }
+
+    /// `push_frame()` for a JSR or JSRR from `from` to `to`, with no undo history to save
+    /// the stack for
+    pub(super) fn push_call(&mut self, from: u16, to: u16, ret: u16) {
+	debug_assert!(self.history.is_none());
+	if self.calls.len() == DEPTH {
+	    self.calls.remove(0);
+	}
+	self.calls.push(Frame { kind: Kind::Subroutine, from, to, ret });
+    }
+
+    /// `returned()` for a jump to `pc`, with no undo history to save the stack for
+    pub(super) fn returned_to(&mut self, pc: u16) {
+	debug_assert!(self.history.is_none());
+	if let Some(i) = self.calls.iter().rposition(|f| f.ret == pc) {
+	    self.calls.truncate(i);
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Frame, Kind};
+    use crate::fixtures::Fixture;
+
+    #[test]
+    fn stack_test() {
+	let mut lc3 = Fixture::with_os()
+	    .code(&[
+		0b0100_1_00000000010, // JSR x3003
+		0b1111_0000_00100101, // HALT
+		0,
+		0b0001_001_001_1_00001, // ADD R1, R1, #1 at x3003
+		0b1111_0000_00100001, // OUT
+		0b1100_000_111_000000 // RET
+	    ])
+	    .build();
+	lc3.record_history(100);
+	lc3.clock();
+	assert_eq!(lc3.call_stack(), &[Frame { kind: Kind::Subroutine, from: 0x3000, to: 0x3003, ret: 0x3001 }]);
+	lc3.clock();
+	lc3.clock();
+	assert_eq!(lc3.call_stack().len(), 2);
+	assert_eq!(lc3.call_stack()[1].kind, Kind::Trap(0x21));
+	assert_eq!(lc3.call_stack()[1].from, 0x3004);
+	assert_eq!(lc3.run_to_depth(1, 1000), None);
+	assert_eq!(lc3.pc, 0x3005);
+	assert_eq!(lc3.take_output().len(), 1); // OUT's character
+	lc3.clock();
+	assert!(lc3.call_stack().is_empty());
+	assert_eq!(lc3.pc, 0x3001);
+	lc3.step_back(1);
+	assert_eq!(lc3.call_stack().len(), 1); // back inside the subroutine
+    }
+}
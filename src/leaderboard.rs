@@ -0,0 +1,164 @@
+use crate::json::{self, Value};
+
+const USAGE: &str = "usage: lc3-emu leaderboard <report.json>... [--by cycles|instructions|memory] [--salt S] [--names] [--json <out.json>]";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Metric {
+    Cycles,
+    Instructions,
+    Memory
+}
+
+/// One submission's numbers from its `lc3-emu report --json` output
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub id: String,
+    pub name: String,
+    pub halted: bool,
+    pub instructions: u64,
+    pub cycles: u64,
+    pub memory: u64 // program words + words written + user stack
+}
+
+impl Entry {
+    pub fn from_report(name: &str, report: &Value, salt: &str) -> Result<Self, String> {
+	let number = |v: Option<&Value>, key: &str| {
+	    v.and_then(|v| v.as_f64()).map(|n| n as u64).ok_or(format!("{}: report has no {}", name, key))
+	};
+	let memory = report.get("memory");
+	Ok(Self {
+	    id: anonymize(name, salt),
+	    name: name.to_string(),
+	    halted: report.get("halted").and_then(|h| h.as_bool()).unwrap_or(false),
+	    instructions: number(report.get("instructions"), "instructions")?,
+	    cycles: number(report.get("cycles"), "cycles")?,
+	    memory: ["program_words", "written_words", "stack_words"].iter()
+		.map(|key| number(memory.and_then(|m| m.get(key)), key))
+		.sum::<Result<u64, String>>()?
+	})
+    }
+
+    fn score(&self, by: Metric) -> u64 {
+	match by {
+	    Metric::Cycles => self.cycles,
+	    Metric::Instructions => self.instructions,
+	    Metric::Memory => self.memory
+	}
+    }
+}
+
+/// Stable short ID for a submission name, FNV-1a so it doesn't change between builds
+pub fn anonymize(name: &str, salt: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in salt.bytes().chain(std::iter::once(0)).chain(name.bytes()) {
+	hash ^= byte as u64;
+	hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("sub-{:06x}", hash & 0xFFFFFF)
+}
+
+/// Best first; submissions that didn't halt go last, ties keep their input order
+pub fn rank(mut entries: Vec<Entry>, by: Metric) -> Vec<Entry> {
+    entries.sort_by_key(|e| (!e.halted, e.score(by)));
+    entries
+}
+
+/// `lc3-emu leaderboard`, returns the process exit code
+pub fn main(args: &[String]) -> i32 {
+    match leaderboard(args) {
+	Ok(()) => 0,
+	Err(e) => {
+	    eprintln!("{}", e);
+	    1
+	}
+    }
+}
+
+fn leaderboard(args: &[String]) -> Result<(), String> {
+    let mut paths = Vec::new();
+    let mut by = Metric::Cycles;
+    let mut salt = String::new();
+    let mut names = false;
+    let mut json_out = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+	match arg.as_str() {
+	    "--by" => by = match args.next().map(|a| a.as_str()) {
+		Some("cycles") => Metric::Cycles,
+		Some("instructions") => Metric::Instructions,
+		Some("memory") => Metric::Memory,
+		_ => return Err(USAGE.to_string())
+	    },
+	    "--salt" => salt = args.next().ok_or(USAGE)?.clone(),
+	    "--names" => names = true,
+	    "--json" => json_out = Some(args.next().ok_or(USAGE)?.clone()),
+	    a if !a.starts_with("--") => paths.push(a.to_string()),
+	    _ => return Err(USAGE.to_string())
+	}
+    }
+    if paths.is_empty() {
+	return Err(USAGE.to_string());
+    }
+
+    let mut entries = Vec::new();
+    for path in &paths {
+	let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+	let report = json::parse(&text).map_err(|e| format!("{}: {}", path, e))?;
+	// reports name the program they ran, fall back to the report's own path
+	let name = report.get("program").and_then(|p| p.as_str()).unwrap_or(path).to_string();
+	entries.push(Entry::from_report(&name, &report, &salt)?);
+    }
+    let ranked = rank(entries, by);
+
+    println!("{:>4}  {:<24} {:>12} {:>12} {:>8}", "rank", "submission", "cycles", "instructions", "memory");
+    for (i, e) in ranked.iter().enumerate() {
+	let who = if names { &e.name } else { &e.id };
+	let rank = if e.halted { (i + 1).to_string() } else { "-".to_string() };
+	println!("{:>4}  {:<24} {:>12} {:>12} {:>8}{}", rank, who, e.cycles, e.instructions, e.memory,
+		 if e.halted { "" } else { "  (did not halt)" });
+    }
+
+    if let Some(path) = json_out {
+	let rows = ranked.iter().enumerate().map(|(i, e)| json::object(vec![
+	    ("rank", (i as u64 + 1).into()),
+	    ("id", e.id.as_str().into()),
+	    ("halted", e.halted.into()),
+	    ("cycles", e.cycles.into()),
+	    ("instructions", e.instructions.into()),
+	    ("memory", e.memory.into())
+	])).collect();
+	std::fs::write(&path, Value::Array(rows).to_string() + "\n").map_err(|e| format!("{}: {}", path, e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{anonymize, rank, Entry, Metric};
+    use crate::json;
+
+    fn entry(name: &str, report: &str) -> Entry {
+	Entry::from_report(name, &json::parse(report).unwrap(), "salt").expect("Failed to read report")
+    }
+
+    #[test]
+    fn rank_test() {
+	let memory = r#""memory":{"program_words":10,"written_words":2,"stack_words":1}"#;
+	let a = entry("alice.obj", &format!(r#"{{"halted":true,"instructions":50,"cycles":60,{}}}"#, memory));
+	let b = entry("bob.obj", &format!(r#"{{"halted":true,"instructions":40,"cycles":70,{}}}"#, memory));
+	let c = entry("carol.obj", &format!(r#"{{"halted":false,"instructions":5,"cycles":5,{}}}"#, memory));
+	assert_eq!(a.memory, 13);
+	let by_cycles: Vec<String> = rank(vec![c.clone(), b.clone(), a.clone()], Metric::Cycles).into_iter().map(|e| e.name).collect();
+	assert_eq!(by_cycles, vec!["alice.obj", "bob.obj", "carol.obj"]);
+	let by_instructions: Vec<String> = rank(vec![a, b, c], Metric::Instructions).into_iter().map(|e| e.name).collect();
+	assert_eq!(by_instructions, vec!["bob.obj", "alice.obj", "carol.obj"]);
+    }
+
+    #[test]
+    fn anonymize_test() {
+	assert_eq!(anonymize("alice.obj", "x"), anonymize("alice.obj", "x"));
+	assert_ne!(anonymize("alice.obj", "x"), anonymize("alice.obj", "y"));
+	assert_ne!(anonymize("alice.obj", "x"), anonymize("bob.obj", "x"));
+	assert!(!anonymize("alice.obj", "x").contains("alice"));
+    }
+}
@@ -0,0 +1,68 @@
+//! LC-3 emulator
+//!
+//! [`LC3`] is the machine: registers, PSR and a [`LC3Memory`] with the memory-mapped
+//! devices. Build one with [`LC3::new`], load code with [`LC3Memory::write_words`] or
+//! [`loader::load_obj`], then call [`LC3::clock`] once per instruction and act on the
+//! [`LC3IO`] it returns. Registers are public fields (or [`LC3::regs`]); memory is read with
+//! [`LC3Memory::get`] like the CPU does, or [`LC3Memory::peek`] without device side effects.
+//!
+//! ```
+//! use lc3_emu::lc3::{LC3, LC3IO};
+//!
+//! let mut lc3 = LC3::new();
+//! lc3.memory.write_words(0x3000, &[
+//!     0b0001_001_001_1_00101,     // ADD R1, R1, #5
+//!     0b0101_000_000_1_00000,     // AND R0, R0, #0
+//!     0b1011_000_000000000u16 as i16, // STI R0, MCR
+//!     0xFFFEu16 as i16            // MCR: x0000 there halts the machine
+//! ]);
+//! lc3.pc = 0x3000;
+//! lc3.start();
+//! while !matches!(lc3.clock(), LC3IO::Halt) {}
+//! assert_eq!(lc3.r[1], 5);
+//! assert_eq!(lc3.memory.peek(0x3000), 0b0001_001_001_1_00101);
+//! ```
+//!
+//! [`os::boot`] loads an object file on top of the built-in supervisor and starts it in user
+//! mode. The remaining modules are the tooling behind the `lc3-emu` binary's subcommands.
+
+#![allow(overflowing_literals, clippy::unusual_byte_groupings)]
+
+#[macro_use]
+pub mod testing;
+pub mod asm;
+pub mod bench;
+#[cfg(feature = "dap")]
+pub mod dap;
+pub mod datapath;
+pub mod debugger;
+pub mod disasm;
+pub mod disk;
+pub mod dump;
+pub mod endian;
+pub mod fixtures;
+pub mod grade;
+pub mod json;
+pub mod lc3;
+pub mod leaderboard;
+pub mod loader;
+pub mod minimize;
+pub mod os;
+pub mod pace;
+pub mod repl;
+pub mod report;
+pub mod rng;
+pub mod selftest;
+pub mod slow;
+pub mod stdlib;
+pub mod symbols;
+pub mod testgen;
+pub mod trace;
+pub mod traplog;
+pub mod video;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use lc3::{LC3, LC3IO, LC3Memory};
@@ -0,0 +1,100 @@
+use crate::endian::{self, Endian};
+use crate::lc3::LC3Memory;
+use crate::rng::Rng;
+
+use std::ops::Range;
+
+/// User memory generated data may be placed in, leaving room below xFE00 for the user stack
+pub const DATA_REGION: Range<u16> = 0x3000..0xF000;
+
+/// Loads an lc3as .obj image (big-endian origin word, then data words) and returns the origin
+pub fn load_obj(memory: &mut LC3Memory, bytes: &[u8]) -> Result<u16, &'static str> {
+    if bytes.len() < 2 {
+	return Err("Object file has no origin");
+    }
+    let words = endian::words(bytes, Endian::Big).map_err(|_| "Object file has an odd number of bytes")?;
+    let origin = words[0] as u16;
+    if words.len() - 1 > 0x10000 - origin as usize {
+	return Err("Object file runs past the end of memory");
+    }
+    memory.write_words(origin, &words[1..]);
+    Ok(origin)
+}
+
+/// Writes each block into `region` clear of `reserved` and of each other, returning the
+/// addresses used. Without a seed blocks are packed first-fit in order, with one they go at
+/// addresses chosen by the seed so programs can't rely on where their data is.
+pub fn place_blocks(memory: &mut LC3Memory, blocks: &[Vec<i16>], region: Range<u16>,
+		    reserved: &[Range<u16>], seed: Option<u64>) -> Result<Vec<u16>, &'static str> {
+    let mut taken: Vec<Range<u32>> = reserved.iter().map(|r| r.start as u32..r.end as u32).collect();
+    let mut rng = seed.map(Rng::new);
+    let mut addrs = Vec::new();
+    for block in blocks {
+	let len = block.len().max(1) as u32;
+	let (low, high) = (region.start as u32, region.end as u32);
+	if low + len > high {
+	    return Err("Data block doesn't fit in the data region");
+	}
+	let free = |start: u32| taken.iter().all(|t| start + len <= t.start || t.end <= start);
+	let mut found = None;
+	if let Some(rng) = rng.as_mut() {
+	    for _ in 0..1000 {
+		let start = rng.range(low as i64, (high - len) as i64) as u32;
+		if free(start) {
+		    found = Some(start);
+		    break;
+		}
+	    }
+	}
+	// first fit, also the fallback when random probing keeps colliding
+	let start = match found {
+	    Some(start) => start,
+	    None => (low..=high - len).find(|&s| free(s)).ok_or("No room left for data block")?
+	};
+	memory.write_words(start as u16, block);
+	taken.push(start..start + len);
+	addrs.push(start as u16);
+    }
+    Ok(addrs)
+}
+
+/// Turns a file into a null-terminated string block, one character per word
+pub fn string_block(bytes: &[u8]) -> Vec<i16> {
+    bytes.iter().map(|b| *b as i16).chain(std::iter::once(0)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_obj, place_blocks, string_block};
+    use crate::lc3::LC3Memory;
+
+    #[test]
+    fn load_obj_test() {
+	let mut memory = LC3Memory::new();
+	let origin = load_obj(&mut memory, &[0x30, 0x00, 0x12, 0x34, 0xF0, 0x25]).expect("Failed to load");
+	assert_eq!(origin, 0x3000);
+	assert_eq!(memory.get(0x3000), 0x1234);
+	assert_eq!(memory.get(0x3001), 0xF025);
+	assert!(load_obj(&mut memory, &[0x30]).is_err());
+	assert!(load_obj(&mut memory, &[0xFF, 0xFF, 0, 1, 0, 2]).is_err());
+    }
+
+    #[test]
+    fn place_blocks_test() {
+	let blocks = vec![string_block(b"hi"), vec![1, 2, 3, 4]];
+	let mut memory = LC3Memory::new();
+	let packed = place_blocks(&mut memory, &blocks, 0x3000..0x4000, &[0x3000..0x3010, 0x3F00..0x4000], None).unwrap();
+	assert_eq!(packed, vec![0x3010, 0x3013]);
+	assert_eq!(memory.get(0x3011), 'i' as i16);
+	assert_eq!(memory.get(0x3012), 0);
+
+	let a = place_blocks(&mut LC3Memory::new(), &blocks, 0x3000..0x8000, &[], Some(5)).unwrap();
+	let b = place_blocks(&mut LC3Memory::new(), &blocks, 0x3000..0x8000, &[], Some(5)).unwrap();
+	let c = place_blocks(&mut LC3Memory::new(), &blocks, 0x3000..0x8000, &[], Some(6)).unwrap();
+	assert_eq!(a, b);
+	assert_ne!(a, c);
+	assert!(a[0] + 3 <= a[1] || a[1] + 4 <= a[0]);
+
+	assert!(place_blocks(&mut memory, &[vec![0; 32]], 0x3000..0x3010, &[], Some(1)).is_err());
+    }
+}
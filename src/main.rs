@@ -1,42 +1,399 @@
-#![allow(overflowing_literals)]
+#![allow(overflowing_literals, clippy::unusual_byte_groupings)]
 
-mod lc3;
-use lc3::{LC3, LC3IO};
+use lc3_emu::{asm, bench, datapath, debugger, disasm, dump, grade, leaderboard, minimize, repl, report, selftest, slow, testgen};
+use lc3_emu::datapath::Datapath;
+use lc3_emu::disk::Disk;
+use lc3_emu::pace::Pacer;
+use lc3_emu::lc3::{snapshot, LC3, LC3IO};
+use lc3_emu::lc3::time::RealTime;
+use lc3_emu::trace::{self, Tracer};
+use lc3_emu::traplog::TrapLog;
+use lc3_emu::video::{self, Framebuffer, Vsync};
+use lc3_emu::report::Profiler;
+use lc3_emu::symbols::{sym_path, Symbols};
+use lc3_emu::loader::load_obj;
+use lc3_emu::os::{prepare_supervisor, prepare_supervisor_mode, prepare_user_mode};
 
-// use std::io;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+const USAGE: &str = "usage: lc3-emu [program.obj... [--disassemble]] [--os builtin|none|<os.obj>] [--pc x3000] [--mode user|supervisor] [--limit N] [--display-delay N] [--video <frames dir>] [--disk <image>] [--dump x4000-x40FF] [--export x4000-x40FF <file.obj|file.bin>] [--sym <program.sym>] [--debug] [--tui] [--slow N] [--hz N] [--datapath <trace.csv>] [--traps] [--profile] [--trace <file> [--trace-range x3000-x30FF] [--trace-op ADD,LDR]] [--protect] [--legacy-traps]\n       lc3-emu --restore <snapshot> [program.obj... --keep-memory] [--debug] ...\n       lc3-emu asm|bench|dap|grade|report|leaderboard|gen|minimize|repl|selftest ...";
 
 fn main() {
-    let mut lc3 = LC3::new();
-    prepare_supervisor(&mut lc3);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(|a| a.as_str()) {
+	Some("asm") => std::process::exit(asm::main(&args[1..])),
+	Some("bench") => std::process::exit(bench::main(&args[1..])),
+	#[cfg(feature = "dap")]
+	Some("dap") => std::process::exit(lc3_emu::dap::main(&args[1..])),
+	#[cfg(not(feature = "dap"))]
+	Some("dap") => fail("lc3-emu was built without dap, rebuild with --features dap"),
+	Some("grade") => std::process::exit(grade::main(&args[1..])),
+	Some("report") => std::process::exit(report::main(&args[1..])),
+	Some("leaderboard") => std::process::exit(leaderboard::main(&args[1..])),
+	Some("gen") => std::process::exit(testgen::main(&args[1..])),
+	Some("minimize") => std::process::exit(minimize::main(&args[1..])),
+	Some("repl") => std::process::exit(repl::main(&args[1..])),
+	Some("selftest") => std::process::exit(selftest::main(&args[1..])),
+	_ => ()
+    }
+    let mut slow = None; // instructions per second, redrawing the machine after each
+    let mut hz = None; // instructions per second
+    let mut trace = None; // datapath signal trace
+    let mut traps = None; // service routine calls logged to stderr
+    let mut tracer = None; // executed instructions logged to a file
+    let mut profile = false; // hot spot report when the program stops
+    let mut trace_ranges = Vec::new();
+    let mut trace_opcodes = Vec::new();
+    let mut programs = Vec::new(); // .objs to run instead of the built-in demo, loaded in order
+    let mut os = Os::Builtin;
+    let mut entry = None; // starting PC, the first program's origin by default
+    let mut supervisor = None; // privilege to start with, user mode unless booting an OS image
+    let mut keep_memory = false; // load the programs into a restored snapshot's memory
+    let mut limit = None; // instructions before giving up
+    let mut display_delay = 0; // microseconds of machine time DSR stays busy after each character
+    let mut video = None; // directory for framebuffer PNGs
+    let mut disk = None; // block storage image
+    let mut dumps = Vec::new(); // ranges listed when the program stops
+    let mut exports = Vec::new(); // ranges written to files when the program stops
+    let mut sym = None; // symbol table, prog.sym beside prog.obj by default
+    let mut resume = None; // snapshot to continue from instead of booting
+    let mut listing = false; // print the program instead of running it
+    let mut debug = false; // monitor prompt instead of free running
+    #[cfg(feature = "tui")]
+    let mut tui = false; // full-screen frontend instead of free running
+    let mut protect = false; // user mode faults on system space and device registers
+    let mut legacy_traps = false; // TRAP saves PC in R7 instead of using the supervisor stack
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+	match arg.as_str() {
+	    "--slow" => match rest.next().and_then(|n| n.parse::<u32>().ok()) {
+		Some(n) if n > 0 => slow = Some(n),
+		_ => usage()
+	    },
+	    "--hz" => match rest.next().and_then(|n| n.parse::<u32>().ok()) {
+		Some(n) if n > 0 => hz = Some(n),
+		_ => usage()
+	    },
+	    "--datapath" => match rest.next().map(File::create) {
+		Some(Ok(file)) => {
+		    let mut file = BufWriter::new(file);
+		    writeln!(file, "{}", datapath::HEADER).ok();
+		    trace = Some((Datapath::new(), file));
+		}
+		Some(Err(e)) => fail(&e.to_string()),
+		None => usage()
+	    },
+	    "--traps" => traps = Some(TrapLog::new()),
+	    "--profile" => profile = true,
+	    "--trace" => match rest.next().map(File::create) {
+		Some(Ok(file)) => tracer = Some(Tracer::new(BufWriter::new(file))),
+		Some(Err(e)) => fail(&e.to_string()),
+		None => usage()
+	    },
+	    "--trace-range" => match rest.next().map(|r| trace::parse_range(r)) {
+		Some(Ok(range)) => trace_ranges.push(range),
+		Some(Err(e)) => fail(&e),
+		None => usage()
+	    },
+	    "--trace-op" => match rest.next().map(|ops| trace::parse_opcodes(ops)) {
+		Some(Ok(ops)) => trace_opcodes.extend(ops),
+		Some(Err(e)) => fail(&e),
+		None => usage()
+	    },
+	    "--disassemble" => listing = true,
+	    "--sym" => sym = Some(rest.next().cloned().unwrap_or_else(|| usage())),
+	    "--debug" => debug = true,
+	    #[cfg(feature = "tui")]
+	    "--tui" => tui = true,
+	    "--protect" => protect = true,
+	    "--legacy-traps" => legacy_traps = true,
+	    "--restore" => resume = Some(rest.next().cloned().unwrap_or_else(|| usage())),
+	    "--keep-memory" => keep_memory = true,
+	    "--os" => os = match rest.next().map(|s| s.as_str()) {
+		Some("builtin") => Os::Builtin,
+		Some("none") => Os::Bare,
+		Some(path) => Os::Image(path.to_string()),
+		None => usage()
+	    },
+	    "--pc" => match rest.next().map(|a| debugger::number(a)) {
+		Some(Ok(pc)) => entry = Some(pc as u16),
+		Some(Err(e)) => fail(&e),
+		None => usage()
+	    },
+	    "--mode" => supervisor = match rest.next().map(|s| s.as_str()) {
+		Some("user") => Some(false),
+		Some("supervisor") => Some(true),
+		_ => usage()
+	    },
+	    "--limit" => match rest.next().and_then(|n| n.parse::<u64>().ok()) {
+		Some(n) => limit = Some(n),
+		None => usage()
+	    },
+	    "--disk" => disk = Some(rest.next().cloned().unwrap_or_else(|| usage())),
+	    "--video" => video = Some(rest.next().cloned().unwrap_or_else(|| usage())),
+	    "--display-delay" => match rest.next().and_then(|n| n.parse::<u32>().ok()) {
+		Some(n) => display_delay = n,
+		None => usage()
+	    },
+	    "--dump" => match rest.next().map(|r| trace::parse_range(r)) {
+		Some(Ok(range)) => dumps.push(range),
+		Some(Err(e)) => fail(&e),
+		None => usage()
+	    },
+	    "--export" => match (rest.next().map(|r| trace::parse_range(r)), rest.next()) {
+		(Some(Ok(range)), Some(path)) => exports.push((range, path.clone())),
+		(Some(Err(e)), _) => fail(&e),
+		_ => usage()
+	    },
+	    a if !a.starts_with("--") => programs.push(a.to_string()),
+	    _ => usage()
+	}
+    }
 
-    prepare_user_program(&mut lc3);
-    
-    lc3.psr = 0b1 << 15;    // user-mode privileges
-    lc3.pc = 0x3000;        // Set program counter to start of user program space
-    lc3.saved_ssp = 0x3000; // Supervisor stack starts right on top of user program space
-    lc3.r6 = 0xFE00;        // Ready user program stack pointer
-    print_registers(&mut lc3);
+    let mut symbols = Symbols::new();
+    match &sym {
+	Some(path) => symbols = Symbols::read_file(Path::new(path)).unwrap_or_else(|e| fail(&e)),
+	None => for program in &programs {
+	    let path = sym_path(Path::new(program));
+	    if path.exists() {
+		symbols.merge(&Symbols::read_file(&path).unwrap_or_else(|e| fail(&e)));
+	    }
+	}
+    }
+    if let Some(tracer) = tracer.as_mut() {
+	tracer.symbols = symbols.clone();
+	tracer.ranges = trace_ranges;
+	tracer.opcodes = trace_opcodes;
+    } else if !trace_ranges.is_empty() || !trace_opcodes.is_empty() {
+	usage();
+    }
 
-    println!(); // spacing
+    let mut lc3 = LC3::new();
+    lc3.time = Box::new(RealTime::new()); // interactive runs follow the wall clock
+    lc3.legacy_traps = legacy_traps;
+    lc3.memory.display_delay = display_delay;
+    if let Some(path) = &disk {
+	let disk = Disk::open(Path::new(path)).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+	lc3.memory.attach(Box::new(disk)).unwrap_or_else(|e| fail(e));
+    }
+    let frames = video.as_ref().map(|dir| {
+	std::fs::create_dir_all(dir).unwrap_or_else(|e| fail(&format!("{}: {}", dir, e)));
+	let (vsync, frames) = Vsync::new();
+	lc3.memory.attach(Box::new(vsync)).unwrap_or_else(|e| fail(e));
+	lc3.memory.attach(Box::new(Framebuffer::default())).unwrap_or_else(|e| fail(e));
+	frames
+    });
+    let os_origin = match &os {
+	Os::Builtin => {
+	    prepare_supervisor(&mut lc3);
+	    None
+	}
+	Os::Bare => None,
+	Os::Image(path) => Some(load(&mut lc3, path))
+    };
+    if protect {
+	lc3.memory.protect_privileged();
+    }
+
+    if listing {
+	if programs.is_empty() || resume.is_some() {
+	    usage();
+	}
+	for path in &programs {
+	    let origin = load(&mut lc3, path);
+	    let len = std::fs::metadata(path).map_or(0, |m| m.len() as usize / 2);
+	    let end = origin as usize + len.max(1) - 1;
+	    for line in disasm::listing_with(&lc3.memory, origin..end.min(0xFFFF) as u16, &symbols) {
+		println!("{}", line);
+	    }
+	}
+	return;
+    }
+    if let Some(path) = &resume {
+	if (!programs.is_empty() && !keep_memory) || supervisor.is_some() {
+	    usage();
+	}
+	let bytes = std::fs::read(path).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+	snapshot::restore(&mut lc3, &bytes).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+	if lc3.halted {
+	    fail(&format!("{}: the machine in this snapshot has halted", path));
+	}
+	for path in &programs {
+	    load(&mut lc3, path); // on top of the snapshot, which carries on from where it was
+	}
+	if let Some(pc) = entry {
+	    lc3.pc = pc as i16;
+	}
+    } else {
+	if keep_memory {
+	    usage();
+	}
+	let mut origins: Vec<u16> = programs.iter().map(|path| load(&mut lc3, path)).collect();
+	if programs.is_empty() && matches!(os, Os::Builtin) {
+	    prepare_user_program(&mut lc3);
+	    origins.push(0x3000);
+	}
+	let pc = entry.or(origins.first().copied()).or(os_origin).unwrap_or_else(|| usage());
+	if supervisor.unwrap_or(origins.is_empty() && os_origin == Some(pc)) {
+	    prepare_supervisor_mode(&mut lc3, pc);
+	} else {
+	    prepare_user_mode(&mut lc3, pc);
+	}
+    }
+    #[cfg(feature = "tui")]
+    if tui {
+	lc3.start();
+	if let Err(e) = lc3_emu::tui::run_terminal(&mut lc3) {
+	    fail(&e.to_string());
+	}
+	return;
+    }
+    if !debug {
+	print_registers(&mut lc3);
+	println!(); // spacing
+    }
     
     lc3.start();
+    if debug {
+	debugger::run(&mut lc3, symbols.clone(), io::stdin().lock(), io::stdout());
+	dump_memory(&lc3, &symbols, &dumps, &exports);
+	return;
+    }
     
+    let mut pacer = hz.or(slow).map(Pacer::new);
+    let mut profiler = if profile { Some(Profiler::new(&lc3)) } else { None };
+    if slow.is_none() {
+	lc3.output_to(io::stdout());
+    }
+    let mut output = String::new(); // console so far, redrawn every frame in slow mode
     let mut done = false;
+    let mut frame = 0; // frames written to the --video directory
     while !done {
+	if limit.is_some_and(|n| lc3.instructions >= n) {
+	    println!("\n -- Instruction limit reached at 0x{:04x} -- ", lc3.pc);
+	    break;
+	}
 	// print_registers(&mut lc3);
 
 	// std::io::stdin().read_line(&mut String::new());
+	if slow.is_some() {
+	    print!("{}", slow::frame(&lc3, &output));
+	    io::stdout().flush().ok();
+	}
+	if let Some(pacer) = pacer.as_mut() {
+	    pacer.wait();
+	}
 	
-	let r = lc3.clock();
+	if let Some(line) = traps.as_mut().and_then(|t| t.entry(&lc3)) {
+	    eprintln!("[trap] {}", line);
+	}
+	if let Some(tracer) = tracer.as_mut() {
+	    tracer.before(&lc3);
+	}
+	if let Some(profiler) = profiler.as_mut() {
+	    profiler.before(&lc3);
+	}
+	let r = match trace.as_mut() {
+	    Some((datapath, file)) => {
+		let (r, states) = datapath.step(&mut lc3);
+		for state in states {
+		    writeln!(file, "{}", state.csv()).ok();
+		}
+		r
+	    }
+	    None => lc3.clock()
+	};
+	if let Some(line) = traps.as_mut().and_then(|t| t.exit(&lc3)) {
+	    eprintln!("[trap] {}", line);
+	}
+	if let Some(Err(e)) = tracer.as_mut().map(|t| t.after(&lc3)) {
+	    fail(&e.to_string());
+	}
+	if let Some(profiler) = profiler.as_mut() {
+	    profiler.after(&lc3);
+	}
+	if let (Some(dir), Some(frames)) = (&video, &frames) {
+	    if frames.get() != frame {
+		frame = frames.get();
+		save_frame(&lc3, dir, frame);
+	    }
+	}
 	match r {
 	    LC3IO::None => (),
-	    LC3IO::Display(c) => print!("{}", (c as u8) as char),
+	    LC3IO::Display(c) if slow.is_some() => output.push((c as u8) as char),
+	    LC3IO::Display(_) => (), // the sink has it
+	    LC3IO::Reset => println!("\n -- Processor reset -- "),
+	    LC3IO::Idle => {
+		// block on the keyboard instead of spinning while the program sleeps or polls
+		lc3.flush_output().ok();
+		let mut key = [0u8];
+		match io::stdin().read(&mut key) {
+		    Ok(1) if lc3.sleeping => {
+			lc3.interrupt(0x80, 4, key[0] as i16).ok();
+		    }
+		    Ok(1) => lc3.memory.key_press(key[0] as i16),
+		    _ => {
+			done = true;
+			println!("\n -- End of input while waiting at 0x{:04x} -- ", lc3.pc);
+		    }
+		}
+	    }
 	    LC3IO::Halt => {
 		done = true;
 		println!("\n -- Processor halted at 0x{:04x} -- ", lc3.pc);
 	    }
 	}
     }
+    if let Some(dir) = &video {
+	save_frame(&lc3, dir, frame.wrapping_add(1)); // however far the program got
+    }
+    if let Some(profiler) = &profiler {
+	report::print_execution(&profiler.profile);
+    }
+    dump_memory(&lc3, &symbols, &dumps, &exports);
+}
+
+/// Lists and writes out the ranges asked for with --dump and --export
+fn dump_memory(lc3: &LC3, symbols: &Symbols, dumps: &[RangeInclusive<u16>], exports: &[(RangeInclusive<u16>, String)]) {
+    for range in dumps {
+	println!();
+	for line in dump::listing(&lc3.memory, range.clone(), symbols) {
+	    println!("{}", line);
+	}
+    }
+    for (range, path) in exports {
+	dump::save(&lc3.memory, range.clone(), Path::new(path)).unwrap_or_else(|e| fail(&e));
+    }
+}
+
+fn save_frame(lc3: &LC3, dir: &str, frame: u16) {
+    let path = Path::new(dir).join(format!("frame-{:04}.png", frame));
+    video::save_png(&lc3.memory, &path).unwrap_or_else(|e| fail(&e));
+}
+
+/// Which operating system is in memory before the programs load
+enum Os {
+    Builtin,
+    Bare, // none, programs run on bare memory
+    Image(String) // an .obj file loaded in place of the built-in one
+}
+
+/// Loads an object file, returning its origin
+fn load(lc3: &mut LC3, path: &str) -> u16 {
+    let bytes = std::fs::read(path).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+    load_obj(&mut lc3.memory, &bytes).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)))
+}
+
+fn usage() -> ! {
+    fail(USAGE)
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
 }
 
 fn prepare_user_program(lc3: &mut LC3) {
@@ -59,66 +416,13 @@ fn prepare_user_program(lc3: &mut LC3) {
     lc3.memory.put(0x300f, 0x00);
 }
 
-fn prepare_supervisor(lc3: &mut LC3) {
-    // trap vector table
-    lc3.memory.put(0x0020, 0x0200); // getc  (read a single character from the keyboard to r0)
-    lc3.memory.put(0x0021, 0x0220); // out   (write r0 to console)
-    lc3.memory.put(0x0022, 0x0240); // puts  (write string pointed to by r0 until 0x0000)
-    lc3.memory.put(0x0023, 0x0260); // in    (getc with echo)
-    lc3.memory.put(0x0024, 0x0280); // putsp (puts but packed 2 chars per memory location)
-    lc3.memory.put(0x0025, 0x02A0); // halt  (stop the LC3)
-    // interrupt vector table
-    lc3.memory.put(0x0100, 0x02C0); // priv
-    lc3.memory.put(0x0101, 0x02C0); // illegal
-    lc3.memory.put(0x0180, 0x02E0); // keystroke
-    
-    // trap code
-
-    //  GETC FE00 Status FE02 Data
-    lc3.memory.put(0x0200, 0b1010_000_000000010); // LDI R0, [PC + 2] ; load *0x203 -> *FE00 into r0
-    lc3.memory.put(0x0201, 0b0000_010_000000001); // BRz  PC - 2      ; r0 == 0, nothing, retry
-    lc3.memory.put(0x0202, 0b0000_000_000000001); // BR   PC + 1      ; continue
-    lc3.memory.put(0x0203, 0xFE00);               // db 0xFE00        ; Keyboard Status
-    lc3.memory.put(0x0204, 0b1010_000_000000001); // LDI R0, [PC + 1] ; load *0x206 -> *FE02 into r0
-    lc3.memory.put(0x0205, 0b1100_000_111_000000);// RET
-    lc3.memory.put(0x0206, 0xFE02);               // db 0xFE02
-
-    //  OUT FE06 Data
-    lc3.memory.put(0x0220, 0b1011_000_000000001); // STI R0, [PC + 1] ; put R0 into display reg
-    lc3.memory.put(0x0221, 0b1100_000_111_000000);// RET
-    lc3.memory.put(0x0222, 0xFE06);
-
-    //  PUTS
-    lc3.memory.put(0x0240, 0b0001_001_111_1_00000); // ADD R1, R7, #0    ; save RET register
-    lc3.memory.put(0x0241, 0b0001_010_000_1_00000); // ADD R2, R0, #0    ; move r0 to r2
-    lc3.memory.put(0x0242, 0b0110_000_010_000000);  // LDR R0, [R2 + #0] ; load character to r0
-    lc3.memory.put(0x0243, 0b0000_010_000000011);   // BRz PC + 3        ; if zero, go to return
-    lc3.memory.put(0x0244, 0b1111_0000_00100001);   // TRAP 0x21 (OUT)   ; print character
-    lc3.memory.put(0x0245, 0b0001_010_010_1_00001); // ADD R2, R2, #1    ; increment string ptr
-    lc3.memory.put(0x0246, 0b0000_111_111111011);   // BR PC - 5         ; go 5 back
-    lc3.memory.put(0x0247, 0b0001_111_001_1_00000); // ADD R7, R1, #0    ; return address back to r7
-    lc3.memory.put(0x0248, 0b1100_000_111_000000);  // RET
-	
-    
-    // TODO ...
-    
-    //  HALT FFFE
-    lc3.memory.put(0x02A0, 0b0101_000_000_1_00000);// zero r0
-    lc3.memory.put(0x02A1, 0b1011_000_000000001);  // STI R0, [PC + 1] ; put R0 into display reg
-    lc3.memory.put(0x02A2, 0b1100_000_111_000000); // RET
-    lc3.memory.put(0x02A3, 0xFFFE);
-    
-    // interrupt code
-    
-} 
-
 fn print_registers(lc3: &mut LC3) {
     println!("-- Registers -----------------");
     println!("pc: {:04x} -> {:016b}", lc3.pc, lc3.memory.get(lc3.pc as u16));
     println!("psr: {:016b}", lc3.psr);
-    println!("r0: {:04x}  r1: {:04x} r2: {:04x}", lc3.r0, lc3.r1, lc3.r2);
-    println!("r3: {:04x}  r4: {:04x} r5: {:04x}", lc3.r3, lc3.r4, lc3.r5);
-    println!("r6  (stack) : {:04x}", lc3.r6);
-    println!("r7  (ret)   : {:04x}", lc3.r7);
+    println!("r0: {:04x}  r1: {:04x} r2: {:04x}", lc3.r[0], lc3.r[1], lc3.r[2]);
+    println!("r3: {:04x}  r4: {:04x} r5: {:04x}", lc3.r[3], lc3.r[4], lc3.r[5]);
+    println!("r6  (stack) : {:04x}", lc3.r[6]);
+    println!("r7  (ret)   : {:04x}", lc3.r[7]);
     println!("------------------------------");
 }
@@ -0,0 +1,180 @@
+use crate::lc3::LC3IO;
+use crate::os::boot;
+
+use std::collections::BTreeSet;
+
+const USAGE: &str = "usage: lc3-emu minimize <program.obj> --input <keys.txt> [--limit N] [--out <min.txt>]";
+
+/// How a run with typed-ahead input ended. A smaller input reproduces a failure when it ends
+/// the same way.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Ending {
+    Halted,
+    Waiting(u16), // out of input, polling at this address
+    Fault(u16), // illegal opcode or privilege violation at this address
+    TimedOut
+}
+
+#[derive(Debug, Clone)]
+pub struct Run {
+    pub ending: Ending,
+    pub consumed: usize, // keys the program read before it stopped
+    pub coverage: BTreeSet<u16> // addresses executed
+}
+
+/// Replays `input` against a fresh boot of the program, deterministically
+pub fn run(obj: &[u8], input: &[u8], limit: u64) -> Result<Run, &'static str> {
+    let mut lc3 = boot(obj)?;
+    for key in input {
+	lc3.schedule_key(0, *key as i16);
+    }
+    let mut coverage = BTreeSet::new();
+    let mut ending = Ending::TimedOut;
+    while lc3.instructions < limit {
+	let pc = lc3.pc as u16;
+	let user = lc3.psr < 0;
+	coverage.insert(pc);
+	let io = lc3.clock();
+	let op = lc3.last_instruction.map(|(_, word)| word as u16 >> 12);
+	if op == Some(0b1101) || (op == Some(0b1000) && user) {
+	    ending = Ending::Fault(pc);
+	    break;
+	}
+	match io {
+	    LC3IO::Halt => {
+		ending = Ending::Halted;
+		break;
+	    }
+	    LC3IO::Idle => {
+		ending = Ending::Waiting(pc);
+		break;
+	    }
+	    _ => ()
+	}
+    }
+    Ok(Run { ending, consumed: input.len() - lc3.unread_keys(), coverage })
+}
+
+/// Shrinks an input that makes the program fail to a smaller one that fails the same way.
+/// Keys the program never read are dropped first, then chunks of keystrokes are removed
+/// delta-debugging style until no single key can go.
+pub fn minimize(obj: &[u8], input: &[u8], limit: u64) -> Result<(Vec<u8>, Run), String> {
+    let original = run(obj, input, limit)?;
+    if original.ending == Ending::Halted {
+	return Err("Program halts normally with this input, nothing to minimize".to_string());
+    }
+    let same = |keys: &[u8]| run(obj, keys, limit).ok().filter(|r| r.ending == original.ending);
+
+    let mut keys = input.to_vec();
+    let mut last = original.clone();
+    if let Some(r) = same(&input[..original.consumed]) {
+	keys.truncate(original.consumed);
+	last = r;
+    }
+    let mut n = 2;
+    while !keys.is_empty() {
+	let size = keys.len().div_ceil(n);
+	let mut reduced = false;
+	for start in (0..keys.len()).step_by(size) {
+	    let end = (start + size).min(keys.len());
+	    let candidate: Vec<u8> = keys[..start].iter().chain(&keys[end..]).copied().collect();
+	    if let Some(r) = same(&candidate) {
+		keys = candidate;
+		last = r;
+		n = (n - 1).max(2);
+		reduced = true;
+		break;
+	    }
+	}
+	if !reduced {
+	    if size == 1 {
+		break;
+	    }
+	    n = (n * 2).min(keys.len());
+	}
+    }
+    Ok((keys, last))
+}
+
+/// `lc3-emu minimize`, returns the process exit code
+pub fn main(args: &[String]) -> i32 {
+    match minimize_command(args) {
+	Ok(()) => 0,
+	Err(e) => {
+	    eprintln!("{}", e);
+	    1
+	}
+    }
+}
+
+fn minimize_command(args: &[String]) -> Result<(), String> {
+    let mut program = None;
+    let mut input = None;
+    let mut limit = 1_000_000;
+    let mut out = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+	match arg.as_str() {
+	    "--input" => {
+		let path = args.next().ok_or(USAGE)?;
+		input = Some(std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?);
+	    }
+	    "--limit" => limit = args.next().and_then(|a| a.parse().ok()).ok_or(USAGE)?,
+	    "--out" => out = Some(args.next().ok_or(USAGE)?.clone()),
+	    a if program.is_none() && !a.starts_with("--") => program = Some(a.to_string()),
+	    _ => return Err(USAGE.to_string())
+	}
+    }
+    let program = program.ok_or(USAGE)?;
+    let input = input.ok_or(USAGE)?;
+    let obj = std::fs::read(&program).map_err(|e| format!("{}: {}", program, e))?;
+    let original = run(&obj, &input, limit)?;
+    let (keys, reduced) = minimize(&obj, &input, limit)?;
+    println!("{:?}: {} keys -> {} keys", reduced.ending, input.len(), keys.len());
+    println!("coverage: {} addresses -> {}", original.coverage.len(), reduced.coverage.len());
+    match out {
+	Some(path) => std::fs::write(&path, &keys).map_err(|e| format!("{}: {}", path, e))?,
+	None => println!("{:?}", String::from_utf8_lossy(&keys))
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{minimize, run, Ending};
+    use crate::endian::{bytes, Endian};
+
+    /// Reads keys forever and executes an illegal opcode on 'x'
+    fn program() -> Vec<u8> {
+	bytes(&[
+	    0x3000,
+	    0b1010_000_000000111, // LDI R0, KBSR
+	    0b0000_010_111111110, // BRz #-2
+	    0b1010_000_000000110, // LDI R0, KBDR
+	    0b0010_001_000000110, // LD R1, -'x'
+	    0b0001_000_000_0_00_001, // ADD R0, R0, R1
+	    0b0000_010_000000001, // BRz #1
+	    0b0000_111_111111001, // BRnzp #-7
+	    0xD000, // illegal
+	    0xFE00,
+	    0xFE02,
+	    -('x' as i16)
+	], Endian::Big)
+    }
+
+    #[test]
+    fn run_test() {
+	let r = run(&program(), b"abxcd", 10_000).unwrap();
+	assert_eq!(r.ending, Ending::Fault(0x3007));
+	assert_eq!(r.consumed, 3);
+	assert_eq!(run(&program(), b"ab", 10_000).unwrap().ending, Ending::Waiting(0x3000));
+    }
+
+    #[test]
+    fn minimize_test() {
+	let (keys, r) = minimize(&program(), b"hello, xylophone", 10_000).unwrap();
+	assert_eq!(keys, b"x");
+	assert_eq!(r.ending, Ending::Fault(0x3007));
+	assert!(minimize(&program(), b"", 10_000).unwrap().0.is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+; Built-in operating system: trap service routines, exception and interrupt handlers
+;
+; The service routines are subroutines that keep every register but R0 (the result of GETC
+; and IN) and R7. TRAP enters them through the TRAP_ stubs, which run in supervisor mode and
+; return with RTI. With LC3::legacy_traps, TRAP saves the return address in R7 and jumps,
+; so prepare_supervisor points the vector table straight at the subroutines (LEGACY_TRAPS).
+; KBSR and DSR set bit 15 (negative) when a key is ready and when the display is.
+
+	.ORIG x0020
+	.FILL TRAP_GETC		; x20 read a character into R0
+	.FILL TRAP_OUT		; x21 write R0 to the console
+	.FILL TRAP_PUTS		; x22 write the string at R0, one character per word
+	.FILL TRAP_IN		; x23 prompt, read and echo a character into R0
+	.FILL TRAP_PUTSP	; x24 write the string at R0, two characters per word
+	.FILL TRAP_HALT		; x25 stop the machine
+	.END
+
+	.ORIG x0100
+	.FILL PRIV_EXCEPTION	; privilege mode violation
+	.FILL ILLEGAL_EXCEPTION	; illegal opcode
+	.FILL ACV_EXCEPTION	; access control violation
+	.END
+
+	.ORIG x0180
+	.FILL KBD_INTERRUPT	; keyboard
+	.END
+
+	.ORIG x0200
+TRAP_GETC
+	ADD R6, R6, #-1
+	STR R7, R6, #0
+	JSR GETC_SR
+	BRnzp SERVICE_RETURN
+TRAP_OUT
+	ADD R6, R6, #-1
+	STR R7, R6, #0
+	JSR OUT_SR
+	BRnzp SERVICE_RETURN
+TRAP_PUTS
+	ADD R6, R6, #-1
+	STR R7, R6, #0
+	JSR PUTS_SR
+	BRnzp SERVICE_RETURN
+TRAP_IN
+	ADD R6, R6, #-1
+	STR R7, R6, #0
+	JSR IN_SR
+	BRnzp SERVICE_RETURN
+TRAP_PUTSP
+	ADD R6, R6, #-1
+	STR R7, R6, #0
+	JSR PUTSP_SR
+	BRnzp SERVICE_RETURN
+TRAP_HALT
+	ADD R6, R6, #-1
+	STR R7, R6, #0
+	JSR HALT_SR
+SERVICE_RETURN
+	LDR R7, R6, #0
+	ADD R6, R6, #1
+	RTI
+
+LEGACY_TRAPS
+	.FILL GETC_SR
+	.FILL OUT_SR
+	.FILL PUTS_SR
+	.FILL IN_SR
+	.FILL PUTSP_SR
+	.FILL HALT_SR
+
+GETC_SR
+	LDI R0, KBSR
+	BRzp GETC_SR
+	LDI R0, KBDR
+	RET
+
+OUT_SR
+	ST R1, OUT_R1
+OUT_WAIT
+	LDI R1, DSR
+	BRzp OUT_WAIT
+	STI R0, DDR
+	LD R1, OUT_R1
+	RET
+
+PUTS_SR
+	ST R0, PUTS_R0
+	ST R1, PUTS_R1
+	ST R7, PUTS_R7
+	ADD R1, R0, #0
+PUTS_LOOP
+	LDR R0, R1, #0
+	BRz PUTS_DONE
+	JSR OUT_SR
+	ADD R1, R1, #1
+	BRnzp PUTS_LOOP
+PUTS_DONE
+	LD R0, PUTS_R0
+	LD R1, PUTS_R1
+	LD R7, PUTS_R7
+	RET
+
+IN_SR
+	ST R7, IN_R7
+	LEA R0, IN_PROMPT
+	JSR PUTS_SR
+	JSR GETC_SR
+	JSR OUT_SR
+	ST R0, IN_R0
+	LD R0, NEWLINE
+	JSR OUT_SR
+	LD R0, IN_R0
+	LD R7, IN_R7
+	RET
+
+; low byte first, stopping at the first zero byte
+PUTSP_SR
+	ST R0, PUTSP_R0
+	ST R1, PUTSP_R1
+	ST R2, PUTSP_R2
+	ST R3, PUTSP_R3
+	ST R7, PUTSP_R7
+	ADD R1, R0, #0
+PUTSP_LOOP
+	LDR R2, R1, #0
+	LD R0, LOW_BYTE
+	AND R0, R2, R0
+	BRz PUTSP_DONE
+	JSR OUT_SR
+	AND R0, R0, #0		; shift the high byte down a bit at a time
+	AND R3, R3, #0
+	ADD R3, R3, #8
+PUTSP_SHIFT
+	ADD R0, R0, R0
+	ADD R2, R2, #0
+	BRzp PUTSP_ZERO
+	ADD R0, R0, #1
+PUTSP_ZERO
+	ADD R2, R2, R2
+	ADD R3, R3, #-1
+	BRp PUTSP_SHIFT
+	ADD R0, R0, #0
+	BRz PUTSP_DONE
+	JSR OUT_SR
+	ADD R1, R1, #1
+	BRnzp PUTSP_LOOP
+PUTSP_DONE
+	LD R0, PUTSP_R0
+	LD R1, PUTSP_R1
+	LD R2, PUTSP_R2
+	LD R3, PUTSP_R3
+	LD R7, PUTSP_R7
+	RET
+
+HALT_SR
+	ST R7, HALT_R7
+	LEA R0, HALT_MSG
+	JSR PUTS_SR
+	LD R7, HALT_R7
+	AND R0, R0, #0
+	STI R0, MCR		; clearing the MCR stops the clock
+	BRnzp HALT_SR
+
+; exceptions are taken in supervisor mode, report and stop
+PRIV_EXCEPTION
+	LEA R0, PRIV_MSG
+	BRnzp FATAL
+ILLEGAL_EXCEPTION
+	LEA R0, ILLEGAL_MSG
+	BRnzp FATAL
+ACV_EXCEPTION
+	LEA R0, ACV_MSG
+FATAL
+	JSR PUTS_SR
+	JSR HALT_SR
+
+; leaves the key in KBDR for the interrupted program to read
+KBD_INTERRUPT
+	RTI
+
+KBSR	.FILL xFE00
+KBDR	.FILL xFE02
+DSR	.FILL xFE04
+DDR	.FILL xFE06
+MCR	.FILL xFFFE
+LOW_BYTE .FILL x00FF
+NEWLINE	.FILL x000A
+
+OUT_R1	.BLKW 1
+PUTS_R0	.BLKW 1
+PUTS_R1	.BLKW 1
+PUTS_R7	.BLKW 1
+IN_R0	.BLKW 1
+IN_R7	.BLKW 1
+PUTSP_R0 .BLKW 1
+PUTSP_R1 .BLKW 1
+PUTSP_R2 .BLKW 1
+PUTSP_R3 .BLKW 1
+PUTSP_R7 .BLKW 1
+HALT_R7	.BLKW 1
+
+IN_PROMPT	.STRINGZ "\nInput a character> "
+HALT_MSG	.STRINGZ "\n----- Halting the processor -----\n"
+PRIV_MSG	.STRINGZ "\n----- Privilege mode violation -----\n"
+ILLEGAL_MSG	.STRINGZ "\n----- Illegal opcode -----\n"
+ACV_MSG		.STRINGZ "\n----- Access control violation -----\n"
+	.END
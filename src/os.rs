@@ -0,0 +1,149 @@
+use crate::asm::{assemble, Program};
+use crate::lc3::LC3;
+use crate::loader::load_obj;
+
+use std::sync::OnceLock;
+
+/// A started machine running an .obj user program on top of the supervisor
+pub fn boot(obj: &[u8]) -> Result<LC3, &'static str> {
+    let mut lc3 = LC3::new();
+    prepare_supervisor(&mut lc3);
+    let origin = load_obj(&mut lc3.memory, obj)?;
+    prepare_user_mode(&mut lc3, origin);
+    lc3.start();
+    Ok(lc3)
+}
+
+/// Sets up registers to run a user program at `pc` on top of the supervisor
+pub fn prepare_user_mode(lc3: &mut LC3, pc: u16) {
+    lc3.psr = 0b1 << 15;    // user-mode privileges
+    lc3.pc = pc as i16;     // Set program counter to start of user program
+    lc3.saved_ssp = 0x3000; // Supervisor stack starts right on top of user program space
+    lc3.r[6] = 0xFE00;      // Ready user program stack pointer
+}
+
+/// Sets up registers to run code at `pc` with supervisor privileges, on the supervisor stack
+pub fn prepare_supervisor_mode(lc3: &mut LC3, pc: u16) {
+    lc3.psr = 0;
+    lc3.pc = pc as i16;
+    lc3.saved_usp = 0xFE00u16 as i16; // where a drop to user mode finds its stack
+    lc3.r[6] = 0x3000;
+}
+
+/// Source of the built-in operating system
+pub const SOURCE: &str = include_str!("os.asm");
+
+/// What the built-in HALT prints before stopping the machine
+pub const HALT_MESSAGE: &str = "\n----- Halting the processor -----\n";
+
+/// The built-in operating system, assembled on first use
+pub fn image() -> &'static Program {
+    static IMAGE: OnceLock<Program> = OnceLock::new();
+    IMAGE.get_or_init(|| assemble(SOURCE).expect("Built-in OS failed to assemble"))
+}
+
+/// Loads the vector tables, trap service routines and exception and interrupt handlers, set
+/// up for the machine's TRAP semantics
+pub fn prepare_supervisor(lc3: &mut LC3) {
+    let image = image();
+    image.load(&mut lc3.memory);
+    if lc3.legacy_traps {
+	// TRAP jumps straight to the subroutines, which return with RET
+	let table = image.symbols["LEGACY_TRAPS"];
+	let vectors = lc3.memory.read_words(table, 6);
+	lc3.memory.write_words(0x0020, &vectors);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::assemble;
+    use crate::lc3::{LC3, LC3IO};
+    use super::{boot, prepare_supervisor, prepare_supervisor_mode, prepare_user_mode, HALT_MESSAGE};
+
+    /// Runs until halt, returning the console output
+    fn console(lc3: &mut LC3) -> String {
+	let mut output = String::new();
+	for _ in 0..10_000 {
+	    match lc3.clock() {
+		LC3IO::Display(c) => output.push((c as u8) as char),
+		LC3IO::Halt => return output,
+		_ => ()
+	    }
+	}
+	panic!("no halt, output so far: {:?}", output);
+    }
+
+    fn run(source: &str, keys: &[u8]) -> (LC3, String) {
+	let program = assemble(source).expect("Failed to assemble");
+	let mut lc3 = boot(&program.sections[0].obj()).expect("Failed to boot");
+	for key in keys {
+	    lc3.schedule_key(0, *key as i16);
+	}
+	let output = console(&mut lc3);
+	(lc3, output)
+    }
+
+    #[test]
+    fn trap_test() {
+	let (lc3, output) = run("
+	    .ORIG x3000
+	    LD R1, SEVEN
+	    GETC
+	    ADD R2, R0, #0
+	    IN
+	    ADD R3, R0, #0
+	    LEA R0, PACKED
+	    PUTSP
+	    HALT
+	SEVEN .FILL #7
+	PACKED .FILL x6261 ; \"ab\"
+	    .FILL x0063 ; \"c\"
+	    .END", b"xy");
+	assert_eq!(output, "\nInput a character> y\nabc\n----- Halting the processor -----\n");
+	assert_regs!(lc3, r1 = 7, r2 = 'x' as i16, r3 = 'y' as i16);
+    }
+
+    #[test]
+    fn halt_test() {
+	let (lc3, output) = run(".ORIG x3000\nHALT\n.END", b"");
+	assert_eq!(output, HALT_MESSAGE);
+	assert!(lc3.halted);
+    }
+
+    #[test]
+    fn exception_test() {
+	let (lc3, output) = run(".ORIG x3000\n.FILL xD000\n.END", b"");
+	assert_eq!(output, "\n----- Illegal opcode -----\n\n----- Halting the processor -----\n");
+	assert!(lc3.psr >= 0); // stopped in supervisor mode
+	let (_, output) = run(".ORIG x3000\nRTI\n.END", b"");
+	assert!(output.starts_with("\n----- Privilege mode violation -----\n"));
+    }
+
+    #[test]
+    fn legacy_test() {
+	let program = assemble(".ORIG x3000\nLEA R0, MSG\nPUTS\nADD R1, R7, #0\nHALT\nMSG .STRINGZ \"ok\"\n.END").unwrap();
+	let mut lc3 = LC3::new();
+	lc3.legacy_traps = true;
+	prepare_supervisor(&mut lc3);
+	program.load(&mut lc3.memory);
+	prepare_user_mode(&mut lc3, 0x3000);
+	lc3.start();
+	assert!(console(&mut lc3).starts_with("ok\n"));
+	assert_regs!(lc3, r1 = 0x3002, r7 = 0x3004); // TRAP left the return address in R7
+	assert!(lc3.psr < 0); // never left user mode
+    }
+
+    #[test]
+    fn supervisor_mode_test() {
+	let program = assemble(".ORIG x0500\nLEA R0, MSG\nPUTS\nHALT\nMSG .STRINGZ \"os\"\n.END").unwrap();
+	let mut lc3 = LC3::new();
+	prepare_supervisor(&mut lc3);
+	program.load(&mut lc3.memory);
+	prepare_supervisor_mode(&mut lc3, 0x0500);
+	lc3.start();
+	assert!(console(&mut lc3).starts_with("os\n"));
+	assert!(lc3.psr >= 0);
+    }
+}
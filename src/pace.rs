@@ -0,0 +1,80 @@
+//! Pacing for run loops: `lc3-emu prog.obj --hz 2000` runs 2000 instructions a second, so
+//! keyboard interrupts and timers happen at a speed a person can follow. The machine itself
+//! doesn't know, the loop calls `Pacer::wait()` before each clock.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Shortest sleep worth making. Faster rates run a little ahead and then sleep it off.
+const MIN_SLEEP: Duration = Duration::from_millis(1);
+
+/// How far behind the schedule can fall before it's abandoned rather than caught up, after
+/// blocking on input for instance
+const SLACK: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+pub struct Pacer {
+    period: Duration, // between clocks
+    next: Instant // when the next clock is due
+}
+
+impl Pacer {
+    pub fn new(hz: u32) -> Self {
+	Pacer { period: Duration::from_secs(1) / hz.max(1), next: Instant::now() }
+    }
+
+    /// Sleeps until the next clock is due
+    pub fn wait(&mut self) {
+	if let Some(sleep) = self.advance(Instant::now()) {
+	    thread::sleep(sleep);
+	}
+    }
+
+    /// Schedules the clock after the one due, given the time is `now`, and returns how long
+    /// to sleep before running the due one, if it's worth sleeping at all
+    fn advance(&mut self, now: Instant) -> Option<Duration> {
+	let sleep = if self.next >= now + MIN_SLEEP {
+	    Some(self.next - now)
+	} else {
+	    if now > self.next + SLACK {
+		self.next = now;
+	    }
+	    None
+	};
+	self.next += self.period;
+	sleep
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pacer;
+
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn pacer_test() {
+	let start = Instant::now();
+	let mut pacer = Pacer { period: Duration::from_micros(250), next: start };
+	// runs ahead below a millisecond, then sleeps it off
+	for _ in 0..4 {
+	    assert_eq!(pacer.advance(start), None);
+	}
+	assert_eq!(pacer.advance(start), Some(Duration::from_millis(1)));
+	assert_eq!(pacer.next, start + Duration::from_micros(1250));
+
+	// a little behind, it catches up on the same schedule
+	let late = start + Duration::from_millis(50);
+	assert_eq!(pacer.advance(late), None);
+	assert_eq!(pacer.next, start + Duration::from_micros(1500));
+
+	// a long way behind, the lost time isn't made up with a burst
+	let later = start + Duration::from_secs(1);
+	assert_eq!(pacer.advance(later), None);
+	assert_eq!(pacer.next, later + Duration::from_micros(250));
+	assert_eq!(pacer.advance(later), None);
+	assert_eq!(pacer.advance(later), None);
+	assert_eq!(pacer.advance(later), None);
+	assert_eq!(pacer.advance(later), Some(Duration::from_millis(1)));
+    }
+}
@@ -0,0 +1,242 @@
+//! Assembly REPL: `lc3-emu repl`
+//!
+//! Each line typed is assembled at PC, written there and executed on the spot, and the
+//! registers it changed are printed with the condition codes. TRAPs run their service routine
+//! through to the instruction after them. Lines starting with `:` are monitor commands.
+
+use crate::asm;
+use crate::debugger::Debugger;
+use crate::disasm;
+use crate::lc3::{LC3, LC3IO};
+use crate::os::{prepare_supervisor, prepare_user_mode};
+
+use std::cell::RefCell;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+const USAGE: &str = "usage: lc3-emu repl [--bare]";
+
+/// Instructions a TRAP's service routine gets before the prompt comes back
+const TRAP_LIMIT: u64 = 1_000_000;
+
+/// Instructions `:back` can undo
+const HISTORY: usize = 100_000;
+
+const HELP: &str = "\
+Type an instruction to assemble it at PC and execute it: ADD R1, R1, #5 or LD R0, x3050.
+TRAPs run their service routine and come back.
+:regs                show registers
+:mem <addr> [count]  disassemble memory
+:poke <addr> <value> write a word to memory
+:set <reg> <value>   set R0-R7, PC or PSR
+:type <text>         queue keys for GETC and IN
+:back [n]            undo the last n instructions
+:reset               start over with a fresh machine
+:quit                leave
+The other debugger commands work after a colon too.";
+
+pub struct Repl<'a> {
+    lc3: &'a mut LC3,
+    bare: bool, // no OS, supervisor mode
+    writes: Rc<RefCell<Vec<(u16, i16)>>> // memory the last instruction stored to
+}
+
+/// A machine at x3000, in user mode on the built-in OS unless `bare`
+fn machine(bare: bool) -> LC3 {
+    let mut lc3 = LC3::new();
+    if bare {
+	lc3.pc = 0x3000;
+    } else {
+	prepare_supervisor(&mut lc3);
+	prepare_user_mode(&mut lc3, 0x3000);
+    }
+    lc3.start();
+    lc3
+}
+
+/// Assembles one instruction as if it were at `pc`
+fn assemble(line: &str, pc: u16) -> Result<i16, String> {
+    if line.trim_start().starts_with('.') {
+	return Err("Directives don't execute, use :poke to put data in memory".to_string());
+    }
+    let program = asm::assemble(&format!(".ORIG x{:04X}\n{}\n.END", pc, line))
+	.map_err(|e| e.strip_prefix("line 2: ").map_or(e.clone(), str::to_string))?;
+    match program.sections[0].words.as_slice() {
+	[word] => Ok(*word),
+	[] => Err(format!("Not an instruction: {}", line.trim())),
+	_ => Err("Expected one instruction".to_string())
+    }
+}
+
+impl<'a> Repl<'a> {
+    pub fn new(lc3: &'a mut LC3, bare: bool) -> Self {
+	let mut repl = Repl { lc3, bare, writes: Rc::default() };
+	repl.watch_writes();
+	repl
+    }
+
+    fn watch_writes(&mut self) {
+	self.lc3.record_history(HISTORY);
+	let writes = self.writes.clone();
+	self.lc3.on_memory_access(move |access| {
+	    if access.write && !access.device {
+		writes.borrow_mut().push((access.addr, access.value));
+	    }
+	});
+    }
+
+    /// Runs one line, returning what to print, or `None` to quit
+    pub fn command(&mut self, line: &str) -> Option<Result<String, String>> {
+	let result = match line.trim() {
+	    "" => Ok(String::new()),
+	    ":quit" | ":q" => return None,
+	    ":help" | ":h" => Ok(HELP.to_string()),
+	    ":reset" => {
+		*self.lc3 = machine(self.bare);
+		self.watch_writes();
+		Ok(format!("Reset, PC x{:04X}", self.lc3.pc as u16))
+	    }
+	    meta if meta.starts_with(':') => return Debugger::new(self.lc3).command(&meta[1..]),
+	    instruction => self.execute(instruction)
+	};
+	Some(result)
+    }
+
+    /// Assembles `line` at PC and executes it, or for a TRAP runs until it returns
+    fn execute(&mut self, line: &str) -> Result<String, String> {
+	if self.lc3.halted {
+	    return Err("Machine is halted, :reset to start over".to_string());
+	}
+	let pc = self.lc3.pc as u16;
+	let word = assemble(line, pc)?;
+	self.lc3.memory.put(pc, word);
+	let (regs, opcode) = (self.lc3.regs(), (word as u16) >> 12);
+	self.writes.borrow_mut().clear();
+	let mut output = String::new();
+	let mut clocks = 0;
+	let mut halted = false;
+	loop {
+	    let io = self.lc3.clock();
+	    clocks += 1;
+	    match io {
+		LC3IO::Display(c) => output.push((c as u8) as char),
+		LC3IO::Idle => {
+		    self.lc3.step_back(clocks);
+		    return Err("Waiting for input, queue keys with :type and try again".to_string());
+		}
+		LC3IO::Halt => halted = true,
+		LC3IO::Reset => output += "\n -- Processor reset -- \n",
+		LC3IO::None => ()
+	    }
+	    if opcode != 0b1111 || halted || self.lc3.pc as u16 == pc.wrapping_add(1) {
+		break;
+	    }
+	    if clocks as u64 == TRAP_LIMIT {
+		output += &format!("Still running at x{:04X} after {} instructions\n", self.lc3.pc as u16, clocks);
+		break;
+	    }
+	}
+
+	let mut lines = vec![disasm::line(pc, word)];
+	if !output.is_empty() {
+	    lines.push(output.trim_matches('\n').to_string());
+	}
+	if halted {
+	    lines.push("Halted".to_string());
+	    return Ok(lines.join("\n"));
+	}
+	let mut changes: Vec<String> = self.lc3.regs().iter().enumerate()
+	    .filter(|(i, r)| regs[*i] != **r)
+	    .map(|(i, r)| format!("R{} x{:04X} #{}", i, *r as u16, r))
+	    .collect();
+	if matches!(opcode, 0b0011 | 0b0111 | 0b1011) { // ST, STR, STI
+	    changes.extend(self.writes.borrow().iter().map(|(addr, value)| format!("[x{:04X}] x{:04X} #{}", addr, *value as u16, value)));
+	}
+	let nzp: String = [(0b100, 'n'), (0b010, 'z'), (0b001, 'p')].iter()
+	    .map(|(bit, c)| if self.lc3.psr & bit != 0 { *c } else { '-' })
+	    .collect();
+	changes.push(nzp);
+	if self.lc3.pc as u16 != pc.wrapping_add(1) {
+	    changes.push(format!("PC x{:04X}", self.lc3.pc as u16));
+	}
+	lines.push(changes.join("  "));
+	Ok(lines.join("\n"))
+    }
+}
+
+/// `lc3-emu repl`, returns the process exit code
+pub fn main(args: &[String]) -> i32 {
+    let bare = match args {
+	[] => false,
+	[flag] if flag == "--bare" => true,
+	_ => {
+	    eprintln!("{}", USAGE);
+	    return 1;
+	}
+    };
+    let mut lc3 = machine(bare);
+    println!("LC-3 assembly REPL, :help for commands");
+    run(&mut lc3, bare, std::io::stdin().lock(), std::io::stdout());
+    0
+}
+
+/// Reads lines from `input` until :quit or end of input
+pub fn run(lc3: &mut LC3, bare: bool, input: impl BufRead, mut output: impl Write) {
+    let mut repl = Repl::new(lc3, bare);
+    write!(output, "x{:04X}> ", repl.lc3.pc as u16).ok();
+    output.flush().ok();
+    for line in input.lines() {
+	let line = match line {
+	    Ok(line) => line,
+	    Err(_) => break
+	};
+	match repl.command(&line) {
+	    None => return,
+	    Some(Ok(text)) if text.is_empty() => (),
+	    Some(Ok(text)) => {
+		writeln!(output, "{}", text).ok();
+	    }
+	    Some(Err(e)) => {
+		writeln!(output, "error: {}", e).ok();
+	    }
+	}
+	write!(output, "x{:04X}> ", repl.lc3.pc as u16).ok();
+	output.flush().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, machine, run};
+
+    #[test]
+    fn assemble_test() {
+	assert_eq!(assemble("ADD R1, R1, #5", 0x3000), Ok(0x1265));
+	assert_eq!(assemble("LD R0, x3050", 0x3000), Ok(0x204F));
+	assert_eq!(assemble("ADD R1, R1", 0x3000), Err("Expected 3 operand(s), found 2".to_string()));
+	assert!(assemble(".FILL #3", 0x3000).is_err());
+	assert!(assemble("LOOP", 0x3000).is_err());
+    }
+
+    #[test]
+    fn repl_test() {
+	let mut lc3 = machine(false);
+	let script = "ADD R1, R1, #5\nAND R2, R2, #0\n:poke x3050 #-7\nLD R0, x3050\nST R1, x3060\n\
+		      GETC\n:type a\nGETC\nOUT\nbogus\n:mem x3050\nBRn x3010\n:reset\nHALT\nADD R1, R1, #1\n:quit\nHALT\n";
+	let mut out = Vec::new();
+	run(&mut lc3, false, script.as_bytes(), &mut out);
+	let out = String::from_utf8(out).unwrap();
+	assert!(out.starts_with("x3000> x3000  1265  ADD R1, R1, #5\nR1 x0005 #5  --p\n"));
+	assert!(out.contains("x3001  54A0  AND R2, R2, #0\n-z-\n")); // nothing changed but the codes
+	assert!(out.contains("x3002  204D  LD R0, x3050\nR0 xFFF9 #-7  n--\n"));
+	assert!(out.contains("x3003  325C  ST R1, x3060\n[x3060] x0005 #5  n--\n"));
+	assert!(out.contains("x3004> error: Waiting for input, queue keys with :type and try again\n"));
+	assert!(out.contains("x3004  F020  GETC\nR0 x0061 #97"));
+	assert!(out.contains("x3005  F021  OUT\na\n"));
+	assert!(out.contains("error: Not an instruction: bogus"));
+	assert!(out.contains("PC x3010\nx3010> "));
+	assert!(out.contains("Reset, PC x3000\nx3000> x3000  F025  HALT\n----- Halting the processor -----\nHalted\n"));
+	assert!(out.contains("> error: Machine is halted, :reset to start over\n"));
+	assert!(lc3.halted);
+    }
+}
@@ -0,0 +1,392 @@
+use crate::disasm::{self, OPCODES};
+use crate::endian::{self, Endian};
+use crate::json::{self, Value};
+use crate::lc3::{LC3, LC3IO};
+use crate::loader::{place_blocks, string_block, DATA_REGION};
+use crate::os::boot;
+
+use std::collections::BTreeMap;
+
+const USAGE: &str = "usage: lc3-emu report <program.obj> [--input <keys.txt>] [--data <file>]... [--seed N] [--limit N] [--little-endian] [--json <out.json>]";
+
+/// Addresses listed under hot spots
+pub const HOTSPOTS: usize = 10;
+
+/// Calls into a subroutine or trap and the instructions spent there
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Routine {
+    pub calls: u64,
+    pub own: u64, // instructions executed in the routine itself
+    pub total: u64 // including everything it called
+}
+
+/// Executions of one instruction address
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Hotspot {
+    pub count: u64,
+    pub word: i16 // the instruction last executed there
+}
+
+/// Everything `lc3-emu report` measures about one run
+#[derive(Debug, Default)]
+pub struct Profile {
+    pub instructions: u64,
+    pub cycles: u64,
+    pub halted: bool,
+    pub opcodes: [u64; 16],
+    pub addresses: BTreeMap<u16, Hotspot>, // executions by instruction address
+    pub routines: BTreeMap<String, Routine>,
+    pub program_words: usize,
+    pub written_words: usize, // user memory words (x3000-xFDFF) left changed, OS scratch excluded
+    pub stack_words: u16, // deepest the user stack got
+    pub blocks: Vec<u16> // where --data blocks were placed, also passed in R0, R1, ...
+}
+
+/// A frame on the shadow call stack
+#[derive(Debug)]
+struct Frame {
+    name: String,
+    ret: i16
+}
+
+/// `lc3-emu report`, returns the process exit code
+pub fn main(args: &[String]) -> i32 {
+    match report(args) {
+	Ok(()) => 0,
+	Err(e) => {
+	    eprintln!("{}", e);
+	    1
+	}
+    }
+}
+
+fn report(args: &[String]) -> Result<(), String> {
+    let mut program = None;
+    let mut input = Vec::new();
+    let mut limit = 10_000_000;
+    let mut endian = Endian::Big;
+    let mut json_out = None;
+    let mut data = Vec::new();
+    let mut seed = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+	match arg.as_str() {
+	    "--input" => {
+		let path = args.next().ok_or(USAGE)?;
+		input = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+	    }
+	    "--data" => {
+		let path = args.next().ok_or(USAGE)?;
+		data.push(string_block(&std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?));
+	    }
+	    "--seed" => seed = Some(args.next().and_then(|a| a.parse().ok()).ok_or(USAGE)?),
+	    "--limit" => limit = args.next().and_then(|a| a.parse().ok()).ok_or(USAGE)?,
+	    "--little-endian" => endian = Endian::Little,
+	    "--json" => json_out = Some(args.next().ok_or(USAGE)?.clone()),
+	    a if program.is_none() && !a.starts_with("--") => program = Some(a.to_string()),
+	    _ => return Err(USAGE.to_string())
+	}
+    }
+    let program = program.ok_or(USAGE)?;
+    let obj = std::fs::read(&program).map_err(|e| format!("{}: {}", program, e))?;
+    let obj = endian::convert(&obj, endian, Endian::Big).map_err(|e| format!("{}: {}", program, e))?;
+    let profile = profile(&obj, &input, &data, seed, limit)?;
+    print_profile(&program, &profile);
+    if let Some(path) = json_out {
+	let text = to_json(&program, &profile).to_string() + "\n";
+	std::fs::write(&path, text).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    Ok(())
+}
+
+/// Runs the program with `input` typed ahead and profiles it. Each data block is placed
+/// (randomly when seeded) and its address handed to the program in R0, R1, ...
+pub fn profile(obj: &[u8], input: &[u8], data: &[Vec<i16>], seed: Option<u64>, limit: u64) -> Result<Profile, String> {
+    if data.len() > 6 {
+	return Err("At most 6 data blocks can be passed in R0-R5".to_string());
+    }
+    let mut lc3 = boot(obj)?;
+    let origin = lc3.pc as u16;
+    let program = origin..origin.saturating_add((obj.len() / 2 - 1) as u16);
+    let blocks = place_blocks(&mut lc3.memory, data, DATA_REGION, &[program], seed)?;
+    for (code, addr) in blocks.iter().enumerate() {
+	lc3.put_reg(code as i16, *addr as i16);
+    }
+    for key in input {
+	lc3.schedule_key(0, *key as i16);
+    }
+    let before = lc3.memory.mem.to_vec();
+    let mut profiler = Profiler::new(&lc3);
+    profiler.profile.program_words = obj.len() / 2 - 1;
+    profiler.profile.blocks = blocks;
+
+    while lc3.instructions < limit {
+	profiler.before(&lc3);
+	let io = lc3.clock();
+	profiler.after(&lc3);
+	if let LC3IO::Idle = io {
+	    if lc3.memory.get(0xFE00) == 0 {
+		return Err(format!("Program ran out of input at 0x{:04x}", lc3.pc));
+	    }
+	}
+	if let LC3IO::Halt = io {
+	    profiler.profile.halted = true;
+	    break;
+	}
+    }
+
+    let mut profile = profiler.profile;
+    profile.written_words = (0x3000..0xFE00).filter(|&i| before[i] != lc3.memory.mem[i]).count();
+    Ok(profile)
+}
+
+/// Builds a `Profile` by watching a machine run: call `before` and `after` around each clock
+#[derive(Default)]
+pub struct Profiler {
+    pub profile: Profile,
+    stack: Vec<Frame>, // shadow call stack
+    user_stack: i16, // R6 when profiling started
+    pending: Option<(i16, i16, u64)> // PC, instruction and count before the clock
+}
+
+impl Profiler {
+    pub fn new(lc3: &LC3) -> Self {
+	Profiler { user_stack: lc3.r[6], ..Profiler::default() }
+    }
+
+    /// Call before clocking
+    pub fn before(&mut self, lc3: &LC3) {
+	let pc = lc3.pc;
+	self.pending = Some((pc, lc3.memory.peek(pc as u16), lc3.instructions));
+    }
+
+    /// Call after clocking, counts the instruction if one ran without raising an exception
+    pub fn after(&mut self, lc3: &LC3) {
+	let profile = &mut self.profile;
+	profile.instructions = lc3.instructions;
+	profile.cycles = lc3.ticks;
+	let (pc, instruction) = match self.pending.take() {
+	    Some((pc, instruction, count)) if lc3.instructions != count && lc3.raised().is_none() => (pc, instruction),
+	    _ => return
+	};
+	let opcode = (instruction as u16 >> 12) as usize;
+	profile.opcodes[opcode] += 1;
+	let hotspot = profile.addresses.entry(pc as u16).or_default();
+	hotspot.count += 1;
+	hotspot.word = instruction;
+	let mut seen = Vec::new();
+	for (depth, frame) in self.stack.iter().enumerate().rev() {
+	    if seen.contains(&&frame.name) {
+		continue; // recursion only counts once
+	    }
+	    seen.push(&frame.name);
+	    let routine = profile.routines.get_mut(&frame.name).unwrap();
+	    routine.total += 1;
+	    if depth == self.stack.len() - 1 {
+		routine.own += 1;
+	    }
+	}
+	if lc3.psr & (0b1 << 15) != 0 {
+	    profile.stack_words = profile.stack_words.max(self.user_stack.wrapping_sub(lc3.r[6]) as u16);
+	}
+
+	// follow calls and returns
+	let name = match opcode {
+	    0b0100 => Some(format!("x{:04X}", lc3.pc)),
+	    0b1111 => Some(format!("TRAP x{:02X}", instruction & 0xFF)),
+	    _ => None
+	};
+	if let Some(name) = name {
+	    profile.routines.entry(name.clone()).or_default().calls += 1;
+	    self.stack.push(Frame { name, ret: pc.wrapping_add(1) });
+	} else if opcode == 0b1100 || opcode == 0b1000 {
+	    if let Some(depth) = self.stack.iter().rposition(|f| f.ret == lc3.pc) {
+		self.stack.truncate(depth);
+	    }
+	}
+    }
+}
+
+/// The `count` most executed addresses, most first, as listing lines with their counts
+pub fn hotspots(profile: &Profile, count: usize) -> Vec<String> {
+    let mut addresses: Vec<(&u16, &Hotspot)> = profile.addresses.iter().collect();
+    addresses.sort_by_key(|(_, h)| std::cmp::Reverse(h.count));
+    addresses.iter().take(count).map(|(addr, h)| {
+	let percent = h.count as f64 * 100.0 / profile.instructions.max(1) as f64;
+	format!("{:>10} {:>6.1}%  {}", h.count, percent, disasm::line(**addr, h.word))
+    }).collect()
+}
+
+fn print_profile(program: &str, profile: &Profile) {
+    println!("== {} ==", program);
+    println!("{}", if profile.halted { "halted normally" } else { "did NOT halt (instruction limit)" });
+    println!("instructions: {}", profile.instructions);
+    println!("cycles:       {}", profile.cycles);
+    println!();
+    println!("memory: {} program words, {} words written, {} words of user stack",
+	     profile.program_words, profile.written_words, profile.stack_words);
+    for (i, addr) in profile.blocks.iter().enumerate() {
+	println!("data block {} at x{:04X} (R{})", i, addr, i);
+    }
+    print_execution(profile);
+}
+
+/// Where the instructions went: opcodes, hot spots and subroutines, TRAPs included
+pub fn print_execution(profile: &Profile) {
+    println!();
+    println!("opcode histogram:");
+    let mut opcodes: Vec<(usize, u64)> = profile.opcodes.iter().copied().enumerate().filter(|(_, n)| *n > 0).collect();
+    opcodes.sort_by_key(|&(_, n)| std::cmp::Reverse(n));
+    for (op, count) in opcodes {
+	let percent = count as f64 * 100.0 / profile.instructions.max(1) as f64;
+	println!("  {:<10} {:>10} {:>6.1}%", OPCODES[op], count, percent);
+    }
+    println!();
+    println!("hot spots:");
+    for line in hotspots(profile, HOTSPOTS) {
+	println!("  {}", line);
+    }
+    if !profile.routines.is_empty() {
+	println!();
+	println!("subroutines:   {:>8} {:>12} {:>12}", "calls", "own", "total");
+	let mut routines: Vec<(&String, &Routine)> = profile.routines.iter().collect();
+	routines.sort_by_key(|(_, r)| std::cmp::Reverse(r.total));
+	for (name, r) in routines {
+	    println!("  {:<12} {:>8} {:>12} {:>12}", name, r.calls, r.own, r.total);
+	}
+    }
+}
+
+/// Machine-readable form of a profile
+pub fn to_json(program: &str, profile: &Profile) -> Value {
+    let opcodes = profile.opcodes.iter().enumerate()
+	.filter(|(_, n)| **n > 0)
+	.map(|(op, n)| (OPCODES[op], (*n).into()))
+	.collect();
+    let addresses: Vec<(String, Value)> = profile.addresses.iter()
+	.map(|(addr, h)| (format!("x{:04X}", addr), h.count.into()))
+	.collect();
+    let routines = profile.routines.iter()
+	.map(|(name, r)| (name.as_str(), json::object(vec![
+	    ("calls", r.calls.into()),
+	    ("own", r.own.into()),
+	    ("total", r.total.into())
+	])))
+	.collect();
+    json::object(vec![
+	("program", program.into()),
+	("halted", profile.halted.into()),
+	("instructions", profile.instructions.into()),
+	("cycles", profile.cycles.into()),
+	("memory", json::object(vec![
+	    ("program_words", (profile.program_words as u64).into()),
+	    ("written_words", (profile.written_words as u64).into()),
+	    ("stack_words", (profile.stack_words as u64).into())
+	])),
+	("blocks", Value::Array(profile.blocks.iter().map(|a| (*a as u64).into()).collect())),
+	("opcodes", json::object(opcodes)),
+	("addresses", Value::Object(addresses)),
+	("subroutines", json::object(routines))
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hotspots, profile, Hotspot, Profiler};
+    use crate::endian::{bytes, Endian};
+    use crate::fixtures::Fixture;
+    use crate::loader::string_block;
+
+    fn obj(words: &[u16]) -> Vec<u8> {
+	bytes(&words.iter().map(|w| *w as i16).collect::<Vec<_>>(), Endian::Big)
+    }
+
+    #[test]
+    fn profile_test() {
+	let program = obj(&[
+	    0x3000,
+	    0b0100_1_00000000010, // JSR #2
+	    0b0100_1_00000000001, // JSR #1
+	    0b1111_0000_00100101, // TRAP x25
+	    0b0001_001_001_1_00001, // ADD R1, R1, #1
+	    0b1100_000_111_000000 // RET
+	]);
+	let p = profile(&program, &[], &[], None, 1000).expect("Failed to profile");
+	assert!(p.halted);
+	assert_eq!(p.program_words, 5);
+	assert_eq!(p.opcodes[0b0100], 2 + 37); // and the HALT routine calling PUTS and OUT
+	assert_eq!(p.opcodes[0b0001], 2 + 37); // and the HALT routine's stack push and PUTS loop
+	let routine = p.routines["x3003"];
+	assert_eq!((routine.calls, routine.own, routine.total), (2, 4, 4));
+	assert_eq!(p.routines["TRAP x25"].calls, 1);
+	assert_eq!(p.addresses[&0x3003], Hotspot { count: 2, word: 0b0001_001_001_1_00001 });
+	assert_eq!(p.addresses.values().map(|h| h.count).sum::<u64>(), p.instructions);
+	let top = hotspots(&p, 3);
+	assert_eq!(top.len(), 3);
+	assert!(top[0].contains("x02"), "{}", top[0]); // the OS output loop
+    }
+
+    #[test]
+    fn input_test() {
+	let program = obj(&[
+	    0x3000,
+	    0b1010_000_000000100, // LDI R0, KBSR
+	    0b0000_010_111111110, // BRz #-2
+	    0b1010_000_000000011, // LDI R0, KBDR
+	    0b0011_000_000000011, // ST R0, #3
+	    0b1111_0000_00100101, // TRAP x25
+	    0xFE00,
+	    0xFE02
+	]);
+	let p = profile(&program, b"q", &[], None, 1000).expect("Failed to profile");
+	assert!(p.halted);
+	assert_eq!(p.written_words, 1);
+	assert!(profile(&program, b"", &[], None, 1000).is_err());
+    }
+
+    #[test]
+    fn data_test() {
+	let program = obj(&[
+	    0x3000,
+	    0b0110_001_000_000001, // LDR R1, R0, #1
+	    0b1111_0000_00100101 // TRAP x25
+	]);
+	let data = vec![string_block(b"ok")];
+	let a = profile(&program, b"", &data, Some(3), 1000).expect("Failed to profile");
+	let b = profile(&program, b"", &data, Some(4), 1000).expect("Failed to profile");
+	assert_ne!(a.blocks, b.blocks);
+	assert!(a.blocks[0] >= 0x3003);
+	let fixed = profile(&program, b"", &data, None, 1000).expect("Failed to profile");
+	assert_eq!(fixed.blocks, vec![0x3002]);
+    }
+
+    #[test]
+    fn profiler_test() {
+	// run through a mirror, then fetch from protected memory in user mode
+	let mut lc3 = Fixture::bare()
+	    .code(&[0b0001_001_001_1_00001, 0b0000_111_000000000]) // ADD R1, R1, #1; NOP
+	    .data(0x0102, &[0x1000]) // ACV handler
+	    .data(0x1000, &[0b0001_010_010_1_00001]) // ADD R2, R2, #1
+	    .pc(0x5000)
+	    .build();
+	lc3.memory.add_mirror(0x3000, 0x5000, 2).unwrap();
+	lc3.memory.protect(0x0000..=0x2FFF);
+	lc3.saved_ssp = 0x3000;
+	let mut profiler = Profiler::new(&lc3);
+	let mut clock = |lc3: &mut crate::lc3::LC3| {
+	    profiler.before(lc3);
+	    lc3.clock();
+	    profiler.after(lc3);
+	};
+	clock(&mut lc3);
+	lc3.psr |= 0b1 << 15;
+	lc3.pc = 0x0200;
+	clock(&mut lc3); // faults
+	clock(&mut lc3);
+	let p = &profiler.profile;
+	assert_eq!(p.addresses[&0x5000], Hotspot { count: 1, word: 0b0001_001_001_1_00001 });
+	assert_eq!(p.addresses[&0x1000].count, 1);
+	assert_eq!(p.addresses.len(), 2);
+	assert_eq!(p.opcodes[0b0001], 2);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::disasm::disassemble;
+use crate::lc3::{LC3, Reg};
+
+const USAGE: &str = "usage: lc3-emu selftest [--verbose]";
+
+/// Final state a case must reach, anything left out isn't checked
+#[derive(Default)]
+struct Expect {
+    regs: Vec<(Reg, i16)>,
+    pc: Option<u16>,
+    psr: Option<u16>,
+    cc: Option<i16>,
+    mem: Vec<(u16, i16)>
+}
+
+/// One conformance program: `code` at x3000, `setup` run on the machine before `steps` clocks
+struct Case {
+    group: &'static str,
+    name: &'static str,
+    code: Vec<i16>,
+    setup: fn(&mut LC3),
+    steps: usize,
+    expect: Expect
+}
+
+const N: i16 = 0b100;
+const Z: i16 = 0b010;
+const P: i16 = 0b001;
+
+fn case(group: &'static str, name: &'static str, code: &[i16], setup: fn(&mut LC3), expect: Expect) -> Case {
+    Case { group, name, code: code.to_vec(), setup, steps: code.len().max(1), expect }
+}
+
+/// User mode with separate user (xFE00) and supervisor (x2F00) stacks and vectors at x0500-x0700
+fn user(lc3: &mut LC3) {
+    lc3.psr = 0x8002u16 as i16;
+    lc3.r[6] = 0xFE00u16 as i16;
+    lc3.saved_ssp = 0x2F00;
+    vectors(lc3);
+}
+
+fn vectors(lc3: &mut LC3) {
+    lc3.memory.put(0x0100, 0x0500); // privilege
+    lc3.memory.put(0x0101, 0x0600); // illegal opcode
+    lc3.memory.put(0x0180, 0x0700); // keyboard
+    lc3.memory.put(0x0700, 0b1000_0000_0000_0000); // RTI
+}
+
+fn cases() -> Vec<Case> {
+    vec![
+	case("ADD", "register", &[0b0001_001_010_0_00_011], |lc3| {
+	    lc3.r[2] = 100;
+	    lc3.r[3] = -50;
+	}, Expect { regs: vec![(Reg::R1, 50)], cc: Some(P), ..Expect::default() }),
+	case("ADD", "immediate", &[0b0001_001_010_1_10000], |lc3| lc3.r[2] = 5,
+	     Expect { regs: vec![(Reg::R1, -11)], cc: Some(N), ..Expect::default() }),
+	case("AND", "register", &[0b0101_001_010_0_00_011], |lc3| {
+	    lc3.r[2] = 0x0F0F;
+	    lc3.r[3] = 0x00FF;
+	}, Expect { regs: vec![(Reg::R1, 0x000F)], cc: Some(P), ..Expect::default() }),
+	case("AND", "immediate", &[0b0101_001_001_1_00000], |lc3| lc3.r[1] = -1283,
+	     Expect { regs: vec![(Reg::R1, 0)], cc: Some(Z), ..Expect::default() }),
+	case("NOT", "register", &[0b1001_010_001_111111], |lc3| lc3.r[1] = 0x00FF,
+	     Expect { regs: vec![(Reg::R2, 0xFF00)], cc: Some(N), ..Expect::default() }),
+	case("BR", "n taken", &[0b0000_100_000000101], |lc3| lc3.psr = N,
+	     Expect { pc: Some(0x3006), ..Expect::default() }),
+	case("BR", "z taken", &[0b0000_010_000000101], |lc3| lc3.psr = Z,
+	     Expect { pc: Some(0x3006), ..Expect::default() }),
+	case("BR", "p taken", &[0b0000_001_000000101], |lc3| lc3.psr = P,
+	     Expect { pc: Some(0x3006), ..Expect::default() }),
+	case("BR", "nzp backward", &[0b0000_111_111111111], |lc3| lc3.psr = Z,
+	     Expect { pc: Some(0x3000), ..Expect::default() }),
+	case("BR", "p not on n", &[0b0000_001_000000101], |lc3| lc3.psr = N,
+	     Expect { pc: Some(0x3001), ..Expect::default() }),
+	case("BR", "n not on p", &[0b0000_100_000000101], |lc3| lc3.psr = P,
+	     Expect { pc: Some(0x3001), ..Expect::default() }),
+	case("JMP", "JMP", &[0b1100_000_010_000000], |lc3| lc3.r[2] = 0x4000,
+	     Expect { pc: Some(0x4000), ..Expect::default() }),
+	case("JMP", "RET", &[0b1100_000_111_000000], |lc3| lc3.r[7] = 0x4000,
+	     Expect { pc: Some(0x4000), ..Expect::default() }),
+	case("JSR", "JSR", &[0b0100_1_11111111110], |_| (),
+	     Expect { pc: Some(0x2FFF), regs: vec![(Reg::R7, 0x3001)], ..Expect::default() }),
+	case("JSR", "JSRR", &[0b0100_0_00_010_000000], |lc3| lc3.r[2] = 0x4000,
+	     Expect { pc: Some(0x4000), regs: vec![(Reg::R7, 0x3001)], ..Expect::default() }),
+	case("JSR", "JSRR R7", &[0b0100_0_00_111_000000], |lc3| lc3.r[7] = 0x4000,
+	     Expect { pc: Some(0x4000), regs: vec![(Reg::R7, 0x3001)], ..Expect::default() }),
+	case("LD", "PC-relative", &[0b0010_010_000000001], |lc3| lc3.memory.put(0x3002, -5),
+	     Expect { regs: vec![(Reg::R2, -5)], cc: Some(N), ..Expect::default() }),
+	case("LDI", "indirect", &[0b1010_010_000000001], |lc3| {
+	    lc3.r[2] = 7;
+	    lc3.memory.put(0x3002, 0x4000);
+	}, Expect { regs: vec![(Reg::R2, 0)], cc: Some(Z), ..Expect::default() }),
+	case("LDR", "base+offset", &[0b0110_010_001_111111], |lc3| {
+	    lc3.r[1] = 0x4001;
+	    lc3.memory.put(0x4000, 42);
+	}, Expect { regs: vec![(Reg::R2, 42)], cc: Some(P), ..Expect::default() }),
+	case("LEA", "PC-relative", &[0b1110_010_111111110], |_| (),
+	     Expect { regs: vec![(Reg::R2, 0x2FFF)], ..Expect::default() }),
+	case("ST", "PC-relative", &[0b0011_010_000000001], |lc3| lc3.r[2] = 0x1234,
+	     Expect { mem: vec![(0x3002, 0x1234)], ..Expect::default() }),
+	case("STI", "indirect", &[0b1011_010_000000001], |lc3| {
+	    lc3.r[2] = 0x1234;
+	    lc3.memory.put(0x3002, 0x4000);
+	}, Expect { mem: vec![(0x4000, 0x1234)], ..Expect::default() }),
+	case("STR", "base+offset", &[0b0111_010_001_111111], |lc3| {
+	    lc3.r[1] = 0x4001;
+	    lc3.r[2] = 0x1234;
+	}, Expect { mem: vec![(0x4000, 0x1234)], ..Expect::default() }),
+	case("TRAP", "vector", &[0b1111_0000_00110000], |lc3| {
+	    user(lc3);
+	    lc3.r[7] = 0x1234;
+	    lc3.memory.put(0x0030, 0x4000);
+	}, Expect {
+	    pc: Some(0x4000),
+	    psr: Some(0x0002),
+	    regs: vec![(Reg::R6, 0x2EFE), (Reg::R7, 0x1234)],
+	    mem: vec![(0x2EFE, 0x3001), (0x2EFF, 0x8002)],
+	    ..Expect::default()
+	}),
+	Case { steps: 3, ..case("TRAP", "return", &[0b1111_0000_00110000, 0b0001_001_001_1_00001], |lc3| {
+	    user(lc3);
+	    lc3.memory.put(0x0030, 0x0700);
+	}, Expect { pc: Some(0x3002), psr: Some(0x8001), regs: vec![(Reg::R1, 1), (Reg::R6, 0xFE00)], ..Expect::default() }) },
+	case("TRAP", "legacy R7", &[0b1111_0000_00110000], |lc3| {
+	    user(lc3);
+	    lc3.legacy_traps = true;
+	    lc3.memory.put(0x0030, 0x4000);
+	}, Expect { pc: Some(0x4000), psr: Some(0x8002), regs: vec![(Reg::R6, 0xFE00), (Reg::R7, 0x3001)], ..Expect::default() }),
+	case("RTI", "privilege", &[0b1000_0000_0000_0000], user, Expect {
+	    pc: Some(0x0500),
+	    psr: Some(0x0002),
+	    regs: vec![(Reg::R6, 0x2EFE)],
+	    mem: vec![(0x2EFE, 0x3001), (0x2EFF, 0x8002)],
+	    ..Expect::default()
+	}),
+	case("exception", "illegal", &[0xD000], |lc3| {
+	    lc3.r[6] = 0x2F00;
+	    vectors(lc3);
+	}, Expect {
+	    pc: Some(0x0600),
+	    regs: vec![(Reg::R6, 0x2EFE)],
+	    mem: vec![(0x2EFE, 0x3001), (0x2EFF, 0x0000)],
+	    ..Expect::default()
+	}),
+	Case { steps: 0, ..case("interrupt", "entry", &[], |lc3| {
+	    user(lc3);
+	    lc3.interrupt(0x80, 4, 0x61).ok();
+	}, Expect {
+	    pc: Some(0x0700),
+	    psr: Some(0x0402),
+	    regs: vec![(Reg::R6, 0x2EFE)],
+	    mem: vec![(0x2EFE, 0x3000), (0x2EFF, 0x8002), (0xFE02, 0x61)],
+	    ..Expect::default()
+	}) },
+	case("interrupt", "return", &[], |lc3| {
+	    user(lc3);
+	    lc3.interrupt(0x80, 4, 0x61).ok();
+	}, Expect { pc: Some(0x3000), psr: Some(0x8002), regs: vec![(Reg::R6, 0xFE00)], ..Expect::default() }),
+	case("interrupt", "nested", &[], |lc3| {
+	    lc3.psr = 0x0101;
+	    lc3.r[6] = 0x2F00;
+	    vectors(lc3);
+	    lc3.interrupt(0x80, 4, 0x61).ok();
+	}, Expect { pc: Some(0x3000), psr: Some(0x0101), regs: vec![(Reg::R6, 0x2F00)], ..Expect::default() }),
+	Case { steps: 0, ..case("interrupt", "masked", &[], |lc3| {
+	    lc3.psr = 0x0500;
+	    vectors(lc3);
+	    lc3.interrupt(0x80, 4, 0x61).ok();
+	}, Expect { pc: Some(0x3000), psr: Some(0x0500), ..Expect::default() }) }
+    ]
+}
+
+/// Runs a case, returning what didn't match
+fn check(case: &Case) -> Vec<String> {
+    let mut lc3 = LC3::new();
+    lc3.memory.write_words(0x3000, &case.code);
+    lc3.pc = 0x3000;
+    lc3.halted = false;
+    (case.setup)(&mut lc3);
+    for _ in 0..case.steps {
+	lc3.clock();
+    }
+
+    let mut wrong = Vec::new();
+    let expect = &case.expect;
+    let regs = lc3.regs();
+    for (reg, value) in &expect.regs {
+	if regs[*reg as usize] != *value {
+	    wrong.push(format!("{:?} = x{:04X}, expected x{:04X}", reg, regs[*reg as usize] as u16, *value as u16));
+	}
+    }
+    if let Some(pc) = expect.pc.filter(|pc| *pc != lc3.pc as u16) {
+	wrong.push(format!("PC = x{:04X}, expected x{:04X}", lc3.pc as u16, pc));
+    }
+    if let Some(psr) = expect.psr.filter(|psr| *psr != lc3.psr as u16) {
+	wrong.push(format!("PSR = x{:04X}, expected x{:04X}", lc3.psr as u16, psr));
+    }
+    if let Some(cc) = expect.cc.filter(|cc| *cc != lc3.psr & 0b111) {
+	wrong.push(format!("NZP = {:03b}, expected {:03b}", lc3.psr & 0b111, cc));
+    }
+    for (addr, value) in &expect.mem {
+	let actual = lc3.memory.peek(*addr);
+	if actual != *value {
+	    wrong.push(format!("M[x{:04X}] = x{:04X}, expected x{:04X}", addr, actual as u16, *value as u16));
+	}
+    }
+    wrong
+}
+
+/// `lc3-emu selftest`, returns the process exit code
+pub fn main(args: &[String]) -> i32 {
+    let verbose = match args {
+	[] => false,
+	[flag] if flag == "--verbose" => true,
+	_ => {
+	    eprintln!("{}", USAGE);
+	    return 1;
+	}
+    };
+    let cases = cases();
+    let results: Vec<Vec<String>> = cases.iter().map(check).collect();
+
+    let mut groups: Vec<&str> = Vec::new();
+    for case in &cases {
+	if !groups.contains(&case.group) {
+	    groups.push(case.group);
+	}
+    }
+    for group in groups {
+	let cells: Vec<String> = cases.iter().zip(&results)
+	    .filter(|(case, _)| case.group == group)
+	    .map(|(case, wrong)| format!("{} {}", if wrong.is_empty() { "ok  " } else { "FAIL" }, case.name))
+	    .collect();
+	println!("{:<10} {}", group, cells.join("   "));
+    }
+
+    let failed: Vec<(&Case, &Vec<String>)> = cases.iter().zip(&results).filter(|(_, w)| !w.is_empty()).collect();
+    for (case, wrong) in &failed {
+	println!("\n{} {}: {}", case.group, case.name, wrong.join(", "));
+	if verbose {
+	    for (i, word) in case.code.iter().enumerate() {
+		let addr = 0x3000 + i as u16;
+		println!("    x{:04X}  {:04X}  {}", addr, *word as u16, disassemble(*word, addr));
+	    }
+	}
+    }
+    println!("\n{}/{} passed", cases.len() - failed.len(), cases.len());
+    if failed.is_empty() { 0 } else { 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cases, check};
+
+    #[test]
+    fn selftest_test() {
+	for case in cases() {
+	    assert_eq!(check(&case), Vec::<String>::new(), "{} {}", case.group, case.name);
+	}
+    }
+}
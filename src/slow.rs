@@ -0,0 +1,60 @@
+use crate::disasm;
+use crate::lc3::LC3;
+
+/// Instructions shown before and after the current one
+const CONTEXT: u16 = 3;
+
+/// One frame of the slow-motion display: registers, condition codes, the code around PC with
+/// the current instruction highlighted, and the console output so far
+pub fn frame(lc3: &LC3, output: &str) -> String {
+    let mut out = String::from("\x1b[H\x1b[2J"); // home and clear
+    let nzp: String = [(0b100, 'N'), (0b010, 'Z'), (0b001, 'P')].iter()
+	.map(|(bit, c)| if lc3.psr & bit != 0 { *c } else { '-' })
+	.collect();
+    let mode = if lc3.psr & (0b1 << 15) != 0 { "user" } else { "supervisor" };
+    out += &format!("PC x{:04X}  PSR x{:04X}  {}  {}  priority {}\n",
+		    lc3.pc as u16, lc3.psr as u16, nzp, mode, (lc3.psr >> 8) & 0b111);
+    let regs = lc3.regs();
+    for row in regs.chunks(4).enumerate() {
+	let (base, values) = row;
+	let cells: Vec<String> = values.iter().enumerate()
+	    .map(|(i, v)| format!("R{} x{:04X}", base * 4 + i, *v as u16))
+	    .collect();
+	out += &cells.join("  ");
+	out += "\n";
+    }
+    out += "\n";
+    let pc = lc3.pc as u16;
+    for addr in pc.wrapping_sub(CONTEXT)..=pc.wrapping_add(CONTEXT) {
+	let line = disasm::line(addr, lc3.memory.peek(addr));
+	if addr == pc {
+	    out += &format!("\x1b[7m> {:<40}\x1b[0m\n", line);
+	} else {
+	    out += &format!("  {}\n", line);
+	}
+    }
+    out += "\n-- output --\n";
+    out += output;
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::frame;
+    use crate::lc3::LC3;
+
+    #[test]
+    fn frame_test() {
+	let mut lc3 = LC3::new();
+	lc3.memory.put(0x3000, 0b0001_001_010_1_00001);
+	lc3.pc = 0x3000;
+	lc3.psr = 0b010;
+	lc3.r[5] = 0x1234;
+	let text = frame(&lc3, "hi");
+	assert!(text.contains("PC x3000"));
+	assert!(text.contains("-Z-"));
+	assert!(text.contains("R5 x1234"));
+	assert!(text.contains("\x1b[7m> x3000  12A1  ADD R1, R2, #1"));
+	assert!(text.ends_with("hi"));
+    }
+}
@@ -0,0 +1,74 @@
+; Standard library for user programs, loaded at xA000 by Fixture::with_stdlib
+;
+; Every routine is a subroutine called with JSR or JSRR that keeps every register but R7 and
+; its results, and leaves the condition codes unspecified. It's out of JSR range of x3000, so
+; callers load the address (stdlib::routine) and use JSRR.
+
+	.ORIG xA000
+
+; R0 = R0 * R1, wrapping at 16 bits
+MULTIPLY
+	ST R1, MUL_R1
+	ST R2, MUL_R2
+	ADD R2, R0, #0		; multiplicand
+	AND R0, R0, #0
+	ADD R1, R1, #0
+	BRzp MUL_LOOP
+	NOT R1, R1		; negative multiplier, negate both sides
+	ADD R1, R1, #1
+	NOT R2, R2
+	ADD R2, R2, #1
+MUL_LOOP
+	ADD R1, R1, #0
+	BRz MUL_DONE
+	ADD R0, R0, R2
+	ADD R1, R1, #-1
+	BRnzp MUL_LOOP
+MUL_DONE
+	LD R1, MUL_R1
+	LD R2, MUL_R2
+	RET
+MUL_R1	.BLKW 1
+MUL_R2	.BLKW 1
+
+; R0 = R0 / R1 and R1 = R0 % R1, for R0 >= 0 and R1 > 0
+DIVIDE
+	ST R2, DIV_R2
+	ST R3, DIV_R3
+	NOT R3, R1
+	ADD R3, R3, #1		; -divisor
+	ADD R1, R0, #0		; remainder
+	AND R0, R0, #0		; quotient
+DIV_LOOP
+	ADD R2, R1, R3
+	BRn DIV_DONE
+	ADD R1, R2, #0
+	ADD R0, R0, #1
+	BRnzp DIV_LOOP
+DIV_DONE
+	LD R2, DIV_R2
+	LD R3, DIV_R3
+	RET
+DIV_R2	.BLKW 1
+DIV_R3	.BLKW 1
+
+; R0 = length of the null-terminated string at R0
+STRLEN
+	ST R1, LEN_R1
+	ST R2, LEN_R2
+	ADD R1, R0, #0
+	AND R0, R0, #0
+LEN_LOOP
+	LDR R2, R1, #0
+	BRz LEN_DONE
+	ADD R0, R0, #1
+	ADD R1, R1, #1
+	BRnzp LEN_LOOP
+LEN_DONE
+	LD R1, LEN_R1
+	LD R2, LEN_R2
+	RET
+LEN_R1	.BLKW 1
+LEN_R2	.BLKW 1
+
+	.END
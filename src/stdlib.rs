@@ -0,0 +1,81 @@
+//! Subroutine library for user programs: MULTIPLY, DIVIDE and STRLEN, assembled from
+//! stdlib.asm like the built-in OS
+
+use crate::asm::{assemble, Program};
+use crate::lc3::LC3;
+
+use std::sync::OnceLock;
+
+/// Source of the standard library
+pub const SOURCE: &str = include_str!("stdlib.asm");
+
+/// The standard library, assembled on first use
+pub fn image() -> &'static Program {
+    static IMAGE: OnceLock<Program> = OnceLock::new();
+    IMAGE.get_or_init(|| assemble(SOURCE).expect("Standard library failed to assemble"))
+}
+
+/// Writes the library into memory
+pub fn load(lc3: &mut LC3) {
+    image().load(&mut lc3.memory);
+}
+
+/// Address of a library routine by its label, e.g. `MULTIPLY`
+pub fn routine(name: &str) -> Option<u16> {
+    image().symbols.get(name).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::routine;
+    use crate::fixtures::Fixture;
+    use crate::lc3::batch::StopReason;
+
+    /// Calls `name` from user mode with R0 and R1 set, returning R0 and R1 afterwards
+    fn call(name: &str, r0: i16, r1: i16) -> (i16, i16) {
+	let mut lc3 = Fixture::with_stdlib()
+	    .code(&[
+		0b0010_010_000000011, // LD R2, x3004
+		0b0100_0_00_010_000000, // JSRR R2
+		0b0001_100_000_1_00000, // ADD R4, R0, #0 ; HALT uses R0
+		0b1111_0000_00100101, // HALT
+		routine(name).expect("No such routine") as i16
+	    ])
+	    .reg(0, r0)
+	    .reg(1, r1)
+	    .build();
+	assert_eq!(lc3.run_until_halt(), StopReason::Halted);
+	(lc3.r[4], lc3.r[1])
+    }
+
+    #[test]
+    fn multiply_test() {
+	assert_eq!(call("MULTIPLY", 6, 7), (42, 7));
+	assert_eq!(call("MULTIPLY", 6, -7), (-42, -7));
+	assert_eq!(call("MULTIPLY", -6, 0), (0, 0));
+    }
+
+    #[test]
+    fn divide_test() {
+	assert_eq!(call("DIVIDE", 47, 5), (9, 2));
+	assert_eq!(call("DIVIDE", 3, 5), (0, 3));
+    }
+
+    #[test]
+    fn strlen_test() {
+	let mut lc3 = Fixture::with_stdlib()
+	    .code(&[
+		0b1110_000_000000101, // LEA R0, x3006
+		0b0010_010_000000011, // LD R2, x3005
+		0b0100_0_00_010_000000, // JSRR R2
+		0b0001_100_000_1_00000, // ADD R4, R0, #0
+		0b1111_0000_00100101, // HALT
+		routine("STRLEN").unwrap() as i16
+	    ])
+	    .string(0x3006, "hello")
+	    .reg(1, 0x1111)
+	    .build();
+	assert_eq!(lc3.run_until_halt(), StopReason::Halted);
+	assert_regs!(lc3, r4 = 5, r1 = 0x1111);
+    }
+}
@@ -0,0 +1,165 @@
+//! Symbol tables: lc3as `.sym` files, and lookups both ways for the disassembler, debugger
+//! and tracer
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Labels and their addresses
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Symbols {
+    names: BTreeMap<String, u16>,
+    addrs: BTreeMap<u16, String> // alphabetically first label at each address
+}
+
+impl From<&BTreeMap<String, u16>> for Symbols {
+    fn from(map: &BTreeMap<String, u16>) -> Self {
+	let mut symbols = Symbols::new();
+	for (name, addr) in map {
+	    symbols.insert(name, *addr);
+	}
+	symbols
+    }
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+	Self::default()
+    }
+
+    /// Adds a label, moving it if the name is already at another address
+    pub fn insert(&mut self, name: &str, addr: u16) {
+	if let Some(old) = self.names.insert(name.to_string(), addr) {
+	    if old != addr && self.name(old) == Some(name) {
+		self.addrs.remove(&old);
+		if let Some((next, _)) = self.names.iter().find(|(_, a)| **a == old) {
+		    self.addrs.insert(old, next.clone());
+		}
+	    }
+	}
+	let label = self.addrs.entry(addr).or_insert_with(|| name.to_string());
+	if name < label.as_str() {
+	    *label = name.to_string();
+	}
+    }
+
+    /// Adds every label from `other`, replacing any of the same name
+    pub fn merge(&mut self, other: &Symbols) {
+	for (name, addr) in &other.names {
+	    self.insert(name, *addr);
+	}
+    }
+
+    pub fn len(&self) -> usize {
+	self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+	self.names.is_empty()
+    }
+
+    /// Every label and its address, alphabetically
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+	self.names.iter().map(|(name, addr)| (name.as_str(), *addr))
+    }
+
+    /// Address of a label, ignoring case if there's no exact match
+    pub fn addr(&self, name: &str) -> Option<u16> {
+	self.names.get(name).copied().or_else(|| {
+	    self.names.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, a)| *a)
+	})
+    }
+
+    /// The label at exactly `addr`
+    pub fn name(&self, addr: u16) -> Option<&str> {
+	self.addrs.get(&addr).map(|n| n.as_str())
+    }
+
+    /// `LOOP` or `LOOP+3` for the closest label at or below `addr`
+    pub fn nearest(&self, addr: u16) -> Option<String> {
+	let (base, name) = self.addrs.range(..=addr).next_back()?;
+	Some(match addr - base {
+	    0 => name.clone(),
+	    offset => format!("{}+{}", name, offset)
+	})
+    }
+
+    /// Reads an lc3as symbol table: `//` comment lines, the symbols as a name and a hex address
+    pub fn parse(text: &str) -> Result<Symbols, String> {
+	let mut symbols = Symbols::new();
+	for (i, line) in text.lines().enumerate() {
+	    let line = line.trim();
+	    let line = line.strip_prefix("//").unwrap_or(line);
+	    match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+		[name, addr] if !name.starts_with('-') => match u16::from_str_radix(addr, 16) {
+		    Ok(addr) => symbols.insert(name, addr),
+		    Err(_) if *name == "Symbol" => (), // the "Symbol table" title
+		    Err(_) => return Err(format!("line {}: bad address {}", i + 1, addr))
+		},
+		_ => () // headings, rules and blank lines
+	    }
+	}
+	Ok(symbols)
+    }
+
+    /// The table in lc3as format
+    pub fn to_sym(&self) -> String {
+	let mut out = String::from("// Symbol table\n// Scope level 0:\n//\tSymbol Name       Page Address\n//\t----------------  ------------\n");
+	let mut entries: Vec<(&String, &u16)> = self.names.iter().collect();
+	entries.sort_by_key(|(name, addr)| (**addr, name.as_str()));
+	for (name, addr) in entries {
+	    out += &format!("//\t{:<16}  {:04X}\n", name, addr);
+	}
+	out
+    }
+
+    pub fn read_file(path: &Path) -> Result<Symbols, String> {
+	let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+	Symbols::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// Where lc3as puts the symbol table for `prog.obj`: `prog.sym` beside it
+pub fn sym_path(obj: &Path) -> PathBuf {
+    obj.with_extension("sym")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Symbols;
+
+    #[test]
+    fn symbols_test() {
+	let mut symbols = Symbols::new();
+	symbols.insert("MAIN", 0x3000);
+	symbols.insert("LOOP", 0x3002);
+	symbols.insert("AGAIN", 0x3002);
+	assert_eq!(symbols.addr("loop"), Some(0x3002));
+	assert_eq!(symbols.name(0x3002), Some("AGAIN"));
+	assert_eq!(symbols.nearest(0x3001).as_deref(), Some("MAIN+1"));
+	assert_eq!(symbols.nearest(0x2FFF), None);
+	let text = symbols.to_sym();
+	assert!(text.contains("//\tLOOP              3002\n"));
+	assert_eq!(Symbols::parse(&text).as_ref(), Ok(&symbols));
+	let mut merged = Symbols::new();
+	merged.insert("DATA", 0x4000);
+	merged.merge(&symbols);
+	assert_eq!((merged.len(), merged.addr("DATA"), merged.addr("MAIN")), (4, Some(0x4000), Some(0x3000)));
+	let mut moved = Symbols::new();
+	moved.insert("MAIN", 0x3100);
+	merged.merge(&moved);
+	assert_eq!((merged.name(0x3000), merged.name(0x3100)), (None, Some("MAIN")));
+	merged.insert("AGAIN", 0x3005);
+	assert_eq!((merged.name(0x3002), merged.name(0x3005)), (Some("LOOP"), Some("AGAIN")));
+    }
+
+    #[test]
+    fn parse_test() {
+	// as written by lc3as
+	let text = "// Symbol table\n// Scope level 0:\n//\tSymbol Name       Page Address\n\
+		    //\t----------------  ------------\n//\tSTART             3000\n//\tDATA              300A\n\n";
+	let symbols = Symbols::parse(text).expect("Failed to parse");
+	assert_eq!(symbols.len(), 2);
+	assert_eq!(symbols.addr("DATA"), Some(0x300A));
+	assert!(Symbols::parse("//\tSTART   30G0\n").is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use crate::json::{self, Value};
+use crate::rng::Rng;
+
+const USAGE: &str = "usage: lc3-emu gen <echo|upper|sum> [--count N] [--min-len N] [--max-len N] [--seed N] [--json <out.json>]";
+
+/// Assignment patterns test vectors can be generated for
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Pattern {
+    Echo, // typed line comes back out
+    Upper, // typed line comes back out in upper case
+    SumArray // R2 = sum of the words in the data block passed in R0, length in R1 (HALT overwrites R0)
+}
+
+/// Shape of the vectors to generate
+#[derive(Debug, Copy, Clone)]
+pub struct Spec {
+    pub pattern: Pattern,
+    pub count: usize,
+    pub min_len: usize,
+    pub max_len: usize,
+    pub seed: u64
+}
+
+/// One generated test case
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    pub name: String,
+    pub input: String, // keys typed ahead
+    pub data: Vec<Vec<i16>>, // data blocks, addresses go in R0, R1, ...
+    pub registers: Vec<(u8, i16)>, // initial registers beyond the data block addresses
+    pub output: Option<String>, // expected console output
+    pub expect_registers: Vec<(u8, i16)> // expected final registers
+}
+
+pub fn generate(spec: &Spec) -> Vec<Case> {
+    let mut rng = Rng::new(spec.seed);
+    (0..spec.count).map(|i| {
+	let len = rng.range(spec.min_len as i64, spec.max_len.max(spec.min_len) as i64) as usize;
+	let mut case = Case {
+	    name: format!("{:?}-{}", spec.pattern, i).to_lowercase(),
+	    input: String::new(),
+	    data: Vec::new(),
+	    registers: Vec::new(),
+	    output: None,
+	    expect_registers: Vec::new()
+	};
+	match spec.pattern {
+	    Pattern::Echo | Pattern::Upper => {
+		let line: String = (0..len).map(|_| rng.range(0x20, 0x7E) as u8 as char).collect();
+		case.input = format!("{}\n", line);
+		case.output = Some(match spec.pattern {
+		    Pattern::Upper => line.to_ascii_uppercase(),
+		    _ => line
+		} + "\n");
+	    }
+	    Pattern::SumArray => {
+		let words: Vec<i16> = (0..len).map(|_| rng.range(-1000, 1000) as i16).collect();
+		let sum = words.iter().fold(0i16, |acc, w| acc.wrapping_add(*w));
+		case.data.push(words);
+		case.registers.push((1, len as i16));
+		case.expect_registers.push((2, sum));
+	    }
+	}
+	case
+    }).collect()
+}
+
+fn registers(regs: &[(u8, i16)]) -> Value {
+    Value::Object(regs.iter().map(|(r, v)| (format!("R{}", r), (*v as f64).into())).collect())
+}
+
+/// The cases as a test file for `lc3-emu grade`
+pub fn to_json(cases: &[Case]) -> Value {
+    Value::Array(cases.iter().map(|c| {
+	let mut fields = vec![
+	    ("name", c.name.as_str().into()),
+	    ("input", c.input.as_str().into())
+	];
+	if !c.data.is_empty() {
+	    fields.push(("data", Value::Array(c.data.iter()
+		.map(|block| Value::Array(block.iter().map(|w| (*w as f64).into()).collect()))
+		.collect())));
+	}
+	if !c.registers.is_empty() {
+	    fields.push(("registers", registers(&c.registers)));
+	}
+	let mut expect = vec![("halted", true.into())];
+	if !c.expect_registers.is_empty() {
+	    expect.push(("registers", registers(&c.expect_registers)));
+	}
+	if let Some(output) = &c.output {
+	    expect.push(("output", output.as_str().into()));
+	}
+	fields.push(("expect", json::object(expect)));
+	json::object(fields)
+    }).collect())
+}
+
+/// `lc3-emu gen`, returns the process exit code
+pub fn main(args: &[String]) -> i32 {
+    match gen(args) {
+	Ok(()) => 0,
+	Err(e) => {
+	    eprintln!("{}", e);
+	    1
+	}
+    }
+}
+
+fn gen(args: &[String]) -> Result<(), String> {
+    let mut args = args.iter();
+    let pattern = match args.next().map(|a| a.as_str()) {
+	Some("echo") => Pattern::Echo,
+	Some("upper") => Pattern::Upper,
+	Some("sum") => Pattern::SumArray,
+	_ => return Err(USAGE.to_string())
+    };
+    let mut spec = Spec { pattern, count: 10, min_len: 1, max_len: 20, seed: 0 };
+    let mut json_out = None;
+    let number = |arg: Option<&String>| arg.and_then(|a| a.parse::<u64>().ok()).ok_or_else(|| USAGE.to_string());
+    while let Some(arg) = args.next() {
+	match arg.as_str() {
+	    "--count" => spec.count = number(args.next())? as usize,
+	    "--min-len" => spec.min_len = number(args.next())? as usize,
+	    "--max-len" => spec.max_len = number(args.next())? as usize,
+	    "--seed" => spec.seed = number(args.next())?,
+	    "--json" => json_out = Some(args.next().ok_or(USAGE)?.clone()),
+	    _ => return Err(USAGE.to_string())
+	}
+    }
+    let text = to_json(&generate(&spec)).to_string() + "\n";
+    match json_out {
+	Some(path) => std::fs::write(&path, text).map_err(|e| format!("{}: {}", path, e)),
+	None => {
+	    print!("{}", text);
+	    Ok(())
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, to_json, Pattern, Spec};
+    use crate::grade::parse_cases;
+
+    fn spec(pattern: Pattern, seed: u64) -> Spec {
+	Spec { pattern, count: 5, min_len: 2, max_len: 8, seed }
+    }
+
+    #[test]
+    fn reproducible_test() {
+	assert_eq!(generate(&spec(Pattern::Echo, 9)), generate(&spec(Pattern::Echo, 9)));
+	assert_ne!(generate(&spec(Pattern::Echo, 9)), generate(&spec(Pattern::Echo, 10)));
+    }
+
+    #[test]
+    fn upper_test() {
+	for case in generate(&spec(Pattern::Upper, 1)) {
+	    let line = case.input.trim_end_matches('\n');
+	    assert!(line.len() >= 2 && line.len() <= 8);
+	    assert_eq!(case.output, Some(line.to_ascii_uppercase() + "\n"));
+	}
+    }
+
+    #[test]
+    fn sum_test() {
+	for case in generate(&spec(Pattern::SumArray, 1)) {
+	    let words = &case.data[0];
+	    assert_eq!(case.registers, vec![(1, words.len() as i16)]);
+	    let sum = words.iter().fold(0i16, |a, w| a.wrapping_add(*w));
+	    assert_eq!(case.expect_registers, vec![(2, sum)]);
+	}
+    }
+
+    #[test]
+    fn json_test() {
+	let cases = generate(&spec(Pattern::SumArray, 1));
+	let graded = parse_cases(&to_json(&cases)).expect("Failed to parse as grade tests");
+	assert_eq!(graded.len(), cases.len());
+	assert_eq!(graded[0].name, cases[0].name);
+	assert_eq!(graded[0].registers, vec![("R1".to_string(), cases[0].registers[0].1)]);
+	assert_eq!(graded[0].expect.registers, vec![("R2".to_string(), cases[0].expect_registers[0].1)]);
+	assert_eq!(graded[0].expect.halted, Some(true));
+	let echo = generate(&spec(Pattern::Echo, 1));
+	let graded = parse_cases(&to_json(&echo)).expect("Failed to parse as grade tests");
+	assert_eq!(graded[0].input, echo[0].input.as_bytes());
+	assert_eq!(graded[0].expect.output, echo[0].output);
+    }
+}
@@ -0,0 +1,109 @@
+//! Assertions for tests that report condition codes by name and show the instruction that
+//! produced them. `assert_cc!` and `assert_regs!` are exported at the crate root for
+//! downstream crates and integration tests.
+//!
+//! ```
+//! use lc3_emu::{assert_cc, assert_regs};
+//! use lc3_emu::lc3::LC3;
+//!
+//! let mut lc3 = LC3::new();
+//! lc3.memory.write_words(0x3000, &[0b0001_001_001_1_11111]); // ADD R1, R1, #-1
+//! lc3.pc = 0x3000;
+//! lc3.start();
+//! lc3.clock();
+//! assert_cc!(lc3, N);
+//! assert_regs!(lc3, r1 = -1, r2 = 0);
+//! ```
+
+use crate::disasm::disassemble;
+use crate::lc3::LC3;
+
+/// PSR condition code bits for `N`, `Z` or `P`
+pub fn cc_bits(name: &str) -> i16 {
+    match name {
+	"N" => 0b100,
+	"Z" => 0b010,
+	"P" => 0b001,
+	_ => panic!("unknown condition code {}", name)
+    }
+}
+
+/// Condition codes set in a PSR, as letters
+pub fn cc_name(psr: i16) -> String {
+    let name: String = [('N', 0b100), ('Z', 0b010), ('P', 0b001)].iter()
+	.filter(|(_, bit)| psr & bit != 0)
+	.map(|(c, _)| *c)
+	.collect();
+    if name.is_empty() { "none".to_string() } else { name }
+}
+
+/// Register number for `r0` through `r7`
+pub fn reg_index(name: &str) -> usize {
+    match name.as_bytes() {
+	[b'r', n @ b'0'..=b'7'] => (n - b'0') as usize,
+	_ => panic!("unknown register {}", name)
+    }
+}
+
+/// Context for failure messages: the last instruction executed and the registers
+pub fn context(lc3: &LC3) -> String {
+    let last = match lc3.last_instruction {
+	Some((addr, word)) => format!("x{:04X}  {:04X}  {}", addr, word as u16, disassemble(word, addr)),
+	None => "nothing executed".to_string()
+    };
+    let regs: Vec<String> = lc3.regs().iter().enumerate()
+	.map(|(i, r)| format!("R{}=x{:04X}", i, *r as u16))
+	.collect();
+    format!("last instruction: {}\n  {}  PC=x{:04X}  PSR=x{:04X}",
+	    last, regs.join(" "), lc3.pc as u16, lc3.psr as u16)
+}
+
+/// `assert_cc!(lc3, P)` checks the condition codes
+#[macro_export]
+macro_rules! assert_cc {
+    ($lc3:expr, $cc:ident) => {{
+	let lc3 = &$lc3;
+	let expected = $crate::testing::cc_bits(stringify!($cc));
+	assert!(lc3.psr & 0b111 == expected, "condition codes are {}, expected {}\n  {}",
+		$crate::testing::cc_name(lc3.psr), stringify!($cc), $crate::testing::context(lc3));
+    }};
+}
+
+/// `assert_regs!(lc3, r1 = 50, r7 = 0x3001)` checks registers, reporting every mismatch
+#[macro_export]
+macro_rules! assert_regs {
+    ($lc3:expr, $($reg:ident = $value:expr),+ $(,)?) => {{
+	let lc3 = &$lc3;
+	let mut wrong: Vec<String> = Vec::new();
+	$(
+	    let expected: i16 = $value;
+	    let actual = lc3.r[$crate::testing::reg_index(stringify!($reg))];
+	    if actual != expected {
+		wrong.push(format!("{} is x{:04X} ({}), expected x{:04X} ({})", stringify!($reg),
+				   actual as u16, actual, expected as u16, expected));
+	    }
+	)+
+	assert!(wrong.is_empty(), "{}\n  {}", wrong.join("\n"), $crate::testing::context(lc3));
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lc3::LC3;
+
+    #[test]
+    fn assert_test() {
+	let mut lc3 = LC3::new();
+	lc3.memory.put(0x3000, 0b0001_001_001_1_11111); // ADD R1, R1, #-1
+	lc3.pc = 0x3000;
+	lc3.halted = false;
+	lc3.clock();
+	assert_cc!(lc3, N);
+	assert_regs!(lc3, r1 = 0xFFFF, r7 = 0);
+
+	let failure = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| assert_regs!(lc3, r1 = 2))).unwrap_err();
+	let message = failure.downcast_ref::<String>().unwrap();
+	assert!(message.starts_with("r1 is xFFFF (-1), expected x0002 (2)"));
+	assert!(message.contains("last instruction: x3000  127F  ADD R1, R1, #-1"));
+    }
+}
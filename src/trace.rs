@@ -0,0 +1,221 @@
+//! Instruction trace: one line per executed instruction with the registers and condition
+//! codes it changed, for diffing a run against another simulator
+
+use crate::disasm::{self, OPCODES};
+use crate::lc3::{LC3, LC3IO};
+use crate::symbols::Symbols;
+
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+/// State before a clock, compared against afterwards
+#[derive(Debug, Copy, Clone)]
+struct Before {
+    pc: u16,
+    word: i16,
+    regs: [i16; 8],
+    psr: i16,
+    instructions: u64
+}
+
+/// Writes a line for every instruction that passes the filters. Empty filters let
+/// everything through.
+pub struct Tracer<W: Write> {
+    pub ranges: Vec<RangeInclusive<u16>>, // addresses of instructions to trace
+    pub opcodes: Vec<u8>, // opcodes to trace
+    pub symbols: Symbols, // labels for operands and the nearest label to each instruction
+    out: W,
+    before: Option<Before>
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(out: W) -> Self {
+	Tracer { ranges: Vec::new(), opcodes: Vec::new(), symbols: Symbols::new(), out, before: None }
+    }
+
+    /// Call before clocking
+    pub fn before(&mut self, lc3: &LC3) {
+	let pc = lc3.pc as u16;
+	self.before = Some(Before {
+	    pc,
+	    word: lc3.memory.peek(pc),
+	    regs: lc3.regs(),
+	    psr: lc3.psr,
+	    instructions: lc3.instructions
+	});
+    }
+
+    /// Call after clocking, writes the line if an instruction ran and passes the filters
+    pub fn after(&mut self, lc3: &LC3) -> io::Result<()> {
+	let before = match self.before.take() {
+	    Some(before) => before,
+	    None => return Ok(())
+	};
+	// nothing ran, or the fetch faulted
+	if lc3.instructions == before.instructions
+	    || lc3.last_instruction.map(|(addr, _)| addr) != Some(before.pc) {
+	    return Ok(());
+	}
+	if !self.traces(before.pc, before.word) {
+	    return Ok(());
+	}
+	writeln!(self.out, "{}", line(&before, lc3, &self.symbols))
+    }
+
+    /// Clocks once, tracing the instruction
+    pub fn step(&mut self, lc3: &mut LC3) -> io::Result<LC3IO> {
+	self.before(lc3);
+	let r = lc3.clock();
+	self.after(lc3)?;
+	Ok(r)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+	self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+	self.out
+    }
+
+    fn traces(&self, pc: u16, word: i16) -> bool {
+	let op = (word as u16 >> 12) as u8;
+	(self.ranges.is_empty() || self.ranges.iter().any(|r| r.contains(&pc)))
+	    && (self.opcodes.is_empty() || self.opcodes.contains(&op))
+    }
+}
+
+/// `x3000  1261  ADD R1, R1, #1` padded, then what changed: registers, condition codes,
+/// privilege and jumps. With symbols the line starts with the nearest label.
+fn line(before: &Before, lc3: &LC3, symbols: &Symbols) -> String {
+    let mut changes = Vec::new();
+    for (i, (old, new)) in before.regs.iter().zip(lc3.regs().iter()).enumerate() {
+	if old != new {
+	    changes.push(format!("R{}=x{:04X}", i, *new as u16));
+	}
+    }
+    if before.psr & 0b111 != lc3.psr & 0b111 {
+	changes.push(format!("CC={}", nzp(lc3.psr)));
+    }
+    if (before.psr < 0) != (lc3.psr < 0) {
+	changes.push(if lc3.psr < 0 { "user" } else { "supervisor" }.to_string());
+    }
+    if lc3.pc as u16 != before.pc.wrapping_add(1) {
+	changes.push(format!("PC=x{:04X}", lc3.pc as u16));
+    }
+    let mut text = disasm::line_with(before.pc, before.word, symbols);
+    if !changes.is_empty() {
+	text = format!("{:<32}{}", text, changes.join(" "));
+    }
+    if symbols.is_empty() {
+	text
+    } else {
+	format!("{:<12}{}", symbols.nearest(before.pc).unwrap_or_default(), text)
+    }
+}
+
+fn nzp(psr: i16) -> String {
+    [(0b100, 'n'), (0b010, 'z'), (0b001, 'p')].iter()
+	.map(|(bit, c)| if psr & bit != 0 { *c } else { '-' })
+	.collect()
+}
+
+/// Parses a comma-separated list of mnemonics like `ADD,LDR,TRAP` into opcodes. `RET` and
+/// `JSRR` count as JMP and JSR.
+pub fn parse_opcodes(text: &str) -> Result<Vec<u8>, String> {
+    text.split(',').map(|name| {
+	let upper = name.trim().to_ascii_uppercase();
+	let upper = match upper.as_str() {
+	    "RET" => "JMP",
+	    "JSRR" => "JSR",
+	    "NOP" => "BR",
+	    other => other
+	};
+	OPCODES.iter().position(|op| *op == upper)
+	    .map(|op| op as u8)
+	    .ok_or_else(|| format!("Unknown opcode: {}", name))
+    }).collect()
+}
+
+/// Parses `x3000-x30FF` (inclusive, hex), refusing ranges that end before they start
+pub fn parse_range(text: &str) -> Result<RangeInclusive<u16>, String> {
+    let addr = |s: &str| {
+	let hex = s.strip_prefix('x').or_else(|| s.strip_prefix('X')).unwrap_or(s);
+	u16::from_str_radix(hex, 16).map_err(|_| format!("Bad address: {}", s))
+    };
+    match text.split_once('-') {
+	Some((start, end)) => span(addr(start)?, addr(end)?),
+	None => addr(text).map(|a| a..=a)
+    }
+}
+
+/// `start..=end`, or an error if `end` comes first
+pub fn span(start: u16, end: u16) -> Result<RangeInclusive<u16>, String> {
+    if end < start {
+	return Err(format!("x{:04X} is before x{:04X}", end, start));
+    }
+    Ok(start..=end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_opcodes, parse_range, Tracer};
+    use crate::fixtures::Fixture;
+
+    #[test]
+    fn trace_test() {
+	let mut lc3 = Fixture::bare()
+	    .code(&[
+		0b0001_001_001_1_00001, // ADD R1, R1, #1
+		0b0001_010_001_1_11110, // ADD R2, R1, #-2
+		0b0000_111_000000001, // BRnzp #1
+		0b0101_000_000_1_00000, // AND R0, R0, #0
+		0b0101_011_011_1_00000 // AND R3, R3, #0
+	    ])
+	    .build();
+	let mut tracer = Tracer::new(Vec::new());
+	for _ in 0..4 {
+	    tracer.step(&mut lc3).unwrap();
+	}
+	let out = String::from_utf8(tracer.into_inner()).unwrap();
+	assert_eq!(out, "\
+x3000  1261  ADD R1, R1, #1     R1=x0001 CC=--p
+x3001  147E  ADD R2, R1, #-2    R2=xFFFF CC=n--
+x3002  0E01  BRnzp x3004        PC=x3004
+x3004  56E0  AND R3, R3, #0     CC=-z-
+");
+    }
+
+    #[test]
+    fn filter_test() {
+	let mut lc3 = Fixture::bare()
+	    .code(&[0b0001_001_001_1_00001, 0b0101_000_000_1_00000, 0b0001_001_001_1_00001])
+	    .build();
+	let mut tracer = Tracer::new(Vec::new());
+	tracer.opcodes = parse_opcodes("add").unwrap();
+	tracer.ranges = vec![parse_range("x3001-x3002").unwrap()];
+	for _ in 0..3 {
+	    tracer.step(&mut lc3).unwrap();
+	}
+	let out = String::from_utf8(tracer.into_inner()).unwrap();
+	assert_eq!(out.lines().count(), 1);
+	assert!(out.starts_with("x3002  1261  ADD R1, R1, #1"));
+	assert_eq!(parse_opcodes("RET,jsrr"), Ok(vec![12, 4]));
+	assert!(parse_opcodes("MUL").is_err());
+	assert_eq!(parse_range("x3000"), Ok(0x3000..=0x3000));
+	assert!(parse_range("x3000-zz").is_err());
+	assert_eq!(parse_range("x4000-x4001"), Ok(0x4000..=0x4001));
+	assert_eq!(parse_range("x4001-x4000"), Err("x4000 is before x4001".to_string()));
+    }
+
+    #[test]
+    fn symbols_test() {
+	let mut lc3 = Fixture::bare().code(&[0b0001_001_001_1_00001, 0b0000_111_111111110]).build();
+	let mut tracer = Tracer::new(Vec::new());
+	tracer.symbols.insert("LOOP", 0x3000);
+	tracer.step(&mut lc3).unwrap();
+	tracer.step(&mut lc3).unwrap();
+	let out = String::from_utf8(tracer.into_inner()).unwrap();
+	assert!(out.ends_with("LOOP+1      x3001  0FFE  BRnzp LOOP         PC=x3000\n"), "{}", out);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::disasm::trap_name;
+use crate::lc3::LC3;
+
+/// Longest string argument shown before it's cut off
+pub const LIMIT: usize = 64;
+
+/// Logs service routine calls: entry with the caller and arguments, exit once control comes
+/// back to the caller
+#[derive(Debug)]
+pub struct TrapLog {
+    pub limit: usize,
+    pending: Vec<(u8, u16)> // vector and return address of traps not yet returned from
+}
+
+impl Default for TrapLog {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+impl TrapLog {
+    pub fn new() -> Self {
+	TrapLog { limit: LIMIT, pending: Vec::new() }
+    }
+
+    /// Call before clocking, returns a line if the next instruction is a TRAP
+    pub fn entry(&mut self, lc3: &LC3) -> Option<String> {
+	if lc3.halted || lc3.sleeping {
+	    return None;
+	}
+	let pc = lc3.pc as u16;
+	let word = lc3.memory.peek(pc);
+	if (word as u16) >> 12 != 0b1111 {
+	    return None;
+	}
+	let vector = word as u8;
+	self.pending.push((vector, pc.wrapping_add(1)));
+	let args = match vector {
+	    0x21 => format!(" R0={}", char_arg(lc3.r[0])),
+	    0x22 => format!(" R0=x{:04X} {}", lc3.r[0] as u16, self.string(lc3, false)),
+	    0x24 => format!(" R0=x{:04X} {}", lc3.r[0] as u16, self.string(lc3, true)),
+	    0x20 | 0x23 | 0x25 => String::new(),
+	    _ => format!(" R0=x{:04X} R1=x{:04X}", lc3.r[0] as u16, lc3.r[1] as u16)
+	};
+	Some(format!("{} from x{:04X}{}", name(vector), pc, args))
+    }
+
+    /// Call after clocking, returns a line if a logged trap just returned
+    pub fn exit(&mut self, lc3: &LC3) -> Option<String> {
+	let pc = lc3.pc as u16;
+	let i = self.pending.iter().rposition(|(_, ret)| *ret == pc)?;
+	let (vector, _) = self.pending[i];
+	self.pending.truncate(i);
+	let result = match vector {
+	    0x20 | 0x23 => format!(" R0={}", char_arg(lc3.r[0])),
+	    _ => String::new()
+	};
+	Some(format!("{} returned to x{:04X}{}", name(vector), pc, result))
+    }
+
+    /// The null-terminated string at R0, one character per word or two when packed
+    fn string(&self, lc3: &LC3, packed: bool) -> String {
+	let text: String = lc3.memory.chars(lc3.r[0] as u16, packed).take(self.limit + 1).collect();
+	if text.chars().count() > self.limit {
+	    format!("{:?}...", text.chars().take(self.limit).collect::<String>())
+	} else {
+	    format!("{:?}", text)
+	}
+    }
+}
+
+fn name(vector: u8) -> String {
+    match trap_name(vector) {
+	Some(name) => format!("TRAP x{:02X} {}", vector, name),
+	None => format!("TRAP x{:02X}", vector)
+    }
+}
+
+fn char_arg(value: i16) -> String {
+    format!("x{:04X} {:?}", value as u16, (value as u8) as char)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TrapLog;
+    use crate::lc3::LC3;
+
+    #[test]
+    fn traplog_test() {
+	let mut lc3 = LC3::new();
+	lc3.halted = false;
+	lc3.pc = 0x3000;
+	lc3.r[0] = 0x4000;
+	lc3.r[6] = 0x2F00;
+	lc3.memory.put(0x3000, 0b1111_0000_00100010); // PUTS
+	lc3.memory.put(0x0022, 0x0500);
+	lc3.memory.put(0x0500, 0b1000_0000_0000_0000); // RTI
+	lc3.memory.write_cstr(0x4000, "hello");
+	let mut log = TrapLog::new();
+	assert_eq!(log.entry(&lc3).as_deref(), Some("TRAP x22 PUTS from x3000 R0=x4000 \"hello\""));
+	lc3.clock();
+	assert_eq!(log.exit(&lc3), None);
+	assert_eq!(log.entry(&lc3), None);
+	lc3.clock();
+	assert_eq!(log.exit(&lc3).as_deref(), Some("TRAP x22 PUTS returned to x3001"));
+
+	log.limit = 3;
+	lc3.pc = 0x3000;
+	assert!(log.entry(&lc3).unwrap().ends_with("\"hel\"..."));
+
+	lc3.memory.write_packed_cstr(0x4000, "hi");
+	lc3.memory.put(0x3000, 0b1111_0000_00100100); // PUTSP
+	assert!(log.entry(&lc3).unwrap().ends_with("\"hi\""));
+    }
+}
@@ -0,0 +1,245 @@
+//! Full-screen terminal frontend, behind the `tui` feature: `lc3-emu prog.obj --tui`
+//!
+//! Panes for the registers, the code around PC, a memory view and the console, redrawn
+//! after every key. Drawing is plain ANSI like the slow-motion display; raw keyboard mode
+//! comes from `stty`, so it needs a Unix terminal.
+
+use crate::disasm;
+use crate::lc3::batch::StopReason;
+use crate::lc3::debug::Breakpoint;
+use crate::lc3::LC3;
+
+use std::io::{self, BufRead, Write};
+use std::process::{Command, Stdio};
+
+/// Instructions `c` runs before redrawing
+const CONTINUE_LIMIT: u64 = 10_000_000;
+
+/// Rows in the code and memory panes
+const ROWS: u16 = 15;
+
+/// Words per memory row
+const WORDS: u16 = 4;
+
+/// Console lines shown
+const CONSOLE: usize = 6;
+
+/// Width of the left column
+const LEFT: usize = 44;
+
+const KEYS: &str = "s step  c continue  b breakpoint at PC  j/k scroll memory  g memory at PC  q quit";
+
+pub struct Tui {
+    pub memory: u16, // first address in the memory pane
+    output: String,
+    status: String,
+    waiting: bool // the program wants a key, the next one goes to it
+}
+
+impl Tui {
+    pub fn new(lc3: &LC3) -> Self {
+	Tui { memory: lc3.pc as u16, output: String::new(), status: String::new(), waiting: false }
+    }
+
+    /// Handles a key press, returning false to quit
+    pub fn key(&mut self, lc3: &mut LC3, key: u8) -> bool {
+	if self.waiting {
+	    self.waiting = false;
+	    if lc3.sleeping {
+		lc3.interrupt(0x80, 4, key as i16).ok();
+	    } else {
+		lc3.memory.key_press(key as i16);
+	    }
+	    self.status = format!("Typed {:?}", key as char);
+	    return true;
+	}
+	match key {
+	    b'q' => return false,
+	    b's' => self.run(lc3, 1),
+	    b'c' => self.run(lc3, CONTINUE_LIMIT),
+	    b'b' => {
+		let pc = lc3.pc as u16;
+		if lc3.remove_breakpoint(pc) {
+		    self.status = format!("Breakpoint at x{:04X} removed", pc);
+		} else {
+		    lc3.add_breakpoint(Breakpoint { addr: pc, when: None });
+		    self.status = format!("Breakpoint at x{:04X}", pc);
+		}
+	    }
+	    b'j' => self.memory = self.memory.wrapping_add(WORDS * ROWS / 2),
+	    b'k' => self.memory = self.memory.wrapping_sub(WORDS * ROWS / 2),
+	    b'g' => self.memory = lc3.pc as u16,
+	    _ => self.status = KEYS.to_string()
+	}
+	true
+    }
+
+    /// Clocks up to `limit` times, collecting output until something needs attention
+    fn run(&mut self, lc3: &mut LC3, limit: u64) {
+	if lc3.halted {
+	    self.status = "Machine is halted".to_string();
+	    return;
+	}
+	let mut left = limit;
+	self.status.clear();
+	while left > 0 && self.status.is_empty() {
+	    let start = lc3.ticks;
+	    let stop = lc3.run_steps(left);
+	    left = left.saturating_sub(lc3.ticks - start);
+	    self.output.extend(lc3.take_output().into_iter().map(|c| c as char));
+	    match stop {
+		StopReason::Reset => self.output += "\n -- Processor reset -- \n",
+		StopReason::Halted => self.status = "Halted".to_string(),
+		StopReason::Idle => {
+		    self.waiting = true;
+		    self.status = "Waiting for input, the next key goes to the program".to_string();
+		}
+		StopReason::Limit => (),
+		StopReason::Breakpoint(addr) => self.status = format!("Breakpoint at x{:04X}", addr),
+		StopReason::Watch(watch) => self.status = format!("{:?}", watch),
+		StopReason::Exception(vector) => self.status = format!("Exception x{:02X} with no handler", vector)
+	    }
+	}
+    }
+
+    /// The whole screen
+    pub fn screen(&self, lc3: &LC3) -> String {
+	let left = self.left(lc3);
+	let right = self.right(lc3);
+	let mut out = String::from("\x1b[H\x1b[2J"); // home and clear
+	for i in 0..left.len().max(right.len()) {
+	    let (text, highlight) = left.get(i).cloned().unwrap_or_default();
+	    let text = format!("{:<width$}", text, width = LEFT);
+	    if highlight {
+		out += &format!("\x1b[7m{}\x1b[0m", text);
+	    } else {
+		out += &text;
+	    }
+	    out += right.get(i).map_or("", |s| s.as_str());
+	    out += "\r\n"; // raw mode doesn't return the carriage
+	}
+	out += &format!("\r\n{}\r\n{}", self.status, KEYS);
+	out
+    }
+
+    /// Registers, then the code around PC. Lines paired with whether to highlight them.
+    fn left(&self, lc3: &LC3) -> Vec<(String, bool)> {
+	let nzp: String = [(0b100, 'N'), (0b010, 'Z'), (0b001, 'P')].iter()
+	    .map(|(bit, c)| if lc3.psr & bit != 0 { *c } else { '-' })
+	    .collect();
+	let mode = if lc3.psr < 0 { "user" } else { "supervisor" };
+	let mut lines = vec![(format!("PC x{:04X}  PSR x{:04X}  {}  {}", lc3.pc as u16, lc3.psr as u16, nzp, mode), false)];
+	for (row, values) in lc3.regs().chunks(4).enumerate() {
+	    let cells: Vec<String> = values.iter().enumerate()
+		.map(|(i, v)| format!("R{} x{:04X}", row * 4 + i, *v as u16))
+		.collect();
+	    lines.push((cells.join("  "), false));
+	}
+	lines.push((String::new(), false));
+	let pc = lc3.pc as u16;
+	let start = pc.wrapping_sub(ROWS / 2);
+	for i in 0..ROWS {
+	    let addr = start.wrapping_add(i);
+	    let mark = if lc3.breakpoints().iter().any(|b| b.addr == addr) { '*' } else { ' ' };
+	    let line = disasm::line(addr, lc3.memory.peek(addr));
+	    lines.push((format!("{}{}", mark, line), addr == pc));
+	}
+	lines
+    }
+
+    /// The memory view, then the last lines of console output
+    fn right(&self, lc3: &LC3) -> Vec<String> {
+	let mut lines = vec!["Memory".to_string()];
+	for row in 0..ROWS / 2 {
+	    let addr = self.memory.wrapping_add(row * WORDS);
+	    let words: Vec<String> = (0..WORDS)
+		.map(|i| format!("{:04X}", lc3.memory.peek(addr.wrapping_add(i)) as u16))
+		.collect();
+	    lines.push(format!("x{:04X}  {}", addr, words.join(" ")));
+	}
+	lines.push(String::new());
+	lines.push("Console".to_string());
+	let console: Vec<&str> = self.output.split('\n').collect();
+	for line in &console[console.len().saturating_sub(CONSOLE)..] {
+	    lines.push(line.chars().filter(|c| !c.is_control()).collect());
+	}
+	lines
+    }
+}
+
+/// Runs the frontend on the controlling terminal until `q` or end of input
+pub fn run_terminal(lc3: &mut LC3) -> io::Result<()> {
+    let saved = stty(&["-g"])?;
+    stty(&["raw", "-echo"])?;
+    let result = run(lc3, io::stdin().lock(), io::stdout());
+    stty(&[saved.trim()])?;
+    println!();
+    result
+}
+
+/// Draws and handles keys from `input` until `q` or end of input
+pub fn run(lc3: &mut LC3, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut tui = Tui::new(lc3);
+    write!(output, "{}", tui.screen(lc3))?;
+    output.flush()?;
+    for key in input.bytes() {
+	if !tui.key(lc3, key?) {
+	    break;
+	}
+	write!(output, "{}", tui.screen(lc3))?;
+	output.flush()?;
+    }
+    Ok(())
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let out = Command::new("stty").args(args).stdin(Stdio::inherit()).output()?;
+    if !out.status.success() {
+	return Err(io::Error::other("stty failed, --tui needs a terminal"));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, Tui};
+    use crate::fixtures::Fixture;
+
+    #[test]
+    fn tui_test() {
+	let mut lc3 = Fixture::with_os()
+	    .code(&[
+		0b0001_001_001_1_00001, // ADD R1, R1, #1
+		0b1111_0000_00100000, // GETC
+		0b1111_0000_00100001, // OUT
+		0b1111_0000_00100101 // HALT
+	    ])
+	    .build();
+	let mut tui = Tui::new(&lc3);
+	let screen = tui.screen(&lc3);
+	assert!(screen.contains("\x1b[7m x3000  1261  ADD R1, R1, #1"));
+	assert!(screen.contains("x3000  1261 F020 F021 F025"));
+	assert!(tui.key(&mut lc3, b's'));
+	assert!(tui.key(&mut lc3, b'b'));
+	assert!(tui.screen(&lc3).contains("*x3001  F020  GETC"));
+	assert!(tui.key(&mut lc3, b'c'));
+	assert!(tui.screen(&lc3).contains("Breakpoint at x3001"));
+	tui.key(&mut lc3, b'c');
+	assert!(tui.screen(&lc3).contains("Waiting for input"));
+	tui.key(&mut lc3, b'z');
+	tui.key(&mut lc3, b'c');
+	let screen = tui.screen(&lc3);
+	assert!(screen.contains("Halted"));
+	assert!(screen.contains("\r\n x0267  B00D  STI R0, x0275                 z\r\n"), "{}", screen);
+	assert!(!tui.key(&mut lc3, b'q'));
+    }
+
+    #[test]
+    fn run_test() {
+	let mut lc3 = Fixture::bare().code(&[0b0001_001_001_1_00001]).build();
+	let mut out = Vec::new();
+	run(&mut lc3, "sjq".as_bytes(), &mut out).unwrap();
+	assert_eq!(lc3.r[1], 1);
+	assert_eq!(String::from_utf8(out).unwrap().matches("\x1b[2J").count(), 3);
+    }
+}
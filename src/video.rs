@@ -0,0 +1,255 @@
+//! Memory-mapped video: xC000-xFDFF is a 128x124 framebuffer, one pixel per word in 15-bit
+//! RGB (`0RRRRRGGGGGBBBBB`) as in PennSim and lc3tools. `Framebuffer` maps the region onto
+//! the device bus and keeps the pixels, and the `Vsync` device is how a program says a frame
+//! is finished.
+//!
+//! There's no window yet, frames are dumped headless: `lc3-emu game.obj --video frames/`
+//! writes `frames/frame-0001.png` and so on at every VSYNC write and once more at the end.
+
+use crate::lc3::device::Device;
+use crate::lc3::LC3Memory;
+
+use std::cell::Cell;
+use std::ops::Range;
+use std::path::Path;
+use std::rc::Rc;
+
+/// First word of the framebuffer
+pub const BASE: u16 = 0xC000;
+
+pub const WIDTH: usize = 128;
+pub const HEIGHT: usize = 124;
+
+/// One past the last word of the framebuffer, where the device page starts
+pub const END: u16 = BASE + (WIDTH * HEIGHT) as u16;
+
+/// Writing any value here marks the frame in memory as finished
+pub const VSYNC: u16 = 0xFE12;
+
+/// The framebuffer region on the bus, one word per pixel, row by row
+#[derive(Debug)]
+pub struct Framebuffer {
+    pixels: Vec<i16>
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+	Framebuffer { pixels: vec![0; WIDTH * HEIGHT] }
+    }
+}
+
+impl Device for Framebuffer {
+    fn range(&self) -> Range<u16> {
+	BASE..END
+    }
+
+    fn read(&mut self, addr: u16) -> i16 {
+	self.pixels[(addr - BASE) as usize]
+    }
+
+    fn peek(&self, addr: u16) -> Option<i16> {
+	Some(self.pixels[(addr - BASE) as usize])
+    }
+
+    fn write(&mut self, addr: u16, value: i16) {
+	self.pixels[(addr - BASE) as usize] = value;
+    }
+
+    fn save(&self) -> Vec<i16> {
+	self.pixels.clone()
+    }
+
+    fn restore(&mut self, state: &[i16]) {
+	let len = state.len().min(self.pixels.len());
+	self.pixels[..len].copy_from_slice(&state[..len]);
+    }
+}
+
+/// The VSYNC register. Reading it gives the number of frames finished so far.
+#[derive(Debug, Default)]
+pub struct Vsync {
+    frames: Rc<Cell<u16>> // shared with the host, which watches for new frames
+}
+
+impl Vsync {
+    /// The device, and a counter the host can keep after attaching it
+    pub fn new() -> (Self, Rc<Cell<u16>>) {
+	let frames = Rc::new(Cell::new(0));
+	(Vsync { frames: frames.clone() }, frames)
+    }
+}
+
+impl Device for Vsync {
+    fn range(&self) -> Range<u16> {
+	VSYNC..VSYNC + 1
+    }
+
+    fn read(&mut self, _addr: u16) -> i16 {
+	self.frames.get() as i16
+    }
+
+    fn write(&mut self, _addr: u16, _value: i16) {
+	self.frames.set(self.frames.get().wrapping_add(1));
+    }
+
+    fn save(&self) -> Vec<i16> {
+	vec![self.frames.get() as i16]
+    }
+
+    fn restore(&mut self, state: &[i16]) {
+	if let Some(&frames) = state.first() {
+	    self.frames.set(frames as u16);
+	}
+    }
+}
+
+/// 8-bit RGB for a 15-bit pixel
+pub fn rgb(word: i16) -> [u8; 3] {
+    let channel = |shift: u16| {
+	let c = (word as u16 >> shift) & 0b11111;
+	((c << 3) | (c >> 2)) as u8 // stretch 5 bits to the full 8
+    };
+    [channel(10), channel(5), channel(0)]
+}
+
+/// The framebuffer as RGB pixels, row by row, from the `Framebuffer` device if one is attached
+pub fn frame(memory: &LC3Memory) -> Vec<[u8; 3]> {
+    (0..WIDTH * HEIGHT).map(|i| rgb(memory.peek(BASE + i as u16))).collect()
+}
+
+/// Encodes pixels as an RGB PNG, uncompressed
+pub fn png(pixels: &[[u8; 3]], width: usize, height: usize) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for row in pixels.chunks(width).take(height) {
+	raw.push(0); // no filter
+	for pixel in row {
+	    raw.extend_from_slice(pixel);
+	}
+    }
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]); // 8 bits per channel, RGB
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut out, b"IHDR", &ihdr);
+    chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+/// Writes the framebuffer to a PNG file
+pub fn save_png(memory: &LC3Memory, path: &Path) -> Result<(), String> {
+    std::fs::write(path, png(&frame(memory), WIDTH, HEIGHT)).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// A zlib stream of stored (uncompressed) deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = data.chunks(0xFFFF).collect();
+    if blocks.is_empty() {
+	out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    for (i, block) in blocks.iter().enumerate() {
+	out.push((i == blocks.len() - 1) as u8);
+	let len = block.len() as u16;
+	out.extend_from_slice(&len.to_le_bytes());
+	out.extend_from_slice(&(!len).to_le_bytes());
+	out.extend_from_slice(block);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+	a = (a + *byte as u32) % 65521;
+	b = (b + a) % 65521;
+    }
+    out.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+	crc ^= *byte as u32;
+	for _ in 0..8 {
+	    crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+	}
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, frame, png, rgb, Framebuffer, Vsync, BASE, END, HEIGHT, VSYNC, WIDTH};
+    use crate::lc3::snapshot;
+    use crate::lc3::LC3;
+
+    #[test]
+    fn rgb_test() {
+	assert_eq!(rgb(0x7C00), [0xFF, 0, 0]);
+	assert_eq!(rgb(0x03E0), [0, 0xFF, 0]);
+	assert_eq!(rgb(0x0010), [0, 0, 0x84]);
+    }
+
+    #[test]
+    fn crc_test() {
+	assert_eq!(crc32(b""), 0);
+	assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+	assert_eq!(crc32(b"123456789"), 0xCBF4_3926); // the CRC-32 check value
+    }
+
+    #[test]
+    fn png_test() {
+	let bytes = png(&[[1, 2, 3], [4, 5, 6]], 2, 1);
+	assert!(bytes.starts_with(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x02\0\0\0\x01\x08\x02"));
+	assert!(bytes.ends_with(b"IEND\xae\x42\x60\x82"));
+	// IDAT: zlib header, one final stored block of the filter byte and both pixels
+	let idat = bytes.windows(4).position(|w| w == b"IDAT").unwrap() + 4;
+	assert_eq!(&bytes[idat..idat + 14], &[0x78, 0x01, 1, 7, 0, 0xF8, 0xFF, 0, 1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn vsync_test() {
+	let mut lc3 = LC3::new();
+	let (vsync, frames) = Vsync::new();
+	lc3.memory.attach(Box::new(vsync)).expect("Failed to attach");
+	lc3.memory.put(BASE + WIDTH as u16 + 1, 0x7FFF); // white at (1, 1)
+	lc3.memory.put(VSYNC, 1);
+	assert_eq!(frames.get(), 1);
+	assert_eq!(lc3.memory.get(VSYNC), 1);
+	let pixels = frame(&lc3.memory);
+	assert_eq!(pixels[WIDTH + 1], [0xFF; 3]);
+	assert_eq!(pixels[0], [0; 3]);
+    }
+
+    #[test]
+    fn framebuffer_test() {
+	let mut lc3 = LC3::new();
+	lc3.memory.attach(Box::new(Framebuffer::default())).expect("Failed to attach");
+	assert_eq!(END, 0xFE00);
+	assert_eq!(lc3.memory.bus().floor(), BASE);
+	lc3.memory.put(0x3000, 0b1011_001_000000001); // STI R1, [PC + 1]
+	lc3.memory.put(0x3002, (END - 1) as i16);
+	lc3.r[1] = 0x001F;
+	lc3.pc = 0x3000;
+	lc3.start();
+	lc3.clock();
+	assert_eq!(lc3.memory.mem[(END - 1) as usize], 0); // the device took the write
+	assert_eq!(lc3.memory.peek(END - 1), 0x001F);
+	assert_eq!(frame(&lc3.memory)[WIDTH * HEIGHT - 1], [0, 0, 0xFF]);
+
+	// the pixels travel in snapshots as the device's state
+	let bytes = snapshot::save(&lc3, true);
+	let mut other = LC3::new();
+	other.memory.attach(Box::new(Framebuffer::default())).expect("Failed to attach");
+	snapshot::restore(&mut other, &bytes).expect("Failed to restore");
+	assert_eq!(other.memory.get(END - 1), 0x001F);
+    }
+}
@@ -0,0 +1,242 @@
+//! Browser embedding, behind the `wasm` feature
+//!
+//! `Machine` wraps an `LC3` running the built-in OS with console output and key input as
+//! callbacks, so nothing touches stdin or stdout. On `wasm32` the same calls are exported as
+//! plain functions over a single machine, with output and input imported from the page:
+//!
+//! ```text
+//! cargo rustc --release --lib --target wasm32-unknown-unknown --features wasm --crate-type cdylib
+//! ```
+//!
+//! The page provides `env.lc3_output(c)` and `env.lc3_input()` (a key, or -1 for none yet),
+//! copies an object file into memory from `lc3_alloc(len)` and passes it to `lc3_load`.
+
+use crate::lc3::batch::StopReason;
+use crate::lc3::LC3;
+use crate::os::boot;
+
+/// An LC-3 with the built-in OS and callback I/O
+pub struct Machine {
+    lc3: Box<LC3>,
+    output: Box<dyn FnMut(u8)>,
+    input: Box<dyn FnMut() -> Option<u8>>
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+impl Machine {
+    /// A halted machine with no program, output discarded and no input
+    pub fn new() -> Self {
+	Machine { lc3: Box::new(LC3::new()), output: Box::new(|_| ()), input: Box::new(|| None) }
+    }
+
+    /// Called with every character the program writes to the display
+    pub fn on_output(&mut self, output: impl FnMut(u8) + 'static) {
+	self.output = Box::new(output);
+    }
+
+    /// Asked for a key whenever the program waits for one, `None` if there isn't one yet
+    pub fn on_input(&mut self, input: impl FnMut() -> Option<u8> + 'static) {
+	self.input = Box::new(input);
+    }
+
+    /// Replaces the machine with a fresh one running `obj`, returning its origin
+    pub fn load(&mut self, obj: &[u8]) -> Result<u16, &'static str> {
+	*self.lc3 = boot(obj)?;
+	Ok(self.lc3.pc as u16)
+    }
+
+    /// Clocks up to `n` times. Stops early on halt, or when the program waits for a key the
+    /// input callback doesn't have yet.
+    pub fn step(&mut self, n: u64) -> StopReason {
+	let mut left = n;
+	loop {
+	    let start = self.lc3.ticks;
+	    let stop = self.lc3.run_steps(left);
+	    left = left.saturating_sub((self.lc3.ticks - start).max(1));
+	    for c in self.lc3.take_output() {
+		(self.output)(c);
+	    }
+	    match stop {
+		StopReason::Idle => match (self.input)() {
+		    Some(key) if self.lc3.sleeping => {
+			self.lc3.interrupt(0x80, 4, key as i16).ok();
+		    }
+		    Some(key) => self.lc3.memory.key_press(key as i16),
+		    None => return StopReason::Idle
+		},
+		StopReason::Reset => (),
+		stop => return stop
+	    }
+	    if left == 0 {
+		return StopReason::Limit;
+	    }
+	}
+    }
+
+    /// R0-R7 by number
+    pub fn reg(&self, reg: u8) -> u16 {
+	self.lc3.regs()[reg as usize & 0b111] as u16
+    }
+
+    pub fn pc(&self) -> u16 {
+	self.lc3.pc as u16
+    }
+
+    pub fn psr(&self) -> u16 {
+	self.lc3.psr as u16
+    }
+
+    pub fn halted(&self) -> bool {
+	self.lc3.halted
+    }
+
+    /// Reads memory without device side effects
+    pub fn peek(&self, addr: u16) -> u16 {
+	self.lc3.memory.peek(addr) as u16
+    }
+
+    /// Writes memory the way the CPU does
+    pub fn poke(&mut self, addr: u16, value: u16) {
+	self.lc3.memory.put(addr, value as i16);
+    }
+
+    pub fn lc3(&mut self) -> &mut LC3 {
+	&mut self.lc3
+    }
+}
+
+/// Stop reasons as the numbers `lc3_step` returns
+pub fn code(stop: StopReason) -> u32 {
+    match stop {
+	StopReason::Limit => 0,
+	StopReason::Halted => 1,
+	StopReason::Idle => 2,
+	StopReason::Reset => 3,
+	StopReason::Breakpoint(_) => 4,
+	StopReason::Exception(_) => 5,
+	StopReason::Watch(_) => 6
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod exports {
+    use super::{code, Machine};
+
+    use std::cell::RefCell;
+
+    extern "C" {
+	fn lc3_output(c: u32);
+	fn lc3_input() -> i32;
+    }
+
+    thread_local! {
+	static MACHINE: RefCell<Machine> = RefCell::new(connected());
+    }
+
+    fn connected() -> Machine {
+	let mut machine = Machine::new();
+	machine.on_output(|c| unsafe { lc3_output(c as u32) });
+	machine.on_input(|| match unsafe { lc3_input() } {
+	    key @ 0..=255 => Some(key as u8),
+	    _ => None
+	});
+	machine
+    }
+
+    fn with<T>(f: impl FnOnce(&mut Machine) -> T) -> T {
+	MACHINE.with(|m| f(&mut m.borrow_mut()))
+    }
+
+    /// Buffer of `len` bytes for the page to fill, handed back to `lc3_load`
+    #[no_mangle]
+    pub extern "C" fn lc3_alloc(len: usize) -> *mut u8 {
+	let mut buffer = vec![0u8; len].into_boxed_slice();
+	let ptr = buffer.as_mut_ptr();
+	std::mem::forget(buffer);
+	ptr
+    }
+
+    /// Loads and frees a buffer from `lc3_alloc`, returning the origin or -1
+    ///
+    /// # Safety
+    /// `ptr` and `len` must come from one `lc3_alloc` call
+    #[no_mangle]
+    pub unsafe extern "C" fn lc3_load(ptr: *mut u8, len: usize) -> i32 {
+	let obj = Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len));
+	with(|m| m.load(&obj).map_or(-1, |origin| origin as i32))
+    }
+
+    #[no_mangle]
+    pub extern "C" fn lc3_step(n: u32) -> u32 {
+	with(|m| code(m.step(n as u64)))
+    }
+
+    #[no_mangle]
+    pub extern "C" fn lc3_reg(reg: u32) -> u32 {
+	with(|m| m.reg(reg as u8) as u32)
+    }
+
+    #[no_mangle]
+    pub extern "C" fn lc3_pc() -> u32 {
+	with(|m| m.pc() as u32)
+    }
+
+    #[no_mangle]
+    pub extern "C" fn lc3_psr() -> u32 {
+	with(|m| m.psr() as u32)
+    }
+
+    #[no_mangle]
+    pub extern "C" fn lc3_peek(addr: u32) -> u32 {
+	with(|m| m.peek(addr as u16) as u32)
+    }
+
+    #[no_mangle]
+    pub extern "C" fn lc3_poke(addr: u32, value: u32) {
+	with(|m| m.poke(addr as u16, value as u16))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{code, Machine};
+    use crate::asm::assemble;
+    use crate::lc3::batch::StopReason;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn machine_test() {
+	let program = assemble("\
+	.ORIG x3000
+	GETC
+	OUT
+	ADD R1, R0, #1
+	HALT
+	.END").expect("Failed to assemble");
+	let mut machine = Machine::new();
+	let output = Rc::new(RefCell::new(Vec::new()));
+	let keys = Rc::new(RefCell::new(Vec::new()));
+	let sink = output.clone();
+	machine.on_output(move |c| sink.borrow_mut().push(c));
+	let source = keys.clone();
+	machine.on_input(move || source.borrow_mut().pop());
+	assert_eq!(machine.load(&program.sections[0].obj()), Ok(0x3000));
+	assert_eq!(machine.step(1), StopReason::Limit);
+	assert_eq!(machine.step(10_000), StopReason::Idle);
+	keys.borrow_mut().push(b'a');
+	assert_eq!(code(machine.step(10_000)), 1);
+	assert!(machine.halted());
+	assert_eq!(machine.reg(1), b'b' as u16);
+	assert!(output.borrow().starts_with(b"a\n-----"));
+	machine.poke(0x4000, 0xBEEF);
+	assert_eq!(machine.peek(0x4000), 0xBEEF);
+	assert!(machine.load(&[0x30]).is_err());
+    }
+}